@@ -3,7 +3,10 @@
 //! Provides channel management, role-based permissions, and administrative operations
 //! for Legion encrypted channels.
 
+use crate::casemap::CaseMapping;
 use crate::error::{IronError, Result};
+use crate::hostmask::{BanMask, Glob, UserMask};
+use crate::secret::Secret;
 use serde::{Serialize, Deserialize};
 use std::collections::{HashMap, HashSet};
 use std::time::SystemTime;
@@ -159,13 +162,14 @@ pub struct ChannelSettings {
     pub modes: HashSet<ChannelMode>,
     /// Maximum number of members
     pub member_limit: Option<usize>,
-    /// Channel password/key
-    pub password: Option<String>,
-    /// Invite-only list
+    /// Channel password/key (not serialized; load it from configuration)
+    #[serde(skip_serializing, default)]
+    pub password: Option<Secret>,
+    /// Invite-only list (nicks or masks; query with [`is_invited`](Self::is_invited))
     pub invite_list: HashSet<String>,
-    /// Exception list (users who can bypass bans)
+    /// Exception list (users who can bypass bans; query with [`is_excepted`](Self::is_excepted))
     pub exception_list: HashSet<String>,
-    /// Quiet list (users who cannot speak)
+    /// Quiet list (users who cannot speak; query with [`is_quieted`](Self::is_quieted))
     pub quiet_list: HashSet<String>,
     /// Rate limiting settings
     pub rate_limit: Option<RateLimit>,
@@ -417,6 +421,27 @@ impl Default for ChannelSettings {
     }
 }
 
+impl ChannelSettings {
+    /// Check if a nick or `nick!user@host` is on the invite list under a server's casemapping
+    pub fn is_invited(&self, name: &str, casemapping: CaseMapping) -> bool {
+        list_matches(&self.invite_list, name, casemapping)
+    }
+
+    /// Check if a nick or `nick!user@host` is on the exception list under a server's casemapping
+    pub fn is_excepted(&self, name: &str, casemapping: CaseMapping) -> bool {
+        list_matches(&self.exception_list, name, casemapping)
+    }
+
+    /// Check if a nick or `nick!user@host` is on the quiet list under a server's casemapping
+    pub fn is_quieted(&self, name: &str, casemapping: CaseMapping) -> bool {
+        list_matches(&self.quiet_list, name, casemapping)
+    }
+}
+
+fn list_matches(list: &HashSet<String>, name: &str, casemapping: CaseMapping) -> bool {
+    list.iter().any(|entry| Glob::new(entry, casemapping).is_match(name))
+}
+
 impl MemberRole {
     /// Check if this role has a specific permission
     pub fn has_permission(&self, permission: &Permission) -> bool {
@@ -513,58 +538,30 @@ impl ChannelBan {
         }
     }
     
-    /// Check if this ban matches a user pattern
+    /// Check if this ban matches a user pattern (compared under rfc1459)
     pub fn matches_pattern(&self, pattern: &str) -> bool {
-        // Simple wildcard matching - in production this would be more sophisticated
-        if self.pattern.contains('*') || self.pattern.contains('?') {
-            self.wildcard_match(&self.pattern, pattern)
-        } else {
-            self.pattern == pattern
-        }
+        self.matches_pattern_with(pattern, CaseMapping::default())
     }
-    
-    fn wildcard_match(&self, pattern: &str, text: &str) -> bool {
-        // Basic wildcard matching implementation
-        // * matches any sequence of characters
-        // ? matches any single character
-        let pattern_chars: Vec<char> = pattern.chars().collect();
-        let text_chars: Vec<char> = text.chars().collect();
-        
-        self.match_recursive(&pattern_chars, &text_chars, 0, 0)
+
+    /// Check if this ban matches a user pattern under a server's casemapping
+    ///
+    /// The ban is applied as a glob to the whole string; use
+    /// [`matches_user`](Self::matches_user) for hostmask and extban semantics.
+    pub fn matches_pattern_with(&self, pattern: &str, casemapping: CaseMapping) -> bool {
+        Glob::new(&self.pattern, casemapping).is_match(pattern)
     }
-    
-    fn match_recursive(&self, pattern: &[char], text: &[char], p_idx: usize, t_idx: usize) -> bool {
-        if p_idx >= pattern.len() {
-            return t_idx >= text.len();
-        }
-        
-        match pattern[p_idx] {
-            '*' => {
-                // Try matching zero or more characters
-                for i in t_idx..=text.len() {
-                    if self.match_recursive(pattern, text, p_idx + 1, i) {
-                        return true;
-                    }
-                }
-                false
-            },
-            '?' => {
-                // Match any single character
-                if t_idx < text.len() {
-                    self.match_recursive(pattern, text, p_idx + 1, t_idx + 1)
-                } else {
-                    false
-                }
-            },
-            c => {
-                // Exact character match
-                if t_idx < text.len() && text[t_idx] == c {
-                    self.match_recursive(pattern, text, p_idx + 1, t_idx + 1)
-                } else {
-                    false
-                }
-            }
-        }
+
+    /// Compile the ban's pattern as a hostmask or extban
+    pub fn mask(&self, casemapping: CaseMapping) -> Result<BanMask> {
+        BanMask::parse(&self.pattern, casemapping)
+    }
+
+    /// Check if this ban applies to a user
+    ///
+    /// Invalid patterns match nobody. To check many bans, build a
+    /// [`BanList`](crate::hostmask::BanList) instead.
+    pub fn matches_user(&self, user: &UserMask, casemapping: CaseMapping) -> bool {
+        self.mask(casemapping).is_ok_and(|mask| mask.matches(user, casemapping))
     }
 }
 
@@ -690,8 +687,33 @@ mod tests {
         assert!(ban.matches_pattern("user@evil.com"));
         assert!(ban.matches_pattern("spammer@evil.com"));
         assert!(!ban.matches_pattern("user@good.com"));
+        assert!(ban.matches_pattern("User@EVIL.com"));
+
+        let ban = ChannelBan { pattern: "[bot]*!*@*".to_string(), ..ban };
+        assert!(ban.matches_pattern("{BOT}7!x@host"));
+        assert!(!ban.matches_pattern_with("{BOT}7!x@host", CaseMapping::Ascii));
+
+        let ban = ChannelBan { pattern: "$~a".to_string(), ..ban };
+        let user = UserMask::new("guest", "g", "host");
+        assert!(ban.mask(CaseMapping::Rfc1459).unwrap().is_extban());
+        assert!(ban.matches_user(&user, CaseMapping::Rfc1459));
+        assert!(!ban.matches_user(&user.with_account("guest"), CaseMapping::Rfc1459));
     }
     
+    #[test]
+    fn test_lists_respect_casemapping() {
+        let mut settings = ChannelSettings::default();
+        settings.invite_list.insert("Trusted[1]".to_string());
+        settings.exception_list.insert("*!*@Friends.example".to_string());
+        settings.quiet_list.insert("loud".to_string());
+
+        assert!(settings.is_invited("trusted{1}", CaseMapping::Rfc1459));
+        assert!(!settings.is_invited("trusted{1}", CaseMapping::Ascii));
+        assert!(settings.is_excepted("bob!b@FRIENDS.EXAMPLE", CaseMapping::Ascii));
+        assert!(settings.is_quieted("LOUD", CaseMapping::Rfc1459));
+        assert!(!settings.is_quieted("louder", CaseMapping::Rfc1459));
+    }
+
     #[test]
     fn test_admin_permissions() {
         let admin = ChannelAdmin::new(
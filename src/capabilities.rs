@@ -4,7 +4,11 @@
 //! including both stable and bleeding-edge capabilities from the 2024-2025 specifications.
 
 use crate::error::{IronError, Result};
+use crate::message::IrcMessage;
+use crate::sasl::{builtin_registry, MechanismRegistry};
+use crate::constants::MAX_CAPABILITY_NAME_LENGTH;
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
 #[cfg(feature = "serde")]
@@ -232,6 +236,209 @@ impl Capability {
     }
 }
 
+/// A namespaced capability name such as `soju.im/bouncer-networks` or
+/// `draft/chathistory/v2`
+///
+/// The namespace is either `draft` or a DNS name owned by the vendor; the
+/// remainder may contain further `/`-separated segments.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VendorCapabilityName {
+    /// Whether the name carries the client-tag `+` prefix
+    pub client_tag: bool,
+    /// The namespace (`draft` or a vendor domain)
+    pub vendor: String,
+    /// The segments after the namespace
+    pub path: Vec<String>,
+}
+
+impl VendorCapabilityName {
+    /// Parse a namespaced capability name, returning `None` if it is not
+    /// namespaced or does not follow the vendor grammar
+    pub fn parse(name: &str) -> Option<Self> {
+        let (client_tag, rest) = match name.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, name),
+        };
+
+        let mut segments = rest.split('/');
+        let vendor = segments.next()?;
+        let path: Vec<String> = segments.map(String::from).collect();
+        if path.is_empty() {
+            return None;
+        }
+
+        if vendor != "draft" && !Self::is_valid_vendor_domain(vendor) {
+            return None;
+        }
+
+        let valid_segment = |segment: &String| {
+            !segment.is_empty() && segment.chars().all(|c| {
+                c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_'
+            })
+        };
+        if !path.iter().all(valid_segment) {
+            return None;
+        }
+
+        Some(Self {
+            client_tag,
+            vendor: vendor.to_string(),
+            path,
+        })
+    }
+
+    /// Check if this is a `draft/` capability
+    pub fn is_draft(&self) -> bool {
+        self.vendor == "draft"
+    }
+
+    /// Check a vendor namespace against DNS hostname rules
+    fn is_valid_vendor_domain(domain: &str) -> bool {
+        if !domain.contains('.') || domain.len() > 253 {
+            return false;
+        }
+
+        domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+    }
+}
+
+impl std::fmt::Display for VendorCapabilityName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.client_tag {
+            write!(f, "+")?;
+        }
+        write!(f, "{}/{}", self.vendor, self.path.join("/"))
+    }
+}
+
+/// Descriptive metadata for a registered vendor capability
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VendorCapabilityInfo {
+    /// Short human-readable description
+    pub description: Option<String>,
+    /// Link to the capability's specification
+    pub spec_url: Option<String>,
+    /// Whether the capability should be requested whenever it is offered
+    pub auto_request: bool,
+}
+
+/// Application hook for a vendor capability's lifecycle
+pub trait VendorCapabilityHandler: Send + Sync {
+    /// Decide whether to request the capability given its advertised value
+    fn should_request(&self, _value: Option<&str>) -> bool {
+        true
+    }
+
+    /// Called when the server acknowledges the capability
+    fn on_enabled(&mut self, _value: Option<&str>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when the capability is disabled or removed with `CAP DEL`
+    fn on_disabled(&mut self) {}
+}
+
+struct VendorCapabilityEntry {
+    capability: Capability,
+    info: VendorCapabilityInfo,
+    handler: Option<Box<dyn VendorCapabilityHandler>>,
+}
+
+/// Registry of vendor capabilities known to the application
+///
+/// Vendor capabilities are represented as [`Capability::Custom`]; the
+/// registry attaches metadata and optional handlers to them.
+#[derive(Default)]
+pub struct VendorCapabilityRegistry {
+    entries: HashMap<String, VendorCapabilityEntry>,
+}
+
+impl VendorCapabilityRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a vendor capability with metadata
+    pub fn register(&mut self, name: &str, info: VendorCapabilityInfo) -> Result<Capability> {
+        if VendorCapabilityName::parse(name).is_none() || name.len() > MAX_CAPABILITY_NAME_LENGTH {
+            return Err(IronError::Capability(
+                format!("Invalid vendor capability name: {}", name)
+            ));
+        }
+
+        let capability = Capability::Custom(name.to_string());
+        self.entries.insert(name.to_string(), VendorCapabilityEntry {
+            capability: capability.clone(),
+            info,
+            handler: None,
+        });
+        Ok(capability)
+    }
+
+    /// Register a vendor capability together with a lifecycle handler
+    ///
+    /// Capabilities with a handler are requested only if `auto_request` is
+    /// set and the handler's `should_request` agrees with the offered value.
+    pub fn register_handler(
+        &mut self,
+        name: &str,
+        info: VendorCapabilityInfo,
+        handler: Box<dyn VendorCapabilityHandler>,
+    ) -> Result<Capability> {
+        let capability = self.register(name, info)?;
+        if let Some(entry) = self.entries.get_mut(name) {
+            entry.handler = Some(handler);
+        }
+        Ok(capability)
+    }
+
+    /// Look up the capability registered under a name
+    pub fn capability(&self, name: &str) -> Option<&Capability> {
+        self.entries.get(name).map(|e| &e.capability)
+    }
+
+    /// Look up metadata registered under a name
+    pub fn info(&self, name: &str) -> Option<&VendorCapabilityInfo> {
+        self.entries.get(name).map(|e| &e.info)
+    }
+
+    /// Check if a capability name is registered
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    /// Check whether a registered capability should be requested
+    pub fn wants(&self, name: &str, value: Option<&str>) -> bool {
+        match self.entries.get(name) {
+            Some(entry) => match &entry.handler {
+                Some(handler) => entry.info.auto_request && handler.should_request(value),
+                None => entry.info.auto_request,
+            },
+            None => false,
+        }
+    }
+
+    fn notify_enabled(&mut self, name: &str, value: Option<&str>) -> Result<()> {
+        match self.entries.get_mut(name).and_then(|e| e.handler.as_mut()) {
+            Some(handler) => handler.on_enabled(value),
+            None => Ok(()),
+        }
+    }
+
+    fn notify_disabled(&mut self, name: &str) {
+        if let Some(handler) = self.entries.get_mut(name).and_then(|e| e.handler.as_mut()) {
+            handler.on_disabled();
+        }
+    }
+}
+
 /// A capability with its value and enabled state
 #[derive(Debug, Clone)]
 pub struct CapabilitySpec {
@@ -248,6 +455,8 @@ pub struct CapabilityHandler {
     enabled_caps: HashMap<String, CapabilitySpec>,
     negotiation_complete: bool,
     sts_policies: HashMap<String, StsPolicy>,
+    vendor_caps: VendorCapabilityRegistry,
+    sasl_mechanisms: Arc<MechanismRegistry>,
 }
 
 /// STS (Strict Transport Security) policy
@@ -269,6 +478,8 @@ impl CapabilityHandler {
             enabled_caps: HashMap::new(),
             negotiation_complete: false,
             sts_policies: HashMap::new(),
+            vendor_caps: VendorCapabilityRegistry::new(),
+            sasl_mechanisms: builtin_registry(),
         }
     }
 
@@ -301,7 +512,9 @@ impl CapabilityHandler {
                     if let Some(cap) = self.available_caps.get(cap_name) {
                         let mut enabled_cap = cap.clone();
                         enabled_cap.enabled = true;
+                        let value = enabled_cap.value.clone();
                         self.enabled_caps.insert(cap_name.to_string(), enabled_cap);
+                        self.vendor_caps.notify_enabled(cap_name, value.as_deref())?;
                     }
                 }
             }
@@ -348,7 +561,9 @@ impl CapabilityHandler {
     pub fn handle_cap_del(&mut self, caps: &[String]) -> Result<()> {
         for cap in caps {
             self.available_caps.remove(cap);
-            self.enabled_caps.remove(cap);
+            if self.enabled_caps.remove(cap).is_some() {
+                self.vendor_caps.notify_disabled(cap);
+            }
         }
         Ok(())
     }
@@ -363,6 +578,18 @@ impl CapabilityHandler {
             }
         }
 
+        // Vendor capabilities the application registered interest in
+        let mut vendor_requests: Vec<&String> = self.available_caps.iter()
+            .filter(|(name, spec)| self.vendor_caps.wants(name, spec.value.as_deref()))
+            .map(|(name, _)| name)
+            .collect();
+        vendor_requests.sort();
+        for name in vendor_requests {
+            if !caps_to_request.contains(name) {
+                caps_to_request.push(name.clone());
+            }
+        }
+
         // Validate SASL mechanisms if present
         if let Some(sasl_cap) = self.available_caps.get("sasl") {
             if let Err(_) = self.validate_sasl_mechanisms(sasl_cap) {
@@ -373,6 +600,26 @@ impl CapabilityHandler {
         caps_to_request
     }
 
+    /// Get the vendor capability registry
+    pub fn vendor_capabilities(&self) -> &VendorCapabilityRegistry {
+        &self.vendor_caps
+    }
+
+    /// Get the vendor capability registry for registration
+    pub fn vendor_capabilities_mut(&mut self) -> &mut VendorCapabilityRegistry {
+        &mut self.vendor_caps
+    }
+
+    /// Get the SASL mechanisms considered supported
+    pub fn sasl_mechanisms(&self) -> &MechanismRegistry {
+        &self.sasl_mechanisms
+    }
+
+    /// Replace the SASL mechanisms considered supported
+    pub fn set_sasl_mechanisms(&mut self, registry: Arc<MechanismRegistry>) {
+        self.sasl_mechanisms = registry;
+    }
+
     /// Check if a capability is enabled
     pub fn is_capability_enabled(&self, cap_name: &str) -> bool {
         self.enabled_caps.contains_key(cap_name)
@@ -503,16 +750,11 @@ impl CapabilityHandler {
     /// Validate SASL mechanisms
     fn validate_sasl_mechanisms(&self, sasl_cap: &CapabilitySpec) -> Result<()> {
         if let Some(value) = &sasl_cap.value {
-            let mechanisms: Vec<&str> = value.split(',').collect();
-            
-            let preferred_order = ["SCRAM-SHA-256", "EXTERNAL", "PLAIN"];
-            
-            for &preferred in &preferred_order {
-                if mechanisms.iter().any(|m| m.trim() == preferred) {
-                    return Ok(());
-                }
+            if value.split(',').any(|m| self.sasl_mechanisms.contains(m.trim())) {
+                return Ok(());
             }
-            
+
+
             return Err(IronError::Auth(
                 "No supported SASL mechanisms".to_string()
             ));
@@ -522,7 +764,7 @@ impl CapabilityHandler {
 
     /// Validate capability name
     fn is_valid_capability_name(&self, name: &str) -> bool {
-        if name.is_empty() || name.len() > 64 {
+        if name.is_empty() || name.len() > MAX_CAPABILITY_NAME_LENGTH {
             return false;
         }
 
@@ -531,21 +773,15 @@ impl CapabilityHandler {
         }
 
         if name.contains('/') {
-            let parts: Vec<&str> = name.split('/').collect();
-            if parts.len() != 2 {
-                return false;
-            }
-            
-            if parts[0].contains('.') && !parts[0].ends_with(".com") 
-                && !parts[0].ends_with(".org") && !parts[0].ends_with(".net") 
-                && !parts[0].ends_with(".chat") && !parts[0].ends_with(".in") {
-                return false;
-            }
+            // Capabilities this crate knows by name (e.g. +legion-protocol/v1)
+            // predate the vendor grammar and are accepted as-is
+            return !matches!(Capability::from_str(name), Capability::Custom(_))
+                || VendorCapabilityName::parse(name).is_some();
         }
 
         name.chars().all(|c| {
             c.is_ascii_alphanumeric() || 
-            c == '-' || c == '/' || c == '.' || c == '_' || c == '+'
+            c == '-' || c == '.' || c == '_' || c == '+'
         })
     }
 }
@@ -664,6 +900,245 @@ impl Default for CapabilitySet {
     }
 }
 
+/// Capability state the server tracks for one connected client
+#[derive(Debug, Clone)]
+pub struct ClientCapabilities {
+    nick: String,
+    version: u16,
+    enabled: HashSet<Capability>,
+}
+
+impl ClientCapabilities {
+    /// Create state for a client that sent `CAP LS <version>`
+    pub fn new(version: u16) -> Self {
+        Self {
+            nick: "*".to_string(),
+            version,
+            enabled: HashSet::new(),
+        }
+    }
+
+    /// Set the client's nickname (used as the CAP target)
+    pub fn set_nick(&mut self, nick: impl Into<String>) {
+        self.nick = nick.into();
+    }
+
+    /// Get the client's nickname, or `*` before registration
+    pub fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    /// Get the CAP version the client negotiated with
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Check if a capability is enabled for this client
+    pub fn is_enabled(&self, cap: &Capability) -> bool {
+        self.enabled.contains(cap)
+    }
+
+    /// Check if the client should receive `CAP NEW` and `CAP DEL`
+    ///
+    /// CAP 302 implicitly enables cap-notify; older clients must request it.
+    pub fn wants_notifications(&self) -> bool {
+        self.version >= 302 || self.enabled.contains(&Capability::CapNotify)
+    }
+
+    /// Enabled capabilities
+    pub fn enabled(&self) -> impl Iterator<Item = &Capability> {
+        self.enabled.iter()
+    }
+}
+
+/// A `CAP NEW` or `CAP DEL` line addressed to one session
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapNotification {
+    /// Session the message must be delivered to
+    pub session_id: String,
+    /// The CAP message to send
+    pub message: IrcMessage,
+}
+
+/// Server-side capability registry that can change at runtime
+///
+/// Wraps the advertised [`CapabilitySet`] together with per-session state so
+/// that adding or removing a capability produces the `CAP NEW`/`CAP DEL`
+/// notifications each connected client is entitled to.
+pub struct CapabilityRegistry {
+    server_name: String,
+    advertised: CapabilitySet,
+    values: HashMap<Capability, String>,
+    sessions: HashMap<String, ClientCapabilities>,
+}
+
+impl CapabilityRegistry {
+    /// Create a registry advertising the given capability set
+    pub fn new(server_name: impl Into<String>, advertised: CapabilitySet) -> Self {
+        Self {
+            server_name: server_name.into(),
+            advertised,
+            values: HashMap::new(),
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// The currently advertised capabilities
+    pub fn advertised(&self) -> &CapabilitySet {
+        &self.advertised
+    }
+
+    /// Set the value advertised alongside a capability (e.g. `sasl=PLAIN,EXTERNAL`)
+    pub fn set_value(&mut self, cap: Capability, value: impl Into<String>) {
+        self.values.insert(cap, value.into());
+    }
+
+    /// Get the advertised value of a capability
+    pub fn value(&self, cap: &Capability) -> Option<&str> {
+        self.values.get(cap).map(|v| v.as_str())
+    }
+
+    /// Register a client that started negotiation with `CAP LS <version>`
+    pub fn register_session(&mut self, session_id: impl Into<String>, version: u16) {
+        self.sessions.insert(session_id.into(), ClientCapabilities::new(version));
+    }
+
+    /// Forget a disconnected client
+    pub fn remove_session(&mut self, session_id: &str) -> Option<ClientCapabilities> {
+        self.sessions.remove(session_id)
+    }
+
+    /// Get a session's capability state
+    pub fn session(&self, session_id: &str) -> Option<&ClientCapabilities> {
+        self.sessions.get(session_id)
+    }
+
+    /// Get a session's capability state for modification
+    pub fn session_mut(&mut self, session_id: &str) -> Option<&mut ClientCapabilities> {
+        self.sessions.get_mut(session_id)
+    }
+
+    /// Build the `CAP LS` capability list for a session
+    ///
+    /// Values are only included for clients that negotiated CAP 302.
+    pub fn ls_string_for(&self, session_id: &str) -> Result<String> {
+        let session = self.sessions.get(session_id)
+            .ok_or_else(|| IronError::Capability(format!("Unknown session: {}", session_id)))?;
+
+        let mut caps: Vec<String> = self.advertised.capabilities.iter()
+            .map(|cap| self.format_cap(cap, session.version >= 302))
+            .collect();
+        caps.sort();
+        Ok(caps.join(" "))
+    }
+
+    /// Handle `CAP REQ` for a session
+    ///
+    /// The request is atomic: either every capability is enabled (returns
+    /// `true`, reply ACK) or none is (returns `false`, reply NAK).
+    pub fn request(&mut self, session_id: &str, caps: &str) -> Result<bool> {
+        let mut enable = Vec::new();
+        let mut disable = Vec::new();
+
+        for name in caps.split_whitespace() {
+            let (removing, name) = match name.strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, name),
+            };
+            let cap = Capability::from_str(name);
+            if !self.advertised.supports(&cap) {
+                return Ok(false);
+            }
+            if removing {
+                disable.push(cap);
+            } else {
+                enable.push(cap);
+            }
+        }
+
+        let session = self.sessions.get_mut(session_id)
+            .ok_or_else(|| IronError::Capability(format!("Unknown session: {}", session_id)))?;
+
+        // cap-notify cannot be disabled by clients that negotiated CAP 302
+        if session.version >= 302 && disable.contains(&Capability::CapNotify) {
+            return Ok(false);
+        }
+
+        for cap in disable {
+            session.enabled.remove(&cap);
+        }
+        session.enabled.extend(enable);
+        Ok(true)
+    }
+
+    /// Start advertising a capability and notify interested sessions
+    ///
+    /// Returns one `CAP NEW` message for every session with cap-notify
+    /// (explicit or implied by CAP 302). Nothing is sent if the capability
+    /// was already advertised with the same value.
+    pub fn add(&mut self, cap: Capability, value: Option<String>) -> Vec<CapNotification> {
+        let value_changed = match &value {
+            Some(v) => self.values.get(&cap) != Some(v),
+            None => self.values.contains_key(&cap),
+        };
+        let newly_added = !self.advertised.supports(&cap);
+
+        match value {
+            Some(v) => { self.values.insert(cap.clone(), v); }
+            None => { self.values.remove(&cap); }
+        }
+
+        if !newly_added && !value_changed {
+            return Vec::new();
+        }
+        self.advertised.add(cap.clone());
+
+        self.sessions.iter()
+            .filter(|(_, session)| session.wants_notifications())
+            .map(|(id, session)| CapNotification {
+                session_id: id.clone(),
+                message: self.cap_message(session, "NEW", self.format_cap(&cap, session.version >= 302)),
+            })
+            .collect()
+    }
+
+    /// Stop advertising a capability and notify interested sessions
+    ///
+    /// The capability is force-disabled on every session, and a `CAP DEL`
+    /// message is produced for each session with cap-notify.
+    pub fn remove(&mut self, cap: &Capability) -> Vec<CapNotification> {
+        if !self.advertised.remove(cap) {
+            return Vec::new();
+        }
+        self.values.remove(cap);
+
+        for session in self.sessions.values_mut() {
+            session.enabled.remove(cap);
+        }
+
+        self.sessions.iter()
+            .filter(|(_, session)| session.wants_notifications())
+            .map(|(id, session)| CapNotification {
+                session_id: id.clone(),
+                message: self.cap_message(session, "DEL", cap.as_str().to_string()),
+            })
+            .collect()
+    }
+
+    fn format_cap(&self, cap: &Capability, with_value: bool) -> String {
+        match self.values.get(cap) {
+            Some(value) if with_value => format!("{}={}", cap.as_str(), value),
+            _ => cap.as_str().to_string(),
+        }
+    }
+
+    fn cap_message(&self, session: &ClientCapabilities, subcommand: &str, caps: String) -> IrcMessage {
+        IrcMessage::new("CAP")
+            .with_prefix(self.server_name.clone())
+            .with_params(vec![session.nick.clone(), subcommand.to_string(), caps])
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -705,6 +1180,21 @@ mod tests {
         assert!(handler.available_caps.contains_key("message-tags"));
     }
 
+    #[test]
+    fn test_sasl_request_uses_mechanism_registry() {
+        let mut handler = CapabilityHandler::new();
+        let params = vec!["testnick".to_string(), "sasl=X-VENDOR".to_string()];
+        handler.handle_cap_ls(&params).unwrap();
+        assert!(!handler.get_capabilities_to_request().contains(&"sasl".to_string()));
+
+        let mut registry = MechanismRegistry::with_builtins();
+        registry.register(crate::sasl::MechanismInfo::new("X-VENDOR", 5, |_| {
+            Err(IronError::Sasl("unused".to_string()))
+        }));
+        handler.set_sasl_mechanisms(Arc::new(registry));
+        assert!(handler.get_capabilities_to_request().contains(&"sasl".to_string()));
+    }
+
     #[test]
     fn test_capability_set() {
         let set = CapabilitySet::bleeding_edge();
@@ -712,4 +1202,128 @@ mod tests {
         assert!(set.supports(&Capability::MessageRedaction));
         assert!(set.supports(&Capability::Multiline));
     }
+
+    #[test]
+    fn test_registry_cap_new_notifies_capable_sessions() {
+        let mut registry = CapabilityRegistry::new("irc.example.com", CapabilitySet::new());
+        registry.register_session("modern", 302);
+        registry.register_session("legacy", 301);
+        registry.register_session("opted-in", 301);
+        assert!(registry.request("opted-in", "cap-notify").unwrap());
+        registry.session_mut("modern").unwrap().set_nick("alice");
+
+        let mut notes = registry.add(Capability::Multiline, Some("max-bytes=4096".to_string()));
+        notes.sort_by(|a, b| a.session_id.cmp(&b.session_id));
+
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].session_id, "modern");
+        assert_eq!(notes[0].message.to_string(),
+            ":irc.example.com CAP alice NEW draft/multiline=max-bytes=4096\r\n");
+        // Values are only sent to CAP 302 clients
+        assert_eq!(notes[1].session_id, "opted-in");
+        assert_eq!(notes[1].message.params, vec!["*", "NEW", "draft/multiline"]);
+
+        // Re-adding with the same value is a no-op
+        assert!(registry.add(Capability::Multiline, Some("max-bytes=4096".to_string())).is_empty());
+    }
+
+    #[test]
+    fn test_registry_cap_del_force_disables() {
+        let mut registry = CapabilityRegistry::new("irc.example.com", CapabilitySet::new());
+        registry.register_session("modern", 302);
+        registry.register_session("legacy", 301);
+        assert!(registry.request("modern", "sasl message-tags").unwrap());
+        assert!(registry.request("legacy", "sasl").unwrap());
+
+        let notes = registry.remove(&Capability::Sasl);
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].session_id, "modern");
+        assert_eq!(notes[0].message.params, vec!["*", "DEL", "sasl"]);
+
+        assert!(!registry.session("modern").unwrap().is_enabled(&Capability::Sasl));
+        assert!(!registry.session("legacy").unwrap().is_enabled(&Capability::Sasl));
+        assert!(registry.session("modern").unwrap().is_enabled(&Capability::MessageTags));
+        assert!(!registry.advertised().supports(&Capability::Sasl));
+        assert!(registry.remove(&Capability::Sasl).is_empty());
+    }
+
+    #[test]
+    fn test_registry_request_is_atomic() {
+        let mut registry = CapabilityRegistry::new("irc.example.com", CapabilitySet::new());
+        registry.register_session("client", 302);
+        registry.set_value(Capability::Sasl, "PLAIN,EXTERNAL");
+
+        assert!(!registry.request("client", "sasl draft/unknown").unwrap());
+        assert!(!registry.session("client").unwrap().is_enabled(&Capability::Sasl));
+        assert!(!registry.request("client", "-cap-notify").unwrap());
+        assert!(registry.ls_string_for("client").unwrap().contains("sasl=PLAIN,EXTERNAL"));
+    }
+
+    #[test]
+    fn test_vendor_capability_grammar() {
+        let handler = CapabilityHandler::new();
+        assert!(handler.is_valid_capability_name("soju.im/bouncer-networks"));
+        assert!(handler.is_valid_capability_name("znc.in/self-message"));
+        assert!(handler.is_valid_capability_name("draft/chathistory/v2"));
+        assert!(handler.is_valid_capability_name("+draft/react"));
+        assert!(handler.is_valid_capability_name("example.co.uk/feature"));
+
+        assert!(!handler.is_valid_capability_name("soju.im/"));
+        assert!(!handler.is_valid_capability_name("vendor/feature")); // not a domain or draft
+        assert!(!handler.is_valid_capability_name("-bad.im/feature"));
+        assert!(!handler.is_valid_capability_name("bad-.im/feature"));
+        assert!(!handler.is_valid_capability_name("draft//double"));
+        assert!(handler.is_valid_capability_name("+legion-protocol/v1"));
+
+        let name = VendorCapabilityName::parse("draft/chathistory/v2").unwrap();
+        assert!(name.is_draft());
+        assert_eq!(name.path, vec!["chathistory", "v2"]);
+        assert_eq!(name.to_string(), "draft/chathistory/v2");
+    }
+
+    #[test]
+    fn test_vendor_capability_handlers() {
+        use std::sync::{Arc, Mutex};
+
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+        impl VendorCapabilityHandler for Recorder {
+            fn on_enabled(&mut self, value: Option<&str>) -> Result<()> {
+                self.0.lock().unwrap().push(format!("on:{}", value.unwrap_or("")));
+                Ok(())
+            }
+            fn on_disabled(&mut self) {
+                self.0.lock().unwrap().push("off".to_string());
+            }
+        }
+
+        struct Picky;
+        impl VendorCapabilityHandler for Picky {
+            fn should_request(&self, value: Option<&str>) -> bool {
+                value == Some("v2")
+            }
+        }
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut handler = CapabilityHandler::new();
+        let auto = VendorCapabilityInfo { auto_request: true, ..VendorCapabilityInfo::default() };
+        let cap = handler.vendor_capabilities_mut().register_handler(
+            "soju.im/bouncer-networks",
+            auto.clone(),
+            Box::new(Recorder(events.clone())),
+        ).unwrap();
+        let registry = handler.vendor_capabilities_mut();
+        registry.register_handler("example.org/picky", auto, Box::new(Picky)).unwrap();
+        assert!(!registry.wants("example.org/picky", Some("v1")));
+        assert!(registry.wants("example.org/picky", Some("v2")));
+        registry.register_handler("example.org/manual", VendorCapabilityInfo::default(), Box::new(Picky)).unwrap();
+        assert!(!registry.wants("example.org/manual", Some("v2")));
+        assert_eq!(cap, Capability::Custom("soju.im/bouncer-networks".to_string()));
+
+        handler.handle_cap_ls(&["*".to_string(), "soju.im/bouncer-networks=notify sasl".to_string()]).unwrap();
+        assert!(handler.get_capabilities_to_request().contains(&"soju.im/bouncer-networks".to_string()));
+
+        handler.handle_cap_ack(&["soju.im/bouncer-networks".to_string()]).unwrap();
+        handler.handle_cap_del(&["soju.im/bouncer-networks".to_string()]).unwrap();
+        assert_eq!(*events.lock().unwrap(), vec!["on:notify", "off"]);
+    }
 }
\ No newline at end of file
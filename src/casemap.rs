@@ -0,0 +1,185 @@
+//! IRC casemapping for nickname, channel and account comparisons
+//!
+//! Servers announce how names are compared with the `CASEMAPPING` ISUPPORT
+//! token. Under `rfc1459`, `[]\~` are the upper-case forms of `{}|^`, so
+//! `[nick]` and `{NICK}` are the same nickname. [`CaseFoldedKey`] carries a
+//! name with its folded form so maps can be keyed case-insensitively while
+//! remembering the spelling that was first seen.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use unicode_normalization::UnicodeNormalization;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// How a server compares names
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CaseMapping {
+    /// Only `A-Z` fold to `a-z`
+    Ascii,
+    /// ASCII plus `[]\~` fold to `{}|^` (the default without ISUPPORT)
+    #[default]
+    Rfc1459,
+    /// ASCII plus `[]\` fold to `{}|`
+    Rfc1459Strict,
+    /// Unicode: width mapping (NFKC) and full lower-casing (RFC 7613)
+    Rfc7613,
+}
+
+impl CaseMapping {
+    /// Parse an ISUPPORT `CASEMAPPING` value
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "ascii" => Some(CaseMapping::Ascii),
+            "rfc1459" => Some(CaseMapping::Rfc1459),
+            "rfc1459-strict" | "strict-rfc1459" => Some(CaseMapping::Rfc1459Strict),
+            "rfc7613" | "precis" => Some(CaseMapping::Rfc7613),
+            _ => None,
+        }
+    }
+
+    /// The ISUPPORT value for this mapping
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CaseMapping::Ascii => "ascii",
+            CaseMapping::Rfc1459 => "rfc1459",
+            CaseMapping::Rfc1459Strict => "rfc1459-strict",
+            CaseMapping::Rfc7613 => "rfc7613",
+        }
+    }
+
+    /// Find the mapping announced in a set of ISUPPORT tokens
+    ///
+    /// Returns `None` if no recognised `CASEMAPPING` token is present.
+    pub fn from_isupport<S: AsRef<str>>(tokens: &[S]) -> Option<Self> {
+        tokens.iter()
+            .filter_map(|token| token.as_ref().strip_prefix("CASEMAPPING="))
+            .filter_map(Self::from_str)
+            .next_back()
+    }
+
+    /// Fold a name to its canonical lower-case form
+    pub fn fold(&self, name: &str) -> String {
+        match self {
+            CaseMapping::Ascii => name.to_ascii_lowercase(),
+            CaseMapping::Rfc1459 | CaseMapping::Rfc1459Strict => name.chars()
+                .map(|c| match c {
+                    '[' => '{',
+                    ']' => '}',
+                    '\\' => '|',
+                    '~' if *self == CaseMapping::Rfc1459 => '^',
+                    c => c.to_ascii_lowercase(),
+                })
+                .collect(),
+            CaseMapping::Rfc7613 => {
+                let lowered: String = name.nfkc().collect::<String>().to_lowercase();
+                lowered.nfc().collect()
+            }
+        }
+    }
+
+    /// Check if two names are equal under this mapping
+    pub fn equals(&self, a: &str, b: &str) -> bool {
+        a == b || self.fold(a) == self.fold(b)
+    }
+
+    /// Build a map key for a name
+    pub fn key(&self, name: &str) -> CaseFoldedKey {
+        CaseFoldedKey::new(name, *self)
+    }
+}
+
+/// A name that compares and hashes by its folded form
+///
+/// Implements `Borrow<str>` for the folded form, so a map keyed by
+/// `CaseFoldedKey` can be queried with `map.get(mapping.fold(name).as_str())`.
+#[derive(Debug, Clone)]
+pub struct CaseFoldedKey {
+    name: String,
+    folded: String,
+}
+
+impl CaseFoldedKey {
+    /// Fold `name` with `mapping`
+    pub fn new(name: &str, mapping: CaseMapping) -> Self {
+        Self {
+            name: name.to_string(),
+            folded: mapping.fold(name),
+        }
+    }
+
+    /// The name as originally spelled
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+
+    /// The folded form used for comparison
+    pub fn folded(&self) -> &str {
+        &self.folded
+    }
+}
+
+impl PartialEq for CaseFoldedKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.folded == other.folded
+    }
+}
+
+impl Eq for CaseFoldedKey {}
+
+impl Hash for CaseFoldedKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.folded.hash(state);
+    }
+}
+
+impl Borrow<str> for CaseFoldedKey {
+    fn borrow(&self) -> &str {
+        &self.folded
+    }
+}
+
+impl fmt::Display for CaseFoldedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_folding() {
+        assert!(CaseMapping::Rfc1459.equals("[Nick]~", "{nick}^"));
+        assert!(!CaseMapping::Rfc1459Strict.equals("nick~", "nick^"));
+        assert!(CaseMapping::Rfc1459Strict.equals("[Nick]", "{nick}"));
+        assert!(!CaseMapping::Ascii.equals("[nick]", "{nick}"));
+        assert!(CaseMapping::Ascii.equals("#Foo", "#foo"));
+        assert!(CaseMapping::Rfc7613.equals("#ＣＡＦÉ", "#café"));
+        assert!(!CaseMapping::Rfc1459.equals("#CAFÉ", "#café"));
+    }
+
+    #[test]
+    fn test_isupport_selection() {
+        let tokens = ["CHANTYPES=#&", "CASEMAPPING=ascii", "NICKLEN=30"];
+        assert_eq!(CaseMapping::from_isupport(&tokens), Some(CaseMapping::Ascii));
+        assert_eq!(CaseMapping::from_isupport(&["CASEMAPPING=unknown"]), None);
+        assert_eq!(CaseMapping::from_str("RFC1459-STRICT"), Some(CaseMapping::Rfc1459Strict));
+        assert_eq!(CaseMapping::default(), CaseMapping::Rfc1459);
+    }
+
+    #[test]
+    fn test_folded_keys() {
+        let mapping = CaseMapping::Rfc1459;
+        let mut map = HashMap::new();
+        map.insert(mapping.key("#Rust[dev]"), 1);
+        assert_eq!(map.get(mapping.fold("#rust{DEV}").as_str()), Some(&1));
+        assert!(map.contains_key(&mapping.key("#RUST[DEV]")));
+        assert_eq!(map.keys().next().unwrap().to_string(), "#Rust[dev]");
+    }
+}
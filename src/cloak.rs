@@ -0,0 +1,263 @@
+//! Keyed hostname and IP cloaking
+//!
+//! Cloaks hide a user's real address behind HMAC-SHA256 digests while
+//! keeping the network structure, so a whole range can still be banned:
+//!
+//! - `192.0.2.1` becomes `H32.H24.cloak-H16.IP`, hashing the /32, /24 and /16
+//! - `2001:db8::1` becomes `H128.H64.cloak-H48.IP`
+//! - `client.example.net` becomes `cloak-H.example.net`
+//!
+//! Banning `*.cloak-ABCD1234.IP` covers the /16 (IPv4) or /48 (IPv6).
+//! Several keys can be active at once: the first produces new cloaks and the
+//! rest keep matching bans written against older cloaks during rotation.
+
+use crate::error::{IronError, Result};
+use crate::hostmask::UserMask;
+use crate::secret::Secret;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::net::IpAddr;
+
+/// Default label prefix for the network part of a cloak
+pub const DEFAULT_PREFIX: &str = "cloak";
+
+/// Produces cloaked hosts from one or more secret keys
+#[derive(Debug, Clone)]
+pub struct Cloaker {
+    keys: Vec<Secret>,
+    prefix: String,
+}
+
+impl Cloaker {
+    /// Create a cloaker with a single key
+    pub fn new(key: impl Into<Secret>) -> Result<Self> {
+        let key = key.into();
+        check_key(&key)?;
+        Ok(Self {
+            keys: vec![key],
+            prefix: DEFAULT_PREFIX.to_string(),
+        })
+    }
+
+    /// Use a different network label prefix
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Keep an older key active for matching
+    pub fn with_previous_key(mut self, key: impl Into<Secret>) -> Result<Self> {
+        let key = key.into();
+        check_key(&key)?;
+        self.keys.push(key);
+        Ok(self)
+    }
+
+    /// Make `key` the primary key, keeping the current ones for matching
+    pub fn rotate(&mut self, key: impl Into<Secret>) -> Result<()> {
+        let key = key.into();
+        check_key(&key)?;
+        self.keys.insert(0, key);
+        Ok(())
+    }
+
+    /// Drop all but the `count` newest keys
+    pub fn retain_keys(&mut self, count: usize) {
+        self.keys.truncate(count.max(1));
+    }
+
+    /// Number of active keys
+    pub fn key_count(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Cloak an IP address with the primary key
+    pub fn cloak_ip(&self, ip: IpAddr) -> String {
+        self.cloak_ip_with(&self.keys[0], ip)
+    }
+
+    /// Cloak a hostname with the primary key
+    ///
+    /// Hostnames that are IP addresses are cloaked as IPs.
+    pub fn cloak_host(&self, host: &str) -> String {
+        self.cloak_host_with(&self.keys[0], host)
+    }
+
+    /// The cloak for a user: their hostname if it resolved, else their IP
+    pub fn cloak_user(&self, user: &UserMask) -> String {
+        self.cloaks_for(user).next().unwrap_or_default()
+    }
+
+    /// Every cloak of a user, one per active key, primary first
+    pub fn all_cloaks(&self, user: &UserMask) -> Vec<String> {
+        self.cloaks_for(user).collect()
+    }
+
+    /// The `nick!user@cloak` source to put on messages from a user
+    pub fn source(&self, user: &UserMask) -> String {
+        format!("{}!{}@{}", user.nick, user.user, self.cloak_user(user))
+    }
+
+    /// Add every active cloak to a ban-matching identity
+    pub fn apply(&self, user: UserMask) -> UserMask {
+        let cloaks = self.all_cloaks(&user);
+        cloaks.into_iter().fold(user, UserMask::with_cloak)
+    }
+
+    /// Check if `cloak` belongs to `user` under any active key
+    pub fn is_cloak_of(&self, cloak: &str, user: &UserMask) -> bool {
+        self.cloaks_for(user).any(|c| c.eq_ignore_ascii_case(cloak))
+    }
+
+    fn cloaks_for<'a>(&'a self, user: &'a UserMask) -> impl Iterator<Item = String> + 'a {
+        let use_host = !user.host.is_empty() && user.host.parse::<IpAddr>().is_err();
+        self.keys.iter().filter_map(move |key| {
+            if use_host {
+                Some(self.cloak_host_with(key, &user.host))
+            } else {
+                let ip = user.ip.or_else(|| user.host.parse().ok())?;
+                Some(self.cloak_ip_with(key, ip))
+            }
+        })
+    }
+
+    fn cloak_ip_with(&self, key: &Secret, ip: IpAddr) -> String {
+        // Compare IPv4-mapped IPv6 clients as IPv4
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            v4 => v4,
+        };
+        let (full, mid, net) = match ip {
+            IpAddr::V4(v4) => {
+                let o = v4.octets();
+                (format!("{}", v4), format!("{}.{}.{}", o[0], o[1], o[2]), format!("{}.{}", o[0], o[1]))
+            }
+            IpAddr::V6(v6) => {
+                let s = v6.segments();
+                let hex = |n: usize| s[..n].iter().map(|x| format!("{:x}", x)).collect::<Vec<_>>().join(":");
+                (hex(8), hex(4), hex(3))
+            }
+        };
+        format!(
+            "{}.{}.{}-{}.IP",
+            digest(key, &full),
+            digest(key, &mid),
+            self.prefix,
+            digest(key, &net)
+        )
+    }
+
+    fn cloak_host_with(&self, key: &Secret, host: &str) -> String {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return self.cloak_ip_with(key, ip);
+        }
+        let host = host.to_ascii_lowercase();
+        let labels: Vec<&str> = host.split('.').collect();
+        // Keep the registrable domain; hide everything that identifies the user
+        let visible = if labels.len() >= 3 { &labels[1..] } else { &labels[labels.len() - 1..] };
+        format!("{}-{}.{}", self.prefix, digest(key, &host), visible.join("."))
+    }
+}
+
+fn check_key(key: &Secret) -> Result<()> {
+    if key.expose().len() < 16 {
+        return Err(IronError::Config("Cloak keys must be at least 16 bytes".to_string()));
+    }
+    Ok(())
+}
+
+/// First 32 bits of HMAC-SHA256 as upper-case hex
+fn digest(key: &Secret, data: &str) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key.expose().as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    let out = mac.finalize().into_bytes();
+    out[..4].iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::casemap::CaseMapping;
+    use crate::hostmask::BanList;
+
+    const KEY: &str = "0123456789abcdef-primary";
+    const OLD_KEY: &str = "0123456789abcdef-previous";
+
+    #[test]
+    fn test_ipv4_keeps_network_structure() {
+        let cloaker = Cloaker::new(KEY).unwrap();
+        let a = cloaker.cloak_ip("192.0.2.1".parse().unwrap());
+        let b = cloaker.cloak_ip("192.0.2.77".parse().unwrap());
+        let c = cloaker.cloak_ip("192.0.99.1".parse().unwrap());
+
+        assert_eq!(a, cloaker.cloak_ip("192.0.2.1".parse().unwrap()));
+        assert!(a.ends_with(".IP") && a.contains(".cloak-"));
+        let parts = |s: &str| s.split('.').map(str::to_string).collect::<Vec<_>>();
+        let (a, b, c) = (parts(&a), parts(&b), parts(&c));
+        assert_ne!(a[0], b[0]);
+        assert_eq!(a[1..], b[1..]); // same /24
+        assert_ne!(a[1], c[1]);
+        assert_eq!(a[2..], c[2..]); // same /16
+        assert!(!a.join(".").contains("192"));
+
+        let mapped = cloaker.cloak_ip("::ffff:192.0.2.1".parse().unwrap());
+        assert_eq!(parts(&mapped), a);
+    }
+
+    #[test]
+    fn test_ipv6_and_hostnames() {
+        let cloaker = Cloaker::new(KEY).unwrap().with_prefix("net");
+        let a = cloaker.cloak_ip("2001:db8:1:2::1".parse().unwrap());
+        let b = cloaker.cloak_ip("2001:db8:1:3::1".parse().unwrap());
+        assert!(a.contains(".net-"));
+        assert_eq!(a.rsplit('.').nth(1), b.rsplit('.').nth(1)); // same /48
+
+        let host = cloaker.cloak_host("Client-42.Example.NET");
+        assert!(host.starts_with("net-") && host.ends_with(".example.net"));
+        assert_eq!(host, cloaker.cloak_host("client-42.example.net"));
+        assert!(cloaker.cloak_host("localhost").starts_with("net-"));
+        assert_eq!(cloaker.cloak_host("192.0.2.1"), cloaker.cloak_ip("192.0.2.1".parse().unwrap()));
+        assert!(Cloaker::new("short").is_err());
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let user = UserMask::new("alice", "a", "192.0.2.1").with_ip("192.0.2.1".parse().unwrap());
+        let old = Cloaker::new(OLD_KEY).unwrap();
+        let old_cloak = old.cloak_user(&user);
+
+        let mut cloaker = old.clone();
+        cloaker.rotate(KEY).unwrap();
+        assert_eq!(cloaker.key_count(), 2);
+        assert_ne!(cloaker.cloak_user(&user), old_cloak);
+        assert!(cloaker.is_cloak_of(&old_cloak, &user));
+        assert_eq!(cloaker.all_cloaks(&user)[1], old_cloak);
+
+        cloaker.retain_keys(1);
+        assert!(!cloaker.is_cloak_of(&old_cloak, &user));
+        assert_eq!(cloaker.source(&user), format!("alice!a@{}", cloaker.cloak_user(&user)));
+    }
+
+    #[test]
+    fn test_bans_on_cloaks() {
+        let old = Cloaker::new(OLD_KEY).unwrap();
+        let cloaker = Cloaker::new(KEY).unwrap().with_previous_key(OLD_KEY).unwrap();
+        let user = UserMask::new("bob", "b", "192.0.2.1").with_ip("192.0.2.1".parse().unwrap());
+        let neighbour = UserMask::new("eve", "e", "192.0.7.9").with_ip("192.0.7.9".parse().unwrap());
+
+        // A ban on the /16 part of an old cloak still applies after rotation
+        let old_cloak = old.cloak_user(&user);
+        let network = old_cloak.splitn(3, '.').nth(2).unwrap();
+        let mut bans = BanList::new(CaseMapping::Rfc1459);
+        bans.add(&format!("*!*@*.{}", network)).unwrap();
+
+        assert!(!bans.matches(&user));
+        assert!(bans.matches(&cloaker.apply(user.clone())));
+        assert!(bans.matches(&cloaker.apply(neighbour)));
+
+        let mut exact = BanList::new(CaseMapping::Rfc1459);
+        exact.add(&format!("*!*@{}", cloaker.cloak_user(&user))).unwrap();
+        assert!(exact.matches(&cloaker.apply(user)));
+    }
+}
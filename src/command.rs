@@ -5,6 +5,9 @@
 
 // use std::str::FromStr; // Not currently used
 
+use crate::connection::webirc::WebIrc;
+use crate::secret::Secret;
+
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
@@ -17,14 +20,16 @@ pub enum Command {
     Nick(String),
     /// USER command - user registration
     User { username: String, realname: String },
-    /// PASS command - connection password
-    Pass(String),
+    /// PASS command - connection password (not serialized; restored empty)
+    Pass(#[cfg_attr(feature = "serde", serde(serialize_with = "crate::secret::serialize_empty"))] Secret),
     /// QUIT command - disconnect
     Quit(Option<String>),
     /// PING command - server ping
     Ping(String),
     /// PONG command - ping response
     Pong(String),
+    /// WEBIRC command - gateway passes on the real client host and IP
+    WebIrc(WebIrc),
     
     // Channel operations
     /// JOIN command - join channels
@@ -101,8 +106,12 @@ pub enum Command {
     ChatHistory { subcommand: String, target: String, params: Vec<String> },
     
     // Operator commands
-    /// OPER command - gain operator privileges
-    Oper { name: String, password: String },
+    /// OPER command - gain operator privileges (password not serialized)
+    Oper {
+        name: String,
+        #[cfg_attr(feature = "serde", serde(skip_serializing, default))]
+        password: Secret,
+    },
     /// KILL command - forcibly disconnect user
     Kill { nick: String, reason: String },
     /// REHASH command - reload server configuration
@@ -146,11 +155,15 @@ impl Command {
             }
             "PASS" => {
                 if let Some(pass) = params.first() {
-                    Command::Pass(pass.clone())
+                    Command::Pass(Secret::new(pass.as_str()))
                 } else {
                     Command::Unknown(command.to_string(), params)
                 }
             }
+            "WEBIRC" => match WebIrc::from_params(&params) {
+                Ok(webirc) => Command::WebIrc(webirc),
+                Err(_) => Command::Unknown(command.to_string(), params),
+            },
             "QUIT" => Command::Quit(params.first().cloned()),
             "PING" => {
                 if let Some(token) = params.first() {
@@ -408,7 +421,7 @@ impl Command {
                 if params.len() >= 2 {
                     Command::Oper {
                         name: params[0].clone(),
-                        password: params[1].clone(),
+                        password: Secret::new(params[1].as_str()),
                     }
                 } else {
                     Command::Unknown(command.to_string(), params)
@@ -441,6 +454,7 @@ impl Command {
             Command::Quit(_) => "QUIT",
             Command::Ping(_) => "PING",
             Command::Pong(_) => "PONG",
+            Command::WebIrc(_) => "WEBIRC",
             Command::Join(_, _) => "JOIN",
             Command::Part(_, _) => "PART",
             Command::Topic { .. } => "TOPIC",
@@ -546,6 +560,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_password_commands_are_redacted() {
+        let cmd = Command::parse("OPER", vec!["admin".to_string(), "hunter2".to_string()]);
+        assert!(!format!("{:?}", cmd).contains("hunter2"));
+        match cmd {
+            Command::Oper { password, .. } => assert_eq!(password.expose(), "hunter2"),
+            _ => panic!("Expected Oper command"),
+        }
+        assert!(!format!("{:?}", Command::parse("PASS", vec!["hunter2".to_string()])).contains("hunter2"));
+    }
+
     #[test]
     fn test_cap_command_parsing() {
         let cmd = Command::parse("CAP", vec!["LS".to_string(), "302".to_string()]);
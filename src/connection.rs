@@ -0,0 +1,425 @@
+//! Connection-level protocol detection
+//!
+//! Browsers, TLS clients and load balancers sometimes reach a plaintext IRC
+//! port. [`ConnectionProtocol::detect`] looks at the first bytes a client
+//! sends, before any line parsing, so a server can route or reject the
+//! connection instead of feeding an HTTP request or a TLS ClientHello to the
+//! IRC parser. [`ConnectionInfo`] records who is really on the other end,
+//! taking a [`proxy`] header into account, and supplies the host, message
+//! source, ban mask and rate-limit key to use for the client.
+
+pub mod proxy;
+pub mod webirc;
+
+pub use proxy::{ProxyAddresses, ProxyCommand, ProxyHeader, SslInfo, Tlv, TrustedProxies};
+pub use webirc::{WebIrc, WebIrcGateway, WebIrcOptions, WebIrcVerifier};
+
+use crate::error::Result;
+use crate::hostmask::UserMask;
+use crate::message::IrcMessage;
+use crate::ratelimit::RateLimitKey;
+use crate::validation::validate_hostname;
+use std::net::{IpAddr, SocketAddr};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Give up waiting for a decisive prefix after this many bytes
+pub const MAX_DETECT_BYTES: usize = 1024;
+
+/// Signature that opens a PROXY protocol v2 header
+pub const PROXY_V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// HTTP request methods recognised at the start of a connection
+const HTTP_METHODS: &[&str] = &["GET", "POST", "PUT", "DELETE", "HEAD", "OPTIONS", "PATCH", "CONNECT", "TRACE"];
+
+/// The protocol a new connection is speaking
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ConnectionProtocol {
+    /// Plain IRC lines
+    Irc,
+    /// A TLS (or SSLv2-compatible) ClientHello
+    Tls,
+    /// An HTTP request
+    Http,
+    /// An HTTP request asking to upgrade to WebSocket
+    WebSocket,
+    /// A PROXY protocol v1 text header
+    ProxyV1,
+    /// A PROXY protocol v2 binary header
+    ProxyV2,
+    /// Something else
+    Unknown,
+}
+
+impl ConnectionProtocol {
+    /// Classify a connection from the bytes received so far
+    ///
+    /// Returns `None` while the prefix is still ambiguous; call again with
+    /// more data. Once [`MAX_DETECT_BYTES`] have arrived a decision is always
+    /// made.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        let detected = Self::detect_prefix(data);
+        if detected.is_none() && data.len() >= MAX_DETECT_BYTES {
+            // Headers that never end are still clearly HTTP, and IRCv3 tags
+            // may legitimately run past the detection limit
+            return Some(if http_method(data).is_some() {
+                ConnectionProtocol::Http
+            } else if skip_blank_lines(data).first() == Some(&b'@') {
+                ConnectionProtocol::Irc
+            } else {
+                ConnectionProtocol::Unknown
+            });
+        }
+        detected
+    }
+
+    /// Check if the connection can be handed to the IRC line parser,
+    /// possibly after stripping a PROXY header
+    pub fn is_irc_compatible(&self) -> bool {
+        matches!(self, ConnectionProtocol::Irc | ConnectionProtocol::ProxyV1 | ConnectionProtocol::ProxyV2)
+    }
+
+    /// A short name for logs
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionProtocol::Irc => "irc",
+            ConnectionProtocol::Tls => "tls",
+            ConnectionProtocol::Http => "http",
+            ConnectionProtocol::WebSocket => "websocket",
+            ConnectionProtocol::ProxyV1 => "proxy-v1",
+            ConnectionProtocol::ProxyV2 => "proxy-v2",
+            ConnectionProtocol::Unknown => "unknown",
+        }
+    }
+
+    fn detect_prefix(data: &[u8]) -> Option<Self> {
+        let first = *data.first()?;
+
+        // PROXY v2 starts with CR LF; otherwise blank lines before the first
+        // command are harmless to IRC
+        if matches!(first, b'\r' | b'\n') {
+            let n = data.len().min(PROXY_V2_SIGNATURE.len());
+            if data[..n] == PROXY_V2_SIGNATURE[..n] {
+                return if n < PROXY_V2_SIGNATURE.len() { None } else { Some(ConnectionProtocol::ProxyV2) };
+            }
+            let rest = skip_blank_lines(data);
+            if rest.is_empty() {
+                return None;
+            }
+            return Some(match Self::detect_prefix(rest)? {
+                // PROXY headers are only valid at the very start
+                ConnectionProtocol::ProxyV1 => ConnectionProtocol::Unknown,
+                detected => detected,
+            });
+        }
+
+        // TLS record: handshake type, major version 3
+        if first == 0x16 {
+            return match data.get(1) {
+                None => None,
+                Some(0x03) => Some(ConnectionProtocol::Tls),
+                Some(_) => Some(ConnectionProtocol::Unknown),
+            };
+        }
+        // SSLv2-framed ClientHello: length with the high bit set, then type 1
+        if first & 0x80 != 0 {
+            return match data.get(2) {
+                None => None,
+                Some(0x01) => Some(ConnectionProtocol::Tls),
+                Some(_) => Some(ConnectionProtocol::Unknown),
+            };
+        }
+
+        let word_end = data.iter().position(|&b| matches!(b, b' ' | b'\r' | b'\n'));
+        let word = &data[..word_end.unwrap_or(data.len())];
+
+        if b"PROXY".starts_with(word) && word_end.is_none() {
+            return None;
+        }
+        if word == b"PROXY" {
+            return Some(ConnectionProtocol::ProxyV1);
+        }
+
+        if HTTP_METHODS.iter().any(|m| m.as_bytes().starts_with(word)) && word_end.is_none() {
+            return None;
+        }
+        if http_method(data).is_some() {
+            return detect_http(data);
+        }
+
+        if word_end.is_none() {
+            // Keep reading while the first token could still be IRC; only
+            // commands are short, tags and prefixes may be long
+            let short_enough = first == b'@' || first == b':' || word.len() <= 32;
+            return if short_enough && looks_like_irc_start(word) {
+                None
+            } else {
+                Some(ConnectionProtocol::Unknown)
+            };
+        }
+        Some(if looks_like_irc_start(word) && is_irc_token(word) {
+            ConnectionProtocol::Irc
+        } else {
+            ConnectionProtocol::Unknown
+        })
+    }
+}
+
+/// Who is on the other end of a connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// The socket peer, which may be a proxy
+    pub peer: SocketAddr,
+    /// The real client, if a proxy reported one
+    pub source: Option<SocketAddr>,
+    /// The address the client connected to, if a proxy reported one
+    pub destination: Option<SocketAddr>,
+    /// TLS details reported by a proxy
+    pub ssl: Option<SslInfo>,
+    /// The host name the client asked for
+    pub authority: Option<String>,
+    /// The client's hostname as reported by a WEBIRC gateway
+    pub hostname: Option<String>,
+    /// The WEBIRC gateway the client came through
+    pub gateway: Option<String>,
+    /// The connection itself uses TLS
+    pub tls: bool,
+    proxied: bool,
+}
+
+impl ConnectionInfo {
+    /// A client connected directly
+    pub fn direct(peer: SocketAddr) -> Self {
+        Self {
+            peer,
+            source: None,
+            destination: None,
+            ssl: None,
+            authority: None,
+            hostname: None,
+            gateway: None,
+            tls: false,
+            proxied: false,
+        }
+    }
+
+    /// A client relayed by a trusted proxy
+    ///
+    /// `LOCAL` headers and UNIX sockets leave the peer as the client.
+    pub fn from_proxy(peer: SocketAddr, header: &ProxyHeader) -> Result<Self> {
+        let mut info = Self::direct(peer);
+        info.proxied = true;
+        if let ProxyAddresses::Inet { source, destination } = header.addresses {
+            info.source = Some(source);
+            info.destination = Some(destination);
+        }
+        info.ssl = header.ssl()?;
+        info.authority = header.authority().map(str::to_string);
+        Ok(info)
+    }
+
+    /// Mark the connection as using TLS
+    pub fn with_tls(mut self, tls: bool) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// The client's real address
+    pub fn client_addr(&self) -> SocketAddr {
+        self.source.unwrap_or(self.peer)
+    }
+
+    /// The client's real IP
+    pub fn client_ip(&self) -> IpAddr {
+        self.client_addr().ip()
+    }
+
+    /// Check if a proxy header was applied
+    pub fn is_proxied(&self) -> bool {
+        self.proxied
+    }
+
+    /// Check if the client's connection is encrypted, directly or up to the proxy
+    pub fn is_secure(&self) -> bool {
+        self.tls || self.ssl.as_ref().is_some_and(|ssl| ssl.ssl)
+    }
+
+    /// The host shown for the client: a valid gateway hostname, else the real IP
+    ///
+    /// IPv6 addresses starting with `:` get a leading `0` so they cannot be
+    /// read as a trailing parameter.
+    pub fn host(&self) -> String {
+        if let Some(hostname) = self.hostname.as_deref().filter(|h| validate_hostname(h).is_ok()) {
+            return hostname.to_string();
+        }
+        let ip = self.client_ip().to_string();
+        if ip.starts_with(':') { format!("0{}", ip) } else { ip }
+    }
+
+    /// The `nick!user@host` source for this client's messages
+    pub fn source(&self, nick: &str, user: &str) -> String {
+        format!("{}!{}@{}", nick, user, self.host())
+    }
+
+    /// Replace whatever prefix the client sent with its real source
+    pub fn stamp(&self, message: IrcMessage, nick: &str, user: &str) -> IrcMessage {
+        message.with_prefix(self.source(nick, user))
+    }
+
+    /// A ban-matching identity using the real client IP
+    pub fn user_mask(&self, nick: &str, user: &str, host: &str) -> UserMask {
+        UserMask::new(nick, user, host).with_ip(self.client_ip())
+    }
+
+    /// The rate-limiting key for this connection
+    pub fn rate_limit_key(&self) -> RateLimitKey {
+        RateLimitKey::Connection(self.client_ip().to_string())
+    }
+}
+
+/// The data after any leading CR and LF bytes
+fn skip_blank_lines(data: &[u8]) -> &[u8] {
+    let start = data.iter().position(|&b| b != b'\r' && b != b'\n').unwrap_or(data.len());
+    &data[start..]
+}
+
+/// The HTTP method the data opens with, if any
+fn http_method(data: &[u8]) -> Option<&'static str> {
+    HTTP_METHODS.iter()
+        .find(|m| data.len() > m.len() && data.starts_with(m.as_bytes()) && data[m.len()] == b' ')
+        .copied()
+}
+
+/// Wait for the end of the headers and look for a WebSocket upgrade
+fn detect_http(data: &[u8]) -> Option<ConnectionProtocol> {
+    let end = data.windows(4).position(|w| w == b"\r\n\r\n")
+        .or_else(|| data.windows(2).position(|w| w == b"\n\n"))?;
+    let head = String::from_utf8_lossy(&data[..end]).to_ascii_lowercase();
+
+    let upgrade = head.lines().skip(1).any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim() == "upgrade" && value.split(',').any(|v| v.trim() == "websocket")
+        })
+    });
+    Some(if upgrade { ConnectionProtocol::WebSocket } else { ConnectionProtocol::Http })
+}
+
+/// The first token of an IRC line: tags, a prefix, or a command
+fn looks_like_irc_start(word: &[u8]) -> bool {
+    match word.first() {
+        Some(b'@') | Some(b':') => true,
+        Some(_) => word.iter().all(|b| b.is_ascii_alphanumeric()),
+        None => false,
+    }
+}
+
+fn is_irc_token(word: &[u8]) -> bool {
+    match word.first() {
+        Some(b'@') | Some(b':') => word.len() > 1,
+        _ => {
+            word.iter().all(|b| b.is_ascii_alphabetic())
+                || (word.len() == 3 && word.iter().all(|b| b.is_ascii_digit()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_irc() {
+        assert_eq!(ConnectionProtocol::detect(b"CAP LS 302\r\n"), Some(ConnectionProtocol::Irc));
+        assert_eq!(ConnectionProtocol::detect(b"NICK alice\r\nUSER a 0 * :A\r\n"), Some(ConnectionProtocol::Irc));
+        assert_eq!(ConnectionProtocol::detect(b"@label=1 PING x\r\n"), Some(ConnectionProtocol::Irc));
+        assert_eq!(ConnectionProtocol::detect(b"AUTH foo\r\n"), Some(ConnectionProtocol::Irc));
+        assert_eq!(ConnectionProtocol::detect(b"NIC"), None);
+        assert_eq!(ConnectionProtocol::detect(b""), None);
+
+        // Blank lines before the first command
+        assert_eq!(ConnectionProtocol::detect(b"\r\nNICK alice\r\n"), Some(ConnectionProtocol::Irc));
+        assert_eq!(ConnectionProtocol::detect(b"\n\nCAP LS\r\n"), Some(ConnectionProtocol::Irc));
+        assert_eq!(ConnectionProtocol::detect(b"\r\n"), None);
+
+        // Long tags are not cut off
+        let mut tags = b"@".to_vec();
+        tags.resize(200, b'a');
+        assert_eq!(ConnectionProtocol::detect(&tags), None);
+        tags.extend_from_slice(b" PING x\r\n");
+        assert_eq!(ConnectionProtocol::detect(&tags), Some(ConnectionProtocol::Irc));
+        tags.truncate(1);
+        tags.resize(MAX_DETECT_BYTES, b'a');
+        assert_eq!(ConnectionProtocol::detect(&tags), Some(ConnectionProtocol::Irc));
+    }
+
+    #[test]
+    fn test_detect_tls_and_proxy() {
+        assert_eq!(ConnectionProtocol::detect(&[0x16, 0x03, 0x01, 0x02, 0x00]), Some(ConnectionProtocol::Tls));
+        assert_eq!(ConnectionProtocol::detect(&[0x80, 0x2e, 0x01]), Some(ConnectionProtocol::Tls));
+        assert_eq!(ConnectionProtocol::detect(&[0x16]), None);
+
+        assert_eq!(ConnectionProtocol::detect(b"PROXY TCP4 1.2.3.4 5.6.7.8 1 2\r\n"), Some(ConnectionProtocol::ProxyV1));
+        assert_eq!(ConnectionProtocol::detect(b"PRO"), None);
+        assert_eq!(ConnectionProtocol::detect(&PROXY_V2_SIGNATURE[..]), Some(ConnectionProtocol::ProxyV2));
+        assert_eq!(ConnectionProtocol::detect(&PROXY_V2_SIGNATURE[..5]), None);
+        assert_eq!(ConnectionProtocol::detect(b"\r\n\r\n\x01"), Some(ConnectionProtocol::Unknown));
+        assert_eq!(ConnectionProtocol::detect(b"\r\nPROXY TCP4 1.2.3.4 5.6.7.8 1 2\r\n"), Some(ConnectionProtocol::Unknown));
+    }
+
+    #[test]
+    fn test_detect_http_and_websocket() {
+        assert_eq!(ConnectionProtocol::detect(b"GET / HTTP/1.1\r\nHost: x\r\n"), None);
+        assert_eq!(
+            ConnectionProtocol::detect(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n"),
+            Some(ConnectionProtocol::Http)
+        );
+        assert_eq!(
+            ConnectionProtocol::detect(b"GET /irc HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: WebSocket\r\n\r\n"),
+            Some(ConnectionProtocol::WebSocket)
+        );
+        assert_eq!(ConnectionProtocol::detect(b"POST /x HTTP/1.1\r\n\r\n"), Some(ConnectionProtocol::Http));
+
+        let mut endless = b"GET / HTTP/1.1\r\n".to_vec();
+        endless.resize(MAX_DETECT_BYTES, b'a');
+        assert_eq!(ConnectionProtocol::detect(&endless), Some(ConnectionProtocol::Http));
+    }
+
+    #[test]
+    fn test_connection_info() {
+        let peer: SocketAddr = "10.0.0.5:4000".parse().unwrap();
+        let info = ConnectionInfo::direct(peer).with_tls(true);
+        assert_eq!(info.client_ip(), peer.ip());
+        assert!(info.is_secure() && !info.is_proxied());
+
+        let (header, _) = ProxyHeader::parse(b"PROXY TCP4 192.0.2.7 10.0.0.1 5 6697\r\n").unwrap().unwrap();
+        let info = ConnectionInfo::from_proxy(peer, &header).unwrap();
+        assert_eq!(info.client_addr(), "192.0.2.7:5".parse().unwrap());
+        assert_eq!(info.user_mask("n", "u", "h").ip, Some("192.0.2.7".parse().unwrap()));
+        assert_eq!(info.rate_limit_key(), RateLimitKey::Connection("192.0.2.7".to_string()));
+    }
+
+    #[test]
+    fn test_message_sources() {
+        let (header, _) = ProxyHeader::parse(b"PROXY TCP6 ::1 ::2 5 6697\r\n").unwrap().unwrap();
+        let mut info = ConnectionInfo::from_proxy("10.0.0.5:4000".parse().unwrap(), &header).unwrap();
+        assert_eq!(info.source("alice", "a"), "alice!a@0::1");
+
+        let spoofed: IrcMessage = ":admin!root@server PRIVMSG #c :hi".parse().unwrap();
+        assert_eq!(info.stamp(spoofed, "alice", "a").prefix.as_deref(), Some("alice!a@0::1"));
+
+        info.hostname = Some("client.example".to_string());
+        assert_eq!(info.host(), "client.example");
+        info.hostname = Some("bad host".to_string());
+        assert_eq!(info.host(), "0::1");
+    }
+
+    #[test]
+    fn test_detect_unknown() {
+        assert_eq!(ConnectionProtocol::detect(b"\x00\x01\x02"), Some(ConnectionProtocol::Unknown));
+        assert_eq!(ConnectionProtocol::detect(b"SSH-2.0-OpenSSH\r\n"), Some(ConnectionProtocol::Unknown));
+        assert!(ConnectionProtocol::ProxyV2.is_irc_compatible());
+        assert!(!ConnectionProtocol::WebSocket.is_irc_compatible());
+    }
+}
@@ -0,0 +1,503 @@
+//! HAProxy PROXY protocol v1 and v2 headers
+//!
+//! A load balancer speaking the PROXY protocol prefixes each connection with
+//! a header carrying the real client and server addresses, and in v2 optional
+//! TLVs such as the TLS details of a terminated connection. Headers are only
+//! honoured from peers in [`TrustedProxies`].
+
+use super::ConnectionInfo;
+use super::PROXY_V2_SIGNATURE;
+use crate::error::{IronError, Result};
+use crate::hostmask::{in_network, parse_cidr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Longest possible v1 header, including CR LF
+pub const MAX_V1_HEADER_LENGTH: usize = 107;
+
+/// Length of the fixed part of a v2 header
+const V2_HEADER_LENGTH: usize = 16;
+
+/// Well-known v2 TLV types
+pub mod tlv {
+    /// Application-layer protocol negotiated
+    pub const ALPN: u8 = 0x01;
+    /// Host name the client asked for (SNI)
+    pub const AUTHORITY: u8 = 0x02;
+    /// CRC32c checksum of the header
+    pub const CRC32C: u8 = 0x03;
+    /// Padding
+    pub const NOOP: u8 = 0x04;
+    /// Opaque connection identifier
+    pub const UNIQUE_ID: u8 = 0x05;
+    /// TLS details, with nested sub-TLVs
+    pub const SSL: u8 = 0x20;
+    /// TLS version, e.g. `TLSv1.3`
+    pub const SSL_VERSION: u8 = 0x21;
+    /// Common name of the client certificate
+    pub const SSL_CN: u8 = 0x22;
+    /// Negotiated cipher
+    pub const SSL_CIPHER: u8 = 0x23;
+    /// Certificate signature algorithm
+    pub const SSL_SIG_ALG: u8 = 0x24;
+    /// Certificate key algorithm
+    pub const SSL_KEY_ALG: u8 = 0x25;
+    /// Network namespace
+    pub const NETNS: u8 = 0x30;
+}
+
+/// Whether the header describes a proxied client or the proxy itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyCommand {
+    /// The proxy's own connection, e.g. a health check
+    Local,
+    /// A relayed client connection
+    Proxy,
+}
+
+/// The addresses carried by a header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxyAddresses {
+    /// TCP over IPv4 or IPv6
+    Inet {
+        /// The client
+        source: SocketAddr,
+        /// The address the client connected to
+        destination: SocketAddr,
+    },
+    /// UNIX socket paths
+    Unix {
+        /// The client socket path
+        source: String,
+        /// The listening socket path
+        destination: String,
+    },
+    /// `UNKNOWN`, `LOCAL` or an unspecified family
+    Unspecified,
+}
+
+/// A type-length-value extension from a v2 header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tlv {
+    /// The TLV type
+    pub kind: u8,
+    /// The raw value
+    pub value: Vec<u8>,
+}
+
+/// TLS details reported by a terminating proxy
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SslInfo {
+    /// The client connected over TLS
+    pub ssl: bool,
+    /// The client presented a certificate on this connection
+    pub client_cert_conn: bool,
+    /// The client presented a certificate in this TLS session
+    pub client_cert_sess: bool,
+    /// The client certificate was verified
+    pub verified: bool,
+    /// TLS version
+    pub version: Option<String>,
+    /// Common name of the client certificate
+    pub cn: Option<String>,
+    /// Negotiated cipher
+    pub cipher: Option<String>,
+    /// Certificate signature algorithm
+    pub sig_alg: Option<String>,
+    /// Certificate key algorithm
+    pub key_alg: Option<String>,
+}
+
+impl SslInfo {
+    fn parse(value: &[u8]) -> Result<Self> {
+        if value.len() < 5 {
+            return Err(IronError::Parse("PROXY SSL TLV too short".to_string()));
+        }
+        let client = value[0];
+        let verify = u32::from_be_bytes([value[1], value[2], value[3], value[4]]);
+        let mut info = SslInfo {
+            ssl: client & 0x01 != 0,
+            client_cert_conn: client & 0x02 != 0,
+            client_cert_sess: client & 0x04 != 0,
+            verified: verify == 0 && client & 0x06 != 0,
+            ..Default::default()
+        };
+        for sub in parse_tlvs(&value[5..])? {
+            let text = Some(String::from_utf8_lossy(&sub.value).into_owned());
+            match sub.kind {
+                tlv::SSL_VERSION => info.version = text,
+                tlv::SSL_CN => info.cn = text,
+                tlv::SSL_CIPHER => info.cipher = text,
+                tlv::SSL_SIG_ALG => info.sig_alg = text,
+                tlv::SSL_KEY_ALG => info.key_alg = text,
+                _ => {}
+            }
+        }
+        Ok(info)
+    }
+}
+
+/// A parsed PROXY header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyHeader {
+    /// Protocol version, 1 or 2
+    pub version: u8,
+    /// `LOCAL` or `PROXY`
+    pub command: ProxyCommand,
+    /// Client and server addresses
+    pub addresses: ProxyAddresses,
+    /// v2 extensions, in header order
+    pub tlvs: Vec<Tlv>,
+}
+
+impl ProxyHeader {
+    /// Parse a header from the start of a connection
+    ///
+    /// Returns the header and the number of bytes it used, or `None` if more
+    /// data is needed.
+    pub fn parse(data: &[u8]) -> Result<Option<(Self, usize)>> {
+        let n = data.len().min(PROXY_V2_SIGNATURE.len());
+        if data[..n] == PROXY_V2_SIGNATURE[..n] {
+            return if n < PROXY_V2_SIGNATURE.len() { Ok(None) } else { Self::parse_v2(data) };
+        }
+        let n = data.len().min(6);
+        if data[..n] == b"PROXY "[..n] {
+            return if n < 6 { Ok(None) } else { Self::parse_v1(data) };
+        }
+        Err(IronError::Parse("Missing PROXY header".to_string()))
+    }
+
+    /// Value of the first TLV of a type
+    pub fn tlv(&self, kind: u8) -> Option<&[u8]> {
+        self.tlvs.iter().find(|t| t.kind == kind).map(|t| t.value.as_slice())
+    }
+
+    /// The negotiated ALPN protocol
+    pub fn alpn(&self) -> Option<&str> {
+        self.tlv(tlv::ALPN).and_then(|v| std::str::from_utf8(v).ok())
+    }
+
+    /// The host name the client asked for
+    pub fn authority(&self) -> Option<&str> {
+        self.tlv(tlv::AUTHORITY).and_then(|v| std::str::from_utf8(v).ok())
+    }
+
+    /// The proxy's connection identifier
+    pub fn unique_id(&self) -> Option<&[u8]> {
+        self.tlv(tlv::UNIQUE_ID)
+    }
+
+    /// TLS details, if the proxy sent them
+    pub fn ssl(&self) -> Result<Option<SslInfo>> {
+        self.tlv(tlv::SSL).map(SslInfo::parse).transpose()
+    }
+
+    fn parse_v1(data: &[u8]) -> Result<Option<(Self, usize)>> {
+        let window = &data[..data.len().min(MAX_V1_HEADER_LENGTH)];
+        let Some(end) = window.windows(2).position(|w| w == b"\r\n") else {
+            return if data.len() >= MAX_V1_HEADER_LENGTH {
+                Err(IronError::Parse("PROXY v1 header too long".to_string()))
+            } else {
+                Ok(None)
+            };
+        };
+        let line = std::str::from_utf8(&window[..end])
+            .map_err(|_| IronError::Parse("PROXY v1 header is not ASCII".to_string()))?;
+        let fields: Vec<&str> = line.split(' ').collect();
+        let invalid = || IronError::Parse(format!("Invalid PROXY v1 header: {}", line));
+
+        let addresses = match fields.get(1).copied() {
+            Some("UNKNOWN") => ProxyAddresses::Unspecified,
+            Some(family @ ("TCP4" | "TCP6")) if fields.len() == 6 => {
+                let source: IpAddr = fields[2].parse().map_err(|_| invalid())?;
+                let destination: IpAddr = fields[3].parse().map_err(|_| invalid())?;
+                let source_port: u16 = fields[4].parse().map_err(|_| invalid())?;
+                let destination_port: u16 = fields[5].parse().map_err(|_| invalid())?;
+                if source.is_ipv4() != (family == "TCP4") || destination.is_ipv4() != (family == "TCP4") {
+                    return Err(invalid());
+                }
+                ProxyAddresses::Inet {
+                    source: SocketAddr::new(source, source_port),
+                    destination: SocketAddr::new(destination, destination_port),
+                }
+            }
+            _ => return Err(invalid()),
+        };
+
+        let header = ProxyHeader {
+            version: 1,
+            command: ProxyCommand::Proxy,
+            addresses,
+            tlvs: Vec::new(),
+        };
+        Ok(Some((header, end + 2)))
+    }
+
+    fn parse_v2(data: &[u8]) -> Result<Option<(Self, usize)>> {
+        if data.len() < V2_HEADER_LENGTH {
+            return Ok(None);
+        }
+        let version_command = data[12];
+        if version_command >> 4 != 2 {
+            return Err(IronError::Parse(format!("Unsupported PROXY version {}", version_command >> 4)));
+        }
+        let command = match version_command & 0x0F {
+            0 => ProxyCommand::Local,
+            1 => ProxyCommand::Proxy,
+            other => return Err(IronError::Parse(format!("Unknown PROXY v2 command {}", other))),
+        };
+        let family = data[13];
+        let length = u16::from_be_bytes([data[14], data[15]]) as usize;
+        let total = V2_HEADER_LENGTH + length;
+        if data.len() < total {
+            return Ok(None);
+        }
+        let body = &data[V2_HEADER_LENGTH..total];
+
+        let too_short = || IronError::Parse("PROXY v2 address block too short".to_string());
+        let (addresses, used) = match family >> 4 {
+            0x1 => {
+                let a = body.get(..12).ok_or_else(too_short)?;
+                let ip = |o: usize| IpAddr::V4(Ipv4Addr::new(a[o], a[o + 1], a[o + 2], a[o + 3]));
+                let port = |o: usize| u16::from_be_bytes([a[o], a[o + 1]]);
+                (ProxyAddresses::Inet {
+                    source: SocketAddr::new(ip(0), port(8)),
+                    destination: SocketAddr::new(ip(4), port(10)),
+                }, 12)
+            }
+            0x2 => {
+                let a = body.get(..36).ok_or_else(too_short)?;
+                let ip = |o: usize| {
+                    let mut octets = [0u8; 16];
+                    octets.copy_from_slice(&a[o..o + 16]);
+                    IpAddr::V6(Ipv6Addr::from(octets))
+                };
+                let port = |o: usize| u16::from_be_bytes([a[o], a[o + 1]]);
+                (ProxyAddresses::Inet {
+                    source: SocketAddr::new(ip(0), port(32)),
+                    destination: SocketAddr::new(ip(16), port(34)),
+                }, 36)
+            }
+            0x3 => {
+                let a = body.get(..216).ok_or_else(too_short)?;
+                let path = |bytes: &[u8]| {
+                    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+                    String::from_utf8_lossy(&bytes[..end]).into_owned()
+                };
+                (ProxyAddresses::Unix {
+                    source: path(&a[..108]),
+                    destination: path(&a[108..216]),
+                }, 216)
+            }
+            _ => (ProxyAddresses::Unspecified, 0),
+        };
+
+        let tlvs = if used == 0 && family >> 4 == 0 {
+            Vec::new()
+        } else {
+            parse_tlvs(&body[used..])?
+        };
+        let addresses = match command {
+            ProxyCommand::Local => ProxyAddresses::Unspecified,
+            ProxyCommand::Proxy => addresses,
+        };
+
+        Ok(Some((ProxyHeader { version: 2, command, addresses, tlvs }, total)))
+    }
+}
+
+fn parse_tlvs(mut data: &[u8]) -> Result<Vec<Tlv>> {
+    let mut tlvs = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 3 {
+            return Err(IronError::Parse("Truncated PROXY v2 TLV".to_string()));
+        }
+        let kind = data[0];
+        let length = u16::from_be_bytes([data[1], data[2]]) as usize;
+        let value = data.get(3..3 + length)
+            .ok_or_else(|| IronError::Parse("Truncated PROXY v2 TLV".to_string()))?;
+        if kind != tlv::NOOP {
+            tlvs.push(Tlv { kind, value: value.to_vec() });
+        }
+        data = &data[3 + length..];
+    }
+    Ok(tlvs)
+}
+
+/// Networks whose PROXY headers are believed
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    /// Trust no one
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust an address or CIDR range
+    pub fn add(&mut self, network: &str) -> Result<()> {
+        let parsed = parse_cidr(network).or_else(|| {
+            let ip: IpAddr = network.parse().ok()?;
+            Some((ip, if ip.is_ipv4() { 32 } else { 128 }))
+        });
+        match parsed {
+            Some(network) => {
+                self.networks.push(network);
+                Ok(())
+            }
+            None => Err(IronError::Config(format!("Invalid trusted proxy: {}", network))),
+        }
+    }
+
+    /// Build from a list of addresses and CIDR ranges
+    pub fn from_list<S: AsRef<str>>(networks: &[S]) -> Result<Self> {
+        let mut trusted = Self::new();
+        for network in networks {
+            trusted.add(network.as_ref())?;
+        }
+        Ok(trusted)
+    }
+
+    /// Check if a peer may send PROXY headers
+    pub fn is_trusted(&self, peer: IpAddr) -> bool {
+        self.networks.iter().any(|(network, prefix)| in_network(peer, *network, *prefix))
+    }
+
+    /// Work out who is really connecting
+    ///
+    /// Trusted peers must start with a PROXY header; anyone else sending one
+    /// is refused. Returns the connection info and the number of header bytes
+    /// to skip, or `None` if more data is needed.
+    pub fn accept(&self, peer: SocketAddr, data: &[u8]) -> Result<Option<(ConnectionInfo, usize)>> {
+        if !self.is_trusted(peer.ip()) {
+            if looks_like_header(data) {
+                return Err(IronError::SecurityViolation(
+                    format!("PROXY header from untrusted peer {}", peer.ip())
+                ));
+            }
+            return Ok(Some((ConnectionInfo::direct(peer), 0)));
+        }
+
+        let Some((header, used)) = ProxyHeader::parse(data)? else {
+            return Ok(None);
+        };
+        Ok(Some((ConnectionInfo::from_proxy(peer, &header)?, used)))
+    }
+}
+
+fn looks_like_header(data: &[u8]) -> bool {
+    data.starts_with(b"PROXY ") || data.starts_with(&PROXY_V2_SIGNATURE[..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2_header(family: u8, addresses: &[u8], tlvs: &[u8]) -> Vec<u8> {
+        let mut data = PROXY_V2_SIGNATURE.to_vec();
+        data.push(0x21);
+        data.push(family);
+        data.extend_from_slice(&((addresses.len() + tlvs.len()) as u16).to_be_bytes());
+        data.extend_from_slice(addresses);
+        data.extend_from_slice(tlvs);
+        data
+    }
+
+    #[test]
+    fn test_parse_v1() {
+        let data = b"PROXY TCP4 192.0.2.1 198.51.100.2 51000 6667\r\nNICK a\r\n";
+        let (header, used) = ProxyHeader::parse(data).unwrap().unwrap();
+        assert_eq!(&data[used..], b"NICK a\r\n");
+        assert_eq!(header.addresses, ProxyAddresses::Inet {
+            source: "192.0.2.1:51000".parse().unwrap(),
+            destination: "198.51.100.2:6667".parse().unwrap(),
+        });
+
+        let (header, _) = ProxyHeader::parse(b"PROXY TCP6 2001:db8::1 2001:db8::2 1 2\r\n").unwrap().unwrap();
+        assert!(matches!(header.addresses, ProxyAddresses::Inet { source, .. } if source.is_ipv6()));
+        let (header, _) = ProxyHeader::parse(b"PROXY UNKNOWN\r\n").unwrap().unwrap();
+        assert_eq!(header.addresses, ProxyAddresses::Unspecified);
+
+        assert!(ProxyHeader::parse(b"PROXY TCP4 192.0.2.1").unwrap().is_none());
+        assert!(ProxyHeader::parse(b"PROXY TCP4 2001:db8::1 192.0.2.1 1 2\r\n").is_err());
+        assert!(ProxyHeader::parse(&[b'A'; 200]).is_err());
+    }
+
+    #[test]
+    fn test_parse_v2_with_tlvs() {
+        let addresses = [192, 0, 2, 1, 10, 0, 0, 1, 0xC7, 0x38, 0x1A, 0x0B];
+        let mut tlvs = vec![tlv::AUTHORITY, 0, 11];
+        tlvs.extend_from_slice(b"irc.example");
+        // SSL: client SSL + cert on connection, verified, with version and CN
+        let mut ssl = vec![0x03, 0, 0, 0, 0, tlv::SSL_VERSION, 0, 7];
+        ssl.extend_from_slice(b"TLSv1.3");
+        ssl.extend_from_slice(&[tlv::SSL_CN, 0, 5]);
+        ssl.extend_from_slice(b"alice");
+        tlvs.extend_from_slice(&[tlv::SSL, 0, ssl.len() as u8]);
+        tlvs.extend_from_slice(&ssl);
+        tlvs.extend_from_slice(&[tlv::NOOP, 0, 2, 0, 0]);
+
+        let mut data = v2_header(0x11, &addresses, &tlvs);
+        let header_len = data.len();
+        data.extend_from_slice(b"CAP LS\r\n");
+
+        let (header, used) = ProxyHeader::parse(&data).unwrap().unwrap();
+        assert_eq!(used, header_len);
+        assert_eq!(header.addresses, ProxyAddresses::Inet {
+            source: "192.0.2.1:51000".parse().unwrap(),
+            destination: "10.0.0.1:6667".parse().unwrap(),
+        });
+        assert_eq!(header.authority(), Some("irc.example"));
+        assert_eq!(header.tlvs.len(), 2);
+        let ssl = header.ssl().unwrap().unwrap();
+        assert!(ssl.ssl && ssl.verified);
+        assert_eq!(ssl.version.as_deref(), Some("TLSv1.3"));
+        assert_eq!(ssl.cn.as_deref(), Some("alice"));
+
+        assert!(ProxyHeader::parse(&data[..header_len - 1]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_parse_v2_families() {
+        let mut addresses = vec![0u8; 36];
+        addresses[15] = 1;
+        addresses[31] = 2;
+        addresses[32..36].copy_from_slice(&[0x1A, 0x0B, 0x1A, 0x0B]);
+        let (header, _) = ProxyHeader::parse(&v2_header(0x21, &addresses, &[])).unwrap().unwrap();
+        assert_eq!(header.addresses, ProxyAddresses::Inet {
+            source: "[::1]:6667".parse().unwrap(),
+            destination: "[::2]:6667".parse().unwrap(),
+        });
+
+        let mut addresses = vec![0u8; 216];
+        addresses[..9].copy_from_slice(b"/tmp/a.sk");
+        let (header, _) = ProxyHeader::parse(&v2_header(0x31, &addresses, &[])).unwrap().unwrap();
+        assert!(matches!(header.addresses, ProxyAddresses::Unix { ref source, .. } if source == "/tmp/a.sk"));
+
+        let mut local = v2_header(0x00, &[], &[]);
+        local[12] = 0x20;
+        let (header, _) = ProxyHeader::parse(&local).unwrap().unwrap();
+        assert_eq!(header.command, ProxyCommand::Local);
+        assert_eq!(header.addresses, ProxyAddresses::Unspecified);
+    }
+
+    #[test]
+    fn test_trusted_proxies() {
+        let trusted = TrustedProxies::from_list(&["10.0.0.0/8", "::1"]).unwrap();
+        let proxy: SocketAddr = "10.1.2.3:40000".parse().unwrap();
+        let stranger: SocketAddr = "203.0.113.9:40000".parse().unwrap();
+        let header = b"PROXY TCP4 192.0.2.1 10.0.0.1 51000 6667\r\nNICK a\r\n";
+
+        let (info, used) = trusted.accept(proxy, header).unwrap().unwrap();
+        assert_eq!(used, 42);
+        assert_eq!(info.client_ip(), "192.0.2.1".parse::<IpAddr>().unwrap());
+        assert!(info.is_proxied());
+
+        assert!(matches!(trusted.accept(stranger, header), Err(IronError::SecurityViolation(_))));
+        let (info, used) = trusted.accept(stranger, b"NICK a\r\n").unwrap().unwrap();
+        assert_eq!((info.client_ip(), used), (stranger.ip(), 0));
+        assert!(trusted.accept(proxy, b"NICK a\r\n").is_err());
+        assert!(TrustedProxies::from_list(&["not-an-ip"]).is_err());
+    }
+}
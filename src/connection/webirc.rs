@@ -0,0 +1,324 @@
+//! WEBIRC gateway support
+//!
+//! Web clients reach IRC through a gateway, so the server sees the gateway's
+//! address. A trusted gateway sends `WEBIRC password gateway hostname ip
+//! [:options]` before registration to pass on the real client's host and
+//! IP. [`WebIrcVerifier`] checks the password against the configured
+//! gateways and rewrites the [`ConnectionInfo`].
+
+use super::ConnectionInfo;
+use crate::error::{IronError, Result};
+use crate::hostmask::{in_network, parse_cidr};
+use crate::message::IrcMessage;
+use crate::secret::Secret;
+use crate::validation::validate_hostname;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Options sent in the last WEBIRC parameter
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct WebIrcOptions {
+    /// The client reached the gateway over TLS
+    pub secure: bool,
+    /// The gateway port the client connected to
+    pub local_port: Option<u16>,
+    /// The client's source port
+    pub remote_port: Option<u16>,
+    /// Any other options, in order
+    pub other: Vec<(String, Option<String>)>,
+}
+
+impl WebIrcOptions {
+    /// Parse a space-separated `flag` / `key=value` list
+    pub fn parse(options: &str) -> Self {
+        let mut parsed = Self::default();
+        for option in options.split(' ').filter(|o| !o.is_empty()) {
+            let (key, value) = match option.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (option, None),
+            };
+            match (key, value.map(str::parse::<u16>)) {
+                ("secure", None) => parsed.secure = true,
+                ("local-port", Some(Ok(port))) => parsed.local_port = Some(port),
+                ("remote-port", Some(Ok(port))) => parsed.remote_port = Some(port),
+                _ => parsed.other.push((key.to_string(), value.map(str::to_string))),
+            }
+        }
+        parsed
+    }
+
+    /// Check if no options are set
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for WebIrcOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut options = Vec::new();
+        if self.secure {
+            options.push("secure".to_string());
+        }
+        if let Some(port) = self.local_port {
+            options.push(format!("local-port={}", port));
+        }
+        if let Some(port) = self.remote_port {
+            options.push(format!("remote-port={}", port));
+        }
+        for (key, value) in &self.other {
+            options.push(match value {
+                Some(value) => format!("{}={}", key, value),
+                None => key.clone(),
+            });
+        }
+        f.write_str(&options.join(" "))
+    }
+}
+
+/// A WEBIRC request
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct WebIrc {
+    /// The gateway password (not serialized; restored empty)
+    #[cfg_attr(feature = "serde", serde(skip_serializing, default))]
+    pub password: Secret,
+    /// The gateway's name
+    pub gateway: String,
+    /// The client's hostname
+    pub hostname: String,
+    /// The client's IP
+    pub ip: IpAddr,
+    /// Extra options
+    pub options: WebIrcOptions,
+}
+
+impl WebIrc {
+    /// Create a request for a client
+    pub fn new(password: impl Into<Secret>, gateway: impl Into<String>, hostname: impl Into<String>, ip: IpAddr) -> Self {
+        Self {
+            password: password.into(),
+            gateway: gateway.into(),
+            hostname: hostname.into(),
+            ip,
+            options: WebIrcOptions::default(),
+        }
+    }
+
+    /// Mark the client's connection to the gateway as secure
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.options.secure = secure;
+        self
+    }
+
+    /// Set the gateway port the client connected to
+    pub fn with_local_port(mut self, port: u16) -> Self {
+        self.options.local_port = Some(port);
+        self
+    }
+
+    /// Parse the parameters of a WEBIRC command
+    pub fn from_params(params: &[String]) -> Result<Self> {
+        if params.len() < 4 {
+            return Err(IronError::Parse("WEBIRC needs at least 4 parameters".to_string()));
+        }
+        let ip = params[3].parse()
+            .map_err(|_| IronError::Parse(format!("Invalid WEBIRC IP: {}", params[3])))?;
+        Ok(Self {
+            password: Secret::new(params[0].as_str()),
+            gateway: params[1].clone(),
+            hostname: params[2].clone(),
+            ip,
+            options: params.get(4).map(|o| WebIrcOptions::parse(o)).unwrap_or_default(),
+        })
+    }
+
+    /// The command parameters
+    pub fn to_params(&self) -> Vec<String> {
+        let mut params = vec![
+            self.password.expose().to_string(),
+            self.gateway.clone(),
+            self.hostname.clone(),
+            self.ip.to_string(),
+        ];
+        if !self.options.is_empty() {
+            params.push(self.options.to_string());
+        }
+        params
+    }
+
+    /// Build the WEBIRC message
+    pub fn to_message(&self) -> IrcMessage {
+        IrcMessage::new("WEBIRC").with_params(self.to_params())
+    }
+}
+
+/// A configured gateway
+#[derive(Debug, Clone)]
+pub struct WebIrcGateway {
+    name: String,
+    password: Secret,
+    hosts: Vec<(IpAddr, u8)>,
+}
+
+impl WebIrcGateway {
+    /// A gateway allowed to connect from the given addresses or CIDR ranges
+    pub fn new<S: AsRef<str>>(name: impl Into<String>, password: impl Into<Secret>, hosts: &[S]) -> Result<Self> {
+        let hosts = hosts.iter()
+            .map(|host| {
+                let host = host.as_ref();
+                parse_cidr(host)
+                    .or_else(|| host.parse::<IpAddr>().ok().map(|ip| (ip, if ip.is_ipv4() { 32 } else { 128 })))
+                    .ok_or_else(|| IronError::Config(format!("Invalid WEBIRC gateway host: {}", host)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            name: name.into(),
+            password: password.into(),
+            hosts,
+        })
+    }
+
+    /// The gateway's configured name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn allows(&self, peer: IpAddr) -> bool {
+        self.hosts.iter().any(|(network, prefix)| in_network(peer, *network, *prefix))
+    }
+}
+
+/// Server-side WEBIRC verification
+#[derive(Debug, Clone, Default)]
+pub struct WebIrcVerifier {
+    gateways: Vec<WebIrcGateway>,
+}
+
+impl WebIrcVerifier {
+    /// A verifier with no gateways
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a gateway
+    pub fn with_gateway(mut self, gateway: WebIrcGateway) -> Self {
+        self.gateways.push(gateway);
+        self
+    }
+
+    /// Check a request from `peer`, returning the matching gateway
+    ///
+    /// Every gateway allowed for the peer is compared in constant time so
+    /// the response time does not reveal which one was close.
+    pub fn verify(&self, peer: IpAddr, request: &WebIrc) -> Result<&WebIrcGateway> {
+        let mut matched = None;
+        let mut allowed = false;
+        for gateway in self.gateways.iter().filter(|g| g.allows(peer)) {
+            allowed = true;
+            if gateway.password == request.password && matched.is_none() {
+                matched = Some(gateway);
+            }
+        }
+
+        match matched {
+            Some(gateway) => Ok(gateway),
+            None if allowed => Err(IronError::Auth("WEBIRC password mismatch".to_string())),
+            None => Err(IronError::SecurityViolation(
+                format!("WEBIRC from untrusted host {}", peer)
+            )),
+        }
+    }
+
+    /// Verify a request and replace the connection's client host and IP
+    ///
+    /// Must be called before registration completes. A hostname that is
+    /// not a valid host is replaced by the IP.
+    pub fn apply(&self, info: &mut ConnectionInfo, request: &WebIrc) -> Result<()> {
+        let gateway = self.verify(info.client_ip(), request)?;
+        let hostname = if validate_hostname(&request.hostname).is_ok() {
+            request.hostname.clone()
+        } else {
+            request.ip.to_string()
+        };
+
+        info.source = Some(SocketAddr::new(request.ip, request.options.remote_port.unwrap_or(0)));
+        info.hostname = Some(hostname);
+        info.gateway = Some(gateway.name().to_string());
+        info.tls = request.options.secure;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+
+    fn verifier() -> WebIrcVerifier {
+        WebIrcVerifier::new()
+            .with_gateway(WebIrcGateway::new("kiwi", "s3cret", &["10.0.0.0/24"]).unwrap())
+            .with_gateway(WebIrcGateway::new("other", "hunter2", &["192.0.2.1"]).unwrap())
+    }
+
+    #[test]
+    fn test_parse_and_build() {
+        let msg: IrcMessage = "WEBIRC pw kiwi user.example 203.0.113.5 :secure local-port=6697 certfp=ab"
+            .parse().unwrap();
+        let webirc = WebIrc::from_params(&msg.params).unwrap();
+        assert_eq!(webirc.ip, "203.0.113.5".parse::<IpAddr>().unwrap());
+        assert!(webirc.options.secure);
+        assert_eq!(webirc.options.local_port, Some(6697));
+        assert_eq!(webirc.options.other, vec![("certfp".to_string(), Some("ab".to_string()))]);
+        assert_eq!(webirc.to_message().to_string(), msg.to_string());
+
+        assert!(matches!(Command::parse("WEBIRC", msg.params.clone()), Command::WebIrc(ref w) if *w == webirc));
+        assert!(matches!(Command::parse("WEBIRC", vec!["pw".into()]), Command::Unknown(..)));
+        assert!(WebIrc::from_params(&["a".into(), "b".into(), "c".into(), "not-ip".into()]).is_err());
+    }
+
+    #[test]
+    fn test_builder() {
+        let webirc = WebIrc::new("pw", "kiwi", "host", "2001:db8::1".parse().unwrap())
+            .with_secure(true)
+            .with_local_port(443);
+        assert_eq!(webirc.to_message().to_string(), "WEBIRC pw kiwi host 2001:db8::1 :secure local-port=443\r\n");
+        assert_eq!(WebIrc::new("pw", "g", "h", "1.2.3.4".parse().unwrap()).to_params().len(), 4);
+    }
+
+    #[test]
+    fn test_verify() {
+        let verifier = verifier();
+        let request = WebIrc::new("s3cret", "kiwi", "client.example", "203.0.113.5".parse().unwrap());
+        let gateway_ip: IpAddr = "10.0.0.7".parse().unwrap();
+
+        assert_eq!(verifier.verify(gateway_ip, &request).unwrap().name(), "kiwi");
+        let wrong = WebIrc::new("hunter2", "kiwi", "h", request.ip);
+        assert!(matches!(verifier.verify(gateway_ip, &wrong), Err(IronError::Auth(_))));
+        assert!(matches!(
+            verifier.verify("198.51.100.1".parse().unwrap(), &request),
+            Err(IronError::SecurityViolation(_))
+        ));
+    }
+
+    #[test]
+    fn test_apply_overrides_connection() {
+        let verifier = verifier();
+        let mut info = ConnectionInfo::direct("10.0.0.7:5000".parse().unwrap());
+        let request = WebIrc::new("s3cret", "kiwi", "bad host!", "203.0.113.5".parse().unwrap()).with_secure(true);
+        verifier.apply(&mut info, &request).unwrap();
+
+        assert_eq!(info.client_ip(), request.ip);
+        assert_eq!(info.hostname.as_deref(), Some("203.0.113.5"));
+        assert_eq!(info.gateway.as_deref(), Some("kiwi"));
+        assert!(info.is_secure());
+
+        let mut untouched = ConnectionInfo::direct("10.0.0.7:5000".parse().unwrap());
+        let wrong = WebIrc::new("nope", "kiwi", "h", request.ip);
+        assert!(verifier.apply(&mut untouched, &wrong).is_err());
+        assert_eq!(untouched, ConnectionInfo::direct("10.0.0.7:5000".parse().unwrap()));
+    }
+}
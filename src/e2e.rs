@@ -0,0 +1,46 @@
+//! End-to-end encryption for Legion `!` channels
+//!
+//! Messages to encrypted channels are sealed with XChaCha20-Poly1305 before
+//! they leave the client. The server only relays base64 ciphertext and a few
+//! client-only tags naming the key epoch, nonce and sender; it never sees
+//! the plaintext or the keys. See [`channel`] for the wire format.
+//!
+//! Direct messages use pairwise sessions instead: [`x3dh`] agrees on a
+//! secret from published prekeys and [`ratchet`] encrypts each message
+//! with a Double Ratchet. [`group`] combines the two: members encrypt with
+//! their own sender keys and hand them out over direct sessions, rekeying
+//! whenever the membership changes.
+
+pub mod channel;
+pub mod group;
+pub mod ratchet;
+pub mod x3dh;
+
+pub use channel::{ChannelCipher, ChannelKey, DecryptedMessage, EncryptedPayload};
+pub use group::{ChannelModes, GroupKeyManager, MembershipChange, SenderKeyDistribution};
+pub use ratchet::{DirectSession, RatchetHeader};
+pub use x3dh::{IdentityKey, IdentityKeyPair, PrekeyBundle, PrekeyMessage, PrekeyStore};
+
+/// Format version carried in [`TAG_VERSION`]
+pub const WIRE_VERSION: &str = "1";
+
+/// Client tag naming the wire format version
+pub const TAG_VERSION: &str = "+legion/e2e";
+/// Client tag carrying the key epoch
+pub const TAG_EPOCH: &str = "+legion/epoch";
+/// Client tag carrying the base64 nonce
+pub const TAG_NONCE: &str = "+legion/nonce";
+/// Client tag carrying the sender's identity
+pub const TAG_SENDER: &str = "+legion/sender";
+/// Client tag carrying the base64 ciphertext of a TAGMSG
+pub const TAG_CIPHERTEXT: &str = "+legion/ct";
+/// Client tag carrying a direct message's base64 ratchet header
+pub const TAG_RATCHET: &str = "+legion/dr";
+/// Client tag carrying the base64 X3DH prekey message
+pub const TAG_PREKEY: &str = "+legion/x3dh";
+/// Client tag carrying a sender-key message's chain iteration
+pub const TAG_ITERATION: &str = "+legion/iter";
+/// Client tag carrying a sender-key message's base64 Ed25519 signature
+pub const TAG_SIGNATURE: &str = "+legion/sig";
+/// Client tag marking a direct message that carries a sender key
+pub const TAG_SENDER_KEY: &str = "+legion/skd";
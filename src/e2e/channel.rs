@@ -0,0 +1,488 @@
+//! Channel message encryption with XChaCha20-Poly1305
+//!
+//! # Wire format (version 1)
+//!
+//! An encrypted `PRIVMSG` or `NOTICE` keeps its command and target; the text
+//! is replaced by the standard base64 of `ciphertext || tag` and four client
+//! tags are added:
+//!
+//! ```text
+//! @+legion/e2e=1;+legion/epoch=<u32>;+legion/nonce=<base64 24 bytes>;+legion/sender=<id>
+//!     PRIVMSG !channel :<base64 ciphertext>
+//! ```
+//!
+//! A `TAGMSG` has no text, so its other client tags are serialized as
+//! `key=value;key=value` (sorted, with IRCv3 tag escaping), encrypted, and
+//! sent in `+legion/ct`.
+//!
+//! The associated data binds each ciphertext to its context:
+//!
+//! ```text
+//! "legion-e2e-v1" 0x00 folded-channel 0x00 command 0x00 sender 0x00 epoch (u32 big-endian)
+//! ```
+//!
+//! so a relay cannot move a message to another channel, turn a NOTICE into a
+//! PRIVMSG, or re-attribute it to another sender.
+
+use super::{TAG_CIPHERTEXT, TAG_EPOCH, TAG_NONCE, TAG_SENDER, TAG_VERSION, WIRE_VERSION};
+use crate::casemap::CaseMapping;
+use crate::error::{IronError, Result};
+use crate::message::{escape_tag_value, unescape_tag_value, IrcMessage};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use std::collections::BTreeMap;
+use std::fmt;
+use zeroize::Zeroize;
+
+/// Length of a channel key in bytes
+pub const KEY_LENGTH: usize = 32;
+/// Length of an XChaCha20 nonce in bytes
+pub const NONCE_LENGTH: usize = 24;
+
+/// Domain separator at the start of the associated data
+const AAD_LABEL: &[u8] = b"legion-e2e-v1";
+
+/// Tags this module adds and consumes
+const LEGION_TAGS: &[&str] = &[TAG_VERSION, TAG_EPOCH, TAG_NONCE, TAG_SENDER, TAG_CIPHERTEXT];
+
+/// A symmetric channel key for one epoch
+#[derive(Clone)]
+pub struct ChannelKey {
+    epoch: u32,
+    key: [u8; KEY_LENGTH],
+}
+
+impl ChannelKey {
+    /// Wrap raw key material
+    pub fn new(epoch: u32, key: [u8; KEY_LENGTH]) -> Self {
+        Self { epoch, key }
+    }
+
+    /// Generate a random key
+    pub fn generate(epoch: u32) -> Self {
+        let mut key = [0u8; KEY_LENGTH];
+        rand::rngs::OsRng.fill_bytes(&mut key);
+        Self { epoch, key }
+    }
+
+    /// The key's epoch
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// The raw key material
+    pub fn as_bytes(&self) -> &[u8; KEY_LENGTH] {
+        &self.key
+    }
+}
+
+impl Drop for ChannelKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl fmt::Debug for ChannelKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChannelKey")
+            .field("epoch", &self.epoch)
+            .field("key", &format_args!("[REDACTED]"))
+            .finish()
+    }
+}
+
+/// The encrypted form of one message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedPayload {
+    /// Epoch of the key used
+    pub epoch: u32,
+    /// Random nonce
+    pub nonce: [u8; NONCE_LENGTH],
+    /// Sender identity bound into the associated data
+    pub sender: String,
+    /// Ciphertext followed by the 16-byte Poly1305 tag
+    pub ciphertext: Vec<u8>,
+}
+
+impl EncryptedPayload {
+    /// The ciphertext as sent on the wire
+    pub fn ciphertext_base64(&self) -> String {
+        BASE64.encode(&self.ciphertext)
+    }
+
+    /// Add the header tags to a message
+    fn tag(&self, message: IrcMessage) -> IrcMessage {
+        message
+            .with_tag(TAG_VERSION, Some(WIRE_VERSION.to_string()))
+            .with_tag(TAG_EPOCH, Some(self.epoch.to_string()))
+            .with_tag(TAG_NONCE, Some(BASE64.encode(self.nonce)))
+            .with_tag(TAG_SENDER, Some(self.sender.clone()))
+    }
+
+    /// Read a payload from an encrypted message
+    pub fn from_message(message: &IrcMessage) -> Result<Self> {
+        let tag = |key: &str| -> Result<&str> {
+            message.get_tag(key)
+                .and_then(|v| v.as_deref())
+                .ok_or_else(|| IronError::Parse(format!("Missing {} tag", key)))
+        };
+
+        let version = tag(TAG_VERSION)?;
+        if version != WIRE_VERSION {
+            return Err(IronError::NotSupported(format!("Unsupported e2e version {}", version)));
+        }
+        let epoch = tag(TAG_EPOCH)?.parse()
+            .map_err(|_| IronError::Parse("Invalid key epoch".to_string()))?;
+        let nonce: [u8; NONCE_LENGTH] = BASE64.decode(tag(TAG_NONCE)?)
+            .ok()
+            .and_then(|n| n.try_into().ok())
+            .ok_or_else(|| IronError::Parse("Invalid nonce".to_string()))?;
+        let sender = tag(TAG_SENDER)?.to_string();
+
+        let body = match message.command.as_str() {
+            "TAGMSG" => tag(TAG_CIPHERTEXT)?,
+            _ => message.params.get(1).map(String::as_str)
+                .ok_or_else(|| IronError::Parse("Missing ciphertext".to_string()))?,
+        };
+        let ciphertext = BASE64.decode(body)
+            .map_err(|_| IronError::Parse("Invalid ciphertext encoding".to_string()))?;
+
+        Ok(Self { epoch, nonce, sender, ciphertext })
+    }
+}
+
+/// A decrypted inbound message
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecryptedMessage {
+    /// The message with plaintext restored and e2e tags removed
+    pub message: IrcMessage,
+    /// The sender identity the message claims
+    ///
+    /// Every holder of the channel key can claim any sender; the associated
+    /// data only stops a relay from re-attributing a message. Use
+    /// [`GroupKeyManager`](super::GroupKeyManager) for signed senders.
+    pub sender: String,
+    /// The key epoch that decrypted it
+    pub epoch: u32,
+}
+
+/// Encrypts and decrypts messages for one `!` channel
+///
+/// Holds every key epoch still in use; new messages use the current one.
+#[derive(Clone)]
+pub struct ChannelCipher {
+    channel: String,
+    casemapping: CaseMapping,
+    keys: BTreeMap<u32, ChannelKey>,
+    current: u32,
+}
+
+impl fmt::Debug for ChannelCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChannelCipher")
+            .field("channel", &self.channel)
+            .field("epochs", &self.keys.keys().collect::<Vec<_>>())
+            .field("current", &self.current)
+            .finish()
+    }
+}
+
+impl ChannelCipher {
+    /// Create a cipher for `channel` using `key` as the current key
+    pub fn new(channel: impl Into<String>, key: ChannelKey) -> Self {
+        let current = key.epoch;
+        Self {
+            channel: channel.into(),
+            casemapping: CaseMapping::default(),
+            keys: BTreeMap::from([(current, key)]),
+            current,
+        }
+    }
+
+    /// Compare channel names with `casemapping`
+    pub fn with_casemapping(mut self, casemapping: CaseMapping) -> Self {
+        self.casemapping = casemapping;
+        self
+    }
+
+    /// The channel this cipher belongs to
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    /// The epoch used for new messages
+    pub fn current_epoch(&self) -> u32 {
+        self.current
+    }
+
+    /// Check if a key epoch is available
+    pub fn has_epoch(&self, epoch: u32) -> bool {
+        self.keys.contains_key(&epoch)
+    }
+
+    /// Keep a key for decryption without sending with it
+    pub fn add_key(&mut self, key: ChannelKey) {
+        self.keys.insert(key.epoch, key);
+    }
+
+    /// Start sending with a newer key
+    pub fn rotate(&mut self, key: ChannelKey) -> Result<()> {
+        if key.epoch <= self.current {
+            return Err(IronError::InvalidInput(format!(
+                "Key epoch {} is not newer than {}", key.epoch, self.current
+            )));
+        }
+        self.current = key.epoch;
+        self.add_key(key);
+        Ok(())
+    }
+
+    /// Forget an old key; the current key cannot be removed
+    pub fn remove_key(&mut self, epoch: u32) -> bool {
+        epoch != self.current && self.keys.remove(&epoch).is_some()
+    }
+
+    /// Encrypt `plaintext` with the current key and an explicit nonce
+    ///
+    /// Nonces must never repeat under one key;
+    /// [`encrypt_message`](Self::encrypt_message) picks random ones.
+    pub fn seal(&self, command: &str, sender: &str, plaintext: &[u8], nonce: [u8; NONCE_LENGTH]) -> Result<EncryptedPayload> {
+        let key = &self.keys[&self.current];
+        let aad = self.aad(command, sender, self.current);
+        let ciphertext = XChaCha20Poly1305::new(key.as_bytes().into())
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: plaintext, aad: &aad })
+            .map_err(|_| IronError::Internal("Encryption failed".to_string()))?;
+        Ok(EncryptedPayload {
+            epoch: self.current,
+            nonce,
+            sender: sender.to_string(),
+            ciphertext,
+        })
+    }
+
+    /// Decrypt and authenticate a payload
+    pub fn open(&self, command: &str, payload: &EncryptedPayload) -> Result<Vec<u8>> {
+        let key = self.keys.get(&payload.epoch).ok_or_else(|| {
+            IronError::Protocol(format!("No key for epoch {} in {}", payload.epoch, self.channel))
+        })?;
+        let aad = self.aad(command, &payload.sender, payload.epoch);
+        XChaCha20Poly1305::new(key.as_bytes().into())
+            .decrypt(XNonce::from_slice(&payload.nonce), Payload { msg: &payload.ciphertext, aad: &aad })
+            .map_err(|_| IronError::SecurityViolation("Message failed authentication".to_string()))
+    }
+
+    /// Encrypt an outbound PRIVMSG, NOTICE or TAGMSG to this channel
+    pub fn encrypt_message(&self, message: &IrcMessage, sender: &str) -> Result<IrcMessage> {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+        self.encrypt_message_with_nonce(message, sender, nonce)
+    }
+
+    fn encrypt_message_with_nonce(&self, message: &IrcMessage, sender: &str, nonce: [u8; NONCE_LENGTH]) -> Result<IrcMessage> {
+        self.check_target(message)?;
+        let mut out = message.clone();
+
+        match message.command.as_str() {
+            "PRIVMSG" | "NOTICE" => {
+                let text = message.params.get(1)
+                    .ok_or_else(|| IronError::InvalidInput("Message has no text".to_string()))?;
+                let payload = self.seal(&message.command, sender, text.as_bytes(), nonce)?;
+                out.params[1] = payload.ciphertext_base64();
+                Ok(payload.tag(out))
+            }
+            "TAGMSG" => {
+                let client_tags: BTreeMap<&String, &Option<String>> = message.tags.iter()
+                    .filter(|(key, _)| key.starts_with('+'))
+                    .collect();
+                let serialized = client_tags.iter()
+                    .map(|(key, value)| match value {
+                        Some(value) => format!("{}={}", key, escape_tag_value(value)),
+                        None => key.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(";");
+                let payload = self.seal(&message.command, sender, serialized.as_bytes(), nonce)?;
+                out.tags.retain(|key, _| !key.starts_with('+'));
+                Ok(payload.tag(out).with_tag(TAG_CIPHERTEXT, Some(payload.ciphertext_base64())))
+            }
+            other => Err(IronError::NotSupported(format!("Cannot encrypt {}", other))),
+        }
+    }
+
+    /// Decrypt an inbound message to this channel
+    pub fn decrypt_message(&self, message: &IrcMessage) -> Result<DecryptedMessage> {
+        self.check_target(message)?;
+        let payload = EncryptedPayload::from_message(message)?;
+        let plaintext = self.open(&message.command, &payload)?;
+        let plaintext = String::from_utf8(plaintext)
+            .map_err(|_| IronError::SecurityViolation("Decrypted text is not UTF-8".to_string()))?;
+
+        let mut out = message.clone();
+        out.tags.retain(|key, _| !LEGION_TAGS.contains(&key.as_str()));
+        if message.command == "TAGMSG" {
+            for tag in plaintext.split(';').filter(|t| !t.is_empty()) {
+                let (key, value) = match tag.split_once('=') {
+                    Some((key, value)) => (key, Some(unescape_tag_value(value))),
+                    None => (tag, None),
+                };
+                // Only client tags may come from a sender; server-set tags stay as they are
+                if key.starts_with('+') && !LEGION_TAGS.contains(&key) && !out.tags.contains_key(key) {
+                    out.tags.insert(key.to_string(), value);
+                }
+            }
+        } else {
+            out.params[1] = plaintext;
+        }
+
+        Ok(DecryptedMessage {
+            message: out,
+            sender: payload.sender,
+            epoch: payload.epoch,
+        })
+    }
+
+    fn check_target(&self, message: &IrcMessage) -> Result<()> {
+        match message.params.first() {
+            Some(target) if self.casemapping.equals(target, &self.channel) => Ok(()),
+            _ => Err(IronError::InvalidInput(format!("Message is not addressed to {}", self.channel))),
+        }
+    }
+
+    fn aad(&self, command: &str, sender: &str, epoch: u32) -> Vec<u8> {
+        let mut aad = Vec::with_capacity(AAD_LABEL.len() + self.channel.len() + command.len() + sender.len() + 8);
+        aad.extend_from_slice(AAD_LABEL);
+        aad.push(0);
+        aad.extend_from_slice(self.casemapping.fold(&self.channel).as_bytes());
+        aad.push(0);
+        aad.extend_from_slice(command.as_bytes());
+        aad.push(0);
+        aad.extend_from_slice(sender.as_bytes());
+        aad.push(0);
+        aad.extend_from_slice(&epoch.to_be_bytes());
+        aad
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn test_cipher() -> ChannelCipher {
+        let key: [u8; KEY_LENGTH] = std::array::from_fn(|i| i as u8);
+        ChannelCipher::new("!Secret", ChannelKey::new(7, key))
+    }
+
+    #[test]
+    fn test_xchacha20poly1305_vector() {
+        // draft-irtf-cfrg-xchacha-03, appendix A.3.1
+        let key = hex("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f");
+        let nonce = hex("404142434445464748494a4b4c4d4e4f5051525354555657");
+        let aad = hex("50515253c0c1c2c3c4c5c6c7");
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let expected = hex(concat!(
+            "bd6d179d3e83d43b9576579493c0e939572a1700252bfaccbed2902c21396cbb",
+            "731c7f1b0b4aa6440bf3a82f4eda7e39ae64c6708c54c216cb96b72e1213b452",
+            "2f8c9ba40db5d945b11b69b982c1bb9e3f3fac2bc369488f76b2383565d3fff9",
+            "21f9664c97637da9768812f615c68b13b52e",
+            "c0875924c1c7987947deafd8780acf49",
+        ));
+
+        let cipher = XChaCha20Poly1305::new_from_slice(&key).unwrap();
+        let sealed = cipher.encrypt(XNonce::from_slice(&nonce), Payload { msg: plaintext, aad: &aad }).unwrap();
+        assert_eq!(sealed, expected);
+    }
+
+    #[test]
+    fn test_wire_format_vector() {
+        let cipher = test_cipher();
+        let msg = IrcMessage::new("PRIVMSG").with_params(vec!["!secret".into(), "hello".into()]);
+        let encrypted = cipher.encrypt_message_with_nonce(&msg, "alice", [0x24; NONCE_LENGTH]).unwrap();
+
+        assert_eq!(encrypted.get_tag(TAG_VERSION), Some(&Some("1".to_string())));
+        assert_eq!(encrypted.get_tag(TAG_EPOCH), Some(&Some("7".to_string())));
+        assert_eq!(encrypted.get_tag(TAG_NONCE), Some(&Some("JCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQk".to_string())));
+        assert_eq!(encrypted.get_tag(TAG_SENDER), Some(&Some("alice".to_string())));
+        assert_eq!(encrypted.params[0], "!secret");
+        assert_eq!(encrypted.params[1], "w6A0Ev6mF8c0i9yUBabOC34l0brJ");
+
+        // The encoded line parses back and decrypts
+        let line = encrypted.to_string();
+        let parsed: IrcMessage = line.parse().unwrap();
+        let decrypted = cipher.decrypt_message(&parsed).unwrap();
+        assert_eq!(decrypted.message.params[1], "hello");
+        assert_eq!((decrypted.sender.as_str(), decrypted.epoch), ("alice", 7));
+        assert!(decrypted.message.tags.is_empty());
+    }
+
+    #[test]
+    fn test_tampering_is_rejected() {
+        let cipher = test_cipher();
+        let msg = IrcMessage::new("PRIVMSG").with_params(vec!["!SECRET".into(), "pay bob".into()]);
+        let encrypted = cipher.encrypt_message(&msg, "alice").unwrap();
+        assert!(cipher.decrypt_message(&encrypted).is_ok());
+
+        let mut resent = encrypted.clone();
+        resent.tags.insert(TAG_SENDER.to_string(), Some("mallory".to_string()));
+        assert!(matches!(cipher.decrypt_message(&resent), Err(IronError::SecurityViolation(_))));
+
+        let mut as_notice = encrypted.clone();
+        as_notice.command = "NOTICE".to_string();
+        assert!(matches!(cipher.decrypt_message(&as_notice), Err(IronError::SecurityViolation(_))));
+
+        let other = ChannelCipher::new("!other", ChannelKey::new(7, *cipher.keys[&7].as_bytes()));
+        let mut moved = encrypted.clone();
+        moved.params[0] = "!other".to_string();
+        assert!(matches!(other.decrypt_message(&moved), Err(IronError::SecurityViolation(_))));
+
+        let mut flipped = encrypted;
+        flipped.params[1] = BASE64.encode([0u8; 23]);
+        assert!(cipher.decrypt_message(&flipped).is_err());
+    }
+
+    #[test]
+    fn test_tagmsg_and_epochs() {
+        let mut cipher = test_cipher();
+        let msg = IrcMessage::new("TAGMSG")
+            .with_params(vec!["!secret".into()])
+            .with_tag("+draft/react", Some("👍; yes".to_string()))
+            .with_tag("+draft/reply", Some("abc".to_string()))
+            .with_tag("label", Some("1".to_string()));
+        let encrypted = cipher.encrypt_message(&msg, "bob").unwrap();
+        assert!(!encrypted.has_tag("+draft/react"));
+        assert!(encrypted.has_tag("label") && encrypted.has_tag(TAG_CIPHERTEXT));
+        assert_eq!(cipher.decrypt_message(&encrypted).unwrap().message, msg);
+
+        cipher.rotate(ChannelKey::new(8, [9; KEY_LENGTH])).unwrap();
+        assert!(cipher.rotate(ChannelKey::new(8, [1; KEY_LENGTH])).is_err());
+        let newer = cipher.encrypt_message(&msg, "bob").unwrap();
+        assert_eq!(newer.get_tag(TAG_EPOCH), Some(&Some("8".to_string())));
+        assert!(cipher.decrypt_message(&encrypted).is_ok());
+
+        assert!(!cipher.remove_key(8));
+        assert!(cipher.remove_key(7));
+        assert!(matches!(cipher.decrypt_message(&encrypted), Err(IronError::Protocol(_))));
+        assert!(cipher.encrypt_message(&IrcMessage::new("JOIN").with_params(vec!["!secret".into()]), "bob").is_err());
+        assert!(format!("{:?}", ChannelKey::new(1, [3; KEY_LENGTH])).contains("REDACTED"));
+    }
+
+    #[test]
+    fn test_tagmsg_cannot_override_server_tags() {
+        let cipher = test_cipher();
+        let plaintext = b"account=admin;time=2000-01-01T00:00:00.000Z;+legion/sender=eve;+draft/react=ok";
+        let payload = cipher.seal("TAGMSG", "mallory", plaintext, [1; NONCE_LENGTH]).unwrap();
+        let msg = payload.tag(IrcMessage::new("TAGMSG").with_params(vec!["!secret".into()]))
+            .with_tag(TAG_CIPHERTEXT, Some(payload.ciphertext_base64()))
+            .with_tag("account", Some("mallory".to_string()));
+
+        let decrypted = cipher.decrypt_message(&msg).unwrap().message;
+        assert_eq!(decrypted.get_tag("account"), Some(&Some("mallory".to_string())));
+        assert!(!decrypted.has_tag("time") && !decrypted.has_tag(TAG_SENDER));
+        assert_eq!(decrypted.get_tag("+draft/react"), Some(&Some("ok".to_string())));
+    }
+}
@@ -0,0 +1,1078 @@
+//! Sender-key group encryption for `!` channels
+//!
+//! Each member encrypts channel messages with their own sender key: a
+//! symmetric chain that yields a fresh message key per message, plus an
+//! Ed25519 key that signs each ciphertext so other members cannot forge it.
+//! Sender keys are handed to every other member as a
+//! [`SenderKeyDistribution`] over a pairwise [`DirectSession`].
+//!
+//! Whenever someone joins, parts, is kicked or is banned, every member
+//! starts a new epoch with a fresh sender key and redistributes it to the
+//! current members only. Departed members keep old keys but cannot read
+//! newer traffic, and new members cannot read older traffic.
+//!
+//! # Wire format
+//!
+//! ```text
+//! @+legion/e2e=1;+legion/epoch=<u32>;+legion/iter=<u32>;+legion/sender=<nick>;+legion/sig=<base64>
+//!     PRIVMSG !channel :<base64 ciphertext>
+//! ```
+//!
+//! The associated data is
+//! `"legion-sk-v1" 0x00 folded-channel 0x00 command 0x00 sender 0x00 epoch iteration`
+//! (big-endian u32s), and the signature covers the associated data followed
+//! by the ciphertext.
+
+use super::ratchet::{self, DirectSession, MAX_SKIP};
+use super::x3dh::IdentityKey;
+use super::{DecryptedMessage, TAG_EPOCH, TAG_ITERATION, TAG_SENDER, TAG_SENDER_KEY, TAG_SIGNATURE, TAG_VERSION, WIRE_VERSION};
+use crate::admin::{AdminData, KeyInfo, KeyOperation};
+use crate::casemap::CaseMapping;
+use crate::error::{IronError, Result};
+use crate::hostmask::{BanMask, UserMask};
+use crate::message::IrcMessage;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::RngCore;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+use zeroize::Zeroize;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Domain separator at the start of the associated data
+const AAD_LABEL: &[u8] = b"legion-sk-v1";
+/// Binary format version of sender key distributions
+const DISTRIBUTION_VERSION: u8 = 1;
+/// Epochs of each member's sender key kept for late messages
+const MAX_EPOCHS: usize = 4;
+
+/// Tags this module adds and consumes
+const GROUP_TAGS: &[&str] = &[TAG_VERSION, TAG_EPOCH, TAG_ITERATION, TAG_SENDER, TAG_SIGNATURE];
+
+/// A change in a channel's membership
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MembershipChange {
+    /// A user joined
+    Join(String),
+    /// A user parted or quit
+    Part(String),
+    /// A user was kicked
+    Kick(String),
+    /// A ban mask was set; every member it matches is removed
+    Ban(String),
+    /// A mode change with an unknown mode letter; rekeys as a precaution
+    UnknownMode(char),
+    /// A member changed nick
+    Nick {
+        /// The previous nick
+        old: String,
+        /// The new nick
+        new: String,
+    },
+}
+
+impl MembershipChange {
+    /// Read the changes a JOIN, PART, QUIT, KICK, NICK or `MODE +b` makes to `channel`
+    ///
+    /// MODE arguments are matched to letters using [`ChannelModes::default`].
+    pub fn from_message(message: &IrcMessage, channel: &str, casemapping: CaseMapping) -> Vec<Self> {
+        Self::from_message_with(message, channel, casemapping, &ChannelModes::default())
+    }
+
+    /// Read the changes a message makes to `channel` under a server's mode classification
+    pub fn from_message_with(
+        message: &IrcMessage,
+        channel: &str,
+        casemapping: CaseMapping,
+        modes: &ChannelModes,
+    ) -> Vec<Self> {
+        let nick = match message.prefix.as_deref().and_then(|p| p.split('!').next()) {
+            Some(nick) => nick.to_string(),
+            None => return Vec::new(),
+        };
+        let in_channel = |param: Option<&String>| {
+            param.is_some_and(|p| p.split(',').any(|c| casemapping.equals(c, channel)))
+        };
+        let change = match message.command.as_str() {
+            "JOIN" if in_channel(message.params.first()) => Some(Self::Join(nick)),
+            "PART" if in_channel(message.params.first()) => Some(Self::Part(nick)),
+            "QUIT" => Some(Self::Part(nick)),
+            "KICK" if in_channel(message.params.first()) => message.params.get(1).cloned().map(Self::Kick),
+            "NICK" => message.params.first().cloned().map(|new| Self::Nick { old: nick, new }),
+            "MODE" if in_channel(message.params.first()) => return ban_masks(&message.params[1..], modes),
+            _ => None,
+        };
+        change.into_iter().collect()
+    }
+}
+
+/// How a server's channel modes take arguments, from `CHANMODES` and `PREFIX`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ChannelModes {
+    /// List modes (type A)
+    pub lists: String,
+    /// Modes that always take an argument (type B)
+    pub always: String,
+    /// Modes that take an argument only when set (type C)
+    pub when_set: String,
+    /// Modes that never take an argument (type D)
+    pub never: String,
+    /// Member prefix modes such as `o` and `v`
+    pub prefixes: String,
+}
+
+impl Default for ChannelModes {
+    /// `CHANMODES=beIq,k,l,imnpst` and `PREFIX=(qaohv)~&@%+`
+    fn default() -> Self {
+        Self {
+            lists: "beIq".to_string(),
+            always: "k".to_string(),
+            when_set: "l".to_string(),
+            never: "imnpst".to_string(),
+            prefixes: "qaohv".to_string(),
+        }
+    }
+}
+
+impl ChannelModes {
+    /// Read `CHANMODES` and `PREFIX` from a set of ISUPPORT tokens
+    ///
+    /// Tokens that are absent keep their [`Default`] values.
+    pub fn from_isupport<S: AsRef<str>>(tokens: &[S]) -> Self {
+        let mut modes = Self::default();
+        for token in tokens.iter().map(AsRef::as_ref) {
+            if let Some(value) = token.strip_prefix("CHANMODES=") {
+                let mut types = value.split(',').map(str::to_string);
+                modes.lists = types.next().unwrap_or_default();
+                modes.always = types.next().unwrap_or_default();
+                modes.when_set = types.next().unwrap_or_default();
+                modes.never = types.next().unwrap_or_default();
+            } else if let Some(value) = token.strip_prefix("PREFIX=") {
+                modes.prefixes = value.strip_prefix('(')
+                    .and_then(|rest| rest.split_once(')'))
+                    .map(|(letters, _)| letters.to_string())
+                    .unwrap_or_default();
+            }
+        }
+        modes
+    }
+
+    /// Whether `mode` takes an argument, or `None` if the letter is unknown
+    pub fn takes_argument(&self, mode: char, adding: bool) -> Option<bool> {
+        if self.lists.contains(mode) || self.always.contains(mode) || self.prefixes.contains(mode) {
+            Some(true)
+        } else if self.when_set.contains(mode) {
+            Some(adding)
+        } else if self.never.contains(mode) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+/// The masks set by `+b` in a channel MODE's modestring and arguments
+///
+/// After an unknown letter the arguments can no longer be lined up with
+/// their modes, so every remaining argument is treated as a possible ban.
+fn ban_masks(params: &[String], modes: &ChannelModes) -> Vec<MembershipChange> {
+    let (modestring, mut args) = match params.split_first() {
+        Some((modestring, args)) => (modestring, args.iter()),
+        None => return Vec::new(),
+    };
+    let mut adding = true;
+    let mut changes = Vec::new();
+    let mut letters = modestring.chars();
+    while let Some(mode) = letters.next() {
+        match (mode, modes.takes_argument(mode, adding)) {
+            ('+', _) => adding = true,
+            ('-', _) => adding = false,
+            (_, Some(true)) => {
+                if let Some(arg) = args.next() {
+                    if mode == 'b' && adding {
+                        changes.push(MembershipChange::Ban(arg.clone()));
+                    }
+                }
+            }
+            (_, Some(false)) => {}
+            (_, None) => {
+                changes.push(MembershipChange::UnknownMode(mode));
+                let rest: String = letters.collect();
+                if rest.contains('b') {
+                    changes.extend(args.map(|arg| MembershipChange::Ban(arg.clone())));
+                }
+                break;
+            }
+        }
+    }
+    changes
+}
+
+/// A sender key handed to one member
+#[derive(Clone, PartialEq, Eq)]
+pub struct SenderKeyDistribution {
+    /// The channel the key is for
+    pub channel: String,
+    /// The member who sends with the key
+    pub sender: String,
+    /// The sender's key epoch
+    pub epoch: u32,
+    /// The chain position `chain_key` is at
+    pub iteration: u32,
+    /// The symmetric chain key
+    pub chain_key: [u8; 32],
+    /// The Ed25519 key that signs the sender's messages
+    pub signing_key: [u8; 32],
+}
+
+impl SenderKeyDistribution {
+    /// Encode as bytes
+    ///
+    /// Fails if the channel or sender name is longer than 65535 bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut out = vec![DISTRIBUTION_VERSION];
+        out.extend_from_slice(&self.epoch.to_be_bytes());
+        out.extend_from_slice(&self.iteration.to_be_bytes());
+        out.extend_from_slice(&self.chain_key);
+        out.extend_from_slice(&self.signing_key);
+        for name in [&self.channel, &self.sender] {
+            let len = u16::try_from(name.len())
+                .map_err(|_| IronError::InvalidInput("Sender key name too long".to_string()))?;
+            out.extend_from_slice(&len.to_be_bytes());
+            out.extend_from_slice(name.as_bytes());
+        }
+        Ok(out)
+    }
+
+    /// Decode from bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let truncated = || IronError::Parse("Truncated sender key".to_string());
+        match bytes.first() {
+            Some(&DISTRIBUTION_VERSION) => {}
+            Some(version) => return Err(IronError::NotSupported(format!("Unsupported sender key format {}", version))),
+            None => return Err(truncated()),
+        }
+        if bytes.len() < 73 {
+            return Err(truncated());
+        }
+        let mut rest = &bytes[73..];
+        let mut names = Vec::with_capacity(2);
+        for _ in 0..2 {
+            let len = rest.get(..2).ok_or_else(truncated)?;
+            let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+            let name = rest.get(2..2 + len).ok_or_else(truncated)?;
+            names.push(String::from_utf8(name.to_vec())
+                .map_err(|_| IronError::Parse("Sender key name is not UTF-8".to_string()))?);
+            rest = &rest[2 + len..];
+        }
+        if !rest.is_empty() {
+            return Err(IronError::Parse("Trailing sender key data".to_string()));
+        }
+        let sender = names.pop().expect("two names");
+        let channel = names.pop().expect("two names");
+        Ok(Self {
+            channel,
+            sender,
+            epoch: u32::from_be_bytes(bytes[1..5].try_into().expect("length checked")),
+            iteration: u32::from_be_bytes(bytes[5..9].try_into().expect("length checked")),
+            chain_key: bytes[9..41].try_into().expect("length checked"),
+            signing_key: bytes[41..73].try_into().expect("length checked"),
+        })
+    }
+}
+
+impl Drop for SenderKeyDistribution {
+    fn drop(&mut self) {
+        self.chain_key.zeroize();
+    }
+}
+
+impl fmt::Debug for SenderKeyDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SenderKeyDistribution")
+            .field("channel", &self.channel)
+            .field("sender", &self.sender)
+            .field("epoch", &self.epoch)
+            .field("iteration", &self.iteration)
+            .field("chain_key", &format_args!("[REDACTED]"))
+            .finish()
+    }
+}
+
+/// Our own sending chain for the current epoch
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct SendingKey {
+    epoch: u32,
+    chain_key: [u8; 32],
+    iteration: u32,
+    signing: [u8; 32],
+    created_at: SystemTime,
+}
+
+impl SendingKey {
+    fn generate(epoch: u32) -> Self {
+        let mut chain_key = [0u8; 32];
+        let mut signing = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut chain_key);
+        rand::rngs::OsRng.fill_bytes(&mut signing);
+        Self { epoch, chain_key, iteration: 0, signing, created_at: SystemTime::now() }
+    }
+}
+
+impl Drop for SendingKey {
+    fn drop(&mut self) {
+        self.chain_key.zeroize();
+        self.signing.zeroize();
+    }
+}
+
+/// Another member's chain for one epoch
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct ReceivingKey {
+    chain_key: [u8; 32],
+    iteration: u32,
+    signing_key: [u8; 32],
+    skipped: BTreeMap<u32, [u8; 32]>,
+}
+
+impl ReceivingKey {
+    /// The message key for `iteration`, keeping keys for any skipped over
+    fn message_key(&mut self, iteration: u32) -> Result<[u8; 32]> {
+        if let Some(key) = self.skipped.remove(&iteration) {
+            return Ok(key);
+        }
+        if iteration < self.iteration {
+            return Err(IronError::SecurityViolation(format!("Replayed message {}", iteration)));
+        }
+        if iteration - self.iteration > MAX_SKIP {
+            return Err(IronError::Protocol(format!("Too many skipped messages ({})", iteration - self.iteration)));
+        }
+        while self.iteration < iteration {
+            self.skipped.insert(self.iteration, ratchet::kdf_chain(&mut self.chain_key));
+            self.iteration += 1;
+        }
+        while self.skipped.len() > MAX_SKIP as usize {
+            self.skipped.pop_first();
+        }
+        self.iteration += 1;
+        Ok(ratchet::kdf_chain(&mut self.chain_key))
+    }
+}
+
+impl Drop for ReceivingKey {
+    fn drop(&mut self) {
+        self.chain_key.zeroize();
+    }
+}
+
+/// Manages sender keys for one `!` channel
+///
+/// Each member runs one per encrypted channel. Feed it membership changes
+/// with [`apply`](Self::apply), then [`distribute`](Self::distribute) the
+/// current key to every member in [`pending_members`](Self::pending_members).
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GroupKeyManager {
+    channel: String,
+    own_nick: String,
+    casemapping: CaseMapping,
+    #[cfg_attr(feature = "serde", serde(default))]
+    modes: ChannelModes,
+    members: BTreeMap<String, String>,
+    sources: HashMap<String, (String, String)>,
+    sending: SendingKey,
+    distributed: BTreeSet<String>,
+    receiving: HashMap<String, BTreeMap<u32, ReceivingKey>>,
+    identities: HashMap<String, IdentityKey>,
+    rotation_interval: Option<Duration>,
+}
+
+impl fmt::Debug for GroupKeyManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GroupKeyManager")
+            .field("channel", &self.channel)
+            .field("own_nick", &self.own_nick)
+            .field("epoch", &self.sending.epoch)
+            .field("members", &self.members.values().collect::<Vec<_>>())
+            .field("distributed", &self.distributed.len())
+            .finish()
+    }
+}
+
+impl GroupKeyManager {
+    /// Start managing `channel` as `own_nick`, with a fresh sender key
+    pub fn new(channel: impl Into<String>, own_nick: impl Into<String>) -> Self {
+        Self {
+            channel: channel.into(),
+            own_nick: own_nick.into(),
+            casemapping: CaseMapping::default(),
+            modes: ChannelModes::default(),
+            members: BTreeMap::new(),
+            sources: HashMap::new(),
+            sending: SendingKey::generate(1),
+            distributed: BTreeSet::new(),
+            receiving: HashMap::new(),
+            identities: HashMap::new(),
+            rotation_interval: None,
+        }
+    }
+
+    /// Compare names with `casemapping`
+    pub fn with_casemapping(mut self, casemapping: CaseMapping) -> Self {
+        self.casemapping = casemapping;
+        self
+    }
+
+    /// Match MODE arguments to letters using the server's ISUPPORT classification
+    pub fn with_channel_modes(mut self, modes: ChannelModes) -> Self {
+        self.modes = modes;
+        self
+    }
+
+    /// Also rotate keys after `interval`
+    pub fn with_rotation_interval(mut self, interval: Duration) -> Self {
+        self.rotation_interval = Some(interval);
+        self
+    }
+
+    /// The channel being managed
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    /// Our current sender key epoch
+    pub fn epoch(&self) -> u32 {
+        self.sending.epoch
+    }
+
+    /// The other members, in folded order
+    pub fn members(&self) -> impl Iterator<Item = &str> {
+        self.members.values().map(String::as_str)
+    }
+
+    /// Check if `nick` is another member
+    pub fn is_member(&self, nick: &str) -> bool {
+        self.members.contains_key(&self.casemapping.fold(nick))
+    }
+
+    /// Members that do not have our current sender key yet
+    pub fn pending_members(&self) -> Vec<String> {
+        self.members.iter()
+            .filter(|(folded, _)| !self.distributed.contains(*folded))
+            .map(|(_, nick)| nick.clone())
+            .collect()
+    }
+
+    /// Pin the identity key a member's sessions must use
+    ///
+    /// Without an imported key, the identity of the first session used with
+    /// a member is pinned; importing replaces that pin.
+    pub fn import_identity(&mut self, nick: &str, identity: IdentityKey) {
+        self.identities.insert(self.casemapping.fold(nick), identity);
+    }
+
+    /// Update the member list, rekeying if anyone joined or left
+    ///
+    /// Returns whether a new epoch started. Ban masks are matched against
+    /// each member's `user@host` as seen in their JOIN (see
+    /// [`apply_message`](Self::apply_message)), or their nick alone.
+    pub fn apply(&mut self, change: &MembershipChange) -> bool {
+        let changed = self.update(change);
+        if changed {
+            self.rotate();
+        }
+        changed
+    }
+
+    /// Apply every membership change in a message from the channel
+    ///
+    /// Also remembers the `user@host` of joining users for ban matching.
+    /// Returns whether a new epoch started; several changes rekey once.
+    pub fn apply_message(&mut self, message: &IrcMessage) -> bool {
+        let changes = MembershipChange::from_message_with(message, &self.channel, self.casemapping, &self.modes);
+        if let (Some(MembershipChange::Join(nick)), Some(prefix)) = (changes.first(), message.prefix.as_deref()) {
+            if let Some((user, host)) = prefix.split_once('!').and_then(|(_, rest)| rest.split_once('@')) {
+                self.sources.insert(self.casemapping.fold(nick), (user.to_string(), host.to_string()));
+            }
+        }
+        let changed = changes.iter().fold(false, |changed, change| self.update(change) | changed);
+        if changed {
+            self.rotate();
+        }
+        changed
+    }
+
+    fn update(&mut self, change: &MembershipChange) -> bool {
+        match change {
+            MembershipChange::Join(nick) => {
+                let folded = self.casemapping.fold(nick);
+                if folded == self.casemapping.fold(&self.own_nick) || self.members.contains_key(&folded) {
+                    return false;
+                }
+                self.members.insert(folded, nick.clone());
+            }
+            MembershipChange::Part(nick) | MembershipChange::Kick(nick) => {
+                return self.remove_member(&self.casemapping.fold(nick));
+            }
+            MembershipChange::Ban(mask) => {
+                let Ok(mask) = BanMask::parse(mask, self.casemapping) else {
+                    return false;
+                };
+                let banned: Vec<String> = self.members.iter()
+                    .filter(|(folded, nick)| {
+                        let (user, host) = self.sources.get(*folded).cloned().unwrap_or_default();
+                        mask.matches(&UserMask::new(nick.as_str(), user, host), self.casemapping)
+                    })
+                    .map(|(folded, _)| folded.clone())
+                    .collect();
+                return banned.iter().fold(false, |changed, folded| self.remove_member(folded) | changed);
+            }
+            MembershipChange::UnknownMode(_) => {}
+            MembershipChange::Nick { old, new } => {
+                let (old_folded, new_folded) = (self.casemapping.fold(old), self.casemapping.fold(new));
+                if old_folded == self.casemapping.fold(&self.own_nick) {
+                    self.own_nick = new.clone();
+                } else if self.members.remove(&old_folded).is_some() {
+                    self.members.insert(new_folded.clone(), new.clone());
+                    rename(&mut self.receiving, &old_folded, &new_folded);
+                    rename(&mut self.identities, &old_folded, &new_folded);
+                    rename(&mut self.sources, &old_folded, &new_folded);
+                    if self.distributed.remove(&old_folded) {
+                        self.distributed.insert(new_folded);
+                    }
+                }
+                return false;
+            }
+        }
+        true
+    }
+
+    fn remove_member(&mut self, folded: &str) -> bool {
+        if self.members.remove(folded).is_none() {
+            return false;
+        }
+        self.sources.remove(folded);
+        self.receiving.remove(folded);
+        self.distributed.remove(folded);
+        true
+    }
+
+    /// Start a new epoch with a fresh sender key
+    pub fn rotate(&mut self) {
+        self.sending = SendingKey::generate(self.sending.epoch.wrapping_add(1));
+        self.distributed.clear();
+    }
+
+    /// Check if the rotation interval has passed
+    pub fn needs_rotation(&self, now: SystemTime) -> bool {
+        self.rotation_interval.is_some_and(|interval| {
+            now.duration_since(self.sending.created_at).is_ok_and(|age| age >= interval)
+        })
+    }
+
+    /// Send our current sender key to `member` over their direct session
+    ///
+    /// The session must be with `member` and match their pinned identity.
+    /// Returns the encrypted NOTICE to send.
+    pub fn distribute(&mut self, member: &str, session: &mut DirectSession) -> Result<IrcMessage> {
+        let folded = self.casemapping.fold(member);
+        if !self.members.contains_key(&folded) {
+            return Err(IronError::InvalidInput(format!("{} is not in {}", member, self.channel)));
+        }
+        self.check_session(&folded, session)?;
+
+        let distribution = SenderKeyDistribution {
+            channel: self.channel.clone(),
+            sender: self.own_nick.clone(),
+            epoch: self.sending.epoch,
+            iteration: self.sending.iteration,
+            chain_key: self.sending.chain_key,
+            signing_key: SigningKey::from_bytes(&self.sending.signing).verifying_key().to_bytes(),
+        };
+        let notice = IrcMessage::new("NOTICE")
+            .with_tag(TAG_SENDER_KEY, None)
+            .with_params(vec![member.to_string(), BASE64.encode(distribution.to_bytes()?)]);
+        let encrypted = session.encrypt_message(&notice)?;
+        self.distributed.insert(folded);
+        Ok(encrypted)
+    }
+
+    /// Check if a decrypted direct message carries a sender key
+    pub fn is_distribution(message: &IrcMessage) -> bool {
+        message.has_tag(TAG_SENDER_KEY)
+    }
+
+    /// Store a sender key received over `session`
+    ///
+    /// `message` is the already decrypted NOTICE; its source and the
+    /// session's peer must both be the member the key belongs to, and the
+    /// session must match the member's pinned identity.
+    pub fn receive_distribution(&mut self, session: &DirectSession, message: &IrcMessage) -> Result<()> {
+        if !Self::is_distribution(message) {
+            return Err(IronError::InvalidInput("Not a sender key distribution".to_string()));
+        }
+        let bytes = message.params.get(1)
+            .and_then(|text| BASE64.decode(text).ok())
+            .ok_or_else(|| IronError::Parse("Invalid sender key encoding".to_string()))?;
+        let distribution = SenderKeyDistribution::from_bytes(&bytes)?;
+
+        let source = message.prefix.as_deref().and_then(|p| p.split('!').next()).unwrap_or_default();
+        if !self.casemapping.equals(&distribution.channel, &self.channel)
+            || !self.casemapping.equals(&distribution.sender, source)
+        {
+            return Err(IronError::SecurityViolation(format!(
+                "{} sent a sender key for {} in {}", source, distribution.sender, distribution.channel
+            )));
+        }
+        let folded = self.casemapping.fold(&distribution.sender);
+        if !self.members.contains_key(&folded) {
+            return Err(IronError::Protocol(format!("{} is not in {}", distribution.sender, self.channel)));
+        }
+        self.check_session(&folded, session)?;
+
+        let epochs = self.receiving.entry(folded).or_default();
+        epochs.insert(distribution.epoch, ReceivingKey {
+            chain_key: distribution.chain_key,
+            iteration: distribution.iteration,
+            signing_key: distribution.signing_key,
+            skipped: BTreeMap::new(),
+        });
+        while epochs.len() > MAX_EPOCHS {
+            epochs.pop_first();
+        }
+        Ok(())
+    }
+
+    /// Check if we hold `member`'s sender key for `epoch`
+    pub fn has_sender_key(&self, member: &str, epoch: u32) -> bool {
+        self.receiving.get(&self.casemapping.fold(member))
+            .is_some_and(|epochs| epochs.contains_key(&epoch))
+    }
+
+    /// Encrypt an outbound PRIVMSG or NOTICE to the channel
+    pub fn encrypt_message(&mut self, message: &IrcMessage) -> Result<IrcMessage> {
+        self.check_target(message)?;
+        if !matches!(message.command.as_str(), "PRIVMSG" | "NOTICE") {
+            return Err(IronError::NotSupported(format!("Cannot encrypt {}", message.command)));
+        }
+        let text = message.params.get(1)
+            .ok_or_else(|| IronError::InvalidInput("Message has no text".to_string()))?;
+
+        let iteration = self.sending.iteration;
+        let message_key = ratchet::kdf_chain(&mut self.sending.chain_key);
+        self.sending.iteration += 1;
+        let aad = self.aad(&message.command, &self.own_nick, self.sending.epoch, iteration);
+        let ciphertext = ratchet::seal(&message_key, &aad, text.as_bytes())?;
+        let signature = SigningKey::from_bytes(&self.sending.signing).sign(&[aad, ciphertext.clone()].concat());
+
+        let mut out = message.clone();
+        out.params[1] = BASE64.encode(ciphertext);
+        Ok(out
+            .with_tag(TAG_VERSION, Some(WIRE_VERSION.to_string()))
+            .with_tag(TAG_EPOCH, Some(self.sending.epoch.to_string()))
+            .with_tag(TAG_ITERATION, Some(iteration.to_string()))
+            .with_tag(TAG_SENDER, Some(self.own_nick.clone()))
+            .with_tag(TAG_SIGNATURE, Some(BASE64.encode(signature.to_bytes()))))
+    }
+
+    /// Decrypt an inbound message to the channel
+    ///
+    /// A message that fails to verify leaves the sender's chain unchanged.
+    pub fn decrypt_message(&mut self, message: &IrcMessage) -> Result<DecryptedMessage> {
+        self.check_target(message)?;
+        let tag = |key: &str| -> Result<&str> {
+            message.get_tag(key)
+                .and_then(|v| v.as_deref())
+                .ok_or_else(|| IronError::Parse(format!("Missing {} tag", key)))
+        };
+        let version = tag(TAG_VERSION)?;
+        if version != WIRE_VERSION {
+            return Err(IronError::NotSupported(format!("Unsupported e2e version {}", version)));
+        }
+        let number = |key: &str| -> Result<u32> {
+            tag(key)?.parse().map_err(|_| IronError::Parse(format!("Invalid {} tag", key)))
+        };
+        let (epoch, iteration) = (number(TAG_EPOCH)?, number(TAG_ITERATION)?);
+        let sender = tag(TAG_SENDER)?.to_string();
+        let signature = BASE64.decode(tag(TAG_SIGNATURE)?).ok()
+            .and_then(|s| Signature::from_slice(&s).ok())
+            .ok_or_else(|| IronError::Parse("Invalid signature".to_string()))?;
+        let ciphertext = message.params.get(1)
+            .and_then(|text| BASE64.decode(text).ok())
+            .ok_or_else(|| IronError::Parse("Invalid ciphertext encoding".to_string()))?;
+
+        let aad = self.aad(&message.command, &sender, epoch, iteration);
+        let chain = self.receiving.get_mut(&self.casemapping.fold(&sender))
+            .and_then(|epochs| epochs.get_mut(&epoch))
+            .ok_or_else(|| IronError::Protocol(format!("No sender key from {} for epoch {}", sender, epoch)))?;
+        VerifyingKey::from_bytes(&chain.signing_key)
+            .and_then(|key| key.verify(&[aad.as_slice(), &ciphertext].concat(), &signature))
+            .map_err(|_| IronError::SecurityViolation("Message signature does not verify".to_string()))?;
+
+        let mut next = chain.clone();
+        let message_key = next.message_key(iteration)?;
+        let plaintext = ratchet::open(&message_key, &aad, &ciphertext)?;
+        *chain = next;
+        let plaintext = String::from_utf8(plaintext)
+            .map_err(|_| IronError::SecurityViolation("Decrypted text is not UTF-8".to_string()))?;
+
+        let mut out = message.clone();
+        out.tags.retain(|key, _| !GROUP_TAGS.contains(&key.as_str()));
+        out.params[1] = plaintext;
+        Ok(DecryptedMessage { message: out, sender, epoch })
+    }
+
+    /// Key state as reported by [`AdminData::KeyInfo`]
+    pub fn key_info(&self) -> KeyInfo {
+        KeyInfo {
+            key_version: self.sending.epoch as u64,
+            created_at: self.sending.created_at,
+            rotation_schedule: self.rotation_interval.map(|interval| self.sending.created_at + interval),
+            member_key_count: self.distributed.len(),
+            has_backup: false,
+        }
+    }
+
+    /// Perform a key operation, returning the resulting key info
+    pub fn handle_operation(&mut self, operation: &KeyOperation) -> Result<AdminData> {
+        match operation {
+            KeyOperation::Rotate | KeyOperation::Generate => self.rotate(),
+            KeyOperation::ImportPublic { user_id, public_key } => {
+                let (dh, signing) = match public_key.len() {
+                    64 => public_key.split_at(32),
+                    _ => return Err(IronError::InvalidInput("Identity keys are 64 bytes".to_string())),
+                };
+                self.import_identity(user_id, IdentityKey {
+                    dh: dh.try_into().expect("32 bytes"),
+                    signing: signing.try_into().expect("32 bytes"),
+                });
+            }
+            KeyOperation::Backup | KeyOperation::Restore { .. } | KeyOperation::ExportPublic => {
+                return Err(IronError::NotSupported(format!("{:?} is not handled by the group key manager", operation)));
+            }
+        }
+        Ok(AdminData::KeyInfo(self.key_info()))
+    }
+
+    /// Check that `session` is with the member, pinning its identity on first use
+    fn check_session(&mut self, folded: &str, session: &DirectSession) -> Result<()> {
+        if self.casemapping.fold(session.peer()) != folded {
+            return Err(IronError::SecurityViolation(format!(
+                "Session with {} cannot carry keys for {}", session.peer(), folded
+            )));
+        }
+        match self.identities.entry(folded.to_string()) {
+            Entry::Occupied(pinned) if pinned.get() != session.remote_identity() => Err(IronError::SecurityViolation(
+                format!("Session identity for {} does not match the pinned key", folded)
+            )),
+            Entry::Occupied(_) => Ok(()),
+            Entry::Vacant(entry) => {
+                entry.insert(*session.remote_identity());
+                Ok(())
+            }
+        }
+    }
+
+    fn check_target(&self, message: &IrcMessage) -> Result<()> {
+        match message.params.first() {
+            Some(target) if self.casemapping.equals(target, &self.channel) => Ok(()),
+            _ => Err(IronError::InvalidInput(format!("Message is not addressed to {}", self.channel))),
+        }
+    }
+
+    fn aad(&self, command: &str, sender: &str, epoch: u32, iteration: u32) -> Vec<u8> {
+        let mut aad = AAD_LABEL.to_vec();
+        for part in [self.casemapping.fold(&self.channel).as_str(), command, sender] {
+            aad.push(0);
+            aad.extend_from_slice(part.as_bytes());
+        }
+        aad.push(0);
+        aad.extend_from_slice(&epoch.to_be_bytes());
+        aad.extend_from_slice(&iteration.to_be_bytes());
+        aad
+    }
+}
+
+fn rename<V>(map: &mut HashMap<String, V>, old: &str, new: &str) {
+    if let Some(value) = map.remove(old) {
+        map.insert(new.to_string(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::e2e::{IdentityKeyPair, PrekeyStore};
+
+    /// A member with their manager and a direct session to each other member
+    struct Member {
+        manager: GroupKeyManager,
+        sessions: HashMap<String, DirectSession>,
+    }
+
+    fn connect(members: &mut BTreeMap<&str, Member>, a: &str, b: &str) {
+        let mut store = PrekeyStore::new(IdentityKeyPair::generate());
+        let mut session = DirectSession::initiate(&IdentityKeyPair::generate(), b, &store.bundle()).unwrap();
+        let hello = IrcMessage::new("PRIVMSG")
+            .with_prefix(format!("{}!u@h", a))
+            .with_params(vec![b.to_string(), "hello".to_string()]);
+        let (accepted, _) = DirectSession::accept(&mut store, &session.encrypt_message(&hello).unwrap()).unwrap();
+        let reply = hello.clone().with_params(vec![a.to_string(), "hi".to_string()]);
+        session.decrypt_message(&accepted.clone().encrypt_message(&reply).unwrap()).unwrap();
+        members.get_mut(a).unwrap().sessions.insert(b.to_string(), session);
+        members.get_mut(b).unwrap().sessions.insert(a.to_string(), accepted);
+    }
+
+    /// Deliver every pending sender key
+    fn sync(members: &mut BTreeMap<&str, Member>) {
+        let names: Vec<&str> = members.keys().copied().collect();
+        for from in &names {
+            let sender = members.get_mut(from).unwrap();
+            let mut outbox = Vec::new();
+            for to in sender.manager.pending_members() {
+                let session = sender.sessions.get_mut(&to).unwrap();
+                outbox.push((to.clone(), sender.manager.distribute(&to, session).unwrap()));
+            }
+            for (to, notice) in outbox {
+                let receiver = members.get_mut(to.as_str()).unwrap();
+                let session = receiver.sessions.get_mut(*from).unwrap();
+                let notice = session.decrypt_message(&notice.with_prefix(format!("{}!u@h", from))).unwrap();
+                assert!(GroupKeyManager::is_distribution(&notice));
+                receiver.manager.receive_distribution(session, &notice).unwrap();
+            }
+        }
+    }
+
+    fn group(names: &[&'static str]) -> BTreeMap<&'static str, Member> {
+        let mut members: BTreeMap<&str, Member> = names.iter()
+            .map(|n| (*n, Member { manager: GroupKeyManager::new("!Team", *n), sessions: HashMap::new() }))
+            .collect();
+        for (i, a) in names.iter().enumerate() {
+            for b in &names[i + 1..] {
+                connect(&mut members, a, b);
+            }
+            for b in names {
+                members.get_mut(a).unwrap().manager.apply(&MembershipChange::Join(b.to_string()));
+            }
+        }
+        sync(&mut members);
+        members
+    }
+
+    fn say(members: &mut BTreeMap<&str, Member>, from: &str, text: &str) -> IrcMessage {
+        let msg = IrcMessage::new("PRIVMSG")
+            .with_prefix(format!("{}!u@h", from))
+            .with_params(vec!["!team".to_string(), text.to_string()]);
+        members.get_mut(from).unwrap().manager.encrypt_message(&msg).unwrap()
+    }
+
+    #[test]
+    fn test_group_messages() {
+        let mut members = group(&["alice", "bob", "carol"]);
+        let sent = say(&mut members, "alice", "hello team");
+        assert_ne!(sent.params[1], "hello team");
+
+        for name in ["bob", "carol"] {
+            let decrypted = members.get_mut(name).unwrap().manager.decrypt_message(&sent).unwrap();
+            assert_eq!(decrypted.message.params[1], "hello team");
+            assert_eq!(decrypted.sender, "alice");
+            assert!(decrypted.message.tags.is_empty());
+        }
+        // Replays and forged signatures are rejected
+        let bob = &mut members.get_mut("bob").unwrap().manager;
+        assert!(bob.decrypt_message(&sent).is_err());
+        let mut forged = sent.clone();
+        forged.tags.insert(TAG_SENDER.to_string(), Some("carol".to_string()));
+        assert!(bob.decrypt_message(&forged).is_err());
+    }
+
+    #[test]
+    fn test_rekey_on_departure() {
+        let mut members = group(&["alice", "bob", "carol"]);
+        let old_epoch = members["alice"].manager.epoch();
+        let kicked = MembershipChange::from_message(
+            &"KICK !team carol :bye".parse::<IrcMessage>().unwrap().with_prefix("alice!u@h"),
+            "!Team",
+            CaseMapping::Rfc1459,
+        ).remove(0);
+        assert_eq!(kicked, MembershipChange::Kick("carol".to_string()));
+
+        for name in ["alice", "bob"] {
+            let manager = &mut members.get_mut(name).unwrap().manager;
+            assert!(manager.apply(&kicked));
+            assert!(!manager.is_member("carol"));
+        }
+        assert!(members["alice"].manager.epoch() > old_epoch);
+        assert_eq!(members["alice"].manager.pending_members(), vec!["bob".to_string()]);
+        members.get_mut("carol").unwrap().manager.apply(&MembershipChange::Part("alice".to_string()));
+        members.get_mut("carol").unwrap().manager.apply(&MembershipChange::Part("bob".to_string()));
+        sync(&mut members);
+
+        let sent = say(&mut members, "alice", "after carol");
+        assert_eq!(members.get_mut("bob").unwrap().manager.decrypt_message(&sent).unwrap().message.params[1], "after carol");
+        assert!(matches!(
+            members.get_mut("carol").unwrap().manager.decrypt_message(&sent),
+            Err(IronError::Protocol(_))
+        ));
+        assert!(!members.get_mut("alice").unwrap().manager.apply(&MembershipChange::Part("carol".to_string())));
+    }
+
+    #[test]
+    fn test_out_of_order_and_nick_change() {
+        let mut members = group(&["alice", "bob"]);
+        let msgs: Vec<_> = (0..3).map(|i| say(&mut members, "alice", &i.to_string())).collect();
+        let epoch = members["alice"].manager.epoch();
+        let bob = &mut members.get_mut("bob").unwrap().manager;
+        for i in [2, 0, 1] {
+            assert_eq!(bob.decrypt_message(&msgs[i]).unwrap().message.params[1], i.to_string());
+        }
+
+        let nick = MembershipChange::from_message(
+            &"NICK alicia".parse::<IrcMessage>().unwrap().with_prefix("alice!u@h"),
+            "!team",
+            CaseMapping::Rfc1459,
+        ).remove(0);
+        assert!(!bob.apply(&nick));
+        assert!(bob.is_member("alicia") && !bob.is_member("alice"));
+        assert!(bob.has_sender_key("alicia", epoch));
+    }
+
+    #[test]
+    fn test_ban_masks_remove_matching_members() {
+        let mut manager = GroupKeyManager::new("!Team", "alice");
+        for prefix in ["bob!b@evil.example", "carol!c@good.example", "dave!d@good.example"] {
+            let join = "JOIN !team".parse::<IrcMessage>().unwrap().with_prefix(prefix);
+            assert!(manager.apply_message(&join));
+        }
+        let epoch = manager.epoch();
+
+        let mode = "MODE !team +ol-b+b carol 5 old!*@* *!*@evil.example".parse::<IrcMessage>().unwrap()
+            .with_prefix("alice!a@h");
+        assert_eq!(
+            MembershipChange::from_message(&mode, "!Team", CaseMapping::Rfc1459),
+            vec![MembershipChange::Ban("*!*@evil.example".to_string())]
+        );
+        assert!(manager.apply_message(&mode));
+        assert_eq!(manager.epoch(), epoch + 1);
+        assert!(!manager.is_member("bob") && manager.is_member("carol"));
+
+        // Nick-only bans still match members whose host was never seen
+        assert!(manager.apply(&MembershipChange::Ban("Dave".to_string())));
+        assert!(!manager.is_member("dave"));
+        assert!(!manager.apply(&MembershipChange::Ban("*!*@nowhere".to_string())));
+    }
+
+    #[test]
+    fn test_isupport_modes_align_ban_arguments() {
+        let modes = ChannelModes::from_isupport(&["CHANMODES=beI,kf,l,imnpst", "PREFIX=(ov)@+"]);
+        assert_eq!(modes.takes_argument('f', true), Some(true));
+        assert_eq!(modes.takes_argument('q', true), None);
+
+        let mut manager = GroupKeyManager::new("!Team", "alice").with_channel_modes(modes);
+        for prefix in ["bob!b@evil.example", "carol!c@good.example"] {
+            manager.apply_message(&"JOIN !team".parse::<IrcMessage>().unwrap().with_prefix(prefix));
+        }
+        let mode = "MODE !team +fb 5:10 *!*@evil.example".parse::<IrcMessage>().unwrap().with_prefix("alice!a@h");
+        assert!(manager.apply_message(&mode));
+        assert!(!manager.is_member("bob") && manager.is_member("carol"));
+
+        // Without CHANMODES `f` is unknown: rekey and treat every later argument as a ban
+        let mode = "MODE !team +fb 5:10 Carol".parse::<IrcMessage>().unwrap().with_prefix("alice!a@h");
+        assert_eq!(
+            MembershipChange::from_message(&mode, "!team", CaseMapping::Rfc1459),
+            vec![
+                MembershipChange::UnknownMode('f'),
+                MembershipChange::Ban("5:10".to_string()),
+                MembershipChange::Ban("Carol".to_string()),
+            ]
+        );
+        let epoch = manager.epoch();
+        let unknown = "MODE !team +X".parse::<IrcMessage>().unwrap().with_prefix("alice!a@h");
+        assert!(manager.apply_message(&unknown));
+        assert_eq!(manager.epoch(), epoch + 1);
+    }
+
+    #[test]
+    fn test_distribution_names_are_not_truncated() {
+        let mut distribution = SenderKeyDistribution {
+            channel: "#team".to_string(),
+            sender: "a".repeat(300),
+            epoch: 1,
+            iteration: 0,
+            chain_key: [1; 32],
+            signing_key: [2; 32],
+        };
+        let decoded = SenderKeyDistribution::from_bytes(&distribution.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.sender, distribution.sender);
+        assert_eq!(decoded.channel, "#team");
+
+        distribution.sender = "a".repeat(70_000);
+        assert!(matches!(distribution.to_bytes(), Err(IronError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_sessions_are_bound_to_members() {
+        let mut members = group(&["alice", "bob", "carol"]);
+        members.get_mut("carol").unwrap().manager.rotate();
+        let epoch = members["carol"].manager.epoch();
+
+        // A key claiming to be carol's but carried over bob's session is refused
+        let carol = members.get_mut("carol").unwrap();
+        let mut to_alice = carol.sessions["alice"].clone();
+        let notice = carol.manager.distribute("alice", &mut to_alice).unwrap();
+        let alice = members.get_mut("alice").unwrap();
+        let decrypted = alice.sessions.get_mut("carol").unwrap()
+            .decrypt_message(&notice.with_prefix("carol!u@h")).unwrap();
+        let bob_session = alice.sessions["bob"].clone();
+        assert!(matches!(
+            alice.manager.receive_distribution(&bob_session, &decrypted),
+            Err(IronError::SecurityViolation(_))
+        ));
+        assert!(!alice.manager.has_sender_key("carol", epoch));
+
+        // The first session's identity is pinned; a new identity under the same nick is refused
+        let mut store = PrekeyStore::new(IdentityKeyPair::generate());
+        let mut impostor = DirectSession::initiate(&IdentityKeyPair::generate(), "carol", &store.bundle()).unwrap();
+        let hello = IrcMessage::new("PRIVMSG").with_prefix("alice!u@h").with_params(vec!["carol".into(), "hi".into()]);
+        DirectSession::accept(&mut store, &impostor.encrypt_message(&hello).unwrap()).unwrap();
+        let alice = &mut members.get_mut("alice").unwrap().manager;
+        assert!(matches!(alice.distribute("carol", &mut impostor), Err(IronError::SecurityViolation(_))));
+    }
+
+    #[test]
+    fn test_key_info_and_operations() {
+        let mut members = group(&["alice", "bob", "carol"]);
+        let mut session = members["alice"].sessions["bob"].clone();
+        let alice = &mut members.get_mut("alice").unwrap().manager;
+        let info = alice.key_info();
+        assert_eq!(info.member_key_count, 2);
+        assert_eq!(info.key_version, alice.epoch() as u64);
+        assert!(info.rotation_schedule.is_none());
+
+        let AdminData::KeyInfo(info) = alice.handle_operation(&KeyOperation::Rotate).unwrap() else {
+            panic!("Expected KeyInfo");
+        };
+        assert_eq!(info.key_version, alice.epoch() as u64);
+        assert_eq!(info.member_key_count, 0);
+        assert!(alice.handle_operation(&KeyOperation::Backup).is_err());
+
+        // A pinned identity must match the session's
+        let wrong = IdentityKeyPair::generate().public();
+        let mut public_key = wrong.dh.to_vec();
+        public_key.extend_from_slice(&wrong.signing);
+        alice.handle_operation(&KeyOperation::ImportPublic { user_id: "bob".to_string(), public_key }).unwrap();
+        assert!(matches!(alice.distribute("bob", &mut session), Err(IronError::SecurityViolation(_))));
+
+        let scheduled = GroupKeyManager::new("!x", "me").with_rotation_interval(Duration::from_secs(60));
+        assert!(scheduled.key_info().rotation_schedule.is_some());
+        assert!(!scheduled.needs_rotation(SystemTime::now()));
+        assert!(scheduled.needs_rotation(SystemTime::now() + Duration::from_secs(61)));
+    }
+}
@@ -0,0 +1,556 @@
+//! Double Ratchet sessions for encrypted direct messages
+//!
+//! A [`DirectSession`] starts from X3DH (see [`super::x3dh`]) and then runs
+//! the Double Ratchet: every message gets a fresh key from a symmetric
+//! chain, and every change of speaker mixes in a new X25519 exchange, so a
+//! leaked key exposes neither earlier nor later messages.
+//!
+//! # Wire format (version 1)
+//!
+//! ```text
+//! @+legion/e2e=1;+legion/dr=<base64 header>[;+legion/x3dh=<base64 prekey message>]
+//!     PRIVMSG nick :<base64 ciphertext>
+//! ```
+//!
+//! The 40-byte header is the sender's ratchet key, the previous chain
+//! length and the message number (both u32 big-endian). The initiator adds
+//! `+legion/x3dh` until it receives a reply. Message keys expand through
+//! HKDF to an XChaCha20-Poly1305 key and nonce; the associated data is the
+//! X3DH associated data, the header and the command.
+//!
+//! Messages may arrive out of order: keys for skipped messages are kept
+//! (at most [`MAX_SKIP`] per chain) until the late message shows up.
+
+use super::x3dh::{self, Agreement, IdentityKey, IdentityKeyPair, KeyPair, PrekeyBundle, PrekeyMessage, PrekeyStore};
+use super::{TAG_PREKEY, TAG_RATCHET, TAG_VERSION, WIRE_VERSION};
+use crate::error::{IronError, Result};
+use crate::message::IrcMessage;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use zeroize::Zeroize;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Most message keys skipped in one chain
+pub const MAX_SKIP: u32 = 1000;
+/// Most skipped message keys kept overall
+const MAX_SKIPPED_KEYS: usize = 2 * MAX_SKIP as usize;
+
+/// HKDF info for the root chain
+const ROOT_INFO: &[u8] = b"LegionRatchet";
+/// HKDF info for per-message keys
+const MESSAGE_INFO: &[u8] = b"LegionMessageKeys";
+
+/// Tags this module adds and consumes
+const DIRECT_TAGS: &[&str] = &[TAG_VERSION, TAG_RATCHET, TAG_PREKEY];
+
+/// The per-message ratchet header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RatchetHeader {
+    /// The sender's current ratchet public key
+    pub ratchet_key: [u8; 32],
+    /// Messages sent in the sender's previous chain
+    pub previous_chain_length: u32,
+    /// Number of this message in the current chain
+    pub message_number: u32,
+}
+
+impl RatchetHeader {
+    /// Encode as 40 bytes
+    pub fn to_bytes(&self) -> [u8; 40] {
+        let mut out = [0u8; 40];
+        out[..32].copy_from_slice(&self.ratchet_key);
+        out[32..36].copy_from_slice(&self.previous_chain_length.to_be_bytes());
+        out[36..].copy_from_slice(&self.message_number.to_be_bytes());
+        out
+    }
+
+    /// Decode from 40 bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != 40 {
+            return Err(IronError::Parse("Invalid ratchet header length".to_string()));
+        }
+        Ok(Self {
+            ratchet_key: bytes[..32].try_into().expect("length checked"),
+            previous_chain_length: u32::from_be_bytes(bytes[32..36].try_into().expect("length checked")),
+            message_number: u32::from_be_bytes(bytes[36..].try_into().expect("length checked")),
+        })
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct SkippedKey {
+    ratchet_key: [u8; 32],
+    message_number: u32,
+    key: [u8; 32],
+}
+
+impl Drop for SkippedKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+/// Double Ratchet state for one peer
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct DoubleRatchet {
+    own_key: KeyPair,
+    remote_key: Option<[u8; 32]>,
+    root_key: [u8; 32],
+    send_chain: Option<[u8; 32]>,
+    recv_chain: Option<[u8; 32]>,
+    send_count: u32,
+    recv_count: u32,
+    previous_send_count: u32,
+    skipped: Vec<SkippedKey>,
+    associated_data: Vec<u8>,
+}
+
+impl DoubleRatchet {
+    /// State for the X3DH initiator, who may send immediately
+    fn initiator(agreement: &Agreement, remote_key: [u8; 32]) -> Result<Self> {
+        let own_key = KeyPair::generate();
+        let (root_key, send_chain) = kdf_root(&agreement.shared_secret, &own_key.dh(&remote_key)?);
+        Ok(Self {
+            own_key,
+            remote_key: Some(remote_key),
+            root_key,
+            send_chain: Some(send_chain),
+            recv_chain: None,
+            send_count: 0,
+            recv_count: 0,
+            previous_send_count: 0,
+            skipped: Vec::new(),
+            associated_data: agreement.associated_data.clone(),
+        })
+    }
+
+    /// State for the X3DH responder, who must receive first
+    fn responder(agreement: &Agreement, own_key: KeyPair) -> Self {
+        Self {
+            own_key,
+            remote_key: None,
+            root_key: agreement.shared_secret,
+            send_chain: None,
+            recv_chain: None,
+            send_count: 0,
+            recv_count: 0,
+            previous_send_count: 0,
+            skipped: Vec::new(),
+            associated_data: agreement.associated_data.clone(),
+        }
+    }
+
+    fn encrypt(&mut self, plaintext: &[u8], context: &[u8]) -> Result<(RatchetHeader, Vec<u8>)> {
+        let chain = self.send_chain.as_mut()
+            .ok_or_else(|| IronError::Protocol("Cannot send before the peer has replied".to_string()))?;
+        let message_key = kdf_chain(chain);
+        let header = RatchetHeader {
+            ratchet_key: self.own_key.public(),
+            previous_chain_length: self.previous_send_count,
+            message_number: self.send_count,
+        };
+        self.send_count += 1;
+        let ciphertext = seal(&message_key, &self.aad(&header, context), plaintext)?;
+        Ok((header, ciphertext))
+    }
+
+    /// Decrypt a message; the state only changes if it authenticates
+    fn decrypt(&mut self, header: &RatchetHeader, ciphertext: &[u8], context: &[u8]) -> Result<Vec<u8>> {
+        let mut next = self.clone();
+        let plaintext = next.decrypt_in_place(header, ciphertext, context)?;
+        *self = next;
+        Ok(plaintext)
+    }
+
+    fn decrypt_in_place(&mut self, header: &RatchetHeader, ciphertext: &[u8], context: &[u8]) -> Result<Vec<u8>> {
+        let aad = self.aad(header, context);
+        if let Some(index) = self.skipped.iter().position(|s| {
+            s.ratchet_key == header.ratchet_key && s.message_number == header.message_number
+        }) {
+            let skipped = self.skipped.remove(index);
+            return open(&skipped.key, &aad, ciphertext);
+        }
+
+        if self.remote_key != Some(header.ratchet_key) {
+            self.skip_until(header.previous_chain_length)?;
+            self.step(header.ratchet_key)?;
+        }
+        self.skip_until(header.message_number)?;
+        let chain = self.recv_chain.as_mut().expect("set by step");
+        let message_key = kdf_chain(chain);
+        self.recv_count += 1;
+        open(&message_key, &aad, ciphertext)
+    }
+
+    fn skip_until(&mut self, until: u32) -> Result<()> {
+        let (Some(chain), Some(remote_key)) = (self.recv_chain.as_mut(), self.remote_key) else {
+            return Ok(());
+        };
+        if until > self.recv_count.saturating_add(MAX_SKIP) {
+            return Err(IronError::Protocol(format!("Too many skipped messages ({})", until - self.recv_count)));
+        }
+        while self.recv_count < until {
+            self.skipped.push(SkippedKey {
+                ratchet_key: remote_key,
+                message_number: self.recv_count,
+                key: kdf_chain(chain),
+            });
+            self.recv_count += 1;
+        }
+        if self.skipped.len() > MAX_SKIPPED_KEYS {
+            self.skipped.drain(..self.skipped.len() - MAX_SKIPPED_KEYS);
+        }
+        Ok(())
+    }
+
+    /// Perform a DH ratchet step on a new remote key
+    fn step(&mut self, remote_key: [u8; 32]) -> Result<()> {
+        self.previous_send_count = self.send_count;
+        self.send_count = 0;
+        self.recv_count = 0;
+        self.remote_key = Some(remote_key);
+
+        let (root_key, recv_chain) = kdf_root(&self.root_key, &self.own_key.dh(&remote_key)?);
+        self.own_key = KeyPair::generate();
+        let (root_key, send_chain) = kdf_root(&root_key, &self.own_key.dh(&remote_key)?);
+        self.root_key = root_key;
+        self.recv_chain = Some(recv_chain);
+        self.send_chain = Some(send_chain);
+        Ok(())
+    }
+
+    fn aad(&self, header: &RatchetHeader, context: &[u8]) -> Vec<u8> {
+        let mut aad = self.associated_data.clone();
+        aad.extend_from_slice(&header.to_bytes());
+        aad.extend_from_slice(context);
+        aad
+    }
+}
+
+impl Drop for DoubleRatchet {
+    fn drop(&mut self) {
+        self.root_key.zeroize();
+        if let Some(chain) = self.send_chain.as_mut() {
+            chain.zeroize();
+        }
+        if let Some(chain) = self.recv_chain.as_mut() {
+            chain.zeroize();
+        }
+    }
+}
+
+/// An encrypted direct-message session with one peer
+///
+/// Serializes with serde so sessions survive restarts; the serialized form
+/// contains private keys and must be stored accordingly.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DirectSession {
+    peer: String,
+    remote_identity: IdentityKey,
+    ratchet: DoubleRatchet,
+    pending_prekey: Option<PrekeyMessage>,
+}
+
+impl fmt::Debug for DirectSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DirectSession")
+            .field("peer", &self.peer)
+            .field("remote_identity", &self.remote_identity)
+            .field("pending_prekey", &self.pending_prekey.is_some())
+            .finish()
+    }
+}
+
+impl DirectSession {
+    /// Start a session with `peer` from their published bundle
+    pub fn initiate(identity: &IdentityKeyPair, peer: impl Into<String>, bundle: &PrekeyBundle) -> Result<Self> {
+        let (agreement, prekey) = x3dh::initiate(identity, bundle)?;
+        Ok(Self {
+            peer: peer.into(),
+            remote_identity: bundle.identity,
+            ratchet: DoubleRatchet::initiator(&agreement, bundle.signed_prekey)?,
+            pending_prekey: Some(prekey),
+        })
+    }
+
+    /// Check if a message starts a new session
+    pub fn is_prekey_message(message: &IrcMessage) -> bool {
+        message.has_tag(TAG_PREKEY)
+    }
+
+    /// Accept a session from the first message a peer sent
+    ///
+    /// Returns the session and the decrypted message. The one-time prekey
+    /// is only consumed if the message authenticates.
+    pub fn accept(store: &mut PrekeyStore, message: &IrcMessage) -> Result<(Self, IrcMessage)> {
+        let prekey = PrekeyMessage::from_bytes(&decode_tag(message, TAG_PREKEY)?)?;
+        let peer = message.prefix.as_deref()
+            .and_then(|prefix| prefix.split('!').next())
+            .filter(|nick| !nick.is_empty())
+            .ok_or_else(|| IronError::InvalidInput("Message has no sender".to_string()))?;
+
+        let (agreement, own_key) = store.agree(&prekey)?;
+        let mut session = Self {
+            peer: peer.to_string(),
+            remote_identity: prekey.identity,
+            ratchet: DoubleRatchet::responder(&agreement, own_key),
+            pending_prekey: None,
+        };
+        let decrypted = session.decrypt_message(message)?;
+        store.consume(prekey.one_time_prekey_id);
+        Ok((session, decrypted))
+    }
+
+    /// The peer's current nick
+    pub fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    /// Follow the peer's nick change
+    pub fn set_peer(&mut self, nick: impl Into<String>) {
+        self.peer = nick.into();
+    }
+
+    /// The peer's identity key, for out-of-band verification
+    pub fn remote_identity(&self) -> &IdentityKey {
+        &self.remote_identity
+    }
+
+    /// Check if the peer has not yet replied to this session
+    pub fn is_pending(&self) -> bool {
+        self.pending_prekey.is_some()
+    }
+
+    /// Encrypt an outbound PRIVMSG or NOTICE
+    pub fn encrypt_message(&mut self, message: &IrcMessage) -> Result<IrcMessage> {
+        if !matches!(message.command.as_str(), "PRIVMSG" | "NOTICE") {
+            return Err(IronError::NotSupported(format!("Cannot encrypt {}", message.command)));
+        }
+        let text = message.params.get(1)
+            .ok_or_else(|| IronError::InvalidInput("Message has no text".to_string()))?;
+        let (header, ciphertext) = self.ratchet.encrypt(text.as_bytes(), message.command.as_bytes())?;
+
+        let mut out = message.clone();
+        out.params[1] = BASE64.encode(ciphertext);
+        out = out
+            .with_tag(TAG_VERSION, Some(WIRE_VERSION.to_string()))
+            .with_tag(TAG_RATCHET, Some(BASE64.encode(header.to_bytes())));
+        if let Some(prekey) = &self.pending_prekey {
+            out = out.with_tag(TAG_PREKEY, Some(BASE64.encode(prekey.to_bytes())));
+        }
+        Ok(out)
+    }
+
+    /// Decrypt an inbound message from the peer
+    ///
+    /// A message that fails to authenticate leaves the session unchanged.
+    pub fn decrypt_message(&mut self, message: &IrcMessage) -> Result<IrcMessage> {
+        match message.get_tag(TAG_VERSION).and_then(|v| v.as_deref()) {
+            Some(WIRE_VERSION) => {}
+            Some(version) => return Err(IronError::NotSupported(format!("Unsupported e2e version {}", version))),
+            None => return Err(IronError::Parse(format!("Missing {} tag", TAG_VERSION))),
+        }
+        let header = RatchetHeader::from_bytes(&decode_tag(message, TAG_RATCHET)?)?;
+        let text = message.params.get(1)
+            .ok_or_else(|| IronError::Parse("Missing ciphertext".to_string()))?;
+        let ciphertext = BASE64.decode(text)
+            .map_err(|_| IronError::Parse("Invalid ciphertext encoding".to_string()))?;
+
+        let plaintext = self.ratchet.decrypt(&header, &ciphertext, message.command.as_bytes())?;
+        let plaintext = String::from_utf8(plaintext)
+            .map_err(|_| IronError::SecurityViolation("Decrypted text is not UTF-8".to_string()))?;
+        self.pending_prekey = None;
+
+        let mut out = message.clone();
+        out.tags.retain(|key, _| !DIRECT_TAGS.contains(&key.as_str()));
+        out.params[1] = plaintext;
+        Ok(out)
+    }
+
+    /// Serialize the session for storage
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self)
+            .map_err(|e| IronError::Internal(format!("Failed to serialize session: {}", e)))
+    }
+
+    /// Restore a stored session
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| IronError::Parse(format!("Invalid stored session: {}", e)))
+    }
+}
+
+fn decode_tag(message: &IrcMessage, key: &str) -> Result<Vec<u8>> {
+    let value = message.get_tag(key)
+        .and_then(|v| v.as_deref())
+        .ok_or_else(|| IronError::Parse(format!("Missing {} tag", key)))?;
+    BASE64.decode(value).map_err(|_| IronError::Parse(format!("Invalid {} encoding", key)))
+}
+
+/// Mix a DH output into the root key, returning the new root and chain keys
+fn kdf_root(root_key: &[u8; 32], dh: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let mut out = [0u8; 64];
+    Hkdf::<Sha256>::new(Some(root_key), dh)
+        .expand(ROOT_INFO, &mut out)
+        .expect("64 bytes is a valid HKDF output length");
+    let keys = (out[..32].try_into().expect("32 bytes"), out[32..].try_into().expect("32 bytes"));
+    out.zeroize();
+    keys
+}
+
+/// Advance a chain key, returning the message key
+pub(crate) fn kdf_chain(chain: &mut [u8; 32]) -> [u8; 32] {
+    let step = |byte: u8| -> [u8; 32] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(chain.as_slice())
+            .expect("HMAC accepts keys of any length");
+        mac.update(&[byte]);
+        mac.finalize().into_bytes().into()
+    };
+    let message_key = step(0x01);
+    *chain = step(0x02);
+    message_key
+}
+
+fn message_cipher(message_key: &[u8; 32]) -> (XChaCha20Poly1305, [u8; 24]) {
+    let mut out = [0u8; 56];
+    Hkdf::<Sha256>::new(Some(&[0u8; 32]), message_key)
+        .expand(MESSAGE_INFO, &mut out)
+        .expect("56 bytes is a valid HKDF output length");
+    let cipher = XChaCha20Poly1305::new_from_slice(&out[..32]).expect("32-byte key");
+    let nonce = out[32..].try_into().expect("24 bytes");
+    out.zeroize();
+    (cipher, nonce)
+}
+
+pub(crate) fn seal(message_key: &[u8; 32], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let (cipher, nonce) = message_cipher(message_key);
+    cipher.encrypt(XNonce::from_slice(&nonce), Payload { msg: plaintext, aad })
+        .map_err(|_| IronError::Internal("Encryption failed".to_string()))
+}
+
+pub(crate) fn open(message_key: &[u8; 32], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    let (cipher, nonce) = message_cipher(message_key);
+    cipher.decrypt(XNonce::from_slice(&nonce), Payload { msg: ciphertext, aad })
+        .map_err(|_| IronError::SecurityViolation("Message failed authentication".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn privmsg(from: &str, to: &str, text: &str) -> IrcMessage {
+        IrcMessage::new("PRIVMSG")
+            .with_prefix(format!("{}!{}@host", from, from))
+            .with_params(vec![to.to_string(), text.to_string()])
+    }
+
+    fn pair() -> (DirectSession, DirectSession, PrekeyStore) {
+        let mut store = PrekeyStore::new(IdentityKeyPair::generate());
+        let ids = store.generate_one_time_prekeys(1);
+        let bundle = store.one_time_bundle(ids[0]).unwrap();
+        let mut alice = DirectSession::initiate(&IdentityKeyPair::generate(), "bob", &bundle).unwrap();
+        let first = alice.encrypt_message(&privmsg("alice", "bob", "hi bob")).unwrap();
+        assert!(DirectSession::is_prekey_message(&first));
+        let (bob, decrypted) = DirectSession::accept(&mut store, &first).unwrap();
+        assert_eq!(decrypted.params[1], "hi bob");
+        assert!(decrypted.tags.is_empty());
+        (alice, bob, store)
+    }
+
+    #[test]
+    fn test_conversation() {
+        let (mut alice, mut bob, store) = pair();
+        assert_eq!(bob.peer(), "alice");
+        assert_eq!(store.one_time_prekey_count(), 0);
+        assert!(alice.is_pending());
+
+        let reply = bob.encrypt_message(&privmsg("bob", "alice", "hi alice")).unwrap();
+        assert!(!DirectSession::is_prekey_message(&reply));
+        assert_ne!(reply.params[1], "hi alice");
+        assert_eq!(alice.decrypt_message(&reply).unwrap().params[1], "hi alice");
+        assert!(!alice.is_pending());
+
+        for i in 0..3 {
+            let msg = alice.encrypt_message(&privmsg("alice", "bob", &format!("msg {}", i))).unwrap();
+            assert!(!DirectSession::is_prekey_message(&msg));
+            assert_eq!(bob.decrypt_message(&msg).unwrap().params[1], format!("msg {}", i));
+        }
+    }
+
+    #[test]
+    fn test_out_of_order_and_replay() {
+        let (mut alice, mut bob, _) = pair();
+        let msgs: Vec<_> = (0..4)
+            .map(|i| alice.encrypt_message(&privmsg("alice", "bob", &i.to_string())).unwrap())
+            .collect();
+
+        for i in [3, 0, 2, 1] {
+            assert_eq!(bob.decrypt_message(&msgs[i]).unwrap().params[1], i.to_string());
+        }
+        assert!(bob.decrypt_message(&msgs[2]).is_err());
+
+        // Skipped keys from an earlier chain survive a ratchet step
+        let reply = bob.encrypt_message(&privmsg("bob", "alice", "r")).unwrap();
+        let late = alice.encrypt_message(&privmsg("alice", "bob", "late")).unwrap();
+        alice.decrypt_message(&reply).unwrap();
+        let next = alice.encrypt_message(&privmsg("alice", "bob", "next")).unwrap();
+        assert_eq!(bob.decrypt_message(&next).unwrap().params[1], "next");
+        assert_eq!(bob.decrypt_message(&late).unwrap().params[1], "late");
+    }
+
+    #[test]
+    fn test_tampering_leaves_state_unchanged() {
+        let (mut alice, mut bob, _) = pair();
+        let msg = alice.encrypt_message(&privmsg("alice", "bob", "secret")).unwrap();
+
+        let mut tampered = msg.clone();
+        tampered.command = "NOTICE".to_string();
+        assert!(matches!(bob.decrypt_message(&tampered), Err(IronError::SecurityViolation(_))));
+
+        let mut header = msg.clone();
+        let mut bytes = RatchetHeader::from_bytes(&decode_tag(&msg, TAG_RATCHET).unwrap()).unwrap();
+        bytes.message_number = MAX_SKIP + 5;
+        header.tags.insert(TAG_RATCHET.to_string(), Some(BASE64.encode(bytes.to_bytes())));
+        assert!(bob.decrypt_message(&header).is_err());
+
+        assert_eq!(bob.decrypt_message(&msg).unwrap().params[1], "secret");
+
+        // A tampered first message does not consume the one-time prekey
+        let mut store = PrekeyStore::new(IdentityKeyPair::generate());
+        let ids = store.generate_one_time_prekeys(1);
+        let bundle = store.one_time_bundle(ids[0]).unwrap();
+        let mut carol = DirectSession::initiate(&IdentityKeyPair::generate(), "dave", &bundle).unwrap();
+        let mut first = carol.encrypt_message(&privmsg("carol", "dave", "hello")).unwrap();
+        first.params[1] = BASE64.encode([0u8; 32]);
+        assert!(DirectSession::accept(&mut store, &first).is_err());
+        assert_eq!(store.one_time_prekey_count(), 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_session_serialization() {
+        let (mut alice, bob, store) = pair();
+        let mut bob = DirectSession::from_json(&bob.to_json().unwrap()).unwrap();
+        let store: PrekeyStore = serde_json::from_str(&serde_json::to_string(&store).unwrap()).unwrap();
+        assert!(store.bundle().verify().is_ok());
+
+        let reply = bob.encrypt_message(&privmsg("bob", "alice", "restored")).unwrap();
+        assert_eq!(alice.decrypt_message(&reply).unwrap().params[1], "restored");
+        let mut alice = DirectSession::from_json(&alice.to_json().unwrap()).unwrap();
+        let msg = alice.encrypt_message(&privmsg("alice", "bob", "again")).unwrap();
+        assert_eq!(bob.decrypt_message(&msg).unwrap().params[1], "again");
+        assert!(DirectSession::from_json("{}").is_err());
+    }
+}
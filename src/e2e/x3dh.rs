@@ -0,0 +1,579 @@
+//! X3DH-style key agreement for encrypted direct messages
+//!
+//! Each user publishes a [`PrekeyBundle`] with an X25519 identity key and a
+//! signed prekey in their `legion/prekeys` METADATA key. Any number of
+//! senders can use the published bundle; one-time prekeys are only offered
+//! in bundles handed to a single peer. A sender verifies the Ed25519
+//! signature and derives a shared secret; the first messages carry a
+//! [`PrekeyMessage`] so the recipient can derive the same secret from their
+//! [`PrekeyStore`].
+//!
+//! ```text
+//! DH1 = DH(IK_A, SPK_B)  DH2 = DH(EK_A, IK_B)  DH3 = DH(EK_A, SPK_B)  [DH4 = DH(EK_A, OPK_B)]
+//! SK  = HKDF-SHA256(salt = 0^32, ikm = 0xFF^32 || DH1 || DH2 || DH3 [|| DH4], info = "LegionX3DH")
+//! AD  = IK_A || SIG_A || IK_B || SIG_B
+//! ```
+
+use crate::error::{IronError, Result};
+use crate::message::IrcMessage;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::Sha256;
+use std::fmt;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroize;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// METADATA key holding a user's published prekey bundle
+pub const PREKEY_METADATA_KEY: &str = "legion/prekeys";
+
+/// Binary format version of bundles and prekey messages
+const FORMAT_VERSION: u8 = 1;
+/// Domain separator for signed prekey signatures
+const SIGNATURE_LABEL: &[u8] = b"legion-spk-v1";
+/// HKDF info for the X3DH shared secret
+const X3DH_INFO: &[u8] = b"LegionX3DH";
+
+/// An X25519 key pair
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub(crate) struct KeyPair {
+    secret: [u8; 32],
+    public: [u8; 32],
+}
+
+impl KeyPair {
+    pub(crate) fn generate() -> Self {
+        let mut secret = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut secret);
+        let public = PublicKey::from(&StaticSecret::from(secret)).to_bytes();
+        Self { secret, public }
+    }
+
+    pub(crate) fn public(&self) -> [u8; 32] {
+        self.public
+    }
+
+    /// X25519 with `public`, rejecting low-order points
+    pub(crate) fn dh(&self, public: &[u8; 32]) -> Result<[u8; 32]> {
+        let shared = StaticSecret::from(self.secret).diffie_hellman(&PublicKey::from(*public));
+        if !shared.was_contributory() {
+            return Err(IronError::SecurityViolation("Low-order X25519 public key".to_string()));
+        }
+        Ok(shared.to_bytes())
+    }
+}
+
+impl Drop for KeyPair {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+impl fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyPair")
+            .field("public", &BASE64.encode(self.public))
+            .field("secret", &format_args!("[REDACTED]"))
+            .finish()
+    }
+}
+
+/// The public half of a user's identity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IdentityKey {
+    /// X25519 key used in the agreement
+    pub dh: [u8; 32],
+    /// Ed25519 key that signs prekeys
+    pub signing: [u8; 32],
+}
+
+impl IdentityKey {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.dh);
+        out.extend_from_slice(&self.signing);
+    }
+}
+
+/// A user's long-term identity key pair
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IdentityKeyPair {
+    dh: KeyPair,
+    signing: [u8; 32],
+}
+
+impl IdentityKeyPair {
+    /// Generate a new identity
+    pub fn generate() -> Self {
+        let mut signing = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut signing);
+        Self { dh: KeyPair::generate(), signing }
+    }
+
+    /// The public identity
+    pub fn public(&self) -> IdentityKey {
+        IdentityKey {
+            dh: self.dh.public(),
+            signing: SigningKey::from_bytes(&self.signing).verifying_key().to_bytes(),
+        }
+    }
+
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        SigningKey::from_bytes(&self.signing).sign(data).to_bytes().to_vec()
+    }
+}
+
+impl Drop for IdentityKeyPair {
+    fn drop(&mut self) {
+        self.signing.zeroize();
+    }
+}
+
+impl fmt::Debug for IdentityKeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdentityKeyPair")
+            .field("public", &self.public())
+            .field("secret", &format_args!("[REDACTED]"))
+            .finish()
+    }
+}
+
+/// Published keys that let others start a session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrekeyBundle {
+    /// The owner's identity
+    pub identity: IdentityKey,
+    /// Id of the signed prekey
+    pub signed_prekey_id: u32,
+    /// Medium-term X25519 prekey
+    pub signed_prekey: [u8; 32],
+    /// Ed25519 signature over the signed prekey
+    pub signature: Vec<u8>,
+    /// Optional one-time prekey and its id
+    pub one_time_prekey: Option<(u32, [u8; 32])>,
+}
+
+impl PrekeyBundle {
+    /// Check the signed prekey signature
+    pub fn verify(&self) -> Result<()> {
+        let key = VerifyingKey::from_bytes(&self.identity.signing)
+            .map_err(|_| IronError::SecurityViolation("Invalid identity signing key".to_string()))?;
+        let signature = Signature::from_slice(&self.signature)
+            .map_err(|_| IronError::SecurityViolation("Malformed prekey signature".to_string()))?;
+        let data = signed_data(&self.identity, self.signed_prekey_id, &self.signed_prekey);
+        key.verify(&data, &signature)
+            .map_err(|_| IronError::SecurityViolation("Prekey signature does not verify".to_string()))
+    }
+
+    /// Encode as bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![FORMAT_VERSION];
+        self.identity.write(&mut out);
+        out.extend_from_slice(&self.signed_prekey_id.to_be_bytes());
+        out.extend_from_slice(&self.signed_prekey);
+        out.extend_from_slice(&self.signature);
+        match &self.one_time_prekey {
+            Some((id, key)) => {
+                out.push(1);
+                out.extend_from_slice(&id.to_be_bytes());
+                out.extend_from_slice(key);
+            }
+            None => out.push(0),
+        }
+        out
+    }
+
+    /// Decode from bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes)?;
+        let identity = reader.identity()?;
+        let signed_prekey_id = reader.u32()?;
+        let signed_prekey = reader.key()?;
+        let signature = reader.take(64)?.to_vec();
+        let one_time_prekey = match reader.flag()? {
+            true => Some((reader.u32()?, reader.key()?)),
+            false => None,
+        };
+        reader.finish()?;
+        Ok(Self { identity, signed_prekey_id, signed_prekey, signature, one_time_prekey })
+    }
+
+    /// The base64 value stored under [`PREKEY_METADATA_KEY`]
+    pub fn to_metadata_value(&self) -> String {
+        BASE64.encode(self.to_bytes())
+    }
+
+    /// Parse and verify a bundle read from METADATA
+    pub fn from_metadata_value(value: &str) -> Result<Self> {
+        let bytes = BASE64.decode(value)
+            .map_err(|_| IronError::Parse("Invalid prekey bundle encoding".to_string()))?;
+        let bundle = Self::from_bytes(&bytes)?;
+        bundle.verify()?;
+        Ok(bundle)
+    }
+
+    /// The `METADATA * SET` message that publishes this bundle
+    pub fn to_metadata_message(&self) -> IrcMessage {
+        IrcMessage::new("METADATA").with_params(vec![
+            "*".to_string(),
+            "SET".to_string(),
+            PREKEY_METADATA_KEY.to_string(),
+            self.to_metadata_value(),
+        ])
+    }
+}
+
+/// Sent with the first messages of a session so the recipient can accept it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PrekeyMessage {
+    /// The initiator's identity
+    pub identity: IdentityKey,
+    /// The initiator's ephemeral key
+    pub ephemeral_key: [u8; 32],
+    /// Which signed prekey was used
+    pub signed_prekey_id: u32,
+    /// Which one-time prekey was used, if any
+    pub one_time_prekey_id: Option<u32>,
+}
+
+impl PrekeyMessage {
+    /// Encode as bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![FORMAT_VERSION];
+        self.identity.write(&mut out);
+        out.extend_from_slice(&self.ephemeral_key);
+        out.extend_from_slice(&self.signed_prekey_id.to_be_bytes());
+        match self.one_time_prekey_id {
+            Some(id) => {
+                out.push(1);
+                out.extend_from_slice(&id.to_be_bytes());
+            }
+            None => out.push(0),
+        }
+        out
+    }
+
+    /// Decode from bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes)?;
+        let identity = reader.identity()?;
+        let ephemeral_key = reader.key()?;
+        let signed_prekey_id = reader.u32()?;
+        let one_time_prekey_id = match reader.flag()? {
+            true => Some(reader.u32()?),
+            false => None,
+        };
+        reader.finish()?;
+        Ok(Self { identity, ephemeral_key, signed_prekey_id, one_time_prekey_id })
+    }
+}
+
+/// The local private half of published prekeys
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PrekeyStore {
+    identity: IdentityKeyPair,
+    signed_prekey_id: u32,
+    signed_prekey: KeyPair,
+    signature: Vec<u8>,
+    previous_signed_prekey: Option<(u32, KeyPair)>,
+    one_time_prekeys: Vec<(u32, KeyPair)>,
+    next_prekey_id: u32,
+}
+
+impl PrekeyStore {
+    /// Create a store with a fresh signed prekey
+    pub fn new(identity: IdentityKeyPair) -> Self {
+        let signed_prekey = KeyPair::generate();
+        let signature = identity.sign(&signed_data(&identity.public(), 1, &signed_prekey.public()));
+        Self {
+            identity,
+            signed_prekey_id: 1,
+            signed_prekey,
+            signature,
+            previous_signed_prekey: None,
+            one_time_prekeys: Vec::new(),
+            next_prekey_id: 1,
+        }
+    }
+
+    /// The identity this store belongs to
+    pub fn identity(&self) -> &IdentityKeyPair {
+        &self.identity
+    }
+
+    /// Generate `count` one-time prekeys, returning their ids
+    pub fn generate_one_time_prekeys(&mut self, count: usize) -> Vec<u32> {
+        (0..count)
+            .map(|_| {
+                let id = self.next_prekey_id;
+                self.next_prekey_id = self.next_prekey_id.wrapping_add(1);
+                self.one_time_prekeys.push((id, KeyPair::generate()));
+                id
+            })
+            .collect()
+    }
+
+    /// Number of unused one-time prekeys
+    pub fn one_time_prekey_count(&self) -> usize {
+        self.one_time_prekeys.len()
+    }
+
+    /// Replace the signed prekey, keeping the previous one for late initiators
+    pub fn rotate_signed_prekey(&mut self) {
+        let id = self.signed_prekey_id.wrapping_add(1);
+        let prekey = KeyPair::generate();
+        self.signature = self.identity.sign(&signed_data(&self.identity.public(), id, &prekey.public()));
+        let previous = std::mem::replace(&mut self.signed_prekey, prekey);
+        self.previous_signed_prekey = Some((self.signed_prekey_id, previous));
+        self.signed_prekey_id = id;
+    }
+
+    /// The bundle to publish in METADATA
+    ///
+    /// It carries no one-time prekey, so concurrent initiators cannot spend
+    /// the same one. Republish it after [`rotate_signed_prekey`](Self::rotate_signed_prekey).
+    pub fn bundle(&self) -> PrekeyBundle {
+        PrekeyBundle {
+            identity: self.identity.public(),
+            signed_prekey_id: self.signed_prekey_id,
+            signed_prekey: self.signed_prekey.public(),
+            signature: self.signature.clone(),
+            one_time_prekey: None,
+        }
+    }
+
+    /// A bundle offering one-time prekey `id`, to hand to a single peer
+    ///
+    /// Returns `None` once the prekey has been used. Each id must only be
+    /// given out once; a second initiator using it will be rejected.
+    pub fn one_time_bundle(&self, id: u32) -> Option<PrekeyBundle> {
+        let (_, key) = self.one_time_prekeys.iter().find(|(known, _)| *known == id)?;
+        Some(PrekeyBundle { one_time_prekey: Some((id, key.public())), ..self.bundle() })
+    }
+
+    /// Derive the agreement for an incoming prekey message without consuming anything
+    pub(crate) fn agree(&self, message: &PrekeyMessage) -> Result<(Agreement, KeyPair)> {
+        let signed_prekey = if message.signed_prekey_id == self.signed_prekey_id {
+            &self.signed_prekey
+        } else {
+            match &self.previous_signed_prekey {
+                Some((id, key)) if *id == message.signed_prekey_id => key,
+                _ => return Err(IronError::Protocol(format!(
+                    "Unknown signed prekey {}", message.signed_prekey_id
+                ))),
+            }
+        };
+        let one_time = match message.one_time_prekey_id {
+            Some(id) => Some(
+                self.one_time_prekeys.iter()
+                    .find(|(known, _)| *known == id)
+                    .map(|(_, key)| key)
+                    .ok_or_else(|| IronError::Protocol(format!("One-time prekey {} already used", id)))?,
+            ),
+            None => None,
+        };
+
+        let mut dhs = vec![
+            signed_prekey.dh(&message.identity.dh)?,
+            self.identity.dh.dh(&message.ephemeral_key)?,
+            signed_prekey.dh(&message.ephemeral_key)?,
+        ];
+        if let Some(key) = one_time {
+            dhs.push(key.dh(&message.ephemeral_key)?);
+        }
+        let agreement = Agreement::derive(&dhs, &message.identity, &self.identity.public());
+        dhs.zeroize();
+        Ok((agreement, signed_prekey.clone()))
+    }
+
+    /// Delete a one-time prekey once a session using it is established
+    pub(crate) fn consume(&mut self, id: Option<u32>) {
+        if let Some(id) = id {
+            self.one_time_prekeys.retain(|(known, _)| *known != id);
+        }
+    }
+}
+
+/// The output of X3DH
+pub(crate) struct Agreement {
+    pub(crate) shared_secret: [u8; 32],
+    pub(crate) associated_data: Vec<u8>,
+}
+
+impl Agreement {
+    fn derive(dhs: &[[u8; 32]], initiator: &IdentityKey, responder: &IdentityKey) -> Self {
+        let mut ikm = vec![0xFF; 32];
+        for dh in dhs {
+            ikm.extend_from_slice(dh);
+        }
+        let mut shared_secret = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(&[0u8; 32]), &ikm)
+            .expand(X3DH_INFO, &mut shared_secret)
+            .expect("32 bytes is a valid HKDF output length");
+        ikm.zeroize();
+
+        let mut associated_data = Vec::with_capacity(128);
+        initiator.write(&mut associated_data);
+        responder.write(&mut associated_data);
+        Self { shared_secret, associated_data }
+    }
+}
+
+impl Drop for Agreement {
+    fn drop(&mut self) {
+        self.shared_secret.zeroize();
+    }
+}
+
+/// Start a session with the owner of `bundle`
+pub(crate) fn initiate(identity: &IdentityKeyPair, bundle: &PrekeyBundle) -> Result<(Agreement, PrekeyMessage)> {
+    bundle.verify()?;
+    let ephemeral = KeyPair::generate();
+    let mut dhs = vec![
+        identity.dh.dh(&bundle.signed_prekey)?,
+        ephemeral.dh(&bundle.identity.dh)?,
+        ephemeral.dh(&bundle.signed_prekey)?,
+    ];
+    if let Some((_, key)) = &bundle.one_time_prekey {
+        dhs.push(ephemeral.dh(key)?);
+    }
+    let agreement = Agreement::derive(&dhs, &identity.public(), &bundle.identity);
+    dhs.zeroize();
+
+    let message = PrekeyMessage {
+        identity: identity.public(),
+        ephemeral_key: ephemeral.public(),
+        signed_prekey_id: bundle.signed_prekey_id,
+        one_time_prekey_id: bundle.one_time_prekey.map(|(id, _)| id),
+    };
+    Ok((agreement, message))
+}
+
+fn signed_data(identity: &IdentityKey, id: u32, prekey: &[u8; 32]) -> Vec<u8> {
+    let mut data = SIGNATURE_LABEL.to_vec();
+    data.extend_from_slice(&identity.dh);
+    data.extend_from_slice(&id.to_be_bytes());
+    data.extend_from_slice(prekey);
+    data
+}
+
+/// Bounds-checked reader for the binary formats
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Result<Self> {
+        match bytes.split_first() {
+            Some((&FORMAT_VERSION, rest)) => Ok(Self { bytes: rest }),
+            Some((version, _)) => Err(IronError::NotSupported(format!("Unsupported prekey format {}", version))),
+            None => Err(IronError::Parse("Empty prekey data".to_string())),
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(IronError::Parse("Truncated prekey data".to_string()));
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn key(&mut self) -> Result<[u8; 32]> {
+        Ok(self.take(32)?.try_into().expect("length checked"))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().expect("length checked")))
+    }
+
+    fn flag(&mut self) -> Result<bool> {
+        match self.take(1)?[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(IronError::Parse("Invalid prekey flag".to_string())),
+        }
+    }
+
+    fn identity(&mut self) -> Result<IdentityKey> {
+        Ok(IdentityKey { dh: self.key()?, signing: self.key()? })
+    }
+
+    fn finish(self) -> Result<()> {
+        if !self.bytes.is_empty() {
+            return Err(IronError::Parse("Trailing prekey data".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agreement_matches() {
+        let alice = IdentityKeyPair::generate();
+        let mut store = PrekeyStore::new(IdentityKeyPair::generate());
+        let ids = store.generate_one_time_prekeys(2);
+        assert_eq!(store.bundle().one_time_prekey, None);
+
+        let bundle = store.one_time_bundle(ids[0]).unwrap();
+        let bundle = PrekeyBundle::from_metadata_value(&bundle.to_metadata_value()).unwrap();
+        let (sent, message) = initiate(&alice, &bundle).unwrap();
+        let message = PrekeyMessage::from_bytes(&message.to_bytes()).unwrap();
+        let (received, _) = store.agree(&message).unwrap();
+        assert_eq!(sent.shared_secret, received.shared_secret);
+        assert_eq!(sent.associated_data, received.associated_data);
+
+        // One-time prekeys are single use
+        store.consume(message.one_time_prekey_id);
+        assert_eq!(store.one_time_prekey_count(), 1);
+        assert!(store.agree(&message).is_err());
+        assert!(store.one_time_bundle(ids[0]).is_none());
+
+        // The published bundle serves any number of initiators
+        for _ in 0..2 {
+            let (sent, message) = initiate(&IdentityKeyPair::generate(), &store.bundle()).unwrap();
+            assert_eq!(store.agree(&message).unwrap().0.shared_secret, sent.shared_secret);
+        }
+    }
+
+    #[test]
+    fn test_bundle_signature_is_checked() {
+        let store = PrekeyStore::new(IdentityKeyPair::generate());
+        let mut bundle = store.bundle();
+        bundle.signed_prekey[0] ^= 1;
+        assert!(matches!(bundle.verify(), Err(IronError::SecurityViolation(_))));
+        assert!(PrekeyBundle::from_metadata_value(&bundle.to_metadata_value()).is_err());
+        assert!(initiate(&IdentityKeyPair::generate(), &bundle).is_err());
+
+        let bytes = store.bundle().to_bytes();
+        assert!(PrekeyBundle::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert_eq!(store.bundle().to_metadata_message().params[2], PREKEY_METADATA_KEY);
+    }
+
+    #[test]
+    fn test_rotated_prekey_still_accepted() {
+        let alice = IdentityKeyPair::generate();
+        let mut store = PrekeyStore::new(IdentityKeyPair::generate());
+        let old_bundle = store.bundle();
+        store.rotate_signed_prekey();
+        assert!(store.bundle().verify().is_ok());
+        assert_ne!(store.bundle().signed_prekey_id, old_bundle.signed_prekey_id);
+
+        let (sent, message) = initiate(&alice, &old_bundle).unwrap();
+        assert_eq!(store.agree(&message).unwrap().0.shared_secret, sent.shared_secret);
+        store.rotate_signed_prekey();
+        assert!(store.agree(&message).is_err());
+    }
+}
@@ -0,0 +1,401 @@
+//! mIRC-style text formatting
+//!
+//! IRC clients style text with in-band control bytes: `\x02` bold, `\x1D`
+//! italic, `\x1F` underline, `\x1E` strikethrough, `\x11` monospace, `\x16`
+//! reverse, `\x03` palette colors (`\x03fg[,bg]`, 0-98), `\x04` hex colors
+//! (`\x04RRGGBB[,RRGGBB]`) and `\x0F` to reset everything. [`parse`] turns
+//! such text into styled [`Span`]s which can be stripped or rendered to ANSI
+//! terminal output or HTML.
+
+use std::fmt::Write;
+
+/// Toggle bold
+pub const BOLD: char = '\x02';
+/// Set or reset palette colors
+pub const COLOR: char = '\x03';
+/// Set or reset hex colors
+pub const HEX_COLOR: char = '\x04';
+/// Reset all formatting
+pub const RESET: char = '\x0F';
+/// Toggle monospace
+pub const MONOSPACE: char = '\x11';
+/// Toggle reverse video
+pub const REVERSE: char = '\x16';
+/// Toggle italics
+pub const ITALIC: char = '\x1D';
+/// Toggle strikethrough
+pub const STRIKETHROUGH: char = '\x1E';
+/// Toggle underline
+pub const UNDERLINE: char = '\x1F';
+
+/// RGB values of the 99-color palette (codes 0-98)
+const PALETTE: [u32; 99] = [
+    0xffffff, 0x000000, 0x00007f, 0x009300, 0xff0000, 0x7f0000, 0x9c009c, 0xfc7f00,
+    0xffff00, 0x00fc00, 0x009393, 0x00ffff, 0x0000fc, 0xff00ff, 0x7f7f7f, 0xd2d2d2,
+    0x470000, 0x472100, 0x474700, 0x324700, 0x004700, 0x00472c, 0x004747, 0x002747,
+    0x000047, 0x2e0047, 0x470047, 0x47002a, 0x740000, 0x743a00, 0x747400, 0x517400,
+    0x007400, 0x007449, 0x007474, 0x004074, 0x000074, 0x4b0074, 0x740074, 0x740045,
+    0xb50000, 0xb56300, 0xb5b500, 0x7db500, 0x00b500, 0x00b571, 0x00b5b5, 0x0063b5,
+    0x0000b5, 0x7500b5, 0xb500b5, 0xb5006b, 0xff0000, 0xff8c00, 0xffff00, 0xb2ff00,
+    0x00ff00, 0x00ffa0, 0x00ffff, 0x008cff, 0x0000ff, 0xa500ff, 0xff00ff, 0xff0098,
+    0xff5959, 0xffb459, 0xffff71, 0xcfff60, 0x6fff6f, 0x65ffc9, 0x6dffff, 0x59b4ff,
+    0x5959ff, 0xc459ff, 0xff66ff, 0xff59bc, 0xff9c9c, 0xffd39c, 0xffff9c, 0xe2ff9c,
+    0x9cff9c, 0x9cffdb, 0x9cffff, 0x9cd3ff, 0x9c9cff, 0xdc9cff, 0xff9cff, 0xff94d3,
+    0x000000, 0x131313, 0x282828, 0x363636, 0x4d4d4d, 0x656565, 0x818181, 0x9f9f9f,
+    0xbcbcbc, 0xe2e2e2, 0xffffff,
+];
+
+/// Check if a character is an IRC formatting code
+pub fn is_formatting_char(c: char) -> bool {
+    matches!(c, BOLD | COLOR | HEX_COLOR | RESET | MONOSPACE | REVERSE | ITALIC | STRIKETHROUGH | UNDERLINE)
+}
+
+/// A foreground or background color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// A palette entry (0-98)
+    Palette(u8),
+    /// A 24-bit color from `\x04`
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// The RGB value of this color
+    pub fn rgb(&self) -> (u8, u8, u8) {
+        match *self {
+            Color::Palette(code) => {
+                let value = PALETTE[code.min(98) as usize];
+                ((value >> 16) as u8, (value >> 8) as u8, value as u8)
+            }
+            Color::Rgb(r, g, b) => (r, g, b),
+        }
+    }
+
+    /// The color as a `#rrggbb` string
+    pub fn hex(&self) -> String {
+        let (r, g, b) = self.rgb();
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+/// The formatting state applied to a run of text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Style {
+    /// Bold text
+    pub bold: bool,
+    /// Italic text
+    pub italic: bool,
+    /// Underlined text
+    pub underline: bool,
+    /// Struck-through text
+    pub strikethrough: bool,
+    /// Monospaced text
+    pub monospace: bool,
+    /// Foreground and background swapped
+    pub reverse: bool,
+    /// Foreground color
+    pub fg: Option<Color>,
+    /// Background color
+    pub bg: Option<Color>,
+}
+
+impl Style {
+    /// Check if no formatting is applied
+    pub fn is_plain(&self) -> bool {
+        *self == Style::default()
+    }
+}
+
+/// A run of text with a single style
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    /// The unformatted text
+    pub text: String,
+    /// The style applied to the text
+    pub style: Style,
+}
+
+/// Parse formatted text into styled spans
+///
+/// Adjacent text with the same style is merged and empty spans are dropped.
+pub fn parse(text: &str) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+    let mut style = Style::default();
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if !is_formatting_char(c) {
+            current.push(c);
+            continue;
+        }
+
+        let previous = style;
+        match c {
+            BOLD => style.bold = !style.bold,
+            ITALIC => style.italic = !style.italic,
+            UNDERLINE => style.underline = !style.underline,
+            STRIKETHROUGH => style.strikethrough = !style.strikethrough,
+            MONOSPACE => style.monospace = !style.monospace,
+            REVERSE => style.reverse = !style.reverse,
+            RESET => style = Style::default(),
+            COLOR => match take_digits(&mut chars) {
+                Some(fg) => {
+                    style.fg = palette(fg);
+                    if let Some(bg) = take_after_comma(&mut chars, take_digits) {
+                        style.bg = palette(bg);
+                    }
+                }
+                None => {
+                    style.fg = None;
+                    style.bg = None;
+                }
+            },
+            HEX_COLOR => match take_hex(&mut chars) {
+                Some(fg) => {
+                    style.fg = Some(fg);
+                    if let Some(bg) = take_after_comma(&mut chars, take_hex) {
+                        style.bg = Some(bg);
+                    }
+                }
+                None => {
+                    style.fg = None;
+                    style.bg = None;
+                }
+            },
+            _ => {}
+        }
+
+        if style != previous && !current.is_empty() {
+            push_span(&mut spans, std::mem::take(&mut current), previous);
+        }
+    }
+
+    if !current.is_empty() {
+        push_span(&mut spans, current, style);
+    }
+    spans
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn push_span(spans: &mut Vec<Span>, text: String, style: Style) {
+    match spans.last_mut() {
+        Some(last) if last.style == style => last.text.push_str(&text),
+        _ => spans.push(Span { text, style }),
+    }
+}
+
+/// Color code 99 means "default color"
+fn palette(code: u8) -> Option<Color> {
+    (code < 99).then_some(Color::Palette(code))
+}
+
+/// Read up to two decimal digits
+fn take_digits(chars: &mut Chars<'_>) -> Option<u8> {
+    let mut value = None;
+    for _ in 0..2 {
+        match chars.peek().and_then(|c| c.to_digit(10)) {
+            Some(digit) => {
+                value = Some(value.unwrap_or(0) * 10 + digit as u8);
+                chars.next();
+            }
+            None => break,
+        }
+    }
+    value
+}
+
+/// Read exactly six hex digits
+fn take_hex(chars: &mut Chars<'_>) -> Option<Color> {
+    let lookahead: String = chars.clone().take(6).collect();
+    if lookahead.len() != 6 || !lookahead.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let value = u32::from_str_radix(&lookahead, 16).ok()?;
+    chars.nth(5);
+    Some(Color::Rgb((value >> 16) as u8, (value >> 8) as u8, value as u8))
+}
+
+/// Read `,<value>`, leaving the comma in place if no value follows it
+fn take_after_comma<T>(chars: &mut Chars<'_>, take: fn(&mut Chars<'_>) -> Option<T>) -> Option<T> {
+    if chars.peek() != Some(&',') {
+        return None;
+    }
+    let mut lookahead = chars.clone();
+    lookahead.next();
+    let value = take(&mut lookahead)?;
+    *chars = lookahead;
+    Some(value)
+}
+
+/// Remove all formatting codes, keeping only the text
+pub fn strip_formatting(text: &str) -> String {
+    parse(text).into_iter().map(|span| span.text).collect()
+}
+
+/// Render formatted text with ANSI SGR escape sequences
+///
+/// Colors use 24-bit sequences; monospace has no terminal equivalent and is
+/// dropped. Other control characters, including ESC, are removed so the text
+/// cannot inject its own terminal sequences.
+pub fn to_ansi(text: &str) -> String {
+    let mut out = String::new();
+    let mut styled = false;
+
+    for span in parse(text) {
+        if styled {
+            out.push_str("\x1b[0m");
+        }
+        let style = span.style;
+        let mut codes: Vec<String> = Vec::new();
+        if style.bold { codes.push("1".into()); }
+        if style.italic { codes.push("3".into()); }
+        if style.underline { codes.push("4".into()); }
+        if style.reverse { codes.push("7".into()); }
+        if style.strikethrough { codes.push("9".into()); }
+        if let Some(fg) = style.fg {
+            let (r, g, b) = fg.rgb();
+            codes.push(format!("38;2;{};{};{}", r, g, b));
+        }
+        if let Some(bg) = style.bg {
+            let (r, g, b) = bg.rgb();
+            codes.push(format!("48;2;{};{};{}", r, g, b));
+        }
+
+        styled = !codes.is_empty();
+        if styled {
+            let _ = write!(out, "\x1b[{}m", codes.join(";"));
+        }
+        out.extend(span.text.chars().filter(|&c| c == '\t' || !c.is_control()));
+    }
+
+    if styled {
+        out.push_str("\x1b[0m");
+    }
+    out
+}
+
+/// Render formatted text as HTML, escaping the text content
+///
+/// Reverse video swaps the colors, assuming black on white when unset.
+pub fn to_html(text: &str) -> String {
+    let mut out = String::new();
+
+    for span in parse(text) {
+        let style = span.style;
+        if style.is_plain() {
+            escape_html(&span.text, &mut out);
+            continue;
+        }
+
+        let mut css: Vec<String> = Vec::new();
+        if style.bold { css.push("font-weight:bold".into()); }
+        if style.italic { css.push("font-style:italic".into()); }
+        match (style.underline, style.strikethrough) {
+            (true, true) => css.push("text-decoration:underline line-through".into()),
+            (true, false) => css.push("text-decoration:underline".into()),
+            (false, true) => css.push("text-decoration:line-through".into()),
+            (false, false) => {}
+        }
+        if style.monospace { css.push("font-family:monospace".into()); }
+
+        let (fg, bg) = if style.reverse {
+            (
+                Some(style.bg.unwrap_or(Color::Palette(0))),
+                Some(style.fg.unwrap_or(Color::Palette(1))),
+            )
+        } else {
+            (style.fg, style.bg)
+        };
+        if let Some(fg) = fg {
+            css.push(format!("color:{}", fg.hex()));
+        }
+        if let Some(bg) = bg {
+            css.push(format!("background-color:{}", bg.hex()));
+        }
+
+        let _ = write!(out, "<span style=\"{}\">", css.join(";"));
+        escape_html(&span.text, &mut out);
+        out.push_str("</span>");
+    }
+    out
+}
+
+fn escape_html(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_toggles_and_colors() {
+        let spans = parse("a\x02b\x034,12c\x0Fd");
+        assert_eq!(spans.len(), 4);
+        assert_eq!(spans[0], Span { text: "a".into(), style: Style::default() });
+        assert!(spans[1].style.bold);
+        assert_eq!(spans[2].style.fg, Some(Color::Palette(4)));
+        assert_eq!(spans[2].style.bg, Some(Color::Palette(12)));
+        assert!(spans[3].style.is_plain());
+
+        // A comma without digits after it is text, and 99 is the default color
+        let spans = parse("\x0399,x");
+        assert_eq!(spans[0].text, ",x");
+        assert_eq!(spans[0].style.fg, None);
+
+        // Toggling twice with no text in between yields nothing styled
+        assert_eq!(parse("\x02\x02plain"), vec![Span { text: "plain".into(), style: Style::default() }]);
+    }
+
+    #[test]
+    fn test_hex_colors() {
+        let spans = parse("\x04ff8000,000000hot\x04 cold");
+        assert_eq!(spans[0].style.fg, Some(Color::Rgb(0xff, 0x80, 0x00)));
+        assert_eq!(spans[0].style.bg, Some(Color::Rgb(0, 0, 0)));
+        assert_eq!(spans[1].text, " cold");
+        assert_eq!(spans[1].style.fg, None);
+        assert_eq!(strip_formatting("\x04zzz"), "zzz");
+    }
+
+    #[test]
+    fn test_strip_formatting() {
+        assert_eq!(strip_formatting("\x02\x1Dhi\x0F \x0312,01there\x03"), "hi there");
+        assert_eq!(strip_formatting("\x1E\x11\x16\x1Fplain"), "plain");
+        assert_eq!(strip_formatting("no codes"), "no codes");
+    }
+
+    #[test]
+    fn test_render_ansi() {
+        assert_eq!(to_ansi("plain"), "plain");
+        assert_eq!(to_ansi("\x02bold\x02 x"), "\x1b[1mbold\x1b[0m x");
+        assert_eq!(to_ansi("\x034red"), "\x1b[38;2;255;0;0mred\x1b[0m");
+    }
+
+    #[test]
+    fn test_ansi_strips_control_characters() {
+        assert_eq!(to_ansi("a\x1b]0;pwned\x07b\x1b[2J\tc\r\n\x7f\u{9b}"), "a]0;pwnedb[2J\tc");
+        assert_eq!(to_ansi("\x02\x1b[31mx"), "\x1b[1m[31mx\x1b[0m");
+    }
+
+    #[test]
+    fn test_render_html() {
+        assert_eq!(to_html("<b>&"), "&lt;b&gt;&amp;");
+        assert_eq!(
+            to_html("\x02\x1Fhi\x0F \x0304,01\"x\""),
+            "<span style=\"font-weight:bold;text-decoration:underline\">hi</span> \
+             <span style=\"color:#ff0000;background-color:#000000\">&quot;x&quot;</span>"
+        );
+        assert_eq!(to_html("\x16r"), "<span style=\"color:#ffffff;background-color:#000000\">r</span>");
+    }
+}
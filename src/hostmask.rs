@@ -0,0 +1,638 @@
+//! Hostmask and extban matching
+//!
+//! Masks are compiled once into [`BanMask`]s and checked against a
+//! [`UserMask`]. Globs support `*`, `?` and backslash escapes (`\*`, `\?`,
+//! `\\`) and match without backtracking, so patterns like `*a*a*a*b` cannot
+//! blow up. Host parts may be CIDR ranges (`192.0.2.0/24`, `2001:db8::/32`).
+//!
+//! Extbans start with `$`, optionally followed by `~` to negate:
+//!
+//! - `$a` / `$a:<account>`: logged in (to a matching account)
+//! - `$r:<realname>`: realname matches
+//! - `$j:<channel>`: member of a matching channel
+//! - `$x:<nick!user@host#realname>`: the full mask matches
+//!
+//! A [`BanList`] holds many compiled masks and indexes literal hosts so one
+//! user can be checked against thousands of entries cheaply.
+
+use crate::admin::ChannelBan;
+use crate::casemap::CaseMapping;
+use crate::error::{IronError, Result};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// One element of a compiled glob segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Char(char),
+    Any,
+}
+
+/// A compiled, casemapped glob pattern
+#[derive(Debug, Clone)]
+pub struct Glob {
+    /// Runs of tokens separated by `*`
+    segments: Vec<Vec<Token>>,
+    casemapping: CaseMapping,
+}
+
+impl Glob {
+    /// Compile a pattern, folding literal text with `casemapping`
+    pub fn new(pattern: &str, casemapping: CaseMapping) -> Self {
+        let mut segments = Vec::new();
+        let mut segment = Vec::new();
+        let mut literal = String::new();
+
+        // Literal runs are folded as a whole since some mappings (rfc7613)
+        // work on strings rather than single characters
+        let flush = |literal: &mut String, segment: &mut Vec<Token>| {
+            segment.extend(casemapping.fold(literal).chars().map(Token::Char));
+            literal.clear();
+        };
+
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.peek() {
+                    Some(&next @ ('*' | '?' | '\\')) => {
+                        chars.next();
+                        literal.push(next);
+                    }
+                    _ => literal.push('\\'),
+                },
+                '*' => {
+                    flush(&mut literal, &mut segment);
+                    segments.push(std::mem::take(&mut segment));
+                }
+                '?' => {
+                    flush(&mut literal, &mut segment);
+                    segment.push(Token::Any);
+                }
+                c => literal.push(c),
+            }
+        }
+        flush(&mut literal, &mut segment);
+        segments.push(segment);
+
+        Self { segments, casemapping }
+    }
+
+    /// Check if `text` matches
+    pub fn is_match(&self, text: &str) -> bool {
+        let folded: Vec<char> = self.casemapping.fold(text).chars().collect();
+        self.matches_folded(&folded)
+    }
+
+    /// Check if the pattern has no wildcards
+    pub fn is_literal(&self) -> bool {
+        self.segments.len() == 1 && !self.segments[0].contains(&Token::Any)
+    }
+
+    /// The folded literal text, if the pattern has no wildcards
+    fn literal(&self) -> Option<String> {
+        if !self.is_literal() {
+            return None;
+        }
+        Some(self.segments[0].iter().filter_map(|t| match t {
+            Token::Char(c) => Some(*c),
+            Token::Any => None,
+        }).collect())
+    }
+
+    /// Match already-folded text
+    ///
+    /// The first and last segments are anchored; each middle segment is
+    /// taken at its leftmost position, which is always sufficient for globs.
+    fn matches_folded(&self, text: &[char]) -> bool {
+        let n = self.segments.len();
+        let first = &self.segments[0];
+        if n == 1 {
+            return first.len() == text.len() && segment_at(first, text, 0);
+        }
+
+        let last = &self.segments[n - 1];
+        if first.len() + last.len() > text.len()
+            || !segment_at(first, text, 0)
+            || !segment_at(last, text, text.len() - last.len())
+        {
+            return false;
+        }
+
+        let mut pos = first.len();
+        let end = text.len() - last.len();
+        for segment in &self.segments[1..n - 1] {
+            match find_segment(segment, &text[pos..end]) {
+                Some(offset) => pos += offset + segment.len(),
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+fn segment_at(segment: &[Token], text: &[char], at: usize) -> bool {
+    at + segment.len() <= text.len()
+        && segment.iter().zip(&text[at..]).all(|(token, c)| match token {
+            Token::Any => true,
+            Token::Char(expected) => expected == c,
+        })
+}
+
+fn find_segment(segment: &[Token], text: &[char]) -> Option<usize> {
+    if segment.len() > text.len() {
+        return None;
+    }
+    (0..=text.len() - segment.len()).find(|&i| segment_at(segment, text, i))
+}
+
+/// Host part of a hostmask
+#[derive(Debug, Clone)]
+enum HostPattern {
+    Glob(Glob),
+    Cidr(IpAddr, u8),
+}
+
+/// A compiled `nick!user@host` mask
+#[derive(Debug, Clone)]
+pub struct Hostmask {
+    nick: Glob,
+    user: Glob,
+    host: HostPattern,
+}
+
+impl Hostmask {
+    /// Compile a mask, filling in missing parts
+    ///
+    /// `nick` becomes `nick!*@*`, `user@host` becomes `*!user@host` and
+    /// `nick!user` becomes `nick!user@*`.
+    pub fn parse(mask: &str, casemapping: CaseMapping) -> Result<Self> {
+        if mask.is_empty() || mask.contains(' ') {
+            return Err(IronError::InvalidInput(format!("Invalid hostmask: {:?}", mask)));
+        }
+        let (nick, rest) = match mask.split_once('!') {
+            Some((nick, rest)) => (nick, rest),
+            None if mask.contains('@') => ("*", mask),
+            None => (mask, "*"),
+        };
+        let (user, host) = rest.split_once('@').unwrap_or((rest, "*"));
+        let or_any = |part: &str| if part.is_empty() { "*".to_string() } else { part.to_string() };
+
+        let host = match parse_cidr(host) {
+            Some((addr, prefix)) => HostPattern::Cidr(addr, prefix),
+            None => HostPattern::Glob(Glob::new(&or_any(host), casemapping)),
+        };
+        Ok(Self {
+            nick: Glob::new(&or_any(nick), casemapping),
+            user: Glob::new(&or_any(user), casemapping),
+            host,
+        })
+    }
+
+    fn matches(&self, user: &FoldedUser) -> bool {
+        self.nick.matches_folded(&user.nick)
+            && self.user.matches_folded(&user.user)
+            && match &self.host {
+                HostPattern::Glob(glob) => glob.matches_folded(&user.host)
+                    || user.ip_text.as_ref().is_some_and(|ip| glob.matches_folded(ip))
+                    || user.cloaks.iter().any(|cloak| glob.matches_folded(cloak)),
+                HostPattern::Cidr(network, prefix) => user.ip.is_some_and(|ip| in_network(ip, *network, *prefix)),
+            }
+    }
+
+    /// The folded host if it is a plain hostname or address
+    fn literal_host(&self) -> Option<String> {
+        match &self.host {
+            HostPattern::Glob(glob) => glob.literal(),
+            HostPattern::Cidr(..) => None,
+        }
+    }
+}
+
+/// Parse `address/prefix`, rejecting prefixes longer than the address
+pub(crate) fn parse_cidr(host: &str) -> Option<(IpAddr, u8)> {
+    let (addr, prefix) = host.split_once('/')?;
+    let addr: IpAddr = addr.parse().ok()?;
+    let prefix: u8 = prefix.parse().ok()?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    (prefix <= max).then_some((addr, prefix))
+}
+
+pub(crate) fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    // Compare IPv4-mapped IPv6 clients against IPv4 ranges
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    };
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Extban types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtbanKind {
+    /// `$a`: account name
+    Account,
+    /// `$r`: realname
+    Realname,
+    /// `$j`: channel membership
+    Channel,
+    /// `$x`: full `nick!user@host#realname` mask
+    FullMask,
+}
+
+impl ExtbanKind {
+    /// Parse the type character
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'a' => Some(ExtbanKind::Account),
+            'r' => Some(ExtbanKind::Realname),
+            'j' => Some(ExtbanKind::Channel),
+            'x' => Some(ExtbanKind::FullMask),
+            _ => None,
+        }
+    }
+}
+
+/// A compiled ban mask: a hostmask or an extban
+#[derive(Debug, Clone)]
+pub enum BanMask {
+    /// `nick!user@host`
+    Hostmask(Hostmask),
+    /// `$[~]type[:pattern]`
+    Extban {
+        /// What the pattern applies to
+        kind: ExtbanKind,
+        /// Whether the result is inverted (`$~`)
+        negated: bool,
+        /// The pattern, if given
+        pattern: Option<Glob>,
+    },
+}
+
+impl BanMask {
+    /// Compile a mask
+    pub fn parse(mask: &str, casemapping: CaseMapping) -> Result<Self> {
+        let rest = match mask.strip_prefix('$') {
+            Some(rest) => rest,
+            None => return Hostmask::parse(mask, casemapping).map(BanMask::Hostmask),
+        };
+
+        let (negated, rest) = match rest.strip_prefix('~') {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+        let mut chars = rest.chars();
+        let kind = chars.next().and_then(ExtbanKind::from_char)
+            .ok_or_else(|| IronError::InvalidInput(format!("Unknown extban: {}", mask)))?;
+        let arg = chars.as_str();
+        let pattern = match arg.strip_prefix(':') {
+            Some(pattern) if !pattern.is_empty() => Some(Glob::new(pattern, casemapping)),
+            None if arg.is_empty() => None,
+            _ => return Err(IronError::InvalidInput(format!("Malformed extban: {}", mask))),
+        };
+        if pattern.is_none() && kind != ExtbanKind::Account {
+            return Err(IronError::InvalidInput(format!("Extban needs a pattern: {}", mask)));
+        }
+        Ok(BanMask::Extban { kind, negated, pattern })
+    }
+
+    /// Check if this is an extban
+    pub fn is_extban(&self) -> bool {
+        matches!(self, BanMask::Extban { .. })
+    }
+
+    /// Check if the mask matches a user
+    pub fn matches(&self, user: &UserMask, casemapping: CaseMapping) -> bool {
+        self.matches_folded(&FoldedUser::new(user, casemapping))
+    }
+
+    fn matches_folded(&self, user: &FoldedUser) -> bool {
+        match self {
+            BanMask::Hostmask(mask) => mask.matches(user),
+            BanMask::Extban { kind, negated, pattern } => {
+                let matched = match (kind, pattern) {
+                    (ExtbanKind::Account, None) => user.account.is_some(),
+                    (ExtbanKind::Account, Some(glob)) => {
+                        user.account.as_ref().is_some_and(|account| glob.matches_folded(account))
+                    }
+                    (ExtbanKind::Realname, Some(glob)) => glob.matches_folded(&user.realname),
+                    (ExtbanKind::Channel, Some(glob)) => user.channels.iter().any(|c| glob.matches_folded(c)),
+                    (ExtbanKind::FullMask, Some(glob)) => glob.matches_folded(&user.full),
+                    (_, None) => false,
+                };
+                matched != *negated
+            }
+        }
+    }
+}
+
+/// The identity a ban is checked against
+#[derive(Debug, Clone, Default)]
+pub struct UserMask {
+    /// Nickname
+    pub nick: String,
+    /// Username (ident)
+    pub user: String,
+    /// Hostname, possibly cloaked
+    pub host: String,
+    /// Connecting address, for CIDR masks
+    pub ip: Option<IpAddr>,
+    /// Cloaked hosts that bans should also match
+    pub cloaks: Vec<String>,
+    /// Logged-in account
+    pub account: Option<String>,
+    /// Realname (gecos)
+    pub realname: String,
+    /// Channels the user is in
+    pub channels: Vec<String>,
+}
+
+impl UserMask {
+    /// Create a mask for `nick!user@host`
+    pub fn new(nick: impl Into<String>, user: impl Into<String>, host: impl Into<String>) -> Self {
+        Self {
+            nick: nick.into(),
+            user: user.into(),
+            host: host.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Set the connecting address
+    pub fn with_ip(mut self, ip: IpAddr) -> Self {
+        self.ip = Some(ip);
+        self
+    }
+
+    /// Add a cloaked host that bans should also match
+    pub fn with_cloak(mut self, cloak: impl Into<String>) -> Self {
+        self.cloaks.push(cloak.into());
+        self
+    }
+
+    /// Set the logged-in account
+    pub fn with_account(mut self, account: impl Into<String>) -> Self {
+        self.account = Some(account.into());
+        self
+    }
+
+    /// Set the realname
+    pub fn with_realname(mut self, realname: impl Into<String>) -> Self {
+        self.realname = realname.into();
+        self
+    }
+
+    /// Set the channels the user is in
+    pub fn with_channels(mut self, channels: Vec<String>) -> Self {
+        self.channels = channels;
+        self
+    }
+}
+
+/// A user with every field folded once, ready for many matches
+struct FoldedUser {
+    nick: Vec<char>,
+    user: Vec<char>,
+    host: Vec<char>,
+    ip: Option<IpAddr>,
+    ip_text: Option<Vec<char>>,
+    cloaks: Vec<Vec<char>>,
+    account: Option<Vec<char>>,
+    realname: Vec<char>,
+    channels: Vec<Vec<char>>,
+    full: Vec<char>,
+}
+
+impl FoldedUser {
+    fn new(user: &UserMask, casemapping: CaseMapping) -> Self {
+        let fold = |s: &str| -> Vec<char> { casemapping.fold(s).chars().collect() };
+        Self {
+            nick: fold(&user.nick),
+            user: fold(&user.user),
+            host: fold(&user.host),
+            ip: user.ip,
+            ip_text: user.ip.map(|ip| fold(&ip.to_string())),
+            cloaks: user.cloaks.iter().map(|c| fold(c)).collect(),
+            account: user.account.as_deref().map(fold),
+            realname: fold(&user.realname),
+            channels: user.channels.iter().map(|c| fold(c)).collect(),
+            full: fold(&format!("{}!{}@{}#{}", user.nick, user.user, user.host, user.realname)),
+        }
+    }
+}
+
+/// A set of compiled ban masks
+#[derive(Debug, Clone)]
+pub struct BanList {
+    casemapping: CaseMapping,
+    entries: Vec<(String, BanMask)>,
+    /// Entry positions by folded mask, for duplicate checks
+    positions: HashMap<String, usize>,
+    /// Entries with a literal host, by folded host
+    by_host: HashMap<String, Vec<usize>>,
+    /// Everything else
+    other: Vec<usize>,
+}
+
+impl Default for BanList {
+    fn default() -> Self {
+        Self::new(CaseMapping::default())
+    }
+}
+
+impl BanList {
+    /// Create an empty list
+    pub fn new(casemapping: CaseMapping) -> Self {
+        Self {
+            casemapping,
+            entries: Vec::new(),
+            positions: HashMap::new(),
+            by_host: HashMap::new(),
+            other: Vec::new(),
+        }
+    }
+
+    /// Build a list from the active bans among `bans`, skipping invalid masks
+    pub fn from_bans<'a>(bans: impl IntoIterator<Item = &'a ChannelBan>, casemapping: CaseMapping) -> Self {
+        let mut list = Self::new(casemapping);
+        for ban in bans.into_iter().filter(|ban| ban.is_active()) {
+            let _ = list.add(&ban.pattern);
+        }
+        list
+    }
+
+    /// Add a mask; returns `false` if an equivalent mask is already present
+    pub fn add(&mut self, mask: &str) -> Result<bool> {
+        let folded = self.casemapping.fold(mask);
+        if self.positions.contains_key(&folded) {
+            return Ok(false);
+        }
+        let compiled = BanMask::parse(mask, self.casemapping)?;
+        let position = self.entries.len();
+        self.index(position, &compiled);
+        self.positions.insert(folded, position);
+        self.entries.push((mask.to_string(), compiled));
+        Ok(true)
+    }
+
+    /// Remove a mask; returns `false` if it was not present
+    pub fn remove(&mut self, mask: &str) -> bool {
+        let position = match self.positions.get(&self.casemapping.fold(mask)) {
+            Some(&position) => position,
+            None => return false,
+        };
+        let mut entries = std::mem::take(&mut self.entries);
+        entries.remove(position);
+
+        self.positions.clear();
+        self.by_host.clear();
+        self.other.clear();
+        for (i, (mask, compiled)) in entries.iter().enumerate() {
+            self.index(i, compiled);
+            self.positions.insert(self.casemapping.fold(mask), i);
+        }
+        self.entries = entries;
+        true
+    }
+
+    /// Check if an equivalent mask is present
+    pub fn contains(&self, mask: &str) -> bool {
+        self.positions.contains_key(&self.casemapping.fold(mask))
+    }
+
+    /// Number of masks
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the list is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over the masks as they were added
+    pub fn masks(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|(mask, _)| mask.as_str())
+    }
+
+    /// Check if any mask matches the user
+    pub fn matches(&self, user: &UserMask) -> bool {
+        self.first_match(user).is_some()
+    }
+
+    /// The earliest-added mask matching the user
+    pub fn first_match(&self, user: &UserMask) -> Option<&str> {
+        let folded = FoldedUser::new(user, self.casemapping);
+        let host: String = folded.host.iter().collect();
+        let ip: Option<String> = folded.ip_text.as_ref().map(|ip| ip.iter().collect());
+        let cloaks: Vec<String> = folded.cloaks.iter().map(|c| c.iter().collect()).collect();
+
+        let candidates = self.by_host.get(&host).into_iter()
+            .chain(ip.as_ref().and_then(|ip| self.by_host.get(ip)))
+            .chain(cloaks.iter().filter_map(|cloak| self.by_host.get(cloak)))
+            .flatten()
+            .chain(self.other.iter());
+        candidates
+            .filter(|&&i| self.entries[i].1.matches_folded(&folded))
+            .min()
+            .map(|&i| self.entries[i].0.as_str())
+    }
+
+    fn index(&mut self, position: usize, compiled: &BanMask) {
+        match compiled {
+            BanMask::Hostmask(mask) => match mask.literal_host() {
+                Some(host) => self.by_host.entry(host).or_default().push(position),
+                None => self.other.push(position),
+            },
+            BanMask::Extban { .. } => self.other.push(position),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alice() -> UserMask {
+        UserMask::new("Alice", "alice", "client.example.net")
+            .with_ip("2001:db8::42".parse().unwrap())
+            .with_realname("Alice Liddell")
+    }
+
+    #[test]
+    fn test_glob_matching() {
+        let cm = CaseMapping::Rfc1459;
+        assert!(Glob::new("*a*a*a*b", cm).is_match("xaxaxab"));
+        assert!(!Glob::new("*a*a*a*b", cm).is_match(&"a".repeat(5000)));
+        assert!(Glob::new("ab?d*", cm).is_match("ABCDEF"));
+        assert!(!Glob::new("ab?d", cm).is_match("abd"));
+        assert!(Glob::new("a\\*b", cm).is_match("a*b"));
+        assert!(!Glob::new("a\\*b", cm).is_match("axb"));
+        assert!(Glob::new("[foo]\\?", cm).is_match("{FOO}?"));
+        assert!(Glob::new("**", cm).is_match(""));
+        assert!(!Glob::new("a*a", cm).is_match("a"));
+    }
+
+    #[test]
+    fn test_hostmasks_and_cidr() {
+        let cm = CaseMapping::Rfc1459;
+        let user = alice();
+        assert!(BanMask::parse("*!*@*.EXAMPLE.net", cm).unwrap().matches(&user, cm));
+        assert!(BanMask::parse("alice", cm).unwrap().matches(&user, cm));
+        assert!(BanMask::parse("*@2001:db8::*", cm).unwrap().matches(&user, cm));
+        assert!(BanMask::parse("*!*@2001:db8::/32", cm).unwrap().matches(&user, cm));
+        assert!(!BanMask::parse("*!*@2001:db9::/32", cm).unwrap().matches(&user, cm));
+
+        let v4 = UserMask::new("bob", "b", "host").with_ip("::ffff:192.0.2.7".parse().unwrap());
+        assert!(BanMask::parse("*!*@192.0.2.0/24", cm).unwrap().matches(&v4, cm));
+        assert!(!BanMask::parse("*!*@192.0.3.0/24", cm).unwrap().matches(&v4, cm));
+    }
+
+    #[test]
+    fn test_extbans() {
+        let cm = CaseMapping::Rfc1459;
+        let user = alice().with_account("Wonder").with_channels(vec!["#Tea".to_string()]);
+        let matches = |mask: &str, user: &UserMask| BanMask::parse(mask, cm).unwrap().matches(user, cm);
+
+        assert!(matches("$a", &user));
+        assert!(matches("$a:wonder", &user));
+        assert!(!matches("$~a", &user));
+        assert!(matches("$~a", &alice()));
+        assert!(matches("$r:*liddell", &user));
+        assert!(matches("$j:#tea", &user));
+        assert!(!matches("$~j:#tea", &user));
+        assert!(matches("$x:alice!*@*#alice*", &user));
+
+        assert!(BanMask::parse("$q:foo", cm).is_err());
+        assert!(BanMask::parse("$r", cm).is_err());
+        assert!(BanMask::parse("$afoo", cm).is_err());
+    }
+
+    #[test]
+    fn test_ban_list() {
+        let mut list = BanList::new(CaseMapping::Rfc1459);
+        for i in 0..2000 {
+            list.add(&format!("*!*@host{}.example.org", i)).unwrap();
+        }
+        list.add("*!*@client.example.net").unwrap();
+        list.add("$r:*liddell").unwrap();
+        assert!(!list.add("*!*@CLIENT.example.net").unwrap());
+
+        assert_eq!(list.first_match(&alice()), Some("*!*@client.example.net"));
+        assert!(list.remove("*!*@client.EXAMPLE.net"));
+        assert_eq!(list.first_match(&alice()), Some("$r:*liddell"));
+        assert!(list.remove("$r:*liddell"));
+        assert!(!list.matches(&alice()));
+        assert_eq!(list.len(), 2000);
+        assert!(list.matches(&UserMask::new("x", "y", "HOST7.example.org")));
+    }
+}
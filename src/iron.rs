@@ -10,6 +10,10 @@
 use crate::{ChannelType, IronError, Result};
 use crate::utils::get_channel_type;
 use crate::capabilities::Capability;
+use crate::casemap::CaseMapping;
+use crate::e2e::{ChannelCipher, DecryptedMessage};
+use crate::message::IrcMessage;
+use std::collections::HashMap;
 
 /// Legion Protocol version information
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -68,6 +72,9 @@ pub struct IronSession {
     legion_version: Option<LegionVersion>,  // Current version
     encrypted_channels: Vec<String>,
     negotiation_complete: bool,
+    casemapping: CaseMapping,
+    /// Channel ciphers by folded channel name
+    ciphers: HashMap<String, ChannelCipher>,
 }
 
 impl IronSession {
@@ -78,6 +85,8 @@ impl IronSession {
             legion_version: None,
             encrypted_channels: Vec::new(),
             negotiation_complete: false,
+            casemapping: CaseMapping::default(),
+            ciphers: HashMap::new(),
         }
     }
 
@@ -116,21 +125,84 @@ impl IronSession {
         self.negotiation_complete = true;
     }
 
+    /// Set how channel names are compared (from ISUPPORT `CASEMAPPING`)
+    pub fn set_casemapping(&mut self, casemapping: CaseMapping) {
+        self.casemapping = casemapping;
+    }
+
+    /// Get how channel names are compared
+    pub fn casemapping(&self) -> CaseMapping {
+        self.casemapping
+    }
+
     /// Check if a channel is in our encrypted channels list
     pub fn is_encrypted_channel(&self, channel: &str) -> bool {
-        self.encrypted_channels.iter().any(|c| c == channel)
+        self.encrypted_channels.iter().any(|c| self.casemapping.equals(c, channel))
     }
 
     /// Add an encrypted channel to our list
     pub fn add_encrypted_channel(&mut self, channel: String) {
-        if !self.encrypted_channels.contains(&channel) {
+        if !self.is_encrypted_channel(&channel) {
             self.encrypted_channels.push(channel);
         }
     }
 
-    /// Remove an encrypted channel from our list
+    /// Remove an encrypted channel from our list, forgetting its keys
     pub fn remove_encrypted_channel(&mut self, channel: &str) {
-        self.encrypted_channels.retain(|c| c != channel);
+        let casemapping = self.casemapping;
+        self.encrypted_channels.retain(|c| !casemapping.equals(c, channel));
+        self.ciphers.remove(&casemapping.fold(channel));
+    }
+
+    /// Install the cipher for an encrypted channel
+    pub fn set_channel_cipher(&mut self, cipher: ChannelCipher) {
+        let cipher = cipher.with_casemapping(self.casemapping);
+        self.add_encrypted_channel(cipher.channel().to_string());
+        self.ciphers.insert(self.casemapping.fold(cipher.channel()), cipher);
+    }
+
+    /// Get the cipher for an encrypted channel
+    pub fn channel_cipher(&self, channel: &str) -> Option<&ChannelCipher> {
+        self.ciphers.get(&self.casemapping.fold(channel))
+    }
+
+    /// Get the cipher for an encrypted channel, e.g. to rotate keys
+    pub fn channel_cipher_mut(&mut self, channel: &str) -> Option<&mut ChannelCipher> {
+        self.ciphers.get_mut(&self.casemapping.fold(channel))
+    }
+
+    /// Encrypt a message if it targets an encrypted channel
+    ///
+    /// Refuses to send plaintext to an encrypted channel with no key.
+    pub fn encrypt_outgoing(&self, message: &IrcMessage, sender: &str) -> Result<IrcMessage> {
+        match self.encrypted_target(message) {
+            Some(channel) => self.channel_cipher(channel)
+                .ok_or_else(|| IronError::SecurityViolation(format!("No key for encrypted channel {}", channel)))?
+                .encrypt_message(message, sender),
+            None => Ok(message.clone()),
+        }
+    }
+
+    /// Decrypt a message if it targets an encrypted channel
+    ///
+    /// Returns `None` for messages to other targets.
+    pub fn decrypt_incoming(&self, message: &IrcMessage) -> Result<Option<DecryptedMessage>> {
+        match self.encrypted_target(message) {
+            Some(channel) => self.channel_cipher(channel)
+                .ok_or_else(|| IronError::SecurityViolation(format!("No key for encrypted channel {}", channel)))?
+                .decrypt_message(message)
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn encrypted_target<'a>(&self, message: &'a IrcMessage) -> Option<&'a str> {
+        if !matches!(message.command.as_str(), "PRIVMSG" | "NOTICE" | "TAGMSG") {
+            return None;
+        }
+        message.params.first()
+            .map(String::as_str)
+            .filter(|target| self.is_encrypted_channel(target))
     }
 }
 
@@ -374,5 +446,41 @@ mod tests {
         session.add_encrypted_channel("!secure".to_string());
         assert!(session.is_encrypted_channel("!secure"));
         assert!(!session.is_encrypted_channel("!other"));
+        assert!(session.is_encrypted_channel("!SECURE"));
+
+        session.set_casemapping(CaseMapping::Ascii);
+        session.add_encrypted_channel("![a]".to_string());
+        assert!(!session.is_encrypted_channel("!{a}"));
+        session.remove_encrypted_channel("!Secure");
+        assert!(!session.is_encrypted_channel("!secure"));
+    }
+
+    #[test]
+    fn test_session_encrypts_legion_channels() {
+        use crate::e2e::ChannelKey;
+
+        let mut alice = IronSession::new();
+        let mut bob = IronSession::new();
+        let key = ChannelKey::generate(1);
+        alice.set_channel_cipher(ChannelCipher::new("!Team", key.clone()));
+        bob.set_channel_cipher(ChannelCipher::new("!team", key));
+        assert!(alice.is_encrypted_channel("!TEAM"));
+
+        let msg = IrcMessage::new("PRIVMSG").with_params(vec!["!team".into(), "hi bob".into()]);
+        let sent = alice.encrypt_outgoing(&msg, "alice").unwrap();
+        assert_ne!(sent.params[1], "hi bob");
+        let received = bob.decrypt_incoming(&sent).unwrap().unwrap();
+        assert_eq!(received.message.params[1], "hi bob");
+
+        let plain = IrcMessage::new("PRIVMSG").with_params(vec!["#rust".into(), "hi".into()]);
+        assert_eq!(alice.encrypt_outgoing(&plain, "alice").unwrap(), plain);
+        assert!(bob.decrypt_incoming(&plain).unwrap().is_none());
+
+        alice.add_encrypted_channel("!nokey".to_string());
+        let orphan = IrcMessage::new("PRIVMSG").with_params(vec!["!nokey".into(), "x".into()]);
+        assert!(alice.encrypt_outgoing(&orphan, "alice").is_err());
+
+        alice.remove_encrypted_channel("!team");
+        assert!(alice.channel_cipher("!team").is_none());
     }
 }
\ No newline at end of file
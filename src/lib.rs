@@ -36,7 +36,16 @@ pub mod error;
 pub mod message;
 pub mod command;
 pub mod capabilities;
+pub mod casemap;
+pub mod cloak;
+pub mod connection;
+pub mod e2e;
+pub mod formatting;
+pub mod hostmask;
+pub mod precis;
+pub mod ratelimit;
 pub mod sasl;
+pub mod secret;
 pub mod validation;
 pub mod replies;
 pub mod iron;
@@ -47,10 +56,18 @@ pub mod bleeding_edge;
 
 // Re-export main types for convenience
 pub use error::{IronError, Result};
-pub use message::IrcMessage;
+pub use message::{CommandBlocklist, IrcMessage};
+pub use e2e::{ChannelCipher, ChannelKey, DirectSession, GroupKeyManager, PrekeyBundle, PrekeyStore};
+pub use connection::{ConnectionInfo, ConnectionProtocol, ProxyHeader, TrustedProxies, WebIrc, WebIrcVerifier};
 pub use command::Command;
-pub use capabilities::{Capability, CapabilitySet, CapabilityHandler};
+pub use capabilities::{Capability, CapabilitySet, CapabilityHandler, CapabilityRegistry, VendorCapabilityRegistry};
+pub use ratelimit::{RateLimiter, RateLimitKey};
+pub use casemap::{CaseFoldedKey, CaseMapping};
+pub use cloak::Cloaker;
+pub use hostmask::{BanList, BanMask, UserMask};
+pub use precis::{NameLimits, NameValidator};
 pub use replies::Reply;
+pub use secret::Secret;
 pub use utils::ChannelType;
 pub use iron::{IronSession, IronVersion, IronNegotiationResult, IronChannelHandler, ChannelJoinResult, IronChannelError};
 pub use admin::{AdminOperation, MemberOperation, BanOperation, KeyOperation, MemberRole, ChannelMode, 
@@ -198,6 +215,15 @@ pub mod utils {
             .replace('\n', " ")
             .replace('\0', "")
     }
+    
+    /// Compare two byte strings without leaking the position of the first
+    /// difference through timing
+    pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+        if a.len() != b.len() {
+            return false;
+        }
+        a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+    }
 }
 
 #[cfg(test)]
@@ -5,7 +5,8 @@
 
 use crate::error::{IronError, Result};
 use crate::constants::*;
-use std::collections::HashMap;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 
 #[cfg(feature = "chrono")]
@@ -198,10 +199,63 @@ impl IrcMessage {
     }
 }
 
-impl FromStr for IrcMessage {
-    type Err = IronError;
+/// Commands from other protocols that occur in cross-protocol attacks
+///
+/// `AUTH` and `SELECT` are left out because IRC extensions use them.
+const DEFAULT_BLOCKED_COMMANDS: &[&str] = &[
+    "GET", "POST", "PUT", "DELETE", "HEAD", "OPTIONS", "PATCH", // HTTP
+    "HELO", "EHLO", "MAIL", "RCPT", "DATA", "RSET", "VRFY", // SMTP
+    "SYST", "STAT", "RETR", "DELE", "UIDL", "APOP", // POP3
+    "LOGIN", "EXAMINE", "CREATE", "RENAME", // IMAP
+];
+
+static DEFAULT_BLOCKLIST: Lazy<CommandBlocklist> = Lazy::new(CommandBlocklist::default);
+
+/// Command names the line parser rejects
+///
+/// Whole connections speaking another protocol are better caught up front
+/// with [`ConnectionProtocol::detect`](crate::connection::ConnectionProtocol::detect);
+/// this list catches individual lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandBlocklist {
+    commands: HashSet<String>,
+}
 
-    fn from_str(line: &str) -> Result<Self> {
+impl Default for CommandBlocklist {
+    fn default() -> Self {
+        Self {
+            commands: DEFAULT_BLOCKED_COMMANDS.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+}
+
+impl CommandBlocklist {
+    /// A blocklist that rejects nothing
+    pub fn empty() -> Self {
+        Self { commands: HashSet::new() }
+    }
+
+    /// Also reject `command`
+    pub fn with_command(mut self, command: &str) -> Self {
+        self.commands.insert(command.to_uppercase());
+        self
+    }
+
+    /// Stop rejecting `command`
+    pub fn without_command(mut self, command: &str) -> Self {
+        self.commands.remove(&command.to_uppercase());
+        self
+    }
+
+    /// Check if a command is rejected
+    pub fn contains(&self, command: &str) -> bool {
+        self.commands.contains(&command.to_uppercase())
+    }
+}
+
+impl IrcMessage {
+    /// Parse a line, rejecting commands in `blocklist`
+    pub fn parse_with_blocklist(line: &str, blocklist: &CommandBlocklist) -> Result<Self> {
         // Check total message length
         if line.len() > MAX_MESSAGE_LENGTH + MAX_TAG_LENGTH {
             return Err(IronError::SecurityViolation(
@@ -284,7 +338,7 @@ impl FromStr for IrcMessage {
 
         message.command = parts.remove(0).to_uppercase();
 
-        if !is_valid_command(&message.command) {
+        if !is_valid_command(&message.command) || blocklist.contains(&message.command) {
             return Err(IronError::SecurityViolation(
                 format!("Invalid command: {}", message.command)
             ));
@@ -307,6 +361,14 @@ impl FromStr for IrcMessage {
     }
 }
 
+impl FromStr for IrcMessage {
+    type Err = IronError;
+
+    fn from_str(line: &str) -> Result<Self> {
+        Self::parse_with_blocklist(line, &DEFAULT_BLOCKLIST)
+    }
+}
+
 impl std::fmt::Display for IrcMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // Write tags if present
@@ -348,7 +410,7 @@ impl std::fmt::Display for IrcMessage {
 }
 
 /// Unescape IRC tag values
-fn unescape_tag_value(value: &str) -> String {
+pub(crate) fn unescape_tag_value(value: &str) -> String {
     value
         .replace("\\:", ";")
         .replace("\\s", " ")
@@ -358,7 +420,7 @@ fn unescape_tag_value(value: &str) -> String {
 }
 
 /// Escape IRC tag values
-fn escape_tag_value(value: &str) -> String {
+pub(crate) fn escape_tag_value(value: &str) -> String {
     value
         .replace("\\", "\\\\")
         .replace(";", "\\:")
@@ -391,19 +453,7 @@ fn is_valid_command(command: &str) -> bool {
     let is_alpha_command = command.chars().all(|c| c.is_ascii_alphabetic());
     let is_numeric_reply = command.len() == 3 && command.chars().all(|c| c.is_ascii_digit());
     
-    if !is_alpha_command && !is_numeric_reply {
-        return false;
-    }
-    
-    // Reject known non-IRC protocols
-    const INVALID_COMMANDS: &[&str] = &[
-        "GET", "POST", "PUT", "DELETE", "HEAD", "OPTIONS", "PATCH", // HTTP
-        "HELO", "EHLO", "MAIL", "RCPT", "DATA", "RSET", "VRFY", // SMTP
-        "SYST", "STAT", "RETR", "DELE", "UIDL", "APOP", // POP3
-        "AUTH", "LOGIN", "SELECT", "EXAMINE", "CREATE", "RENAME", // IMAP
-    ];
-    
-    !INVALID_COMMANDS.contains(&command)
+    is_alpha_command || is_numeric_reply
 }
 
 #[cfg(test)]
@@ -452,6 +502,18 @@ mod tests {
         assert!(matches!(result, Err(IronError::SecurityViolation(_))));
     }
 
+    #[test]
+    fn test_command_blocklist() {
+        assert!("GET / HTTP/1.1".parse::<IrcMessage>().is_err());
+        assert!("AUTH PLAIN".parse::<IrcMessage>().is_ok());
+        assert!("SELECT #chan".parse::<IrcMessage>().is_ok());
+
+        let blocklist = CommandBlocklist::default().without_command("get").with_command("select");
+        assert!(IrcMessage::parse_with_blocklist("GET key", &blocklist).is_ok());
+        assert!(IrcMessage::parse_with_blocklist("SELECT #chan", &blocklist).is_err());
+        assert!(IrcMessage::parse_with_blocklist("HELO x", &CommandBlocklist::empty()).is_ok());
+    }
+
     #[test]
     fn test_helper_methods() {
         let msg = "PRIVMSG #channel :Hello world".parse::<IrcMessage>().unwrap();
@@ -0,0 +1,306 @@
+//! Unicode nickname and channel validation
+//!
+//! Without `utf8only`, names are limited to the traditional ASCII set. Once
+//! `utf8only` is negotiated, [`NameValidator`] accepts Unicode letters and
+//! digits in the spirit of the PRECIS nickname profiles (RFC 8265/8266):
+//! names are NFC-normalized, bidi controls and zero-width characters are
+//! rejected, and [`skeleton`] reduces homoglyphs so that `аdmin` (Cyrillic
+//! `а`) can be caught impersonating `admin`. Length limits are in bytes and
+//! follow `NICKLEN` and `CHANNELLEN`.
+
+use crate::casemap::CaseMapping;
+use crate::constants::{MAX_CHANNEL_LENGTH, MAX_NICK_LENGTH};
+use crate::error::{IronError, Result};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Special characters allowed in ASCII nicknames
+const NICK_SPECIALS: &[char] = &['[', ']', '\\', '`', '_', '^', '{', '|', '}'];
+
+/// Characters that render the same as a Latin letter or digit
+const CONFUSABLES: &[(char, char)] = &[
+    // Cyrillic
+    ('а', 'a'), ('в', 'b'), ('е', 'e'), ('ё', 'e'), ('һ', 'h'), ('і', 'i'), ('ї', 'i'),
+    ('ј', 'j'), ('к', 'k'), ('м', 'm'), ('н', 'h'), ('о', 'o'), ('р', 'p'), ('с', 'c'),
+    ('ѕ', 's'), ('т', 't'), ('у', 'y'), ('х', 'x'), ('ԁ', 'd'), ('ԛ', 'q'), ('ԝ', 'w'),
+    // Greek
+    ('α', 'a'), ('β', 'b'), ('ε', 'e'), ('η', 'n'), ('ι', 'i'), ('κ', 'k'), ('ν', 'v'),
+    ('ο', 'o'), ('ρ', 'p'), ('τ', 't'), ('υ', 'u'), ('χ', 'x'),
+    // Latin lookalikes
+    ('ı', 'i'), ('ɡ', 'g'), ('ʏ', 'y'), ('0', 'o'), ('1', 'l'), ('|', 'l'),
+];
+
+/// Check if a character is a bidirectional formatting control
+pub fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+/// Check if a character is invisible and has no width
+pub fn is_zero_width(c: char) -> bool {
+    matches!(c, '\u{00AD}' | '\u{180E}' | '\u{200B}'..='\u{200D}' | '\u{2060}'..='\u{2064}' | '\u{FEFF}')
+}
+
+/// Reduce a name to a form where visually confusable names are equal
+///
+/// Applies NFKC, lower-casing and a homoglyph table, and drops invisible
+/// characters. Two names with the same skeleton should not both be allowed.
+pub fn skeleton(name: &str) -> String {
+    name.nfkc()
+        .collect::<String>()
+        .to_lowercase()
+        .chars()
+        .filter(|&c| !is_zero_width(c) && !is_bidi_control(c))
+        .map(|c| CONFUSABLES.iter().find(|(from, _)| *from == c).map_or(c, |(_, to)| *to))
+        .collect()
+}
+
+/// Check if two names look alike
+pub fn is_confusable(a: &str, b: &str) -> bool {
+    skeleton(a) == skeleton(b)
+}
+
+/// Byte length limits announced in ISUPPORT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NameLimits {
+    /// `NICKLEN`
+    pub nicklen: usize,
+    /// `CHANNELLEN`
+    pub channellen: usize,
+}
+
+impl Default for NameLimits {
+    fn default() -> Self {
+        Self {
+            nicklen: MAX_NICK_LENGTH,
+            channellen: MAX_CHANNEL_LENGTH,
+        }
+    }
+}
+
+impl NameLimits {
+    /// Read `NICKLEN` and `CHANNELLEN` from ISUPPORT tokens, keeping the
+    /// defaults for anything missing or malformed
+    pub fn from_isupport<S: AsRef<str>>(tokens: &[S]) -> Self {
+        let mut limits = Self::default();
+        for token in tokens {
+            let token = token.as_ref();
+            if let Some(Ok(len)) = token.strip_prefix("NICKLEN=").map(str::parse) {
+                limits.nicklen = len;
+            } else if let Some(Ok(len)) = token.strip_prefix("CHANNELLEN=").map(str::parse) {
+                limits.channellen = len;
+            }
+        }
+        limits
+    }
+}
+
+/// Validates and normalizes nicknames and channel names
+#[derive(Debug, Clone, Default)]
+pub struct NameValidator {
+    limits: NameLimits,
+    utf8only: bool,
+}
+
+impl NameValidator {
+    /// ASCII-only validation with default limits
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Configure limits and `UTF8ONLY` from ISUPPORT tokens
+    pub fn from_isupport<S: AsRef<str>>(tokens: &[S]) -> Self {
+        Self {
+            limits: NameLimits::from_isupport(tokens),
+            utf8only: tokens.iter().any(|t| t.as_ref() == "UTF8ONLY"),
+        }
+    }
+
+    /// Set the length limits
+    pub fn with_limits(mut self, limits: NameLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Allow Unicode names, as when `utf8only` is negotiated
+    pub fn with_utf8only(mut self, utf8only: bool) -> Self {
+        self.utf8only = utf8only;
+        self
+    }
+
+    /// The active length limits
+    pub fn limits(&self) -> NameLimits {
+        self.limits
+    }
+
+    /// Check if Unicode names are allowed
+    pub fn is_utf8only(&self) -> bool {
+        self.utf8only
+    }
+
+    /// Validate a nickname, returning its normalized form
+    pub fn validate_nick(&self, nick: &str) -> Result<String> {
+        let nick = self.prepare(nick, "Nickname")?;
+        check_length(&nick, self.limits.nicklen, "Nickname")?;
+
+        let mut chars = nick.chars();
+        let first = chars.next().unwrap_or(' ');
+        let first_ok = if first.is_ascii() {
+            first.is_ascii_alphabetic() || NICK_SPECIALS.contains(&first)
+        } else {
+            first.is_alphabetic()
+        };
+        if !first_ok {
+            return Err(IronError::InvalidInput(
+                format!("Nickname cannot start with {:?}", first)
+            ));
+        }
+
+        if let Some(c) = chars.find(|&c| !self.is_nick_char(c)) {
+            return Err(IronError::InvalidInput(
+                format!("Invalid character in nickname: {:?}", c)
+            ));
+        }
+
+        Ok(nick)
+    }
+
+    /// Validate a channel name, returning its normalized form
+    pub fn validate_channel(&self, channel: &str) -> Result<String> {
+        let channel = self.prepare(channel, "Channel name")?;
+        check_length(&channel, self.limits.channellen, "Channel name")?;
+
+        if !channel.starts_with('#') && !channel.starts_with('&') {
+            return Err(IronError::InvalidInput(
+                "Channel name must start with # or &".to_string()
+            ));
+        }
+
+        if let Some(c) = channel.chars().find(|&c| c.is_control() || c.is_whitespace() || c == ',') {
+            return Err(IronError::InvalidInput(
+                format!("Invalid character in channel name: {:?}", c)
+            ));
+        }
+
+        Ok(channel)
+    }
+
+    /// Reject `name` if it looks like one of `existing` without being the
+    /// same name under `casemapping`
+    pub fn check_impersonation<'a, I>(&self, name: &str, existing: I, casemapping: CaseMapping) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let target = skeleton(name);
+        for other in existing {
+            if !casemapping.equals(name, other) && skeleton(other) == target {
+                return Err(IronError::SecurityViolation(
+                    format!("{} is confusable with {}", name, other)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Common checks, returning the NFC form
+    fn prepare(&self, name: &str, what: &str) -> Result<String> {
+        if name.is_empty() {
+            return Err(IronError::InvalidInput(format!("{} cannot be empty", what)));
+        }
+
+        if let Some(c) = name.chars().find(|&c| is_bidi_control(c) || is_zero_width(c)) {
+            return Err(IronError::SecurityViolation(
+                format!("{} contains invisible character U+{:04X}", what, c as u32)
+            ));
+        }
+
+        if !self.utf8only && !name.is_ascii() {
+            return Err(IronError::InvalidInput(
+                format!("{} must be ASCII without UTF8ONLY", what)
+            ));
+        }
+
+        Ok(name.nfc().collect())
+    }
+
+    fn is_nick_char(&self, c: char) -> bool {
+        if c.is_ascii() {
+            c.is_ascii_alphanumeric() || c == '-' || NICK_SPECIALS.contains(&c)
+        } else {
+            c.is_alphanumeric() || is_combining_mark(c)
+        }
+    }
+}
+
+fn check_length(name: &str, limit: usize, what: &str) -> Result<()> {
+    if name.len() > limit {
+        return Err(IronError::InvalidInput(
+            format!("{} too long: {} > {} bytes", what, name.len(), limit)
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii_mode() {
+        let validator = NameValidator::new();
+        assert_eq!(validator.validate_nick("[Server]").unwrap(), "[Server]");
+        assert!(validator.validate_nick("héllo").is_err());
+        assert!(validator.validate_nick("1abc").is_err());
+        assert!(validator.validate_channel("#rust").is_ok());
+        assert!(validator.validate_channel("#a,b").is_err());
+    }
+
+    #[test]
+    fn test_utf8only_normalization() {
+        let validator = NameValidator::new().with_utf8only(true);
+        // Decomposed e + combining acute is stored as the composed form
+        assert_eq!(validator.validate_nick("Jose\u{0301}").unwrap(), "José");
+        assert_eq!(validator.validate_channel("#café").unwrap(), "#café");
+        assert!(validator.validate_nick("日本語").is_ok());
+        assert!(validator.validate_nick("a b").is_err());
+        assert!(validator.validate_nick("nick\u{2764}").is_err());
+    }
+
+    #[test]
+    fn test_invisible_characters_rejected() {
+        let validator = NameValidator::new().with_utf8only(true);
+        for bad in ["ad\u{200B}min", "admin\u{202E}", "\u{FEFF}admin", "#chan\u{2066}"] {
+            let err = if bad.starts_with('#') {
+                validator.validate_channel(bad)
+            } else {
+                validator.validate_nick(bad)
+            };
+            assert!(matches!(err, Err(IronError::SecurityViolation(_))), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_confusables() {
+        assert!(is_confusable("\u{0430}dmin", "admin"));
+        assert!(is_confusable("ΑDMΙN", "admin"));
+        assert!(is_confusable("g00gle", "google"));
+        assert!(!is_confusable("adrnin", "admin"));
+
+        let validator = NameValidator::new().with_utf8only(true);
+        let existing = ["admin", "Alice"];
+        assert!(validator.check_impersonation("\u{0430}dmin", existing, CaseMapping::Rfc7613).is_err());
+        assert!(validator.check_impersonation("ADMIN", existing, CaseMapping::Rfc7613).is_ok());
+        assert!(validator.check_impersonation("bob", existing, CaseMapping::Rfc7613).is_ok());
+    }
+
+    #[test]
+    fn test_byte_length_limits() {
+        let validator = NameValidator::from_isupport(&["UTF8ONLY", "NICKLEN=6", "CHANNELLEN=8"]);
+        assert!(validator.is_utf8only());
+        assert_eq!(validator.limits(), NameLimits { nicklen: 6, channellen: 8 });
+        assert!(validator.validate_nick("abcdef").is_ok());
+        // Three characters but nine bytes
+        assert!(validator.validate_nick("日本語").is_err());
+        assert!(validator.validate_channel("#ab").is_ok());
+        assert!(validator.validate_channel("#abcdefgh").is_err());
+    }
+}
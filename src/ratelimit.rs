@@ -0,0 +1,368 @@
+//! Token-bucket and sliding-window rate limiting
+//!
+//! A [`RateLimiter`] tracks one budget per [`RateLimitKey`] (a connection,
+//! account or channel). Each command has a cost from [`CommandCosts`], and a
+//! command is allowed only if both checks pass:
+//!
+//! - a sliding window admits at most `messages` cost units per `window`
+//! - a token bucket holding `burst` tokens, refilled at `messages / window`
+//!   per second, limits how much of that budget can be spent at once
+//!
+//! Time comes from a [`Clock`] so tests can drive it with a [`ManualClock`].
+
+use crate::admin::{ChannelSettings, RateLimit};
+use crate::casemap::CaseMapping;
+use crate::error::{IronError, Result};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Source of the current time
+pub trait Clock: Send + Sync {
+    /// The current instant
+    fn now(&self) -> Instant;
+}
+
+/// The system monotonic clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to
+#[derive(Debug)]
+pub struct ManualClock {
+    start: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ManualClock {
+    /// Create a clock stopped at the current instant
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// Move the clock forward
+    pub fn advance(&self, by: Duration) {
+        if let Ok(mut elapsed) = self.elapsed.lock() {
+            *elapsed += by;
+        }
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        let elapsed = self.elapsed.lock().map(|e| *e).unwrap_or_default();
+        self.start + elapsed
+    }
+}
+
+/// What a rate limit budget belongs to
+///
+/// Account and channel names are folded with the limiter's casemapping.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RateLimitKey {
+    /// A single client connection
+    Connection(String),
+    /// An account, across all of its connections
+    Account(String),
+    /// A channel, across all of its members
+    Channel(String),
+}
+
+/// Cost of each command in rate limit units
+#[derive(Debug, Clone)]
+pub struct CommandCosts {
+    costs: HashMap<String, u32>,
+    default_cost: u32,
+}
+
+impl Default for CommandCosts {
+    /// Expensive lookups and joins cost more than messages
+    fn default() -> Self {
+        let costs = [
+            ("PRIVMSG", 1), ("NOTICE", 1), ("TAGMSG", 1),
+            ("PART", 1), ("NICK", 2), ("WHOIS", 2), ("NAMES", 2),
+            ("JOIN", 3), ("WHO", 4), ("LIST", 5),
+        ];
+        Self {
+            costs: costs.iter().map(|(c, n)| (c.to_string(), *n)).collect(),
+            default_cost: 1,
+        }
+    }
+}
+
+impl CommandCosts {
+    /// Set the cost of a command
+    pub fn with_cost(mut self, command: &str, cost: u32) -> Self {
+        self.costs.insert(command.to_uppercase(), cost);
+        self
+    }
+
+    /// Set the cost of commands without an explicit cost
+    pub fn with_default_cost(mut self, cost: u32) -> Self {
+        self.default_cost = cost;
+        self
+    }
+
+    /// Cost of a command
+    pub fn cost(&self, command: &str) -> u32 {
+        self.costs.get(&command.to_uppercase()).copied().unwrap_or(self.default_cost)
+    }
+}
+
+/// Budget state for one key
+#[derive(Debug, Clone)]
+struct Budget {
+    tokens: f64,
+    refilled_at: Instant,
+    window: VecDeque<(Instant, u32)>,
+    spent: u32,
+}
+
+/// Rate limiter keyed by connection, account or channel
+pub struct RateLimiter {
+    default_limit: RateLimit,
+    limits: HashMap<RateLimitKey, RateLimit>,
+    costs: CommandCosts,
+    clock: Arc<dyn Clock>,
+    casemapping: CaseMapping,
+    budgets: HashMap<RateLimitKey, Budget>,
+}
+
+impl RateLimiter {
+    /// Create a limiter applying `limit` to every key
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            default_limit: limit,
+            limits: HashMap::new(),
+            costs: CommandCosts::default(),
+            clock: Arc::new(SystemClock),
+            casemapping: CaseMapping::default(),
+            budgets: HashMap::new(),
+        }
+    }
+
+    /// Use a different time source
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Use different command costs
+    pub fn with_costs(mut self, costs: CommandCosts) -> Self {
+        self.costs = costs;
+        self
+    }
+
+    /// Compare account and channel names under a server's casemapping
+    pub fn with_casemapping(mut self, casemapping: CaseMapping) -> Self {
+        self.casemapping = casemapping;
+        self
+    }
+
+    /// Apply a specific limit to one key
+    pub fn set_limit(&mut self, key: RateLimitKey, limit: RateLimit) {
+        let key = self.normalize(&key);
+        self.limits.insert(key, limit);
+    }
+
+    /// Check and record a command, failing if it exceeds the key's budget
+    pub fn check(&mut self, key: &RateLimitKey, command: &str) -> Result<()> {
+        let cost = self.costs.cost(command);
+        self.check_cost(key, cost)
+    }
+
+    /// Check and record an explicit cost
+    ///
+    /// Costs larger than the budget are clamped so that any command can
+    /// eventually be sent. A rejected command consumes nothing.
+    pub fn check_cost(&mut self, key: &RateLimitKey, cost: u32) -> Result<()> {
+        let key = &self.normalize(key);
+        let limit = self.limits.get(key).unwrap_or(&self.default_limit).clone();
+        let now = self.clock.now();
+        let window = window_of(&limit);
+        let capacity = capacity_of(&limit);
+        let cost = cost.min(capacity).min(limit.messages.max(1));
+
+        let budget = self.budgets.entry(key.clone()).or_insert_with(|| Budget {
+            tokens: capacity as f64,
+            refilled_at: now,
+            window: VecDeque::new(),
+            spent: 0,
+        });
+
+        // Refill the bucket for the time elapsed since the last check
+        let rate = limit.messages as f64 / window.as_secs_f64();
+        let elapsed = now.saturating_duration_since(budget.refilled_at).as_secs_f64();
+        budget.tokens = (budget.tokens + elapsed * rate).min(capacity as f64);
+        budget.refilled_at = now;
+
+        // Forget window entries that have slid out
+        while let Some(&(at, spent)) = budget.window.front() {
+            if now.saturating_duration_since(at) < window {
+                break;
+            }
+            budget.window.pop_front();
+            budget.spent -= spent;
+        }
+
+        if budget.spent + cost > limit.messages {
+            let retry = budget.window.front()
+                .map(|&(at, _)| window.saturating_sub(now.saturating_duration_since(at)))
+                .unwrap_or_default();
+            return Err(IronError::RateLimit(format!(
+                "{} units per {}s exceeded; retry in {:.1}s", limit.messages, window.as_secs(), retry.as_secs_f64()
+            )));
+        }
+        if budget.tokens < cost as f64 {
+            let retry = (cost as f64 - budget.tokens) / rate;
+            return Err(IronError::RateLimit(format!(
+                "burst of {} exceeded; retry in {:.1}s", capacity, retry
+            )));
+        }
+
+        budget.tokens -= cost as f64;
+        budget.window.push_back((now, cost));
+        budget.spent += cost;
+        Ok(())
+    }
+
+    /// Check a command against a channel's own limit
+    ///
+    /// Channels without a `rate_limit` are not limited.
+    pub fn check_channel(&mut self, channel: &str, settings: &ChannelSettings, command: &str) -> Result<()> {
+        let limit = match &settings.rate_limit {
+            Some(limit) => limit.clone(),
+            None => return Ok(()),
+        };
+        let key = RateLimitKey::Channel(channel.to_string());
+        self.set_limit(key.clone(), limit);
+        self.check(&key, command)
+    }
+
+    /// Forget a key's budget and specific limit
+    pub fn reset(&mut self, key: &RateLimitKey) {
+        let key = &self.normalize(key);
+        self.budgets.remove(key);
+        self.limits.remove(key);
+    }
+
+    /// Drop budgets that have been idle for a full window
+    pub fn purge_idle(&mut self) {
+        let now = self.clock.now();
+        let limits = &self.limits;
+        let default_limit = &self.default_limit;
+        self.budgets.retain(|key, budget| {
+            let window = window_of(limits.get(key).unwrap_or(default_limit));
+            now.saturating_duration_since(budget.refilled_at) < window
+        });
+    }
+
+    fn normalize(&self, key: &RateLimitKey) -> RateLimitKey {
+        match key {
+            RateLimitKey::Connection(id) => RateLimitKey::Connection(id.clone()),
+            RateLimitKey::Account(name) => RateLimitKey::Account(self.casemapping.fold(name)),
+            RateLimitKey::Channel(name) => RateLimitKey::Channel(self.casemapping.fold(name)),
+        }
+    }
+}
+
+fn window_of(limit: &RateLimit) -> Duration {
+    Duration::from_secs(limit.window.max(1))
+}
+
+/// Bucket size: the burst allowance, or the whole window budget if unset
+fn capacity_of(limit: &RateLimit) -> u32 {
+    if limit.burst == 0 { limit.messages.max(1) } else { limit.burst }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(messages: u32, window: u64, burst: u32) -> (RateLimiter, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new());
+        let limiter = RateLimiter::new(RateLimit { messages, window, burst }).with_clock(clock.clone());
+        (limiter, clock)
+    }
+
+    #[test]
+    fn test_burst_then_refill() {
+        // 10 per 10s, at most 3 at once
+        let (mut limiter, clock) = limiter(10, 10, 3);
+        let key = RateLimitKey::Connection("c1".to_string());
+        for _ in 0..3 {
+            assert!(limiter.check(&key, "PRIVMSG").is_ok());
+        }
+        assert!(limiter.check(&key, "PRIVMSG").is_err());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(limiter.check(&key, "PRIVMSG").is_ok());
+        assert!(limiter.check(&key, "PRIVMSG").is_err());
+
+        // Other keys have their own budget
+        assert!(limiter.check(&RateLimitKey::Account("alice".to_string()), "PRIVMSG").is_ok());
+    }
+
+    #[test]
+    fn test_sliding_window_caps_sustained_rate() {
+        // The bucket alone would allow 5 + 1/s; the window caps at 5 per 10s
+        let (mut limiter, clock) = limiter(5, 10, 5);
+        let key = RateLimitKey::Connection("c1".to_string());
+        for _ in 0..5 {
+            assert!(limiter.check(&key, "PRIVMSG").is_ok());
+        }
+        clock.advance(Duration::from_secs(5));
+        assert!(limiter.check(&key, "PRIVMSG").is_err());
+
+        clock.advance(Duration::from_secs(5));
+        assert!(limiter.check(&key, "PRIVMSG").is_ok());
+    }
+
+    #[test]
+    fn test_command_costs() {
+        let (mut limiter, _clock) = limiter(10, 10, 6);
+        let key = RateLimitKey::Connection("c1".to_string());
+        assert!(limiter.check(&key, "JOIN").is_ok());
+        assert!(limiter.check(&key, "join").is_ok());
+        assert!(limiter.check(&key, "WHO").is_err());
+        assert!(limiter.check(&key, "PRIVMSG").is_err());
+
+        let costs = CommandCosts::default().with_cost("WHO", 1).with_default_cost(2);
+        assert_eq!(costs.cost("who"), 1);
+        assert_eq!(costs.cost("MOTD"), 2);
+    }
+
+    #[test]
+    fn test_channel_settings_limit() {
+        let (mut limiter, clock) = limiter(100, 1, 0);
+        let mut settings = ChannelSettings::default();
+        for _ in 0..10 {
+            assert!(limiter.check_channel("#rust", &settings, "PRIVMSG").is_ok());
+        }
+
+        settings.rate_limit = Some(RateLimit { messages: 2, window: 60, burst: 0 });
+        assert!(limiter.check_channel("#rust", &settings, "PRIVMSG").is_ok());
+        assert!(limiter.check_channel("#rust", &settings, "PRIVMSG").is_ok());
+        assert!(limiter.check_channel("#rust", &settings, "PRIVMSG").is_err());
+
+        assert!(limiter.check_channel("#RUST", &settings, "PRIVMSG").is_err());
+
+        clock.advance(Duration::from_secs(60));
+        assert!(limiter.check_channel("#rust", &settings, "PRIVMSG").is_ok());
+    }
+}
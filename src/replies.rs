@@ -89,6 +89,26 @@ pub enum Reply {
     ListStart { nick: String },
     /// 323 RPL_LISTEND
     ListEnd { nick: String },
+    
+    // SASL (900-908)
+    /// 900 RPL_LOGGEDIN
+    LoggedIn { nick: String, mask: String, account: String },
+    /// 901 RPL_LOGGEDOUT
+    LoggedOut { nick: String, mask: String },
+    /// 902 ERR_NICKLOCKED
+    NickLocked { nick: String },
+    /// 903 RPL_SASLSUCCESS
+    SaslSuccess { nick: String },
+    /// 904 ERR_SASLFAIL
+    SaslFail { nick: String },
+    /// 905 ERR_SASLTOOLONG
+    SaslTooLong { nick: String },
+    /// 906 ERR_SASLABORTED
+    SaslAborted { nick: String },
+    /// 907 ERR_SASLALREADY
+    SaslAlready { nick: String },
+    /// 908 RPL_SASLMECHS
+    SaslMechs { nick: String, mechanisms: Vec<String> },
 }
 
 impl Reply {
@@ -416,6 +436,82 @@ impl Reply {
                         "End of /LIST".to_string(),
                     ])
             }
+            Reply::LoggedIn { nick, mask, account } => {
+                IrcMessage::new("900")
+                    .with_prefix(server_name)
+                    .with_params(vec![
+                        nick.clone(),
+                        mask.clone(),
+                        account.clone(),
+                        format!("You are now logged in as {}", account),
+                    ])
+            }
+            Reply::LoggedOut { nick, mask } => {
+                IrcMessage::new("901")
+                    .with_prefix(server_name)
+                    .with_params(vec![
+                        nick.clone(),
+                        mask.clone(),
+                        "You are now logged out".to_string(),
+                    ])
+            }
+            Reply::NickLocked { nick } => {
+                IrcMessage::new("902")
+                    .with_prefix(server_name)
+                    .with_params(vec![
+                        nick.clone(),
+                        "You must use a nick assigned to you".to_string(),
+                    ])
+            }
+            Reply::SaslSuccess { nick } => {
+                IrcMessage::new("903")
+                    .with_prefix(server_name)
+                    .with_params(vec![
+                        nick.clone(),
+                        "SASL authentication successful".to_string(),
+                    ])
+            }
+            Reply::SaslFail { nick } => {
+                IrcMessage::new("904")
+                    .with_prefix(server_name)
+                    .with_params(vec![
+                        nick.clone(),
+                        "SASL authentication failed".to_string(),
+                    ])
+            }
+            Reply::SaslTooLong { nick } => {
+                IrcMessage::new("905")
+                    .with_prefix(server_name)
+                    .with_params(vec![
+                        nick.clone(),
+                        "SASL message too long".to_string(),
+                    ])
+            }
+            Reply::SaslAborted { nick } => {
+                IrcMessage::new("906")
+                    .with_prefix(server_name)
+                    .with_params(vec![
+                        nick.clone(),
+                        "SASL authentication aborted".to_string(),
+                    ])
+            }
+            Reply::SaslAlready { nick } => {
+                IrcMessage::new("907")
+                    .with_prefix(server_name)
+                    .with_params(vec![
+                        nick.clone(),
+                        "You have already authenticated using SASL".to_string(),
+                    ])
+            }
+            Reply::SaslMechs { nick, mechanisms } => {
+                IrcMessage::new("908")
+                    .with_prefix(server_name)
+                    .with_params(vec![
+                        nick.clone(),
+                        mechanisms.join(","),
+                        "are available SASL mechanisms".to_string(),
+                    ])
+            }
         }
     }
 }
@@ -1,15 +1,33 @@
 //! SASL (Simple Authentication and Security Layer) support for IRC
 //!
 //! This module provides SASL authentication mechanisms commonly used in IRCv3.
+//! [`SaslAuth`] implements the client side; [`SaslServer`] verifies clients
+//! against a [`CredentialStore`].
+
+pub mod client;
+pub mod ecdsa;
+pub mod framing;
+pub mod mechanism;
+pub mod oauthbearer;
+mod scram;
+pub mod server;
+pub mod store;
+
+pub use client::{SaslClient, SaslClientState};
+pub use ecdsa::{EcdsaPrivateKey, EcdsaPublicKey};
+pub use framing::AuthenticateBuffer;
+pub use mechanism::{builtin_registry, Mechanism, MechanismInfo, MechanismRegistry, SaslCredentials};
+pub use oauthbearer::{OAuthBearerError, OAuthBearerMessage, TokenValidator};
+#[cfg(feature = "serde")]
+pub use oauthbearer::HmacJwtValidator;
+pub use scram::{ChannelBinding, ChannelBindingType, ScramHash};
+pub use server::{AuthorizationPolicy, SaslServer};
+pub use store::{AccountCredentials, CredentialStore, FileCredentialStore, MemoryCredentialStore, ScramCredentials};
 
 use crate::error::{IronError, Result};
+use crate::message::IrcMessage;
+use crate::secret::Secret;
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
-use hmac::{Hmac, Mac};
-use pbkdf2::pbkdf2;
-use rand::RngCore;
-use sha2::{Sha256, Digest};
-
-type HmacSha256 = Hmac<Sha256>;
 
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
@@ -22,27 +40,102 @@ pub enum SaslMechanism {
     Plain,
     /// EXTERNAL mechanism (client certificate)
     External,
+    /// SCRAM-SHA-1 mechanism
+    ScramSha1,
     /// SCRAM-SHA-256 mechanism
     ScramSha256,
+    /// SCRAM-SHA-512 mechanism
+    ScramSha512,
+    /// SCRAM-SHA-1 with channel binding
+    ScramSha1Plus,
+    /// SCRAM-SHA-256 with channel binding
+    ScramSha256Plus,
+    /// SCRAM-SHA-512 with channel binding
+    ScramSha512Plus,
+    /// ECDSA-NIST256P-CHALLENGE mechanism (keypair)
+    EcdsaNist256pChallenge,
+    /// OAUTHBEARER mechanism (RFC 7628 bearer token)
+    OAuthBearer,
 }
 
 impl SaslMechanism {
+    /// Every built-in mechanism
+    pub const ALL: [SaslMechanism; 10] = [
+        SaslMechanism::Plain,
+        SaslMechanism::External,
+        SaslMechanism::ScramSha1,
+        SaslMechanism::ScramSha256,
+        SaslMechanism::ScramSha512,
+        SaslMechanism::ScramSha1Plus,
+        SaslMechanism::ScramSha256Plus,
+        SaslMechanism::ScramSha512Plus,
+        SaslMechanism::EcdsaNist256pChallenge,
+        SaslMechanism::OAuthBearer,
+    ];
+
     /// Parse mechanism from string
     pub fn from_str(s: &str) -> Option<Self> {
         match s.to_uppercase().as_str() {
             "PLAIN" => Some(SaslMechanism::Plain),
             "EXTERNAL" => Some(SaslMechanism::External),
+            "SCRAM-SHA-1" => Some(SaslMechanism::ScramSha1),
             "SCRAM-SHA-256" => Some(SaslMechanism::ScramSha256),
+            "SCRAM-SHA-512" => Some(SaslMechanism::ScramSha512),
+            "SCRAM-SHA-1-PLUS" => Some(SaslMechanism::ScramSha1Plus),
+            "SCRAM-SHA-256-PLUS" => Some(SaslMechanism::ScramSha256Plus),
+            "SCRAM-SHA-512-PLUS" => Some(SaslMechanism::ScramSha512Plus),
+            "ECDSA-NIST256P-CHALLENGE" => Some(SaslMechanism::EcdsaNist256pChallenge),
+            "OAUTHBEARER" => Some(SaslMechanism::OAuthBearer),
             _ => None,
         }
     }
 
+    /// The SCRAM mechanism for a hash, with or without channel binding
+    pub fn scram(hash: ScramHash, plus: bool) -> Self {
+        match (hash, plus) {
+            (ScramHash::Sha1, false) => SaslMechanism::ScramSha1,
+            (ScramHash::Sha256, false) => SaslMechanism::ScramSha256,
+            (ScramHash::Sha512, false) => SaslMechanism::ScramSha512,
+            (ScramHash::Sha1, true) => SaslMechanism::ScramSha1Plus,
+            (ScramHash::Sha256, true) => SaslMechanism::ScramSha256Plus,
+            (ScramHash::Sha512, true) => SaslMechanism::ScramSha512Plus,
+        }
+    }
+
+    /// The hash used if this is a SCRAM mechanism
+    pub fn scram_hash(&self) -> Option<ScramHash> {
+        match self {
+            SaslMechanism::ScramSha1 | SaslMechanism::ScramSha1Plus => Some(ScramHash::Sha1),
+            SaslMechanism::ScramSha256 | SaslMechanism::ScramSha256Plus => Some(ScramHash::Sha256),
+            SaslMechanism::ScramSha512 | SaslMechanism::ScramSha512Plus => Some(ScramHash::Sha512),
+            SaslMechanism::Plain
+            | SaslMechanism::External
+            | SaslMechanism::EcdsaNist256pChallenge
+            | SaslMechanism::OAuthBearer => None,
+        }
+    }
+
+    /// Check if this mechanism requires TLS channel binding
+    pub fn is_plus(&self) -> bool {
+        matches!(
+            self,
+            SaslMechanism::ScramSha1Plus | SaslMechanism::ScramSha256Plus | SaslMechanism::ScramSha512Plus
+        )
+    }
+
     /// Get mechanism name as string
     pub fn as_str(&self) -> &str {
         match self {
             SaslMechanism::Plain => "PLAIN",
             SaslMechanism::External => "EXTERNAL",
+            SaslMechanism::ScramSha1 => "SCRAM-SHA-1",
             SaslMechanism::ScramSha256 => "SCRAM-SHA-256",
+            SaslMechanism::ScramSha512 => "SCRAM-SHA-512",
+            SaslMechanism::ScramSha1Plus => "SCRAM-SHA-1-PLUS",
+            SaslMechanism::ScramSha256Plus => "SCRAM-SHA-256-PLUS",
+            SaslMechanism::ScramSha512Plus => "SCRAM-SHA-512-PLUS",
+            SaslMechanism::EcdsaNist256pChallenge => "ECDSA-NIST256P-CHALLENGE",
+            SaslMechanism::OAuthBearer => "OAUTHBEARER",
         }
     }
 
@@ -50,30 +143,39 @@ impl SaslMechanism {
     pub fn is_secure(&self) -> bool {
         match self {
             SaslMechanism::Plain => false, // Only secure over TLS
+            SaslMechanism::OAuthBearer => false, // Token is sent as-is
             SaslMechanism::External => true,
-            SaslMechanism::ScramSha256 => true,
+            SaslMechanism::EcdsaNist256pChallenge => true,
+            _ => true, // SCRAM never reveals the password
         }
     }
 
     /// Get security strength (higher is better)
+    ///
+    /// The `-PLUS` variants rank highest because they also bind the exchange
+    /// to the TLS channel; they are only usable when binding data is present.
     pub fn security_strength(&self) -> u8 {
         match self {
             SaslMechanism::Plain => 1,
-            SaslMechanism::External => 3,
-            SaslMechanism::ScramSha256 => 2,
+            SaslMechanism::OAuthBearer => 1,
+            SaslMechanism::ScramSha1 => 2,
+            SaslMechanism::ScramSha256 => 3,
+            SaslMechanism::ScramSha512 => 4,
+            SaslMechanism::EcdsaNist256pChallenge => 5,
+            SaslMechanism::External => 6,
+            SaslMechanism::ScramSha1Plus => 7,
+            SaslMechanism::ScramSha256Plus => 8,
+            SaslMechanism::ScramSha512Plus => 9,
         }
     }
 }
 
 /// SASL authentication context
 pub struct SaslAuth {
-    mechanism: SaslMechanism,
-    username: String,
-    password: Option<String>,
-    client_nonce: Option<String>,
-    server_nonce: Option<String>,
-    salt: Option<Vec<u8>>,
-    iterations: Option<u32>,
+    info: Option<MechanismInfo>,
+    credentials: SaslCredentials,
+    mechanism: Option<Box<dyn Mechanism>>,
+    inbound: AuthenticateBuffer,
     state: SaslState,
 }
 
@@ -88,46 +190,149 @@ enum SaslState {
 
 impl SaslAuth {
     /// Create new SASL authentication context
+    ///
+    /// For OAUTHBEARER `password` is the bearer token and a non-empty
+    /// `username` is sent as the authorization identity unless one is set
+    /// with [`with_authzid`](Self::with_authzid).
     pub fn new(mechanism: SaslMechanism, username: String, password: Option<String>) -> Self {
         Self {
-            mechanism,
-            username,
-            password,
-            client_nonce: None,
-            server_nonce: None,
-            salt: None,
-            iterations: None,
+            info: builtin_registry().get(mechanism.as_str()).cloned(),
+            credentials: SaslCredentials {
+                username,
+                password: password.map(Secret::from),
+                ..SaslCredentials::default()
+            },
+            mechanism: None,
+            inbound: AuthenticateBuffer::new(),
             state: SaslState::Initial,
         }
     }
 
+    /// Create a context for a mechanism looked up in `registry`
+    pub fn from_registry(
+        registry: &MechanismRegistry,
+        name: &str,
+        username: String,
+        password: Option<String>,
+    ) -> Result<Self> {
+        let info = registry.get(name)
+            .ok_or_else(|| IronError::Sasl(format!("Unsupported SASL mechanism: {}", name)))?;
+        let credentials = SaslCredentials {
+            username,
+            password: password.map(Secret::from),
+            ..SaslCredentials::default()
+        };
+        Ok(Self::from_parts(info.clone(), credentials))
+    }
+
+    /// Create a context from a registry entry and full credentials
+    pub(crate) fn from_parts(info: MechanismInfo, credentials: SaslCredentials) -> Self {
+        Self {
+            info: Some(info),
+            credentials,
+            ..Self::new(SaslMechanism::Plain, String::new(), None)
+        }
+    }
+
+    /// Create a context driving an already constructed mechanism
+    ///
+    /// The credential builders have no effect on such a context.
+    pub fn from_mechanism(mechanism: Box<dyn Mechanism>) -> Self {
+        Self {
+            info: None,
+            mechanism: Some(mechanism),
+            ..Self::new(SaslMechanism::Plain, String::new(), None)
+        }
+    }
+
+    /// Authenticate as `username` but act as `authzid`
+    ///
+    /// Supported by PLAIN, EXTERNAL, SCRAM (`a=`), ECDSA-NIST256P-CHALLENGE
+    /// and OAUTHBEARER. The server decides whether the authorization is
+    /// allowed.
+    pub fn with_authzid(mut self, authzid: impl Into<String>) -> Self {
+        let authzid = authzid.into();
+        self.credentials.authzid = if authzid.is_empty() { None } else { Some(authzid) };
+        self
+    }
+
+    /// Supply TLS channel binding data for the SCRAM mechanisms
+    ///
+    /// Required for the `-PLUS` variants. With a plain SCRAM mechanism the
+    /// client signals binding support so the server can detect a downgrade.
+    pub fn with_channel_binding(mut self, binding: ChannelBinding) -> Self {
+        self.credentials.channel_binding = Some(binding);
+        self
+    }
+
+    /// Supply the private key for ECDSA-NIST256P-CHALLENGE
+    pub fn with_ecdsa_key(mut self, key: EcdsaPrivateKey) -> Self {
+        self.credentials.ecdsa_key = Some(key);
+        self
+    }
+
+    /// Name of the mechanism in use
+    pub fn mechanism_name(&self) -> &str {
+        match (&self.info, &self.mechanism) {
+            (Some(info), _) => info.name(),
+            (None, Some(mechanism)) => mechanism.name(),
+            (None, None) => "",
+        }
+    }
+
     /// Generate initial authentication message
     pub fn generate_initial_response(&mut self) -> Result<String> {
-        match self.mechanism {
-            SaslMechanism::Plain => self.generate_plain_response(),
-            SaslMechanism::External => Ok(BASE64.encode("")), // Empty for EXTERNAL
-            SaslMechanism::ScramSha256 => self.generate_scram_initial(),
+        // Registry-backed contexts start every exchange with a fresh instance
+        if let Some(info) = &self.info {
+            self.mechanism = Some(info.create(&self.credentials)?);
         }
+        let data = self.mechanism()?.start()?;
+        self.state = SaslState::Authenticating;
+        Ok(BASE64.encode(data))
+    }
+
+    /// Generate the initial response as `AUTHENTICATE` lines
+    pub fn initial_response_messages(&mut self) -> Result<Vec<IrcMessage>> {
+        let response = self.generate_initial_response()?;
+        Ok(framing::chunk_authenticate(&response))
+    }
+
+    /// Process the parameter of an inbound `AUTHENTICATE` command
+    ///
+    /// Chunks are accumulated until the challenge is complete; until then an
+    /// empty list is returned. The server's initial empty challenge produces
+    /// the initial response. Responses are split into `AUTHENTICATE` lines.
+    pub fn handle_authenticate(&mut self, param: &str) -> Result<Vec<IrcMessage>> {
+        let challenge = match self.inbound.push(param)? {
+            Some(challenge) => challenge,
+            None => return Ok(Vec::new()),
+        };
+
+        if self.state == SaslState::Initial && challenge.is_empty() {
+            return self.initial_response_messages();
+        }
+        let response = self.process_challenge(&BASE64.encode(challenge))?;
+        Ok(framing::chunk_authenticate(&response))
     }
 
     /// Process server challenge and generate response
+    ///
+    /// An empty response is returned as `+`, e.g. after SCRAM verifies the
+    /// server-final-message. A challenge the mechanism rejects fails the
+    /// exchange.
     pub fn process_challenge(&mut self, challenge: &str) -> Result<String> {
         let challenge_data = BASE64.decode(challenge)
             .map_err(|_| IronError::Sasl("Invalid base64 in challenge".to_string()))?;
-        
-        let challenge_str = String::from_utf8(challenge_data)
-            .map_err(|_| IronError::Sasl("Invalid UTF-8 in challenge".to_string()))?;
 
-        match self.mechanism {
-            SaslMechanism::Plain => {
-                // PLAIN doesn't typically use challenges
-                Err(IronError::Sasl("PLAIN doesn't use challenges".to_string()))
-            }
-            SaslMechanism::External => {
-                // EXTERNAL doesn't use challenges
-                Err(IronError::Sasl("EXTERNAL doesn't use challenges".to_string()))
+        let mechanism = self.mechanism.as_mut()
+            .ok_or_else(|| IronError::Sasl("SASL exchange not started".to_string()))?;
+        match mechanism.step(&challenge_data) {
+            Ok(response) if response.is_empty() => Ok("+".to_string()),
+            Ok(response) => Ok(BASE64.encode(response)),
+            Err(e) => {
+                self.state = SaslState::Failed;
+                Err(e)
             }
-            SaslMechanism::ScramSha256 => self.process_scram_challenge(&challenge_str),
         }
     }
 
@@ -141,9 +346,24 @@ impl SaslAuth {
         matches!(self.state, SaslState::Success)
     }
 
-    /// Mark authentication as successful
+    /// The JSON error sent by the server if it rejected our OAUTHBEARER token
+    pub fn oauth_error(&self) -> Option<&str> {
+        self.mechanism.as_ref().and_then(|m| m.server_error())
+    }
+
+    /// Check if the server proved its identity (SCRAM `v=` verified)
+    pub fn is_server_verified(&self) -> bool {
+        self.mechanism.as_ref().map(|m| m.is_server_verified()).unwrap_or(false)
+    }
+
+    /// Mark authentication as successful (on RPL_SASLSUCCESS)
+    ///
+    /// The mechanism gets the final say: for SCRAM the exchange only
+    /// succeeds if the server-final signature was verified, and a server
+    /// reporting success without it is treated as forged.
     pub fn mark_success(&mut self) {
-        self.state = SaslState::Success;
+        let verified = self.mechanism().and_then(|m| m.finish()).is_ok();
+        self.state = if verified { SaslState::Success } else { SaslState::Failed };
     }
 
     /// Mark authentication as failed
@@ -151,151 +371,47 @@ impl SaslAuth {
         self.state = SaslState::Failed;
     }
 
-    /// Generate PLAIN mechanism response
-    fn generate_plain_response(&self) -> Result<String> {
-        let password = self.password.as_ref()
-            .ok_or_else(|| IronError::Sasl("Password required for PLAIN".to_string()))?;
-
-        // PLAIN format: \0username\0password
-        let auth_string = format!("\0{}\0{}", self.username, password);
-        Ok(BASE64.encode(auth_string.as_bytes()))
-    }
-
-    /// Generate SCRAM-SHA-256 initial message
-    fn generate_scram_initial(&mut self) -> Result<String> {
-        // Generate client nonce
-        let mut nonce_bytes = [0u8; 16];
-        rand::thread_rng().fill_bytes(&mut nonce_bytes);
-        let client_nonce = BASE64.encode(&nonce_bytes);
-        
-        self.client_nonce = Some(client_nonce.clone());
-        self.state = SaslState::Authenticating;
-
-        // Initial message: n,,n=username,r=clientnonce
-        let initial_message = format!("n,,n={},r={}", self.username, client_nonce);
-        Ok(BASE64.encode(initial_message.as_bytes()))
-    }
-
-    /// Process SCRAM-SHA-256 server challenge
-    fn process_scram_challenge(&mut self, challenge: &str) -> Result<String> {
-        let password = self.password.as_ref()
-            .ok_or_else(|| IronError::Sasl("Password required for SCRAM".to_string()))?;
-
-        let client_nonce = self.client_nonce.as_ref()
-            .ok_or_else(|| IronError::Sasl("Client nonce not set".to_string()))?;
-
-        // Parse server challenge: r=servernonce,s=salt,i=iterations
-        let mut server_nonce = None;
-        let mut salt = None;
-        let mut iterations = None;
-
-        for part in challenge.split(',') {
-            if let Some(value) = part.strip_prefix("r=") {
-                if !value.starts_with(client_nonce) {
-                    return Err(IronError::Sasl("Server nonce doesn't start with client nonce".to_string()));
-                }
-                server_nonce = Some(value.to_string());
-            } else if let Some(value) = part.strip_prefix("s=") {
-                salt = Some(BASE64.decode(value)
-                    .map_err(|_| IronError::Sasl("Invalid salt encoding".to_string()))?);
-            } else if let Some(value) = part.strip_prefix("i=") {
-                iterations = Some(value.parse()
-                    .map_err(|_| IronError::Sasl("Invalid iteration count".to_string()))?);
-            }
+    /// The mechanism instance, created on first use
+    fn mechanism(&mut self) -> Result<&mut Box<dyn Mechanism>> {
+        if self.mechanism.is_none() {
+            let info = self.info.as_ref()
+                .ok_or_else(|| IronError::Sasl("No SASL mechanism configured".to_string()))?;
+            self.mechanism = Some(info.create(&self.credentials)?);
         }
-
-        let server_nonce = server_nonce
-            .ok_or_else(|| IronError::Sasl("Missing server nonce".to_string()))?;
-        let salt = salt
-            .ok_or_else(|| IronError::Sasl("Missing salt".to_string()))?;
-        let iterations = iterations
-            .ok_or_else(|| IronError::Sasl("Missing iteration count".to_string()))?;
-
-        // Store for potential future verification
-        self.server_nonce = Some(server_nonce.clone());
-        self.salt = Some(salt.clone());
-        self.iterations = Some(iterations);
-
-        // Generate salted password
-        let salted_password = self.pbkdf2_sha256(password.as_bytes(), &salt, iterations)?;
-
-        // Generate client key
-        let client_key = self.hmac_sha256(&salted_password, b"Client Key")?;
-        let stored_key = Sha256::digest(&client_key);
-
-        // Create auth message
-        let auth_message = format!("n={},r={},r={},s={},i={},c=biws,r={}",
-            self.username, client_nonce, server_nonce, 
-            BASE64.encode(&salt), iterations, server_nonce);
-
-        // Generate client signature and proof
-        let client_signature = self.hmac_sha256(&stored_key, auth_message.as_bytes())?;
-        let client_proof: Vec<u8> = client_key.iter().zip(client_signature.iter())
-            .map(|(a, b)| a ^ b)
-            .collect();
-
-        // Format final response
-        let response = format!("c=biws,r={},p={}", server_nonce, BASE64.encode(&client_proof));
-        Ok(BASE64.encode(response.as_bytes()))
-    }
-
-    /// PBKDF2-SHA256 key derivation
-    fn pbkdf2_sha256(&self, password: &[u8], salt: &[u8], iterations: u32) -> Result<Vec<u8>> {
-        let mut result = vec![0u8; 32]; // SHA-256 output size
-        pbkdf2::<HmacSha256>(password, salt, iterations, &mut result)
-            .map_err(|_| IronError::Sasl("PBKDF2 failed".to_string()))?;
-        Ok(result)
-    }
-
-    /// HMAC-SHA256
-    fn hmac_sha256(&self, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
-        let mut mac = HmacSha256::new_from_slice(key)
-            .map_err(|_| IronError::Sasl("HMAC key error".to_string()))?;
-        mac.update(data);
-        Ok(mac.finalize().into_bytes().to_vec())
+        self.mechanism.as_mut()
+            .ok_or_else(|| IronError::Sasl("No SASL mechanism configured".to_string()))
     }
 }
 
 /// Choose the best SASL mechanism from available options
+///
+/// `-PLUS` mechanisms are never chosen since no channel binding data is
+/// available; see [`choose_best_mechanism_with_binding`].
 pub fn choose_best_mechanism(available: &[String], tls_enabled: bool) -> Option<SaslMechanism> {
-    let mut mechanisms: Vec<SaslMechanism> = available
-        .iter()
-        .filter_map(|s| SaslMechanism::from_str(s))
-        .collect();
-
-    // Sort by security strength (descending)
-    mechanisms.sort_by(|a, b| b.security_strength().cmp(&a.security_strength()));
-
-    // If TLS is not enabled, prefer secure mechanisms
-    if !tls_enabled {
-        mechanisms.retain(|m| m.is_secure());
-    }
+    choose_best_mechanism_with_binding(available, tls_enabled, None)
+}
 
-    mechanisms.into_iter().next()
+/// Choose the best SASL mechanism, considering `-PLUS` variants if the
+/// caller has TLS channel binding data
+///
+/// Only built-in mechanisms are considered; use
+/// [`MechanismRegistry::choose_best`] to include custom ones.
+pub fn choose_best_mechanism_with_binding(
+    available: &[String],
+    tls_enabled: bool,
+    binding: Option<&ChannelBinding>,
+) -> Option<SaslMechanism> {
+    builtin_registry()
+        .choose_best(available, tls_enabled, binding.is_some())
+        .and_then(|info| SaslMechanism::from_str(info.name()))
 }
 
 /// Validate SASL mechanism list from server
+///
+/// Fails unless at least one listed mechanism is built in; use
+/// [`MechanismRegistry::validate_list`] to include custom ones.
 pub fn validate_mechanism_list(mechanisms: &str) -> Result<Vec<String>> {
-    let mechs: Vec<String> = mechanisms
-        .split(',')
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect();
-
-    if mechs.is_empty() {
-        return Err(IronError::Sasl("No SASL mechanisms available".to_string()));
-    }
-
-    // Validate each mechanism name
-    for mech in &mechs {
-        if mech.len() > 32 || !mech.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
-            return Err(IronError::Sasl(
-                format!("Invalid mechanism name: {}", mech)
-            ));
-        }
-    }
-
-    Ok(mechs)
+    builtin_registry().validate_list(mechanisms)
 }
 
 #[cfg(test)]
@@ -307,7 +423,11 @@ mod tests {
         assert_eq!(SaslMechanism::from_str("PLAIN"), Some(SaslMechanism::Plain));
         assert_eq!(SaslMechanism::from_str("plain"), Some(SaslMechanism::Plain));
         assert_eq!(SaslMechanism::from_str("SCRAM-SHA-256"), Some(SaslMechanism::ScramSha256));
+        assert_eq!(SaslMechanism::from_str("scram-sha-512-plus"), Some(SaslMechanism::ScramSha512Plus));
         assert_eq!(SaslMechanism::from_str("UNKNOWN"), None);
+        assert_eq!(SaslMechanism::scram(ScramHash::Sha1, true), SaslMechanism::ScramSha1Plus);
+        assert_eq!(SaslMechanism::ScramSha1Plus.scram_hash(), Some(ScramHash::Sha1));
+        assert!(!SaslMechanism::ScramSha512.is_plus());
     }
 
     #[test]
@@ -332,6 +452,17 @@ mod tests {
         assert_eq!(auth_string, "\0testuser\0testpass");
     }
 
+    #[test]
+    fn test_plain_and_external_authzid() {
+        let mut auth = SaslAuth::new(SaslMechanism::Plain, "bouncer".to_string(), Some("pw".to_string()))
+            .with_authzid("alice");
+        let decoded = BASE64.decode(auth.generate_initial_response().unwrap()).unwrap();
+        assert_eq!(decoded, b"alice\0bouncer\0pw");
+
+        let mut auth = SaslAuth::new(SaslMechanism::External, String::new(), None).with_authzid("alice");
+        assert_eq!(auth.generate_initial_response().unwrap(), BASE64.encode("alice"));
+    }
+
     #[test]
     fn test_external_authentication() {
         let mut auth = SaslAuth::new(
@@ -357,6 +488,21 @@ mod tests {
         assert_eq!(best_no_tls, SaslMechanism::External);
     }
 
+    #[test]
+    fn test_mechanism_selection_with_binding() {
+        let available: Vec<String> = ["SCRAM-SHA-1", "SCRAM-SHA-512", "SCRAM-SHA-256-PLUS", "PLAIN"]
+            .iter().map(|s| s.to_string()).collect();
+
+        // Without binding data the PLUS variant is unusable
+        assert_eq!(choose_best_mechanism(&available, true), Some(SaslMechanism::ScramSha512));
+
+        let binding = ChannelBinding::tls_exporter(vec![0u8; 32]);
+        assert_eq!(
+            choose_best_mechanism_with_binding(&available, true, Some(&binding)),
+            Some(SaslMechanism::ScramSha256Plus)
+        );
+    }
+
     #[test]
     fn test_mechanism_validation() {
         assert!(validate_mechanism_list("PLAIN,SCRAM-SHA-256").is_ok());
@@ -384,4 +530,184 @@ mod tests {
         assert!(auth.is_complete());
         assert!(!auth.is_success());
     }
+
+    fn scram_server() -> SaslServer {
+        let mut store = MemoryCredentialStore::new();
+        store.set_password("alice", "wonderland", 4096).unwrap();
+        SaslServer::new(std::sync::Arc::new(store), "irc.example.com")
+    }
+
+    #[test]
+    fn test_scram_against_server() {
+        let mut server = scram_server();
+        let mut auth = SaslAuth::new(
+            SaslMechanism::ScramSha256,
+            "alice".to_string(),
+            Some("wonderland".to_string())
+        );
+
+        server.handle_authenticate("SCRAM-SHA-256");
+        let client_first = auth.generate_initial_response().unwrap();
+        let server_first = server.handle_authenticate(&client_first);
+        let client_final = auth.process_challenge(&server_first[0].params[0]).unwrap();
+        let server_final = server.handle_authenticate(&client_final);
+
+        let ack = auth.process_challenge(&server_final[0].params[0]).unwrap();
+        assert_eq!(ack, "+");
+        assert!(auth.is_server_verified());
+
+        let done = server.handle_authenticate(&ack);
+        assert_eq!(done[1].command, "903");
+        auth.mark_success();
+        assert!(auth.is_success());
+    }
+
+    #[test]
+    fn test_scram_plus_against_server() {
+        let binding = ChannelBinding::tls_exporter(vec![42u8; 32]);
+        let mut server = scram_server().with_mechanisms(vec![SaslMechanism::ScramSha512Plus]);
+        server.set_channel_binding(Some(binding.clone()));
+        let mut auth = SaslAuth::new(
+            SaslMechanism::ScramSha512Plus,
+            "alice".to_string(),
+            Some("wonderland".to_string())
+        ).with_channel_binding(binding);
+
+        server.handle_authenticate("SCRAM-SHA-512-PLUS");
+        let client_first = auth.generate_initial_response().unwrap();
+        let server_first = server.handle_authenticate(&client_first);
+        let client_final = auth.process_challenge(&server_first[0].params[0]).unwrap();
+        let server_final = server.handle_authenticate(&client_final);
+        let ack = auth.process_challenge(&server_final[0].params[0]).unwrap();
+        assert!(auth.is_server_verified());
+        assert_eq!(server.handle_authenticate(&ack)[1].command, "903");
+
+        // A -PLUS mechanism can't start without binding data
+        let mut auth = SaslAuth::new(
+            SaslMechanism::ScramSha256Plus,
+            "alice".to_string(),
+            Some("wonderland".to_string())
+        );
+        assert!(auth.generate_initial_response().is_err());
+    }
+
+    #[test]
+    fn test_scram_chunked_exchange() {
+        let mut server = scram_server();
+        // A long username pushes the client-first-message past one line
+        let mut auth = SaslAuth::new(
+            SaslMechanism::ScramSha256,
+            "a".repeat(350),
+            Some("wonderland".to_string())
+        );
+
+        let mut to_server = vec![IrcMessage::new("AUTHENTICATE").with_params(vec!["SCRAM-SHA-256".to_string()])];
+        let mut rounds = 0;
+        while !to_server.is_empty() {
+            let mut to_client = Vec::new();
+            for message in &to_server {
+                to_client.extend(server.handle_authenticate(&message.params[0]));
+            }
+            to_server.clear();
+            for message in to_client.iter().filter(|m| m.command == "AUTHENTICATE") {
+                to_server.extend(auth.handle_authenticate(&message.params[0]).unwrap());
+            }
+            if rounds == 0 {
+                assert_eq!(to_server.len(), 2);
+            }
+            rounds += 1;
+        }
+
+        // Unknown account, so the server rejects the proof
+        assert_eq!(rounds, 3);
+        assert!(!auth.is_server_verified());
+    }
+
+    #[test]
+    fn test_ecdsa_against_server() {
+        let key = EcdsaPrivateKey::generate();
+        let mut store = MemoryCredentialStore::new();
+        store.set_ecdsa_key("bot", key.public_key());
+        let mut server = SaslServer::new(std::sync::Arc::new(store), "irc.example.com")
+            .with_mechanisms(vec![SaslMechanism::EcdsaNist256pChallenge]);
+        let mut auth = SaslAuth::new(SaslMechanism::EcdsaNist256pChallenge, "bot".to_string(), None)
+            .with_ecdsa_key(key);
+
+        let ready = server.handle_authenticate("ECDSA-NIST256P-CHALLENGE");
+        let first = auth.handle_authenticate(&ready[0].params[0]).unwrap();
+        let challenge = server.handle_authenticate(&first[0].params[0]);
+        let signature = auth.handle_authenticate(&challenge[0].params[0]).unwrap();
+        let done = server.handle_authenticate(&signature[0].params[0]);
+        assert_eq!(done[1].command, "903");
+
+        auth.mark_success();
+        assert!(auth.is_success());
+        assert!(SaslMechanism::EcdsaNist256pChallenge.is_secure());
+        assert_eq!(
+            choose_best_mechanism(&["PLAIN".to_string(), "ECDSA-NIST256P-CHALLENGE".to_string()], false),
+            Some(SaslMechanism::EcdsaNist256pChallenge)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_oauthbearer_error_handling() {
+        let validator = std::sync::Arc::new(HmacJwtValidator::new("secret"));
+        let mut server = scram_server()
+            .with_mechanisms(vec![SaslMechanism::OAuthBearer])
+            .with_token_validator(validator);
+        let mut auth = SaslAuth::new(SaslMechanism::OAuthBearer, String::new(), Some("expired".to_string()));
+
+        let ready = server.handle_authenticate("OAUTHBEARER");
+        let first = auth.handle_authenticate(&ready[0].params[0]).unwrap();
+        let challenge = server.handle_authenticate(&first[0].params[0]);
+        let ack = auth.handle_authenticate(&challenge[0].params[0]).unwrap();
+        assert_eq!(server.handle_authenticate(&ack[0].params[0])[0].command, "904");
+
+        let error = OAuthBearerError::from_json(auth.oauth_error().unwrap()).unwrap();
+        assert_eq!(error.status, "invalid_token");
+    }
+
+    #[test]
+    fn test_scram_authzid_against_server() {
+        let policy = |_: &str, authzid: &str| authzid == "ops";
+        let mut server = scram_server().with_authorization_policy(std::sync::Arc::new(policy));
+        let mut auth = SaslAuth::new(
+            SaslMechanism::ScramSha256,
+            "alice".to_string(),
+            Some("wonderland".to_string())
+        ).with_authzid("ops");
+
+        let ready = server.handle_authenticate("SCRAM-SHA-256");
+        let mut to_server = auth.handle_authenticate(&ready[0].params[0]).unwrap();
+        let mut replies = Vec::new();
+        while let Some(message) = to_server.pop() {
+            replies = server.handle_authenticate(&message.params[0]);
+            if replies[0].command == "AUTHENTICATE" {
+                to_server = auth.handle_authenticate(&replies[0].params[0]).unwrap();
+            }
+        }
+        assert_eq!(replies[1].command, "903");
+        assert_eq!(server.account(), Some("ops"));
+    }
+
+    #[test]
+    fn test_scram_success_requires_server_signature() {
+        let mut server = scram_server();
+        let mut auth = SaslAuth::new(
+            SaslMechanism::ScramSha256,
+            "alice".to_string(),
+            Some("wonderland".to_string())
+        );
+
+        server.handle_authenticate("SCRAM-SHA-256");
+        let client_first = auth.generate_initial_response().unwrap();
+        let server_first = server.handle_authenticate(&client_first);
+        auth.process_challenge(&server_first[0].params[0]).unwrap();
+
+        // A fake server skips the v= step and claims success
+        auth.mark_success();
+        assert!(auth.is_complete());
+        assert!(!auth.is_success());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,480 @@
+//! Client-side SASL session driver
+//!
+//! [`SaslClient`] ties [`SaslAuth`] to the wire. It picks mechanisms from the
+//! server's `sasl` capability value, relays `AUTHENTICATE` lines and falls
+//! back to the next mechanism on `ERR_SASLFAIL`. It tracks the account
+//! reported by `RPL_LOGGEDIN`, and after registration it authenticates again
+//! when the server re-advertises `sasl` in `CAP NEW`.
+
+use super::ecdsa::EcdsaPrivateKey;
+use super::mechanism::{builtin_registry, MechanismInfo, MechanismRegistry, SaslCredentials};
+use super::scram::ChannelBinding;
+use super::SaslAuth;
+use crate::capabilities::CapabilityHandler;
+use crate::error::{IronError, Result};
+use crate::message::IrcMessage;
+use crate::secret::Secret;
+use std::cmp::Reverse;
+use std::sync::Arc;
+
+/// Progress of a client's SASL session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaslClientState {
+    /// No exchange has been started
+    Idle,
+    /// An exchange is in progress
+    Authenticating,
+    /// The server accepted a mechanism (or we were already authenticated)
+    Success,
+    /// Every usable mechanism failed or the exchange was aborted
+    Failed,
+}
+
+/// Drives SASL authentication over a client connection
+pub struct SaslClient {
+    registry: Arc<MechanismRegistry>,
+    credentials: SaslCredentials,
+    tls_enabled: bool,
+    candidates: Vec<String>,
+    auth: Option<SaslAuth>,
+    aborting: bool,
+    sasl_enabled: bool,
+    pending_reauth: Option<Vec<String>>,
+    state: SaslClientState,
+    account: Option<String>,
+    error: Option<String>,
+}
+
+impl SaslClient {
+    /// Create a driver for `username` (and `password` or bearer token)
+    pub fn new(username: impl Into<String>, password: Option<String>) -> Self {
+        Self {
+            registry: builtin_registry(),
+            credentials: SaslCredentials {
+                username: username.into(),
+                password: password.map(Secret::from),
+                ..SaslCredentials::default()
+            },
+            tls_enabled: false,
+            candidates: Vec::new(),
+            auth: None,
+            aborting: false,
+            sasl_enabled: false,
+            pending_reauth: None,
+            state: SaslClientState::Idle,
+            account: None,
+            error: None,
+        }
+    }
+
+    /// Use mechanisms from `registry` instead of the built-in ones
+    pub fn with_registry(mut self, registry: Arc<MechanismRegistry>) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Set whether the connection is encrypted
+    ///
+    /// Without TLS, mechanisms that reveal the password are never tried.
+    pub fn with_tls(mut self, tls_enabled: bool) -> Self {
+        self.tls_enabled = tls_enabled;
+        self
+    }
+
+    /// Authenticate as the username but act as `authzid`
+    pub fn with_authzid(mut self, authzid: impl Into<String>) -> Self {
+        let authzid = authzid.into();
+        self.credentials.authzid = if authzid.is_empty() { None } else { Some(authzid) };
+        self
+    }
+
+    /// Supply TLS channel binding data, enabling the `-PLUS` mechanisms
+    pub fn with_channel_binding(mut self, binding: ChannelBinding) -> Self {
+        self.credentials.channel_binding = Some(binding);
+        self
+    }
+
+    /// Supply the private key for ECDSA-NIST256P-CHALLENGE
+    pub fn with_ecdsa_key(mut self, key: EcdsaPrivateKey) -> Self {
+        self.credentials.ecdsa_key = Some(key);
+        self
+    }
+
+    /// Start authenticating with the mechanisms the server advertised
+    ///
+    /// Call once the `sasl` capability is acknowledged.
+    pub fn start(&mut self, caps: &CapabilityHandler) -> Result<Vec<IrcMessage>> {
+        self.start_with(&caps.get_sasl_mechanisms())
+    }
+
+    /// Start authenticating with an explicit mechanism list
+    ///
+    /// An empty list (a `sasl` capability without a value) tries every
+    /// registered mechanism. Fails if none is usable with our credentials.
+    pub fn start_with(&mut self, advertised: &[String]) -> Result<Vec<IrcMessage>> {
+        let names: Vec<String> = if advertised.is_empty() {
+            self.registry.names().into_iter().map(str::to_string).collect()
+        } else {
+            advertised.to_vec()
+        };
+
+        let has_binding = self.credentials.channel_binding.is_some();
+        let mut usable: Vec<&MechanismInfo> = names.iter()
+            .filter_map(|name| self.registry.get(name.trim()))
+            .filter(|info| has_binding || !info.requires_channel_binding())
+            .filter(|info| self.tls_enabled || info.is_secure())
+            .collect();
+        usable.sort_by_key(|info| Reverse(info.security_strength()));
+
+        self.candidates.clear();
+        for info in usable {
+            if !self.candidates.iter().any(|name| name == info.name()) {
+                self.candidates.push(info.name().to_string());
+            }
+        }
+        self.sasl_enabled = true;
+        self.aborting = false;
+        self.error = None;
+
+        let messages = self.next_attempt();
+        if messages.is_empty() {
+            return Err(IronError::Sasl("No usable SASL mechanism".to_string()));
+        }
+        Ok(messages)
+    }
+
+    /// Process a message from the server
+    ///
+    /// Handles `AUTHENTICATE`, the 900-908 numerics and `CAP` subcommands
+    /// affecting `sasl`; anything else is ignored. Returns the messages to
+    /// send. A server claiming success without proving its identity (SCRAM)
+    /// is reported as [`IronError::SecurityViolation`].
+    pub fn handle_message(&mut self, message: &IrcMessage) -> Result<Vec<IrcMessage>> {
+        match message.command.as_str() {
+            "AUTHENTICATE" => {
+                let param = message.params.first()
+                    .ok_or_else(|| IronError::Protocol("AUTHENTICATE without parameter".to_string()))?;
+                let auth = match self.auth.as_mut() {
+                    Some(auth) if !self.aborting => auth,
+                    _ => return Ok(Vec::new()),
+                };
+                match auth.handle_authenticate(param) {
+                    Ok(messages) => Ok(messages),
+                    Err(e) => {
+                        // Never fall back after a local failure: a server
+                        // that failed to prove itself must not see weaker
+                        // mechanisms
+                        self.error = Some(e.to_string());
+                        Ok(self.abort())
+                    }
+                }
+            }
+            // RPL_LOGGEDIN
+            "900" => {
+                self.account = message.params.get(2).cloned();
+                Ok(Vec::new())
+            }
+            // RPL_LOGGEDOUT
+            "901" => {
+                self.account = None;
+                Ok(Vec::new())
+            }
+            // ERR_NICKLOCKED: no mechanism can succeed with this nick
+            "902" => {
+                self.fail(trailing(message));
+                Ok(Vec::new())
+            }
+            // RPL_SASLSUCCESS
+            "903" => {
+                if let Some(mut auth) = self.auth.take() {
+                    auth.mark_success();
+                    if !auth.is_success() {
+                        self.fail(Some("Server did not prove its identity".to_string()));
+                        return Err(IronError::SecurityViolation(
+                            "SASL success reported without server verification".to_string()
+                        ));
+                    }
+                }
+                self.finish(SaslClientState::Success);
+                Ok(Vec::new())
+            }
+            // ERR_SASLFAIL, ERR_SASLTOOLONG: try the next mechanism
+            "904" | "905" => {
+                if self.state != SaslClientState::Authenticating {
+                    return Ok(Vec::new());
+                }
+                self.error = trailing(message);
+                if self.aborting {
+                    self.fail(None);
+                    return Ok(Vec::new());
+                }
+                Ok(self.next_attempt())
+            }
+            // ERR_SASLABORTED
+            "906" => {
+                if self.state == SaslClientState::Authenticating {
+                    let reason = self.error.take().or_else(|| trailing(message));
+                    self.fail(reason);
+                }
+                Ok(Vec::new())
+            }
+            // ERR_SASLALREADY
+            "907" => {
+                self.finish(SaslClientState::Success);
+                Ok(Vec::new())
+            }
+            // RPL_SASLMECHS, sent before ERR_SASLFAIL
+            "908" => {
+                if let Some(list) = message.params.get(1) {
+                    let offered: Vec<&str> = list.split(',').map(str::trim).collect();
+                    self.candidates.retain(|c| offered.iter().any(|o| o.eq_ignore_ascii_case(c)));
+                }
+                Ok(Vec::new())
+            }
+            "CAP" => self.handle_cap(message),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Abort the exchange in progress
+    pub fn abort(&mut self) -> Vec<IrcMessage> {
+        if self.state != SaslClientState::Authenticating || self.aborting {
+            return Vec::new();
+        }
+        self.aborting = true;
+        self.candidates.clear();
+        vec![IrcMessage::new("AUTHENTICATE").with_params(vec!["*".to_string()])]
+    }
+
+    /// Current session state
+    pub fn state(&self) -> SaslClientState {
+        self.state
+    }
+
+    /// Check if the session has finished, successfully or not
+    pub fn is_complete(&self) -> bool {
+        matches!(self.state, SaslClientState::Success | SaslClientState::Failed)
+    }
+
+    /// Check if the server accepted our credentials
+    pub fn is_authenticated(&self) -> bool {
+        self.state == SaslClientState::Success
+    }
+
+    /// The account reported by `RPL_LOGGEDIN`
+    pub fn account(&self) -> Option<&str> {
+        self.account.as_deref()
+    }
+
+    /// The mechanism being tried
+    pub fn mechanism(&self) -> Option<&str> {
+        self.auth.as_ref().map(|auth| auth.mechanism_name())
+    }
+
+    /// Why the last attempt failed, if known
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    fn handle_cap(&mut self, message: &IrcMessage) -> Result<Vec<IrcMessage>> {
+        let subcommand = message.params.get(1).map(|s| s.to_uppercase()).unwrap_or_default();
+        let caps = message.params.last().map(String::as_str).unwrap_or("");
+        let sasl = caps.split_whitespace().find_map(|cap| {
+            let (name, value) = cap.split_once('=').unwrap_or((cap, ""));
+            (name == "sasl").then_some(value)
+        });
+        let sasl = match sasl {
+            Some(value) => value,
+            None => return Ok(Vec::new()),
+        };
+
+        match subcommand.as_str() {
+            "NEW" if self.state != SaslClientState::Authenticating => {
+                let mechanisms: Vec<String> = sasl.split(',')
+                    .filter(|m| !m.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                if self.sasl_enabled {
+                    return self.start_with(&mechanisms);
+                }
+                // Request the capability and authenticate once it is acked
+                self.pending_reauth = Some(mechanisms);
+                Ok(vec![IrcMessage::new("CAP").with_params(vec!["REQ".to_string(), "sasl".to_string()])])
+            }
+            "ACK" => {
+                self.sasl_enabled = true;
+                match self.pending_reauth.take() {
+                    Some(mechanisms) => self.start_with(&mechanisms),
+                    None => Ok(Vec::new()),
+                }
+            }
+            "NAK" => {
+                self.pending_reauth = None;
+                Ok(Vec::new())
+            }
+            "DEL" => {
+                self.sasl_enabled = false;
+                if self.state == SaslClientState::Authenticating {
+                    self.fail(Some("Server withdrew the sasl capability".to_string()));
+                }
+                Ok(Vec::new())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Start the next candidate mechanism, or fail if none are left
+    fn next_attempt(&mut self) -> Vec<IrcMessage> {
+        while !self.candidates.is_empty() {
+            let name = self.candidates.remove(0);
+            let info = match self.registry.get(&name) {
+                Some(info) => info,
+                None => continue,
+            };
+            // Skip mechanisms our credentials can't drive (e.g. no key)
+            if info.create(&self.credentials).is_err() {
+                continue;
+            }
+            self.auth = Some(SaslAuth::from_parts(info.clone(), self.credentials.clone()));
+            self.state = SaslClientState::Authenticating;
+            return vec![IrcMessage::new("AUTHENTICATE").with_params(vec![info.name().to_string()])];
+        }
+        self.fail(None);
+        Vec::new()
+    }
+
+    fn fail(&mut self, reason: Option<String>) {
+        if reason.is_some() {
+            self.error = reason;
+        }
+        self.finish(SaslClientState::Failed);
+    }
+
+    fn finish(&mut self, state: SaslClientState) {
+        self.state = state;
+        self.auth = None;
+        self.candidates.clear();
+        self.aborting = false;
+    }
+}
+
+fn trailing(message: &IrcMessage) -> Option<String> {
+    message.params.last().cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sasl::{MemoryCredentialStore, SaslMechanism, SaslServer};
+
+    fn server(mechanisms: Vec<SaslMechanism>) -> SaslServer {
+        let mut store = MemoryCredentialStore::new();
+        store.set_password("alice", "wonderland", 4096).unwrap();
+        let mut sasl = SaslServer::new(Arc::new(store), "irc.example.com").with_mechanisms(mechanisms);
+        sasl.set_client("alice", "alice!a@host");
+        sasl
+    }
+
+    /// Relay messages between client and server until neither has more to say
+    fn run(client: &mut SaslClient, server: &mut SaslServer, mut outbound: Vec<IrcMessage>) -> Vec<String> {
+        let mut numerics = Vec::new();
+        while !outbound.is_empty() {
+            let mut replies = Vec::new();
+            for message in outbound.drain(..) {
+                replies.extend(server.handle_authenticate(&message.params[0]));
+            }
+            for reply in replies {
+                if reply.command != "AUTHENTICATE" {
+                    numerics.push(reply.command.clone());
+                }
+                outbound.extend(client.handle_message(&reply).unwrap());
+            }
+        }
+        numerics
+    }
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_falls_back_to_next_mechanism() {
+        let mut sasl = server(vec![SaslMechanism::Plain]);
+        let mut client = SaslClient::new("alice", Some("wonderland".to_string())).with_tls(true);
+
+        let first = client.start_with(&names(&["PLAIN", "SCRAM-SHA-256"])).unwrap();
+        assert_eq!(first[0].params[0], "SCRAM-SHA-256");
+
+        let numerics = run(&mut client, &mut sasl, first);
+        assert_eq!(numerics, vec!["908", "904", "900", "903"]);
+        assert!(client.is_authenticated());
+        assert_eq!(client.account(), Some("alice"));
+    }
+
+    #[test]
+    fn test_fails_when_mechanisms_exhausted() {
+        let mut sasl = server(vec![SaslMechanism::ScramSha256]);
+        let mut client = SaslClient::new("alice", Some("wrong".to_string()));
+
+        // Without TLS, PLAIN is never offered to the server
+        let first = client.start_with(&names(&["PLAIN", "SCRAM-SHA-256"])).unwrap();
+        run(&mut client, &mut sasl, first);
+        assert_eq!(client.state(), SaslClientState::Failed);
+        assert!(client.account().is_none());
+
+        assert!(SaslClient::new("alice", None).start_with(&names(&["PLAIN"])).is_err());
+    }
+
+    #[test]
+    fn test_nick_locked_and_already_authenticated() {
+        let mut client = SaslClient::new("alice", Some("pw".to_string())).with_tls(true);
+        client.start_with(&names(&["PLAIN"])).unwrap();
+        let locked = IrcMessage::new("902").with_params(names(&["alice", "You must use a nick assigned to you"]));
+        client.handle_message(&locked).unwrap();
+        assert_eq!(client.state(), SaslClientState::Failed);
+        assert_eq!(client.error(), Some("You must use a nick assigned to you"));
+
+        client.start_with(&names(&["PLAIN"])).unwrap();
+        client.handle_message(&IrcMessage::new("907").with_params(names(&["alice", "already"]))).unwrap();
+        assert!(client.is_authenticated());
+    }
+
+    #[test]
+    fn test_local_error_aborts_without_fallback() {
+        let mut client = SaslClient::new("alice", Some("pw".to_string())).with_tls(true);
+        client.start_with(&names(&["SCRAM-SHA-256", "PLAIN"])).unwrap();
+        client.handle_message(&IrcMessage::new("AUTHENTICATE").with_params(names(&["+"]))).unwrap();
+
+        // A malformed server-first-message
+        let reply = client.handle_message(&IrcMessage::new("AUTHENTICATE").with_params(names(&["Ym9ndXM="])))
+            .unwrap();
+        assert_eq!(reply[0].params[0], "*");
+        client.handle_message(&IrcMessage::new("904").with_params(names(&["alice", "failed"]))).unwrap();
+        assert_eq!(client.state(), SaslClientState::Failed);
+        assert!(client.mechanism().is_none());
+    }
+
+    #[test]
+    fn test_reauthenticates_on_cap_new() {
+        let mut sasl = server(vec![SaslMechanism::Plain]);
+        let mut client = SaslClient::new("alice", Some("wonderland".to_string())).with_tls(true);
+        let first = client.start_with(&names(&["PLAIN"])).unwrap();
+        run(&mut client, &mut sasl, first);
+        assert!(client.is_authenticated());
+
+        let del = IrcMessage::new("CAP").with_params(names(&["alice", "DEL", "sasl"]));
+        assert!(client.handle_message(&del).unwrap().is_empty());
+
+        let new = IrcMessage::new("CAP").with_params(names(&["alice", "NEW", "sasl=PLAIN,EXTERNAL"]));
+        let request = client.handle_message(&new).unwrap();
+        assert_eq!(request[0].params, names(&["REQ", "sasl"]));
+
+        let ack = IrcMessage::new("CAP").with_params(names(&["alice", "ACK", "sasl"]));
+        let restart = client.handle_message(&ack).unwrap();
+        assert_eq!(restart[0].params[0], "EXTERNAL");
+
+        let mut sasl = server(vec![SaslMechanism::Plain]);
+        assert_eq!(run(&mut client, &mut sasl, restart), vec!["908", "904", "900", "903"]);
+        assert!(client.is_authenticated());
+    }
+}
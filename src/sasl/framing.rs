@@ -0,0 +1,170 @@
+//! `AUTHENTICATE` payload framing
+//!
+//! SASL payloads are base64-encoded and split into `AUTHENTICATE` lines of
+//! at most 400 bytes. A line shorter than 400 bytes ends the payload; if the
+//! encoded length is an exact multiple of 400, an extra `AUTHENTICATE +`
+//! terminates it. An empty payload is sent as a lone `+`.
+
+use crate::error::{IronError, Result};
+use crate::message::IrcMessage;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+
+/// Maximum length of a single `AUTHENTICATE` parameter
+pub const AUTHENTICATE_CHUNK_SIZE: usize = 400;
+
+/// Default cap on the total encoded size of a reassembled payload
+pub const DEFAULT_MAX_PAYLOAD: usize = 16 * 1024;
+
+/// Encode a raw payload as `AUTHENTICATE` lines
+pub fn encode_authenticate(data: &[u8]) -> Vec<IrcMessage> {
+    chunk_authenticate(&BASE64.encode(data))
+}
+
+/// Split an already base64-encoded payload into `AUTHENTICATE` lines
+///
+/// An empty string or a lone `+` both denote the empty payload.
+pub fn chunk_authenticate(encoded: &str) -> Vec<IrcMessage> {
+    if encoded.is_empty() || encoded == "+" {
+        return vec![authenticate("+")];
+    }
+
+    // base64 is pure ASCII, so byte offsets are char boundaries
+    let mut messages: Vec<IrcMessage> = encoded.as_bytes()
+        .chunks(AUTHENTICATE_CHUNK_SIZE)
+        .map(|chunk| authenticate(std::str::from_utf8(chunk).unwrap_or_default()))
+        .collect();
+    if encoded.len().is_multiple_of(AUTHENTICATE_CHUNK_SIZE) {
+        messages.push(authenticate("+"));
+    }
+    messages
+}
+
+fn authenticate(param: &str) -> IrcMessage {
+    IrcMessage::new("AUTHENTICATE").with_params(vec![param.to_string()])
+}
+
+/// Reassembles inbound `AUTHENTICATE` chunks into a decoded payload
+#[derive(Debug, Clone)]
+pub struct AuthenticateBuffer {
+    buffer: String,
+    max_len: usize,
+}
+
+impl Default for AuthenticateBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AuthenticateBuffer {
+    /// Create a buffer with the default size cap
+    pub fn new() -> Self {
+        Self::with_max_len(DEFAULT_MAX_PAYLOAD)
+    }
+
+    /// Create a buffer that rejects payloads over `max_len` encoded bytes
+    pub fn with_max_len(max_len: usize) -> Self {
+        Self {
+            buffer: String::new(),
+            max_len,
+        }
+    }
+
+    /// Add one `AUTHENTICATE` parameter
+    ///
+    /// Returns the decoded payload once it is complete, or `None` while more
+    /// chunks are expected. Oversized lines and payloads are reported as
+    /// [`IronError::Protocol`] (i.e. `ERR_SASLTOOLONG`); malformed base64 as
+    /// [`IronError::Sasl`]. The buffer is cleared after any error.
+    pub fn push(&mut self, param: &str) -> Result<Option<Vec<u8>>> {
+        if param.len() > AUTHENTICATE_CHUNK_SIZE {
+            self.clear();
+            return Err(IronError::Protocol(format!(
+                "AUTHENTICATE parameter exceeds {} bytes", AUTHENTICATE_CHUNK_SIZE
+            )));
+        }
+
+        if param != "+" {
+            if self.buffer.len() + param.len() > self.max_len {
+                self.clear();
+                return Err(IronError::Protocol(format!(
+                    "SASL payload exceeds {} bytes", self.max_len
+                )));
+            }
+            self.buffer.push_str(param);
+
+            // A full-length chunk means more data follows
+            if param.len() == AUTHENTICATE_CHUNK_SIZE {
+                return Ok(None);
+            }
+        }
+
+        let encoded = std::mem::take(&mut self.buffer);
+        BASE64.decode(encoded.as_bytes())
+            .map(Some)
+            .map_err(|_| IronError::Sasl("Invalid base64 in AUTHENTICATE payload".to_string()))
+    }
+
+    /// Check if a partial payload is buffered
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Discard any partial payload
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(messages: &[IrcMessage]) -> Vec<String> {
+        messages.iter().map(|m| m.params[0].clone()).collect()
+    }
+
+    #[test]
+    fn test_chunking() {
+        assert_eq!(params(&encode_authenticate(b"")), vec!["+"]);
+        assert_eq!(params(&encode_authenticate(b"abc")), vec!["YWJj"]);
+
+        // 300 raw bytes encode to exactly 400 characters
+        let exact = encode_authenticate(&[0u8; 300]);
+        assert_eq!(exact.len(), 2);
+        assert_eq!(exact[0].params[0].len(), 400);
+        assert_eq!(exact[1].params[0], "+");
+
+        let long = encode_authenticate(&[0u8; 700]);
+        let lens: Vec<usize> = long.iter().map(|m| m.params[0].len()).collect();
+        assert_eq!(lens, vec![400, 400, 136]);
+    }
+
+    #[test]
+    fn test_reassembly_roundtrip() {
+        for size in [0usize, 1, 299, 300, 301, 600, 1000] {
+            let data: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let mut buffer = AuthenticateBuffer::new();
+            let mut result = None;
+            for message in encode_authenticate(&data) {
+                assert!(result.is_none());
+                result = buffer.push(&message.params[0]).unwrap();
+            }
+            assert_eq!(result, Some(data));
+            assert!(buffer.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let mut buffer = AuthenticateBuffer::with_max_len(800);
+        assert!(matches!(buffer.push(&"A".repeat(401)), Err(IronError::Protocol(_))));
+
+        assert_eq!(buffer.push(&"A".repeat(400)).unwrap(), None);
+        assert_eq!(buffer.push(&"A".repeat(400)).unwrap(), None);
+        assert!(matches!(buffer.push("AAAA"), Err(IronError::Protocol(_))));
+        assert!(buffer.is_empty());
+
+        assert!(matches!(buffer.push("!!!"), Err(IronError::Sasl(_))));
+    }
+}
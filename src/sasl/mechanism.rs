@@ -0,0 +1,507 @@
+//! Pluggable client-side SASL mechanisms
+//!
+//! A [`Mechanism`] drives one side of an exchange: [`start`](Mechanism::start)
+//! produces the initial response, [`step`](Mechanism::step) answers each
+//! server challenge and [`finish`](Mechanism::finish) decides whether a
+//! reported success can be trusted. A [`MechanismRegistry`] maps mechanism
+//! names to factories so applications can add custom or vendor mechanisms
+//! alongside the built-in ones.
+
+use super::ecdsa::EcdsaPrivateKey;
+use super::oauthbearer::OAuthBearerMessage;
+use super::scram::{ChannelBinding, ScramClient};
+use super::SaslMechanism;
+use crate::error::{IronError, Result};
+use crate::secret::Secret;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Maximum length of a mechanism name
+const MAX_MECHANISM_NAME_LENGTH: usize = 32;
+
+/// One client-side SASL exchange
+pub trait Mechanism: Send {
+    /// Mechanism name as advertised by servers
+    fn name(&self) -> &str;
+
+    /// Produce the initial client response
+    fn start(&mut self) -> Result<Vec<u8>>;
+
+    /// Answer a server challenge
+    fn step(&mut self, challenge: &[u8]) -> Result<Vec<u8>>;
+
+    /// Confirm the exchange when the server reports success
+    ///
+    /// Mechanisms with mutual authentication fail here if the server never
+    /// proved its identity.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Check if the server proved its identity during the exchange
+    fn is_server_verified(&self) -> bool {
+        false
+    }
+
+    /// Error details sent by the server, if the mechanism carries any
+    fn server_error(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Credentials handed to mechanism factories
+#[derive(Debug, Clone, Default)]
+pub struct SaslCredentials {
+    /// Authentication identity
+    pub username: String,
+    /// Password, or bearer token for OAUTHBEARER
+    pub password: Option<Secret>,
+    /// Identity to act as, if different from `username`
+    pub authzid: Option<String>,
+    /// TLS channel binding data for the SCRAM mechanisms
+    pub channel_binding: Option<ChannelBinding>,
+    /// Private key for ECDSA-NIST256P-CHALLENGE
+    pub ecdsa_key: Option<EcdsaPrivateKey>,
+}
+
+impl SaslCredentials {
+    fn password(&self, mechanism: &str) -> Result<Secret> {
+        self.password.clone()
+            .ok_or_else(|| IronError::Sasl(format!("Password required for {}", mechanism)))
+    }
+}
+
+type Factory = Arc<dyn Fn(&SaslCredentials) -> Result<Box<dyn Mechanism>> + Send + Sync>;
+
+/// Registry entry describing one mechanism
+#[derive(Clone)]
+pub struct MechanismInfo {
+    name: String,
+    security_strength: u8,
+    secure: bool,
+    requires_channel_binding: bool,
+    factory: Factory,
+}
+
+impl fmt::Debug for MechanismInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MechanismInfo")
+            .field("name", &self.name)
+            .field("security_strength", &self.security_strength)
+            .field("secure", &self.secure)
+            .field("requires_channel_binding", &self.requires_channel_binding)
+            .finish()
+    }
+}
+
+impl MechanismInfo {
+    /// Describe a mechanism built by `factory`
+    ///
+    /// Mechanisms are only offered over TLS unless marked safe with
+    /// [`with_secure`](Self::with_secure).
+    pub fn new<F>(name: impl Into<String>, security_strength: u8, factory: F) -> Self
+    where
+        F: Fn(&SaslCredentials) -> Result<Box<dyn Mechanism>> + Send + Sync + 'static,
+    {
+        Self {
+            name: name.into().to_uppercase(),
+            security_strength,
+            secure: false,
+            requires_channel_binding: false,
+            factory: Arc::new(factory),
+        }
+    }
+
+    /// Set whether the mechanism is safe to use without TLS
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Mark the mechanism as needing TLS channel binding data
+    pub fn with_channel_binding(mut self, required: bool) -> Self {
+        self.requires_channel_binding = required;
+        self
+    }
+
+    /// Mechanism name (upper case)
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Security strength (higher is better)
+    pub fn security_strength(&self) -> u8 {
+        self.security_strength
+    }
+
+    /// Check if the mechanism is safe to use without TLS
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    /// Check if the mechanism needs TLS channel binding data
+    pub fn requires_channel_binding(&self) -> bool {
+        self.requires_channel_binding
+    }
+
+    /// Create a mechanism instance for one exchange
+    pub fn create(&self, credentials: &SaslCredentials) -> Result<Box<dyn Mechanism>> {
+        (self.factory)(credentials)
+    }
+}
+
+/// Mechanisms keyed by name
+#[derive(Debug, Clone, Default)]
+pub struct MechanismRegistry {
+    mechanisms: HashMap<String, MechanismInfo>,
+}
+
+static BUILTIN: Lazy<Arc<MechanismRegistry>> = Lazy::new(|| Arc::new(MechanismRegistry::with_builtins()));
+
+/// The shared registry holding the built-in mechanisms
+pub fn builtin_registry() -> Arc<MechanismRegistry> {
+    BUILTIN.clone()
+}
+
+impl MechanismRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a registry holding every [`SaslMechanism`]
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        for mechanism in SaslMechanism::ALL {
+            registry.register(builtin_info(mechanism));
+        }
+        registry
+    }
+
+    /// Add or replace a mechanism
+    pub fn register(&mut self, info: MechanismInfo) {
+        self.mechanisms.insert(info.name.clone(), info);
+    }
+
+    /// Remove a mechanism
+    pub fn unregister(&mut self, name: &str) -> Option<MechanismInfo> {
+        self.mechanisms.remove(&name.to_uppercase())
+    }
+
+    /// Look up a mechanism by name (case-insensitive)
+    pub fn get(&self, name: &str) -> Option<&MechanismInfo> {
+        self.mechanisms.get(&name.to_uppercase())
+    }
+
+    /// Check if a mechanism is registered
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Registered mechanism names, strongest first
+    pub fn names(&self) -> Vec<&str> {
+        let mut infos: Vec<&MechanismInfo> = self.mechanisms.values().collect();
+        infos.sort_by(|a, b| b.security_strength.cmp(&a.security_strength).then(a.name.cmp(&b.name)));
+        infos.into_iter().map(|info| info.name()).collect()
+    }
+
+    /// Choose the strongest usable mechanism from a server's list
+    ///
+    /// Mechanisms needing channel binding are skipped unless `has_binding`,
+    /// and insecure ones unless `tls_enabled`.
+    pub fn choose_best(&self, available: &[String], tls_enabled: bool, has_binding: bool) -> Option<&MechanismInfo> {
+        let mut candidates: Vec<&MechanismInfo> = available.iter()
+            .filter_map(|name| self.get(name.trim()))
+            .filter(|info| has_binding || !info.requires_channel_binding)
+            .filter(|info| tls_enabled || info.secure)
+            .collect();
+        // Stable sort keeps the server's order among equal strengths
+        candidates.sort_by_key(|info| std::cmp::Reverse(info.security_strength));
+        candidates.into_iter().next()
+    }
+
+    /// Parse a server's mechanism list, requiring at least one usable entry
+    pub fn validate_list(&self, mechanisms: &str) -> Result<Vec<String>> {
+        let mechs: Vec<String> = mechanisms
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        if mechs.is_empty() {
+            return Err(IronError::Sasl("No SASL mechanisms available".to_string()));
+        }
+
+        for mech in &mechs {
+            if mech.len() > MAX_MECHANISM_NAME_LENGTH
+                || !mech.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(IronError::Sasl(format!("Invalid mechanism name: {}", mech)));
+            }
+        }
+
+        if !mechs.iter().any(|m| self.contains(m)) {
+            return Err(IronError::Sasl("No supported SASL mechanisms".to_string()));
+        }
+
+        Ok(mechs)
+    }
+}
+
+fn builtin_info(mechanism: SaslMechanism) -> MechanismInfo {
+    MechanismInfo {
+        name: mechanism.as_str().to_string(),
+        security_strength: mechanism.security_strength(),
+        secure: mechanism.is_secure(),
+        requires_channel_binding: mechanism.is_plus(),
+        factory: Arc::new(move |creds| create_builtin(&mechanism, creds)),
+    }
+}
+
+fn create_builtin(mechanism: &SaslMechanism, creds: &SaslCredentials) -> Result<Box<dyn Mechanism>> {
+    let name = mechanism.as_str();
+    Ok(match mechanism {
+        SaslMechanism::Plain => Box::new(Plain {
+            authzid: creds.authzid.clone().unwrap_or_default(),
+            username: creds.username.clone(),
+            password: creds.password(name)?,
+        }),
+        SaslMechanism::External => Box::new(External {
+            authzid: creds.authzid.clone().unwrap_or_default(),
+        }),
+        SaslMechanism::EcdsaNist256pChallenge => Box::new(Ecdsa {
+            username: creds.username.clone(),
+            authzid: creds.authzid.clone(),
+            key: creds.ecdsa_key.clone()
+                .ok_or_else(|| IronError::Sasl("Private key required for ECDSA".to_string()))?,
+        }),
+        SaslMechanism::OAuthBearer => {
+            let token = creds.password.as_ref().map(Secret::expose)
+                .ok_or_else(|| IronError::Sasl("Bearer token required for OAUTHBEARER".to_string()))?;
+            let mut message = OAuthBearerMessage::new(token);
+            // Without an explicit authzid a non-empty username names the account
+            if let Some(authzid) = creds.authzid.as_ref().or(Some(&creds.username).filter(|u| !u.is_empty())) {
+                message = message.with_authzid(authzid.as_str());
+            }
+            Box::new(OAuthBearer { message, error: None })
+        }
+        _ => {
+            let hash = mechanism.scram_hash()
+                .ok_or_else(|| IronError::Sasl(format!("{} is not a SCRAM mechanism", name)))?;
+            let mut client = ScramClient::new(hash, &creds.username, creds.password(name)?.expose())?;
+            match &creds.channel_binding {
+                Some(binding) => client.set_channel_binding(binding, mechanism.is_plus()),
+                None if mechanism.is_plus() => {
+                    return Err(IronError::Sasl(format!("Channel binding data required for {}", name)));
+                }
+                None => {}
+            }
+            if let Some(authzid) = &creds.authzid {
+                client.set_authzid(authzid)?;
+            }
+            Box::new(Scram { name: name.to_string(), client })
+        }
+    })
+}
+
+struct Plain {
+    authzid: String,
+    username: String,
+    password: Secret,
+}
+
+impl Mechanism for Plain {
+    fn name(&self) -> &str {
+        "PLAIN"
+    }
+
+    fn start(&mut self) -> Result<Vec<u8>> {
+        // authzid \0 authcid \0 password
+        Ok(format!("{}\0{}\0{}", self.authzid, self.username, self.password.expose()).into_bytes())
+    }
+
+    fn step(&mut self, _challenge: &[u8]) -> Result<Vec<u8>> {
+        Err(IronError::Sasl("PLAIN doesn't use challenges".to_string()))
+    }
+}
+
+struct External {
+    authzid: String,
+}
+
+impl Mechanism for External {
+    fn name(&self) -> &str {
+        "EXTERNAL"
+    }
+
+    fn start(&mut self) -> Result<Vec<u8>> {
+        Ok(self.authzid.clone().into_bytes())
+    }
+
+    fn step(&mut self, _challenge: &[u8]) -> Result<Vec<u8>> {
+        Err(IronError::Sasl("EXTERNAL doesn't use challenges".to_string()))
+    }
+}
+
+struct Scram {
+    name: String,
+    client: ScramClient,
+}
+
+impl Mechanism for Scram {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn start(&mut self) -> Result<Vec<u8>> {
+        Ok(self.client.client_first()?.into_bytes())
+    }
+
+    fn step(&mut self, challenge: &[u8]) -> Result<Vec<u8>> {
+        let challenge = std::str::from_utf8(challenge)
+            .map_err(|_| IronError::Sasl("Invalid UTF-8 in challenge".to_string()))?;
+
+        // The server-final-message is acknowledged with an empty response
+        if challenge.starts_with("v=") || challenge.starts_with("e=") {
+            self.client.verify_server_final(challenge)?;
+            return Ok(Vec::new());
+        }
+        Ok(self.client.client_final(challenge)?.into_bytes())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.client.is_verified() {
+            Ok(())
+        } else {
+            Err(IronError::SecurityViolation("Server signature was not verified".to_string()))
+        }
+    }
+
+    fn is_server_verified(&self) -> bool {
+        self.client.is_verified()
+    }
+}
+
+struct Ecdsa {
+    username: String,
+    authzid: Option<String>,
+    key: EcdsaPrivateKey,
+}
+
+impl Mechanism for Ecdsa {
+    fn name(&self) -> &str {
+        "ECDSA-NIST256P-CHALLENGE"
+    }
+
+    fn start(&mut self) -> Result<Vec<u8>> {
+        Ok(match &self.authzid {
+            Some(authzid) => format!("{}\0{}", self.username, authzid),
+            None => self.username.clone(),
+        }.into_bytes())
+    }
+
+    fn step(&mut self, challenge: &[u8]) -> Result<Vec<u8>> {
+        self.key.sign_challenge(challenge)
+    }
+}
+
+struct OAuthBearer {
+    message: OAuthBearerMessage,
+    error: Option<String>,
+}
+
+impl Mechanism for OAuthBearer {
+    fn name(&self) -> &str {
+        "OAUTHBEARER"
+    }
+
+    fn start(&mut self) -> Result<Vec<u8>> {
+        Ok(self.message.encode().into_bytes())
+    }
+
+    fn step(&mut self, challenge: &[u8]) -> Result<Vec<u8>> {
+        // The only challenge is an error; acknowledge it so the server can
+        // finish the exchange
+        self.error = Some(String::from_utf8_lossy(challenge).into_owned());
+        Ok(vec![0x01])
+    }
+
+    fn server_error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Anonymous;
+
+    impl Mechanism for Anonymous {
+        fn name(&self) -> &str {
+            "ANONYMOUS"
+        }
+
+        fn start(&mut self) -> Result<Vec<u8>> {
+            Ok(b"guest".to_vec())
+        }
+
+        fn step(&mut self, _challenge: &[u8]) -> Result<Vec<u8>> {
+            Err(IronError::Sasl("ANONYMOUS doesn't use challenges".to_string()))
+        }
+    }
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_builtin_registry() {
+        let registry = builtin_registry();
+        assert!(registry.contains("scram-sha-256"));
+        assert_eq!(registry.names()[0], "SCRAM-SHA-512-PLUS");
+
+        let best = registry.choose_best(&names(&["PLAIN", "SCRAM-SHA-1-PLUS", "SCRAM-SHA-1"]), true, false);
+        assert_eq!(best.map(|m| m.name()), Some("SCRAM-SHA-1"));
+        let best = registry.choose_best(&names(&["PLAIN", "SCRAM-SHA-1-PLUS", "SCRAM-SHA-1"]), true, true);
+        assert_eq!(best.map(|m| m.name()), Some("SCRAM-SHA-1-PLUS"));
+        assert!(registry.choose_best(&names(&["PLAIN"]), false, false).is_none());
+
+        assert!(registry.validate_list("PLAIN,X-CUSTOM").is_ok());
+        assert!(registry.validate_list("X-CUSTOM").is_err());
+    }
+
+    #[test]
+    fn test_custom_mechanism() {
+        let mut registry = MechanismRegistry::with_builtins();
+        registry.register(
+            MechanismInfo::new("anonymous", 0, |_| Ok(Box::new(Anonymous) as Box<dyn Mechanism>))
+        );
+
+        let info = registry.get("ANONYMOUS").unwrap();
+        assert!(!info.is_secure());
+        let mut mechanism = info.create(&SaslCredentials::default()).unwrap();
+        assert_eq!(mechanism.start().unwrap(), b"guest");
+        assert!(mechanism.finish().is_ok());
+
+        let best = registry.choose_best(&names(&["ANONYMOUS", "PLAIN"]), true, false);
+        assert_eq!(best.map(|m| m.name()), Some("PLAIN"));
+        let best = registry.choose_best(&names(&["ANONYMOUS"]), true, false);
+        assert_eq!(best.map(|m| m.name()), Some("ANONYMOUS"));
+    }
+
+    #[test]
+    fn test_builtin_factories_check_credentials() {
+        let registry = builtin_registry();
+        let creds = SaslCredentials { username: "alice".to_string(), ..SaslCredentials::default() };
+        assert!(registry.get("PLAIN").unwrap().create(&creds).is_err());
+        assert!(registry.get("EXTERNAL").unwrap().create(&creds).is_ok());
+        assert!(registry.get("ECDSA-NIST256P-CHALLENGE").unwrap().create(&creds).is_err());
+
+        let creds = SaslCredentials { password: Some(Secret::new("pw")), ..creds };
+        assert!(registry.get("SCRAM-SHA-256").unwrap().create(&creds).is_ok());
+        assert!(registry.get("SCRAM-SHA-256-PLUS").unwrap().create(&creds).is_err());
+    }
+}
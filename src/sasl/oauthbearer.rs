@@ -0,0 +1,422 @@
+//! OAUTHBEARER (RFC 7628)
+//!
+//! The client sends a GS2 header followed by `\x01`-separated key/value
+//! pairs carrying the bearer token. A server that rejects the token answers
+//! with a JSON error as a challenge; the client acknowledges it with a lone
+//! `\x01` and the server then fails the exchange.
+
+use crate::error::{IronError, Result};
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Key/value separator
+const KVSEP: char = '\x01';
+
+/// The client's initial response
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct OAuthBearerMessage {
+    /// Identity to act as, if different from the token's subject
+    pub authzid: Option<String>,
+    /// The bearer token
+    pub token: String,
+    /// Host the client connected to
+    pub host: Option<String>,
+    /// Port the client connected to
+    pub port: Option<u16>,
+}
+
+impl fmt::Debug for OAuthBearerMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OAuthBearerMessage")
+            .field("authzid", &self.authzid)
+            .field("token", &format_args!("[REDACTED]"))
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .finish()
+    }
+}
+
+impl OAuthBearerMessage {
+    /// Create a message carrying a bearer token
+    pub fn new(token: impl Into<String>) -> Self {
+        Self {
+            token: token.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Set the authorization identity
+    pub fn with_authzid(mut self, authzid: impl Into<String>) -> Self {
+        self.authzid = Some(authzid.into());
+        self
+    }
+
+    /// Set the host and port the client connected to
+    pub fn with_host(mut self, host: impl Into<String>, port: u16) -> Self {
+        self.host = Some(host.into());
+        self.port = Some(port);
+        self
+    }
+
+    /// Encode as `n,a=<authzid>,^Aauth=Bearer <token>^A...^A`
+    pub fn encode(&self) -> String {
+        let authzid = self.authzid.as_deref()
+            .map(|a| format!("a={}", super::scram::escape_username(a)))
+            .unwrap_or_default();
+
+        let mut message = format!("n,{},{}", authzid, KVSEP);
+        if let Some(host) = &self.host {
+            message.push_str(&format!("host={}{}", host, KVSEP));
+        }
+        if let Some(port) = self.port {
+            message.push_str(&format!("port={}{}", port, KVSEP));
+        }
+        message.push_str(&format!("auth=Bearer {}{}{}", self.token, KVSEP, KVSEP));
+        message
+    }
+
+    /// Parse a client initial response
+    pub fn parse(message: &str) -> Result<Self> {
+        let malformed = || IronError::Sasl("Malformed OAUTHBEARER message".to_string());
+
+        let (gs2, rest) = message.split_once(KVSEP).ok_or_else(malformed)?;
+        let mut header = gs2.splitn(3, ',');
+        let authzid = match (header.next(), header.next(), header.next()) {
+            // Channel binding is not defined for OAUTHBEARER
+            (Some("n"), Some(""), Some("")) => None,
+            (Some("n"), Some(a), Some("")) => {
+                let a = a.strip_prefix("a=").ok_or_else(malformed)?;
+                Some(super::scram::unescape_username(a)?)
+            }
+            _ => return Err(malformed()),
+        };
+
+        let pairs = rest.strip_suffix(KVSEP).ok_or_else(malformed)?;
+        let mut parsed = Self { authzid, ..Self::default() };
+        for pair in pairs.split(KVSEP).filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').ok_or_else(malformed)?;
+            match key {
+                "auth" => {
+                    let (scheme, token) = value.split_once(' ').ok_or_else(malformed)?;
+                    if !scheme.eq_ignore_ascii_case("Bearer") || token.is_empty() {
+                        return Err(malformed());
+                    }
+                    parsed.token = token.trim().to_string();
+                }
+                "host" => parsed.host = Some(value.to_string()),
+                "port" => parsed.port = Some(value.parse().map_err(|_| malformed())?),
+                _ => {} // Unknown keys are ignored
+            }
+        }
+
+        if parsed.token.is_empty() {
+            return Err(malformed());
+        }
+        Ok(parsed)
+    }
+}
+
+/// The JSON error a server returns for a rejected token (RFC 7628 3.2.2)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OAuthBearerError {
+    /// Error status, e.g. `invalid_token`
+    pub status: String,
+    /// Scope required to access the service
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub scope: Option<String>,
+    /// OpenID discovery document for obtaining a new token
+    #[cfg_attr(feature = "serde", serde(
+        rename = "openid-configuration", default, skip_serializing_if = "Option::is_none"
+    ))]
+    pub openid_configuration: Option<String>,
+}
+
+impl OAuthBearerError {
+    /// An `invalid_token` error
+    pub fn invalid_token() -> Self {
+        Self {
+            status: "invalid_token".to_string(),
+            scope: None,
+            openid_configuration: None,
+        }
+    }
+
+    /// Set the required scope
+    pub fn with_scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    /// Set the OpenID discovery URL
+    pub fn with_openid_configuration(mut self, url: impl Into<String>) -> Self {
+        self.openid_configuration = Some(url.into());
+        self
+    }
+
+    /// Encode as the JSON challenge sent to the client
+    pub fn to_json(&self) -> String {
+        let mut fields = vec![format!("\"status\":{}", json_string(&self.status))];
+        if let Some(scope) = &self.scope {
+            fields.push(format!("\"scope\":{}", json_string(scope)));
+        }
+        if let Some(url) = &self.openid_configuration {
+            fields.push(format!("\"openid-configuration\":{}", json_string(url)));
+        }
+        format!("{{{}}}", fields.join(","))
+    }
+
+    /// Parse the JSON challenge received from a server
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Server-side bearer token check
+pub trait TokenValidator: Send + Sync {
+    /// Validate a token, returning the account it authenticates
+    fn validate(&self, token: &str) -> std::result::Result<String, OAuthBearerError>;
+}
+
+#[cfg(feature = "serde")]
+pub use jwt::HmacJwtValidator;
+
+#[cfg(feature = "serde")]
+mod jwt {
+    use super::{OAuthBearerError, TokenValidator};
+    use crate::secret::Secret;
+    use crate::utils::constant_time_eq;
+    use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// Validates HS256-signed JWTs against a shared secret
+    ///
+    /// Intended for tests and simple deployments; production setups usually
+    /// verify tokens against their identity provider instead.
+    pub struct HmacJwtValidator {
+        secret: Secret,
+        issuer: Option<String>,
+        audience: Option<String>,
+        account_claim: String,
+        leeway: u64,
+    }
+
+    impl HmacJwtValidator {
+        /// Create a validator for tokens signed with `secret`
+        pub fn new(secret: impl Into<Secret>) -> Self {
+            Self {
+                secret: secret.into(),
+                issuer: None,
+                audience: None,
+                account_claim: "sub".to_string(),
+                leeway: 0,
+            }
+        }
+
+        /// Require the `iss` claim to match
+        pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+            self.issuer = Some(issuer.into());
+            self
+        }
+
+        /// Require the `aud` claim to contain this audience
+        pub fn with_audience(mut self, audience: impl Into<String>) -> Self {
+            self.audience = Some(audience.into());
+            self
+        }
+
+        /// Take the account name from this claim instead of `sub`
+        pub fn with_account_claim(mut self, claim: impl Into<String>) -> Self {
+            self.account_claim = claim.into();
+            self
+        }
+
+        /// Allow this many seconds of clock skew for `exp`/`nbf`
+        pub fn with_leeway(mut self, seconds: u64) -> Self {
+            self.leeway = seconds;
+            self
+        }
+
+        /// Sign a set of claims, producing a token this validator accepts
+        pub fn sign(&self, claims: &serde_json::Value) -> String {
+            let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"HS256","typ":"JWT"}"#);
+            let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+            let signing_input = format!("{}.{}", header, payload);
+            format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(self.mac(signing_input.as_bytes())))
+        }
+
+        fn mac(&self, data: &[u8]) -> Vec<u8> {
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(self.secret.expose().as_bytes())
+                .expect("HMAC accepts keys of any length");
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        }
+
+        fn decode_json(part: &str) -> Option<serde_json::Value> {
+            let bytes = URL_SAFE_NO_PAD.decode(part).ok()?;
+            serde_json::from_slice(&bytes).ok()
+        }
+    }
+
+    impl TokenValidator for HmacJwtValidator {
+        fn validate(&self, token: &str) -> Result<String, OAuthBearerError> {
+            let invalid = OAuthBearerError::invalid_token;
+
+            let mut parts = token.split('.');
+            let (header, payload, signature) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(h), Some(p), Some(s), None) => (h, p, s),
+                _ => return Err(invalid()),
+            };
+
+            let header_json = Self::decode_json(header).ok_or_else(invalid)?;
+            if header_json.get("alg").and_then(|v| v.as_str()) != Some("HS256") {
+                return Err(invalid());
+            }
+            let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+            let expected = self.mac(format!("{}.{}", header, payload).as_bytes());
+            if !constant_time_eq(&signature, &expected) {
+                return Err(invalid());
+            }
+
+            let claims = Self::decode_json(payload).ok_or_else(invalid)?;
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            match claims.get("exp").map(|v| v.as_u64()) {
+                Some(Some(exp)) if exp.saturating_add(self.leeway) > now => {}
+                _ => return Err(invalid()),
+            }
+            if let Some(nbf) = claims.get("nbf") {
+                match nbf.as_u64() {
+                    Some(nbf) if nbf <= now.saturating_add(self.leeway) => {}
+                    _ => return Err(invalid()),
+                }
+            }
+            if let Some(issuer) = &self.issuer {
+                if claims.get("iss").and_then(|v| v.as_str()) != Some(issuer.as_str()) {
+                    return Err(invalid());
+                }
+            }
+            if let Some(audience) = &self.audience {
+                let matches = match claims.get("aud") {
+                    Some(serde_json::Value::String(aud)) => aud == audience,
+                    Some(serde_json::Value::Array(auds)) => auds.iter().any(|a| a.as_str() == Some(audience.as_str())),
+                    _ => false,
+                };
+                if !matches {
+                    return Err(invalid());
+                }
+            }
+
+            claims.get(&self.account_claim)
+                .and_then(|v| v.as_str())
+                .filter(|account| !account.is_empty())
+                .map(str::to_string)
+                .ok_or_else(invalid)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_roundtrip() {
+        let message = OAuthBearerMessage::new("mF_9.B5f-4.1JqM")
+            .with_authzid("user@example.com")
+            .with_host("server.example.com", 143);
+        let encoded = message.encode();
+        assert_eq!(
+            encoded,
+            "n,a=user@example.com,\x01host=server.example.com\x01port=143\x01auth=Bearer mF_9.B5f-4.1JqM\x01\x01"
+        );
+        assert_eq!(OAuthBearerMessage::parse(&encoded).unwrap(), message);
+
+        let bare = OAuthBearerMessage::new("tok");
+        assert_eq!(OAuthBearerMessage::parse(&bare.encode()).unwrap(), bare);
+
+        assert!(OAuthBearerMessage::parse("y,,\x01auth=Bearer tok\x01\x01").is_err());
+        assert!(OAuthBearerMessage::parse("n,,\x01auth=Basic tok\x01\x01").is_err());
+        assert!(OAuthBearerMessage::parse("n,,\x01host=x\x01\x01").is_err());
+        assert!(!format!("{:?}", message).contains("mF_9"));
+    }
+
+    #[test]
+    fn test_error_json() {
+        let error = OAuthBearerError::invalid_token()
+            .with_scope("irc")
+            .with_openid_configuration("https://example.com/.well-known/openid-configuration");
+        let json = error.to_json();
+        assert_eq!(
+            json,
+            r#"{"status":"invalid_token","scope":"irc","openid-configuration":"https://example.com/.well-known/openid-configuration"}"#
+        );
+        #[cfg(feature = "serde")]
+        assert_eq!(OAuthBearerError::from_json(&json).unwrap(), error);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_hmac_jwt_validator() {
+        let validator = HmacJwtValidator::new("secret")
+            .with_issuer("https://sso.example.com")
+            .with_audience("irc");
+        let exp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() + 3600;
+
+        let token = validator.sign(&serde_json::json!({
+            "sub": "alice", "iss": "https://sso.example.com", "aud": ["irc", "web"], "exp": exp,
+        }));
+        assert_eq!(validator.validate(&token).unwrap(), "alice");
+
+        // Tampered payload
+        let mut parts: Vec<&str> = token.split('.').collect();
+        let forged = base64::Engine::encode(
+            &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+            serde_json::json!({"sub": "root", "iss": "https://sso.example.com", "aud": "irc", "exp": exp}).to_string(),
+        );
+        parts[1] = &forged;
+        assert!(validator.validate(&parts.join(".")).is_err());
+
+        let expired = validator.sign(&serde_json::json!({
+            "sub": "alice", "iss": "https://sso.example.com", "aud": "irc", "exp": 1,
+        }));
+        assert!(validator.validate(&expired).is_err());
+
+        let wrong_audience = validator.sign(&serde_json::json!({
+            "sub": "alice", "iss": "https://sso.example.com", "aud": "web", "exp": exp,
+        }));
+        assert!(validator.validate(&wrong_audience).is_err());
+
+        let other_key = HmacJwtValidator::new("other").sign(&serde_json::json!({
+            "sub": "alice", "iss": "https://sso.example.com", "aud": "irc", "exp": exp,
+        }));
+        assert!(validator.validate(&other_key).is_err());
+
+        // Extreme timestamps and leeway saturate instead of overflowing
+        let lenient = HmacJwtValidator::new("secret").with_leeway(u64::MAX);
+        let far = lenient.sign(&serde_json::json!({"sub": "alice", "exp": u64::MAX, "nbf": u64::MAX}));
+        assert_eq!(lenient.validate(&far).unwrap(), "alice");
+    }
+}
@@ -0,0 +1,550 @@
+//! SCRAM (RFC 5802) primitives shared by the client and server
+
+use crate::error::{IronError, Result};
+use crate::secret::Secret;
+use crate::utils::constant_time_eq;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use hmac::{Hmac, Mac};
+use pbkdf2::pbkdf2;
+use rand::RngCore;
+use sha1::Sha1;
+use sha2::{Sha256, Sha512, Digest};
+use unicode_normalization::UnicodeNormalization;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Hash function underlying a SCRAM mechanism
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ScramHash {
+    /// SHA-1 (RFC 5802)
+    Sha1,
+    /// SHA-256 (RFC 7677)
+    Sha256,
+    /// SHA-512
+    Sha512,
+}
+
+impl ScramHash {
+    /// All supported hashes, weakest first
+    pub const ALL: [ScramHash; 3] = [ScramHash::Sha1, ScramHash::Sha256, ScramHash::Sha512];
+
+    /// Parse the hash part of a mechanism name (e.g. `SHA-256`)
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.to_uppercase().as_str() {
+            "SHA-1" => Some(ScramHash::Sha1),
+            "SHA-256" => Some(ScramHash::Sha256),
+            "SHA-512" => Some(ScramHash::Sha512),
+            _ => None,
+        }
+    }
+
+    /// Get the hash name as used in mechanism names
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScramHash::Sha1 => "SHA-1",
+            ScramHash::Sha256 => "SHA-256",
+            ScramHash::Sha512 => "SHA-512",
+        }
+    }
+
+    /// Digest length in bytes
+    pub fn output_len(&self) -> usize {
+        match self {
+            ScramHash::Sha1 => 20,
+            ScramHash::Sha256 => 32,
+            ScramHash::Sha512 => 64,
+        }
+    }
+
+    /// Minimum iteration count accepted for this hash
+    pub fn min_iterations(&self) -> u32 {
+        match self {
+            ScramHash::Sha1 | ScramHash::Sha256 => 4096,
+            ScramHash::Sha512 => 10000,
+        }
+    }
+
+    /// HMAC keyed with `key`
+    pub(crate) fn hmac(&self, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        fn mac<M: Mac + hmac::digest::KeyInit>(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+            let mut mac = <M as Mac>::new_from_slice(key)
+                .map_err(|_| IronError::Sasl("HMAC key error".to_string()))?;
+            mac.update(data);
+            Ok(mac.finalize().into_bytes().to_vec())
+        }
+
+        match self {
+            ScramHash::Sha1 => mac::<Hmac<Sha1>>(key, data),
+            ScramHash::Sha256 => mac::<Hmac<Sha256>>(key, data),
+            ScramHash::Sha512 => mac::<Hmac<Sha512>>(key, data),
+        }
+    }
+
+    /// H() from RFC 5802
+    pub(crate) fn hash(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            ScramHash::Sha1 => Sha1::digest(data).to_vec(),
+            ScramHash::Sha256 => Sha256::digest(data).to_vec(),
+            ScramHash::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+
+    /// Hi() from RFC 5802, i.e. PBKDF2 with this hash's HMAC
+    pub(crate) fn salted_password(&self, password: &[u8], salt: &[u8], iterations: u32) -> Result<Vec<u8>> {
+        let mut result = vec![0u8; self.output_len()];
+        let derived = match self {
+            ScramHash::Sha1 => pbkdf2::<Hmac<Sha1>>(password, salt, iterations, &mut result),
+            ScramHash::Sha256 => pbkdf2::<Hmac<Sha256>>(password, salt, iterations, &mut result),
+            ScramHash::Sha512 => pbkdf2::<Hmac<Sha512>>(password, salt, iterations, &mut result),
+        };
+        derived.map_err(|_| IronError::Sasl("PBKDF2 failed".to_string()))?;
+        Ok(result)
+    }
+}
+
+/// Channel binding types usable with the SCRAM `-PLUS` mechanisms
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ChannelBindingType {
+    /// Hash of the server certificate (RFC 5929)
+    TlsServerEndPoint,
+    /// TLS 1.3 exporter value (RFC 9266)
+    TlsExporter,
+}
+
+impl ChannelBindingType {
+    /// Parse a channel binding name
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "tls-server-end-point" => Some(ChannelBindingType::TlsServerEndPoint),
+            "tls-exporter" => Some(ChannelBindingType::TlsExporter),
+            _ => None,
+        }
+    }
+
+    /// Get the channel binding name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelBindingType::TlsServerEndPoint => "tls-server-end-point",
+            ChannelBindingType::TlsExporter => "tls-exporter",
+        }
+    }
+}
+
+/// Channel binding data obtained from the TLS layer
+///
+/// The TLS implementation is outside this crate, so the caller extracts the
+/// binding data (certificate hash or exporter output) and passes it in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelBinding {
+    /// Binding type
+    pub kind: ChannelBindingType,
+    /// Raw binding data
+    pub data: Vec<u8>,
+}
+
+impl ChannelBinding {
+    /// Create channel binding data of the given type
+    pub fn new(kind: ChannelBindingType, data: impl Into<Vec<u8>>) -> Self {
+        Self { kind, data: data.into() }
+    }
+
+    /// `tls-server-end-point` binding from the server certificate hash
+    pub fn tls_server_end_point(data: impl Into<Vec<u8>>) -> Self {
+        Self::new(ChannelBindingType::TlsServerEndPoint, data)
+    }
+
+    /// `tls-exporter` binding from the TLS exporter output
+    pub fn tls_exporter(data: impl Into<Vec<u8>>) -> Self {
+        Self::new(ChannelBindingType::TlsExporter, data)
+    }
+}
+
+/// The `c=` attribute value: the GS2 header followed by any binding data
+pub(crate) fn channel_binding_attribute(gs2_header: &str, cbind_data: &[u8]) -> String {
+    let mut input = gs2_header.as_bytes().to_vec();
+    input.extend_from_slice(cbind_data);
+    BASE64.encode(input)
+}
+
+/// XOR two equal-length byte strings
+pub(crate) fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b.iter()).map(|(x, y)| x ^ y).collect()
+}
+
+/// Encode a username as a SCRAM `saslname` (`,` => `=2C`, `=` => `=3D`)
+pub(crate) fn escape_username(name: &str) -> String {
+    name.replace('=', "=3D").replace(',', "=2C")
+}
+
+/// Decode a SCRAM `saslname`, rejecting stray `=` sequences
+pub(crate) fn unescape_username(name: &str) -> Result<String> {
+    let mut result = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(pos) = rest.find('=') {
+        result.push_str(&rest[..pos]);
+        match rest.get(pos..pos + 3) {
+            Some("=2C") => result.push(','),
+            Some("=3D") => result.push('='),
+            _ => return Err(IronError::Sasl("Invalid escape in SCRAM username".to_string())),
+        }
+        rest = &rest[pos + 3..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Split a SCRAM message into its `k=value` attributes
+pub(crate) fn parse_attributes(message: &str) -> Result<Vec<(char, &str)>> {
+    message.split(',')
+        .map(|attr| {
+            let mut chars = attr.chars();
+            match (chars.next(), chars.next()) {
+                (Some(key), Some('=')) if key.is_ascii_alphabetic() => Ok((key, &attr[2..])),
+                _ => Err(IronError::Sasl(format!("Malformed SCRAM attribute: {}", attr))),
+            }
+        })
+        .collect()
+}
+
+/// Find the value of a SCRAM attribute
+pub(crate) fn attribute<'a>(attrs: &[(char, &'a str)], key: char) -> Option<&'a str> {
+    attrs.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+}
+
+/// Prepare a username or password with SASLprep (RFC 4013)
+///
+/// Applies the RFC 3454 mapping tables B.1 and C.1.2, NFKC normalization,
+/// and rejects prohibited output. The bidirectional check treats the Hebrew
+/// and Arabic blocks as RandALCat.
+pub(crate) fn saslprep(input: &str) -> Result<String> {
+    let mapped: String = input.chars()
+        .filter(|c| !is_mapped_to_nothing(*c))
+        .map(|c| if is_non_ascii_space(c) { ' ' } else { c })
+        .collect();
+    let normalized: String = mapped.nfkc().collect();
+
+    if let Some(c) = normalized.chars().find(|c| is_prohibited(*c)) {
+        return Err(IronError::Sasl(format!("SASLprep: prohibited character U+{:04X}", c as u32)));
+    }
+
+    if normalized.chars().any(is_rand_al) {
+        let first = normalized.chars().next().map(is_rand_al).unwrap_or(false);
+        let last = normalized.chars().last().map(is_rand_al).unwrap_or(false);
+        let has_l = normalized.chars().any(|c| c.is_alphabetic() && !is_rand_al(c));
+        if !first || !last || has_l {
+            return Err(IronError::Sasl("SASLprep: invalid bidirectional string".to_string()));
+        }
+    }
+
+    Ok(normalized)
+}
+
+/// RFC 3454 table B.1
+fn is_mapped_to_nothing(c: char) -> bool {
+    matches!(c as u32,
+        0x00AD | 0x034F | 0x1806 | 0x180B..=0x180D | 0x200B..=0x200D |
+        0x2060 | 0xFE00..=0xFE0F | 0xFEFF)
+}
+
+/// RFC 3454 table C.1.2
+fn is_non_ascii_space(c: char) -> bool {
+    matches!(c as u32,
+        0x00A0 | 0x1680 | 0x2000..=0x200B | 0x202F | 0x205F | 0x3000)
+}
+
+/// RFC 3454 tables C.2.1 through C.9
+fn is_prohibited(c: char) -> bool {
+    let cp = c as u32;
+    is_non_ascii_space(c)
+        || cp < 0x20 || cp == 0x7F
+        || matches!(cp,
+            0x80..=0x9F | 0x06DD | 0x070F | 0x180E | 0x200C | 0x200D | 0x2028 | 0x2029 |
+            0x2060..=0x2063 | 0x206A..=0x206F | 0xFEFF | 0xFFF9..=0xFFFD |
+            0x1D173..=0x1D17A | 0xE000..=0xF8FF | 0xF0000..=0xFFFFD | 0x100000..=0x10FFFD |
+            0xFDD0..=0xFDEF | 0x2FF0..=0x2FFB | 0x0340 | 0x0341 | 0x200E | 0x200F |
+            0x202A..=0x202E | 0xE0001 | 0xE0020..=0xE007F)
+        || (cp & 0xFFFE) == 0xFFFE
+}
+
+/// Approximation of RFC 3454 table D.1 (RandALCat)
+fn is_rand_al(c: char) -> bool {
+    matches!(c as u32, 0x0590..=0x08FF | 0xFB1D..=0xFDFF | 0xFE70..=0xFEFC)
+}
+
+/// Where a SCRAM client is in the exchange
+#[derive(Debug, Clone, PartialEq)]
+enum ClientState {
+    Initial,
+    ClientFirstSent,
+    ClientFinalSent { server_signature: Vec<u8> },
+    Verified,
+}
+
+/// Client side of a SCRAM exchange
+pub(crate) struct ScramClient {
+    hash: ScramHash,
+    username: String,
+    password: Secret,
+    client_nonce: String,
+    client_first_bare: String,
+    cbind_flag: String,
+    authzid: Option<String>,
+    gs2_header: String,
+    cbind_data: Vec<u8>,
+    state: ClientState,
+}
+
+impl ScramClient {
+    /// Create a client with a random nonce
+    pub(crate) fn new(hash: ScramHash, username: &str, password: &str) -> Result<Self> {
+        let mut nonce_bytes = [0u8; 18];
+        rand::thread_rng().fill_bytes(&mut nonce_bytes);
+        Self::with_nonce(hash, username, password, &BASE64.encode(nonce_bytes))
+    }
+
+    /// Create a client with a fixed nonce
+    pub(crate) fn with_nonce(hash: ScramHash, username: &str, password: &str, client_nonce: &str) -> Result<Self> {
+        Ok(Self {
+            hash,
+            username: saslprep(username)?,
+            password: Secret::new(saslprep(password)?),
+            client_nonce: client_nonce.to_string(),
+            client_first_bare: String::new(),
+            cbind_flag: "n".to_string(),
+            authzid: None,
+            gs2_header: String::new(),
+            cbind_data: Vec::new(),
+            state: ClientState::Initial,
+        })
+    }
+
+    /// Supply TLS channel binding data
+    ///
+    /// With `plus` the exchange is bound to the channel (`p=`); otherwise the
+    /// client only signals that it supports binding but believes the server
+    /// does not (`y`), which lets the server detect a mechanism downgrade.
+    pub(crate) fn set_channel_binding(&mut self, binding: &ChannelBinding, plus: bool) {
+        if plus {
+            self.cbind_flag = format!("p={}", binding.kind.as_str());
+            self.cbind_data = binding.data.clone();
+        } else {
+            self.cbind_flag = "y".to_string();
+            self.cbind_data.clear();
+        }
+    }
+
+    /// Request to act as another identity (`a=` in the GS2 header)
+    pub(crate) fn set_authzid(&mut self, authzid: &str) -> Result<()> {
+        self.authzid = Some(saslprep(authzid)?);
+        Ok(())
+    }
+
+    /// Produce the client-first-message
+    pub(crate) fn client_first(&mut self) -> Result<String> {
+        if self.state != ClientState::Initial {
+            return Err(IronError::Sasl("SCRAM exchange already started".to_string()));
+        }
+
+        self.gs2_header = match &self.authzid {
+            Some(authzid) => format!("{},a={},", self.cbind_flag, escape_username(authzid)),
+            None => format!("{},,", self.cbind_flag),
+        };
+        self.client_first_bare = format!("n={},r={}", escape_username(&self.username), self.client_nonce);
+        self.state = ClientState::ClientFirstSent;
+        Ok(format!("{}{}", self.gs2_header, self.client_first_bare))
+    }
+
+    /// Process the server-first-message and produce the client-final-message
+    pub(crate) fn client_final(&mut self, server_first: &str) -> Result<String> {
+        if self.state != ClientState::ClientFirstSent {
+            return Err(IronError::Sasl("Unexpected SCRAM server-first-message".to_string()));
+        }
+
+        let attrs = parse_attributes(server_first)?;
+        if attribute(&attrs, 'm').is_some() {
+            return Err(IronError::Sasl("Unsupported mandatory SCRAM extension".to_string()));
+        }
+
+        let nonce = attribute(&attrs, 'r')
+            .ok_or_else(|| IronError::Sasl("Missing server nonce".to_string()))?;
+        if !nonce.starts_with(&self.client_nonce) || nonce.len() <= self.client_nonce.len() {
+            return Err(IronError::Sasl("Server nonce doesn't extend client nonce".to_string()));
+        }
+        let salt = attribute(&attrs, 's')
+            .ok_or_else(|| IronError::Sasl("Missing salt".to_string()))
+            .and_then(|s| BASE64.decode(s)
+                .map_err(|_| IronError::Sasl("Invalid salt encoding".to_string())))?;
+        let iterations: u32 = attribute(&attrs, 'i')
+            .ok_or_else(|| IronError::Sasl("Missing iteration count".to_string()))?
+            .parse()
+            .map_err(|_| IronError::Sasl("Invalid iteration count".to_string()))?;
+        if iterations < self.hash.min_iterations() {
+            return Err(IronError::Sasl(format!(
+                "Iteration count {} is below the minimum of {}", iterations, self.hash.min_iterations()
+            )));
+        }
+
+        let h = self.hash;
+        let salted = h.salted_password(self.password.expose().as_bytes(), &salt, iterations)?;
+        let client_key = h.hmac(&salted, b"Client Key")?;
+        let stored_key = h.hash(&client_key);
+        let server_key = h.hmac(&salted, b"Server Key")?;
+
+        let without_proof = format!(
+            "c={},r={}",
+            channel_binding_attribute(&self.gs2_header, &self.cbind_data),
+            nonce
+        );
+        let auth_message = format!("{},{},{}", self.client_first_bare, server_first, without_proof);
+
+        let client_signature = h.hmac(&stored_key, auth_message.as_bytes())?;
+        let client_proof = xor(&client_key, &client_signature);
+        let server_signature = h.hmac(&server_key, auth_message.as_bytes())?;
+
+        self.state = ClientState::ClientFinalSent { server_signature };
+        Ok(format!("{},p={}", without_proof, BASE64.encode(client_proof)))
+    }
+
+    /// Verify the server-final-message signature
+    pub(crate) fn verify_server_final(&mut self, server_final: &str) -> Result<()> {
+        let expected = match &self.state {
+            ClientState::ClientFinalSent { server_signature } => server_signature.clone(),
+            _ => return Err(IronError::Sasl("Unexpected SCRAM server-final-message".to_string())),
+        };
+
+        let attrs = parse_attributes(server_final)?;
+        if let Some(error) = attribute(&attrs, 'e') {
+            return Err(IronError::Auth(format!("SCRAM server error: {}", error)));
+        }
+        let signature = attribute(&attrs, 'v')
+            .ok_or_else(|| IronError::Sasl("Missing server signature".to_string()))
+            .and_then(|v| BASE64.decode(v)
+                .map_err(|_| IronError::Sasl("Invalid server signature encoding".to_string())))?;
+
+        if !constant_time_eq(&signature, &expected) {
+            return Err(IronError::SecurityViolation("SCRAM server signature mismatch".to_string()));
+        }
+        self.state = ClientState::Verified;
+        Ok(())
+    }
+
+    /// Check if the server proved knowledge of the password
+    pub(crate) fn is_verified(&self) -> bool {
+        self.state == ClientState::Verified
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_username_escaping() {
+        assert_eq!(escape_username("a,b=c"), "a=2Cb=3Dc");
+        assert_eq!(unescape_username("a=2Cb=3Dc").unwrap(), "a,b=c");
+        assert!(unescape_username("bad=2").is_err());
+        assert!(unescape_username("bad=41").is_err());
+    }
+
+    #[test]
+    fn test_attribute_parsing() {
+        let attrs = parse_attributes("r=abc,s=c2FsdA==,i=4096").unwrap();
+        assert_eq!(attribute(&attrs, 'r'), Some("abc"));
+        assert_eq!(attribute(&attrs, 's'), Some("c2FsdA=="));
+        assert_eq!(attribute(&attrs, 'i'), Some("4096"));
+        assert!(parse_attributes("r=abc,bogus").is_err());
+    }
+
+    #[test]
+    fn test_rfc7677_vector() {
+        let mut client = ScramClient::with_nonce(ScramHash::Sha256, "user", "pencil", "rOprNGfwEbeRWgbNEkqO").unwrap();
+        assert_eq!(client.client_first().unwrap(), "n,,n=user,r=rOprNGfwEbeRWgbNEkqO");
+
+        let server_first = "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                            s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+        assert_eq!(
+            client.client_final(server_first).unwrap(),
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+             p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+
+        assert!(!client.is_verified());
+        client.verify_server_final("v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=").unwrap();
+        assert!(client.is_verified());
+    }
+
+    #[test]
+    fn test_rfc5802_sha1_vector() {
+        let mut client = ScramClient::with_nonce(ScramHash::Sha1, "user", "pencil", "fyko+d2lbbFgONRv9qkxdawL").unwrap();
+        assert_eq!(client.client_first().unwrap(), "n,,n=user,r=fyko+d2lbbFgONRv9qkxdawL");
+        assert_eq!(
+            client.client_final("r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096").unwrap(),
+            "c=biws,r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,p=v0X8v3Bz2T0CJGbJQyF0X+HI4Ts="
+        );
+        client.verify_server_final("v=rmF9pqV8S7suAoZWja4dJRkFsKQ=").unwrap();
+        assert!(client.is_verified());
+    }
+
+    #[test]
+    fn test_channel_binding_header() {
+        let binding = ChannelBinding::tls_exporter(vec![1, 2, 3]);
+
+        let mut client = ScramClient::with_nonce(ScramHash::Sha512, "user", "pencil", "abc").unwrap();
+        client.set_channel_binding(&binding, true);
+        assert_eq!(client.client_first().unwrap(), "p=tls-exporter,,n=user,r=abc");
+        let client_final = client.client_final("r=abcdef,s=c2FsdA==,i=10000").unwrap();
+        let expected = BASE64.encode(b"p=tls-exporter,,\x01\x02\x03");
+        assert!(client_final.starts_with(&format!("c={},", expected)));
+
+        let mut client = ScramClient::with_nonce(ScramHash::Sha512, "user", "pencil", "abc").unwrap();
+        client.set_channel_binding(&binding, false);
+        client.set_authzid("admin,ops").unwrap();
+        assert_eq!(client.client_first().unwrap(), "y,a=admin=2Cops,n=user,r=abc");
+        // SHA-512 requires a higher iteration count
+        assert!(client.client_final("r=abcdef,s=c2FsdA==,i=4096").is_err());
+    }
+
+    #[test]
+    fn test_forged_server_signature_rejected() {
+        let mut client = ScramClient::with_nonce(ScramHash::Sha256, "user", "pencil", "rOprNGfwEbeRWgbNEkqO").unwrap();
+        client.client_first().unwrap();
+        client.client_final("r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                             s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096").unwrap();
+        let forged = format!("v={}", BASE64.encode([0u8; 32]));
+        assert!(client.verify_server_final(&forged).is_err());
+        assert!(!client.is_verified());
+    }
+
+    #[test]
+    fn test_server_first_validation() {
+        let mut client = ScramClient::with_nonce(ScramHash::Sha256, "user", "pencil", "abc").unwrap();
+        client.client_first().unwrap();
+        // Too few iterations
+        assert!(client.client_final("r=abcdef,s=c2FsdA==,i=1024").is_err());
+
+        let mut client = ScramClient::with_nonce(ScramHash::Sha256, "user", "pencil", "abc").unwrap();
+        client.client_first().unwrap();
+        // Nonce must extend ours
+        assert!(client.client_final("r=xyzdef,s=c2FsdA==,i=4096").is_err());
+    }
+
+    #[test]
+    fn test_saslprep() {
+        // RFC 4013 section 3 examples
+        assert_eq!(saslprep("I\u{00AD}X").unwrap(), "IX");
+        assert_eq!(saslprep("user").unwrap(), "user");
+        assert_eq!(saslprep("USER").unwrap(), "USER");
+        assert_eq!(saslprep("\u{00AA}").unwrap(), "a");
+        assert_eq!(saslprep("\u{2168}").unwrap(), "IX");
+        assert!(saslprep("\u{0007}").is_err());
+        assert!(saslprep("\u{0627}1").is_err());
+        // Non-ASCII space maps to ASCII space
+        assert_eq!(saslprep("a\u{00A0}b").unwrap(), "a b");
+
+        let mut client = ScramClient::with_nonce(ScramHash::Sha256, "a,b=c", "x", "n").unwrap();
+        assert_eq!(client.client_first().unwrap(), "n,,n=a=2Cb=3Dc,r=n");
+    }
+}
@@ -0,0 +1,909 @@
+//! Server-side SASL authentication
+//!
+//! [`SaslServer`] consumes the parameters of inbound `AUTHENTICATE` commands
+//! and produces the `AUTHENTICATE` challenges and 900-908 numerics to send
+//! back, looking accounts up in a [`CredentialStore`].
+
+use super::ecdsa::{EcdsaPublicKey, CHALLENGE_LENGTH};
+use super::framing::{self, AuthenticateBuffer};
+use super::oauthbearer::{OAuthBearerMessage, TokenValidator};
+use super::scram::{self, ChannelBinding, ScramHash};
+use super::store::{CredentialStore, ScramCredentials};
+use super::SaslMechanism;
+use crate::error::{IronError, Result};
+use crate::message::IrcMessage;
+use crate::replies::Reply;
+use crate::secret::Secret;
+use crate::utils::constant_time_eq;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use rand::RngCore;
+use sha2::Sha256;
+use std::sync::Arc;
+
+/// Per-process key for decoy salts when none is configured
+static DEFAULT_DECOY_KEY: Lazy<Secret> = Lazy::new(|| {
+    let mut key = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut key);
+    Secret::new(BASE64.encode(key))
+});
+
+/// Progress of one authentication exchange
+#[derive(Debug, Clone)]
+enum ServerState {
+    Idle,
+    Started(SaslMechanism),
+    ScramClientFinal {
+        channel_binding: String,
+        client_first_bare: String,
+        server_first: String,
+        nonce: String,
+        account: String,
+        authzid: String,
+        credentials: ScramCredentials,
+    },
+    ScramServerFinal {
+        account: String,
+        authzid: String,
+    },
+    EcdsaSignature {
+        account: Option<(String, EcdsaPublicKey)>,
+        authzid: String,
+        challenge: Vec<u8>,
+    },
+    OAuthBearerError,
+}
+
+/// Outcome of processing a decoded client response
+enum Step {
+    Challenge(Vec<u8>),
+    Success(String),
+    Failure,
+}
+
+/// Decides whether an authenticated account may act as another identity
+///
+/// Consulted only after the client has proven its credentials and only when
+/// the requested authzid differs from the authenticated account. Any
+/// `Fn(&str, &str) -> bool` closure can be used as a policy.
+pub trait AuthorizationPolicy: Send + Sync {
+    /// Check if `authcid` may assume the identity `authzid`
+    fn authorize(&self, authcid: &str, authzid: &str) -> bool;
+}
+
+impl<F> AuthorizationPolicy for F
+where
+    F: Fn(&str, &str) -> bool + Send + Sync,
+{
+    fn authorize(&self, authcid: &str, authzid: &str) -> bool {
+        self(authcid, authzid)
+    }
+}
+
+/// Per-connection server-side SASL state machine
+pub struct SaslServer {
+    store: Arc<dyn CredentialStore>,
+    token_validator: Option<Arc<dyn TokenValidator>>,
+    authorization_policy: Option<Arc<dyn AuthorizationPolicy>>,
+    server_name: String,
+    nick: String,
+    mask: String,
+    mechanisms: Vec<SaslMechanism>,
+    client_certfp: Option<String>,
+    channel_binding: Option<ChannelBinding>,
+    allow_reauthentication: bool,
+    decoy_key: Secret,
+    decoy_iterations: Option<u32>,
+    plain_hash: ScramHash,
+    state: ServerState,
+    buffer: AuthenticateBuffer,
+    account: Option<String>,
+}
+
+impl SaslServer {
+    /// Create a SASL server for one connection
+    pub fn new(store: Arc<dyn CredentialStore>, server_name: impl Into<String>) -> Self {
+        Self {
+            store,
+            token_validator: None,
+            authorization_policy: None,
+            server_name: server_name.into(),
+            nick: "*".to_string(),
+            mask: "*".to_string(),
+            mechanisms: vec![
+                SaslMechanism::ScramSha256,
+                SaslMechanism::External,
+                SaslMechanism::Plain,
+            ],
+            client_certfp: None,
+            channel_binding: None,
+            allow_reauthentication: false,
+            decoy_key: DEFAULT_DECOY_KEY.clone(),
+            decoy_iterations: None,
+            plain_hash: ScramHash::Sha256,
+            state: ServerState::Idle,
+            buffer: AuthenticateBuffer::new(),
+            account: None,
+        }
+    }
+
+    /// Restrict the mechanisms offered to clients
+    pub fn with_mechanisms(mut self, mechanisms: Vec<SaslMechanism>) -> Self {
+        self.mechanisms = mechanisms;
+        self
+    }
+
+    /// Validate OAUTHBEARER tokens with `validator`
+    ///
+    /// OAUTHBEARER is only usable when a validator is configured.
+    pub fn with_token_validator(mut self, validator: Arc<dyn TokenValidator>) -> Self {
+        self.token_validator = Some(validator);
+        self
+    }
+
+    /// Allow clients to act as other identities when `policy` agrees
+    ///
+    /// Without a policy the authzid must be empty or equal the account.
+    pub fn with_authorization_policy(mut self, policy: Arc<dyn AuthorizationPolicy>) -> Self {
+        self.authorization_policy = Some(policy);
+        self
+    }
+
+    /// Limit the total encoded size of a client response
+    pub fn with_max_payload(mut self, max_len: usize) -> Self {
+        self.buffer = AuthenticateBuffer::with_max_len(max_len);
+        self
+    }
+
+    /// Allow a new exchange after a successful one instead of replying 907
+    pub fn with_reauthentication(mut self, allow: bool) -> Self {
+        self.allow_reauthentication = allow;
+        self
+    }
+
+    /// Derive decoy salts for unknown accounts from `key`
+    ///
+    /// Use the same key on every server of a network so an unknown account
+    /// gets the same salt wherever it is tried. Defaults to a random key per
+    /// process.
+    pub fn with_decoy_key(mut self, key: impl Into<Secret>) -> Self {
+        self.decoy_key = key.into();
+        self
+    }
+
+    /// Advertise `iterations` for unknown accounts
+    ///
+    /// Set this to the count used when storing passwords so unknown accounts
+    /// cannot be told apart from real ones. Defaults to the hash's minimum.
+    pub fn with_decoy_iterations(mut self, iterations: u32) -> Self {
+        self.decoy_iterations = Some(iterations);
+        self
+    }
+
+    /// Check PLAIN passwords against the keys for `hash`
+    ///
+    /// Unknown accounts are checked against a decoy for the same hash, so
+    /// both cost the same. Defaults to SHA-256; accounts without keys for
+    /// the hash fall back to their strongest keys.
+    pub fn with_plain_hash(mut self, hash: ScramHash) -> Self {
+        self.plain_hash = hash;
+        self
+    }
+
+    /// Set the client's nickname and `nick!user@host` mask used in replies
+    pub fn set_client(&mut self, nick: impl Into<String>, mask: impl Into<String>) {
+        self.nick = nick.into();
+        self.mask = mask.into();
+    }
+
+    /// Set the fingerprint of the client's TLS certificate (for EXTERNAL)
+    pub fn set_client_certfp(&mut self, fingerprint: Option<String>) {
+        self.client_certfp = fingerprint;
+    }
+
+    /// Set the TLS channel binding data for this connection (for `-PLUS`)
+    pub fn set_channel_binding(&mut self, binding: Option<ChannelBinding>) {
+        self.channel_binding = binding;
+    }
+
+    /// Mechanisms offered, formatted for the `sasl=` capability value
+    pub fn mechanism_list(&self) -> String {
+        self.mechanisms.iter().map(|m| m.as_str()).collect::<Vec<_>>().join(",")
+    }
+
+    /// The account the client logged in to, if any
+    pub fn account(&self) -> Option<&str> {
+        self.account.as_deref()
+    }
+
+    /// Check if an exchange is currently in progress
+    pub fn in_progress(&self) -> bool {
+        !matches!(self.state, ServerState::Idle)
+    }
+
+    /// Abort any exchange in progress, e.g. because registration completed
+    ///
+    /// Returns the 906 reply if an exchange was interrupted.
+    pub fn abort(&mut self) -> Vec<IrcMessage> {
+        if self.in_progress() {
+            self.reset();
+            vec![self.reply(Reply::SaslAborted { nick: self.nick.clone() })]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Process the parameter of an inbound `AUTHENTICATE` command
+    pub fn handle_authenticate(&mut self, param: &str) -> Vec<IrcMessage> {
+        if param == "*" {
+            self.reset();
+            return vec![self.reply(Reply::SaslAborted { nick: self.nick.clone() })];
+        }
+
+        if let ServerState::Idle = self.state {
+            return self.start(param);
+        }
+
+        let payload = match self.buffer.push(param) {
+            Ok(Some(payload)) => payload,
+            Ok(None) => return Vec::new(),
+            Err(IronError::Protocol(_)) => {
+                self.reset();
+                return vec![self.reply(Reply::SaslTooLong { nick: self.nick.clone() })];
+            }
+            Err(_) => return self.fail(),
+        };
+
+        match self.step(&payload) {
+            Ok(Step::Challenge(data)) => framing::encode_authenticate(&data),
+            Ok(Step::Success(account)) => self.succeed(account),
+            Ok(Step::Failure) | Err(_) => self.fail(),
+        }
+    }
+
+    fn start(&mut self, mechanism: &str) -> Vec<IrcMessage> {
+        if self.account.is_some() && !self.allow_reauthentication {
+            return vec![self.reply(Reply::SaslAlready { nick: self.nick.clone() })];
+        }
+
+        match SaslMechanism::from_str(mechanism).filter(|m| self.mechanisms.contains(m)) {
+            Some(mechanism) => {
+                self.state = ServerState::Started(mechanism);
+                vec![IrcMessage::new("AUTHENTICATE").with_params(vec!["+".to_string()])]
+            }
+            None => vec![
+                self.reply(Reply::SaslMechs {
+                    nick: self.nick.clone(),
+                    mechanisms: self.mechanisms.iter().map(|m| m.as_str().to_string()).collect(),
+                }),
+                self.reply(Reply::SaslFail { nick: self.nick.clone() }),
+            ],
+        }
+    }
+
+    fn step(&mut self, payload: &[u8]) -> Result<Step> {
+        match std::mem::replace(&mut self.state, ServerState::Idle) {
+            ServerState::Idle => Ok(Step::Failure),
+            ServerState::Started(SaslMechanism::Plain) => self.verify_plain(payload),
+            ServerState::Started(SaslMechanism::External) => self.verify_external(payload),
+            ServerState::Started(SaslMechanism::EcdsaNist256pChallenge) => self.ecdsa_challenge(payload),
+            ServerState::Started(SaslMechanism::OAuthBearer) => self.verify_oauthbearer(payload),
+            ServerState::Started(mechanism) => self.scram_client_first(payload, mechanism),
+            ServerState::ScramClientFinal {
+                channel_binding, client_first_bare, server_first, nonce, account, authzid, credentials,
+            } => {
+                let client_final = std::str::from_utf8(payload)
+                    .map_err(|_| IronError::Sasl("Invalid UTF-8 in SCRAM message".to_string()))?;
+                self.scram_client_final(
+                    client_final, &channel_binding, &client_first_bare, &server_first,
+                    &nonce, account, authzid, &credentials,
+                )
+            }
+            ServerState::ScramServerFinal { account, authzid } => {
+                // The client acknowledges our signature with an empty response
+                if payload.is_empty() {
+                    Ok(self.authorize(account, &authzid))
+                } else {
+                    Ok(Step::Failure)
+                }
+            }
+            ServerState::EcdsaSignature { account, authzid, challenge } => Ok(match account {
+                Some((account, key)) if key.verify_challenge(&challenge, payload) => self.authorize(account, &authzid),
+                _ => Step::Failure,
+            }),
+            // The client's dummy response to our error challenge
+            ServerState::OAuthBearerError => Ok(Step::Failure),
+        }
+    }
+
+    fn verify_plain(&mut self, payload: &[u8]) -> Result<Step> {
+        let mut parts = payload.split(|b| *b == 0);
+        let (authzid, authcid, password) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(z), Some(c), Some(p), None) => (z, c, p),
+            _ => return Ok(Step::Failure),
+        };
+        let to_str = |bytes: &[u8]| String::from_utf8(bytes.to_vec())
+            .map_err(|_| IronError::Sasl("Invalid UTF-8 in PLAIN payload".to_string()));
+        let (authzid, authcid, password) = (to_str(authzid)?, to_str(authcid)?, to_str(password)?);
+        let authcid = match scram::saslprep(&authcid) {
+            Ok(authcid) => authcid,
+            Err(_) => return Ok(Step::Failure),
+        };
+
+        let (account, scram) = self.plain_credentials(&authcid)?;
+        // Unknown accounts still run PBKDF2 so they take as long as real ones
+        let verified = scram.verify_password(&password).then_some(account).flatten();
+
+        Ok(match verified {
+            Some(account) => self.authorize(account, &authzid),
+            None => Step::Failure,
+        })
+    }
+
+    fn verify_external(&mut self, payload: &[u8]) -> Result<Step> {
+        let fingerprint = match &self.client_certfp {
+            Some(fp) => fp.clone(),
+            None => return Ok(Step::Failure),
+        };
+        let authzid = String::from_utf8(payload.to_vec())
+            .map_err(|_| IronError::Sasl("Invalid UTF-8 in EXTERNAL payload".to_string()))?;
+
+        Ok(match self.store.lookup_by_certfp(&fingerprint)? {
+            Some(creds) => self.authorize(creds.account, &authzid),
+            None => Step::Failure,
+        })
+    }
+
+    fn verify_oauthbearer(&mut self, payload: &[u8]) -> Result<Step> {
+        let validator = match &self.token_validator {
+            Some(validator) => validator.clone(),
+            None => return Ok(Step::Failure),
+        };
+        let message = std::str::from_utf8(payload)
+            .map_err(|_| IronError::Sasl("Invalid UTF-8 in OAUTHBEARER message".to_string()))
+            .and_then(OAuthBearerMessage::parse)?;
+
+        match validator.validate(&message.token) {
+            Ok(account) => Ok(self.authorize(account, message.authzid.as_deref().unwrap_or(""))),
+            Err(error) => {
+                self.state = ServerState::OAuthBearerError;
+                Ok(Step::Challenge(error.to_json().into_bytes()))
+            }
+        }
+    }
+
+    fn ecdsa_challenge(&mut self, payload: &[u8]) -> Result<Step> {
+        // authcid, optionally followed by NUL and an authzid
+        let message = String::from_utf8(payload.to_vec())
+            .map_err(|_| IronError::Sasl("Invalid UTF-8 in ECDSA payload".to_string()))?;
+        let (authcid, authzid) = match message.split_once('\0') {
+            Some((authcid, authzid)) => (authcid, authzid),
+            None => (message.as_str(), ""),
+        };
+        if authcid.is_empty() {
+            return Ok(Step::Failure);
+        }
+
+        // Unknown accounts still get a challenge, failing only at the signature
+        let account = self.store.lookup(authcid)?
+            .and_then(|creds| creds.ecdsa_public_key.map(|key| (creds.account, key)));
+
+        let mut challenge = vec![0u8; CHALLENGE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut challenge);
+        self.state = ServerState::EcdsaSignature {
+            account,
+            authzid: authzid.to_string(),
+            challenge: challenge.clone(),
+        };
+        Ok(Step::Challenge(challenge))
+    }
+
+    fn scram_client_first(&mut self, payload: &[u8], mechanism: SaslMechanism) -> Result<Step> {
+        let hash = mechanism.scram_hash()
+            .ok_or_else(|| IronError::Sasl(format!("{} is not a SCRAM mechanism", mechanism.as_str())))?;
+        let message = std::str::from_utf8(payload)
+            .map_err(|_| IronError::Sasl("Invalid UTF-8 in SCRAM message".to_string()))?;
+
+        // gs2-header = cbind-flag "," [ authzid ] ","
+        let mut split = message.splitn(3, ',');
+        let (cbind, authzid, bare) = match (split.next(), split.next(), split.next()) {
+            (Some(c), Some(a), Some(b)) => (c, a, b),
+            _ => return Ok(Step::Failure),
+        };
+        let cbind_data = match (cbind, mechanism.is_plus()) {
+            ("n", false) => Vec::new(),
+            ("y", false) => {
+                // The client can bind but thinks we can't: if we could, a
+                // man in the middle has stripped the -PLUS mechanisms
+                if self.channel_binding.is_some() && self.mechanisms.iter().any(|m| m.is_plus()) {
+                    return Ok(Step::Failure);
+                }
+                Vec::new()
+            }
+            (flag, true) => match (flag.strip_prefix("p="), &self.channel_binding) {
+                (Some(kind), Some(binding)) if kind == binding.kind.as_str() => binding.data.clone(),
+                _ => return Ok(Step::Failure),
+            },
+            _ => return Ok(Step::Failure),
+        };
+
+        let attrs = scram::parse_attributes(bare)?;
+        if scram::attribute(&attrs, 'm').is_some() {
+            return Ok(Step::Failure);
+        }
+        let (username, client_nonce) = match (scram::attribute(&attrs, 'n'), scram::attribute(&attrs, 'r')) {
+            (Some(n), Some(r)) if !r.is_empty() => (scram::saslprep(&scram::unescape_username(n)?)?, r),
+            _ => return Ok(Step::Failure),
+        };
+        let requested_authzid = match authzid.strip_prefix("a=") {
+            Some(a) => scram::unescape_username(a)?,
+            None if authzid.is_empty() => String::new(),
+            None => return Ok(Step::Failure),
+        };
+
+        // Unknown accounts get a stable per-username salt and throwaway keys
+        // so the exchange looks like a wrong password on a real account
+        let (account, credentials) = match self.store.lookup(&username)?
+            .and_then(|creds| creds.scram(hash).cloned().map(|scram| (creds.account, scram)))
+        {
+            Some(found) => found,
+            None => (username.clone(), self.decoy_credentials(hash, &username)),
+        };
+
+        let mut nonce_bytes = [0u8; 18];
+        rand::thread_rng().fill_bytes(&mut nonce_bytes);
+        let nonce = format!("{}{}", client_nonce, BASE64.encode(nonce_bytes));
+        let server_first = format!(
+            "r={},s={},i={}",
+            nonce, BASE64.encode(&credentials.salt), credentials.iterations
+        );
+
+        self.state = ServerState::ScramClientFinal {
+            channel_binding: scram::channel_binding_attribute(&format!("{},{},", cbind, authzid), &cbind_data),
+            client_first_bare: bare.to_string(),
+            server_first: server_first.clone(),
+            nonce,
+            account,
+            authzid: requested_authzid,
+            credentials,
+        };
+        Ok(Step::Challenge(server_first.into_bytes()))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn scram_client_final(
+        &mut self,
+        client_final: &str,
+        channel_binding: &str,
+        client_first_bare: &str,
+        server_first: &str,
+        nonce: &str,
+        account: String,
+        authzid: String,
+        credentials: &ScramCredentials,
+    ) -> Result<Step> {
+        let (without_proof, proof) = match client_final.rsplit_once(",p=") {
+            Some(parts) => parts,
+            None => return Ok(Step::Failure),
+        };
+        let attrs = scram::parse_attributes(without_proof)?;
+        if scram::attribute(&attrs, 'c') != Some(channel_binding)
+            || scram::attribute(&attrs, 'r') != Some(nonce)
+        {
+            return Ok(Step::Failure);
+        }
+        let proof = BASE64.decode(proof)
+            .map_err(|_| IronError::Sasl("Invalid SCRAM proof encoding".to_string()))?;
+
+        let h = credentials.hash;
+        let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+        let client_signature = h.hmac(&credentials.stored_key, auth_message.as_bytes())?;
+        if proof.len() != client_signature.len() {
+            return Ok(Step::Failure);
+        }
+        let client_key = scram::xor(&proof, &client_signature);
+        if !constant_time_eq(&h.hash(&client_key), &credentials.stored_key) {
+            return Ok(Step::Failure);
+        }
+
+        let server_signature = h.hmac(&credentials.server_key, auth_message.as_bytes())?;
+        self.state = ServerState::ScramServerFinal { account, authzid };
+        Ok(Step::Challenge(format!("v={}", BASE64.encode(server_signature)).into_bytes()))
+    }
+
+    /// Resolve the identity an authenticated account is logged in as
+    fn authorize(&self, account: String, authzid: &str) -> Step {
+        if authzid.is_empty() || authzid == account {
+            return Step::Success(account);
+        }
+        match &self.authorization_policy {
+            Some(policy) if policy.authorize(&account, authzid) => Step::Success(authzid.to_string()),
+            _ => Step::Failure,
+        }
+    }
+
+    fn succeed(&mut self, account: String) -> Vec<IrcMessage> {
+        self.reset();
+        let messages = vec![
+            self.reply(Reply::LoggedIn {
+                nick: self.nick.clone(),
+                mask: self.mask.clone(),
+                account: account.clone(),
+            }),
+            self.reply(Reply::SaslSuccess { nick: self.nick.clone() }),
+        ];
+        self.account = Some(account);
+        messages
+    }
+
+    fn fail(&mut self) -> Vec<IrcMessage> {
+        self.reset();
+        vec![self.reply(Reply::SaslFail { nick: self.nick.clone() })]
+    }
+
+    fn reset(&mut self) {
+        self.state = ServerState::Idle;
+        self.buffer.clear();
+    }
+
+    fn reply(&self, reply: Reply) -> IrcMessage {
+        reply.to_message(&self.server_name)
+    }
+
+    /// Keys that can never verify, used for unknown accounts
+    ///
+    /// The salt is `HMAC(decoy_key, hash || 0 || username)`, so repeated
+    /// attempts see the same salt just like a real account.
+    /// The keys a PLAIN password is checked against, and the account if it exists
+    fn plain_credentials(&self, authcid: &str) -> Result<(Option<String>, ScramCredentials)> {
+        let found = self.store.lookup(authcid)?.and_then(|creds| {
+            let scram = creds.scram(self.plain_hash).or_else(|| creds.scram.last()).cloned()?;
+            Some((Some(creds.account), scram))
+        });
+        Ok(found.unwrap_or_else(|| (None, self.decoy_credentials(self.plain_hash, authcid))))
+    }
+
+    fn decoy_credentials(&self, hash: ScramHash, username: &str) -> ScramCredentials {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(self.decoy_key.expose().as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(hash.as_str().as_bytes());
+        mac.update(&[0]);
+        mac.update(username.as_bytes());
+        let salt = mac.finalize().into_bytes()[..16].to_vec();
+
+        let mut rng = rand::thread_rng();
+        let mut stored_key = vec![0u8; hash.output_len()];
+        let mut server_key = vec![0u8; hash.output_len()];
+        rng.fill_bytes(&mut stored_key);
+        rng.fill_bytes(&mut server_key);
+        ScramCredentials {
+            hash,
+            salt,
+            iterations: self.decoy_iterations.unwrap_or_else(|| hash.min_iterations()),
+            stored_key,
+            server_key,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sasl::store::MemoryCredentialStore;
+
+    fn server() -> SaslServer {
+        let mut store = MemoryCredentialStore::new();
+        store.set_password("alice", "wonderland", 4096).unwrap();
+        store.add_certfp("bob", "0A:0B:0C");
+        let mut server = SaslServer::new(Arc::new(store), "irc.example.com");
+        server.set_client("alice", "alice!a@host");
+        server
+    }
+
+    fn commands(messages: &[IrcMessage]) -> Vec<String> {
+        messages.iter().map(|m| m.command.clone()).collect()
+    }
+
+    #[test]
+    fn test_plain_success_and_already_authenticated() {
+        let mut server = server();
+        let reply = server.handle_authenticate("PLAIN");
+        assert_eq!(reply[0].params, vec!["+"]);
+
+        let payload = BASE64.encode("\0alice\0wonderland");
+        let reply = server.handle_authenticate(&payload);
+        assert_eq!(commands(&reply), vec!["900", "903"]);
+        assert_eq!(reply[0].params[2], "alice");
+        assert_eq!(server.account(), Some("alice"));
+
+        assert_eq!(commands(&server.handle_authenticate("PLAIN")), vec!["907"]);
+    }
+
+    #[test]
+    fn test_plain_wrong_password_and_unknown_mechanism() {
+        let mut server = server();
+        server.handle_authenticate("PLAIN");
+        let reply = server.handle_authenticate(&BASE64.encode("\0alice\0queen"));
+        assert_eq!(commands(&reply), vec!["904"]);
+        assert!(server.account().is_none());
+
+        let reply = server.handle_authenticate("X-UNKNOWN");
+        assert_eq!(commands(&reply), vec!["908", "904"]);
+        assert_eq!(reply[0].params[1], "SCRAM-SHA-256,EXTERNAL,PLAIN");
+    }
+
+    #[test]
+    fn test_authzid_requires_policy() {
+        let mut plain = server();
+        plain.handle_authenticate("PLAIN");
+        let reply = plain.handle_authenticate(&BASE64.encode("services\0alice\0wonderland"));
+        assert_eq!(commands(&reply), vec!["904"]);
+
+        let policy = |authcid: &str, authzid: &str| authcid == "alice" && authzid == "services";
+        let mut delegated = server().with_authorization_policy(Arc::new(policy));
+        delegated.handle_authenticate("PLAIN");
+        let reply = delegated.handle_authenticate(&BASE64.encode("services\0alice\0wonderland"));
+        assert_eq!(commands(&reply), vec!["900", "903"]);
+        assert_eq!(delegated.account(), Some("services"));
+
+        // The policy is not consulted before the password checks out
+        let mut wrong = server().with_authorization_policy(Arc::new(policy));
+        wrong.handle_authenticate("PLAIN");
+        let reply = wrong.handle_authenticate(&BASE64.encode("services\0alice\0queen"));
+        assert_eq!(commands(&reply), vec!["904"]);
+
+        // Same identity is always allowed
+        let mut external = server();
+        external.set_client_certfp(Some("0a0b0c".to_string()));
+        external.handle_authenticate("EXTERNAL");
+        assert_eq!(commands(&external.handle_authenticate(&BASE64.encode("bob"))), vec!["900", "903"]);
+    }
+
+    #[test]
+    fn test_external_uses_certfp() {
+        let mut server = server();
+        server.set_client_certfp(Some("0a0b0c".to_string()));
+        server.handle_authenticate("EXTERNAL");
+        let reply = server.handle_authenticate("+");
+        assert_eq!(commands(&reply), vec!["900", "903"]);
+        assert_eq!(server.account(), Some("bob"));
+    }
+
+    #[test]
+    fn test_abort_and_too_long() {
+        let mut server = server();
+        server.handle_authenticate("PLAIN");
+        assert_eq!(commands(&server.handle_authenticate("*")), vec!["906"]);
+        assert!(!server.in_progress());
+
+        server.handle_authenticate("PLAIN");
+        assert_eq!(commands(&server.handle_authenticate(&"A".repeat(401))), vec!["905"]);
+    }
+
+    #[test]
+    fn test_chunked_response() {
+        let mut server = server().with_max_payload(1000);
+        server.handle_authenticate("PLAIN");
+
+        // Pad the password so the encoded payload spans several lines
+        let payload = BASE64.encode(format!("\0alice\0{}", "x".repeat(400)));
+        let messages = framing::chunk_authenticate(&payload);
+        assert!(messages.len() > 1);
+        for message in &messages[..messages.len() - 1] {
+            assert!(server.handle_authenticate(&message.params[0]).is_empty());
+        }
+        let reply = server.handle_authenticate(&messages.last().unwrap().params[0]);
+        assert_eq!(commands(&reply), vec!["904"]);
+
+        server.handle_authenticate("PLAIN");
+        for _ in 0..2 {
+            assert!(server.handle_authenticate(&"A".repeat(400)).is_empty());
+        }
+        assert_eq!(commands(&server.handle_authenticate(&"A".repeat(400))), vec!["905"]);
+    }
+
+    #[test]
+    fn test_ecdsa_challenge() {
+        let key = crate::sasl::EcdsaPrivateKey::generate();
+        let mut store = MemoryCredentialStore::new();
+        store.set_ecdsa_key("bot", key.public_key());
+        let mut server = SaslServer::new(Arc::new(store), "irc.example.com")
+            .with_mechanisms(vec![SaslMechanism::EcdsaNist256pChallenge]);
+
+        server.handle_authenticate("ECDSA-NIST256P-CHALLENGE");
+        let reply = server.handle_authenticate(&BASE64.encode("bot"));
+        let challenge = BASE64.decode(&reply[0].params[0]).unwrap();
+        assert_eq!(challenge.len(), CHALLENGE_LENGTH);
+
+        // A signature from the wrong key fails
+        let mut other = SaslServer::new(Arc::new(MemoryCredentialStore::new()), "irc.example.com")
+            .with_mechanisms(vec![SaslMechanism::EcdsaNist256pChallenge]);
+        other.handle_authenticate("ECDSA-NIST256P-CHALLENGE");
+        let other_challenge = BASE64.decode(&other.handle_authenticate(&BASE64.encode("bot"))[0].params[0]).unwrap();
+        let signature = key.sign_challenge(&other_challenge).unwrap();
+        assert_eq!(commands(&other.handle_authenticate(&BASE64.encode(signature))), vec!["904"]);
+
+        let signature = key.sign_challenge(&challenge).unwrap();
+        let reply = server.handle_authenticate(&BASE64.encode(signature));
+        assert_eq!(commands(&reply), vec!["900", "903"]);
+        assert_eq!(server.account(), Some("bot"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_oauthbearer() {
+        use crate::sasl::HmacJwtValidator;
+
+        let validator = Arc::new(HmacJwtValidator::new("sso-secret"));
+        let exp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() + 60;
+        let token = validator.sign(&serde_json::json!({"sub": "alice", "exp": exp}));
+        let mut server = server()
+            .with_mechanisms(vec![SaslMechanism::OAuthBearer])
+            .with_token_validator(validator);
+
+        server.handle_authenticate("OAUTHBEARER");
+        let message = OAuthBearerMessage::new("not-a-jwt");
+        let reply = server.handle_authenticate(&BASE64.encode(message.encode()));
+        let error = String::from_utf8(BASE64.decode(&reply[0].params[0]).unwrap()).unwrap();
+        assert_eq!(error, r#"{"status":"invalid_token"}"#);
+        assert_eq!(commands(&server.handle_authenticate("AQ==")), vec!["904"]);
+
+        server.handle_authenticate("OAUTHBEARER");
+        let message = OAuthBearerMessage::new(token.as_str()).with_authzid("bob");
+        assert_eq!(commands(&server.handle_authenticate(&BASE64.encode(message.encode()))), vec!["904"]);
+
+        server.handle_authenticate("OAUTHBEARER");
+        let message = OAuthBearerMessage::new(token.as_str());
+        assert_eq!(commands(&server.handle_authenticate(&BASE64.encode(message.encode()))), vec!["900", "903"]);
+        assert_eq!(server.account(), Some("alice"));
+    }
+
+    #[test]
+    fn test_scram_exchange() {
+        let mut server = server();
+        server.handle_authenticate("SCRAM-SHA-256");
+
+        let client_first_bare = "n=alice,r=clientnonce";
+        let reply = server.handle_authenticate(&BASE64.encode(format!("n,,{}", client_first_bare)));
+        let server_first = String::from_utf8(BASE64.decode(&reply[0].params[0]).unwrap()).unwrap();
+        let attrs = scram::parse_attributes(&server_first).unwrap();
+        let nonce = scram::attribute(&attrs, 'r').unwrap();
+        assert!(nonce.starts_with("clientnonce"));
+        let salt = BASE64.decode(scram::attribute(&attrs, 's').unwrap()).unwrap();
+
+        let h = ScramHash::Sha256;
+        let salted = h.salted_password(b"wonderland", &salt, 4096).unwrap();
+        let client_key = h.hmac(&salted, b"Client Key").unwrap();
+        let without_proof = format!("c=biws,r={}", nonce);
+        let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+        let signature = h.hmac(&h.hash(&client_key), auth_message.as_bytes()).unwrap();
+        let proof = BASE64.encode(scram::xor(&client_key, &signature));
+
+        let reply = server.handle_authenticate(&BASE64.encode(format!("{},p={}", without_proof, proof)));
+        let server_final = String::from_utf8(BASE64.decode(&reply[0].params[0]).unwrap()).unwrap();
+        let server_key = h.hmac(&salted, b"Server Key").unwrap();
+        let expected = h.hmac(&server_key, auth_message.as_bytes()).unwrap();
+        assert_eq!(server_final, format!("v={}", BASE64.encode(expected)));
+
+        assert_eq!(commands(&server.handle_authenticate("+")), vec!["900", "903"]);
+        assert_eq!(server.account(), Some("alice"));
+    }
+
+    #[test]
+    fn test_scram_channel_binding_checks() {
+        // Without our own binding data -PLUS cannot succeed
+        let mut unbound = server().with_mechanisms(vec![SaslMechanism::ScramSha256Plus]);
+        unbound.handle_authenticate("SCRAM-SHA-256-PLUS");
+        let reply = unbound.handle_authenticate(&BASE64.encode("p=tls-server-end-point,,n=alice,r=abc"));
+        assert_eq!(commands(&reply), vec!["904"]);
+
+        let binding = ChannelBinding::tls_server_end_point(vec![7u8; 32]);
+        let plus_server = || {
+            let mut server = server().with_mechanisms(vec![
+                SaslMechanism::ScramSha256Plus,
+                SaslMechanism::ScramSha256,
+            ]);
+            server.set_channel_binding(Some(binding.clone()));
+            server
+        };
+
+        // "y" while we offer -PLUS with binding data means a downgrade
+        let mut server = plus_server();
+        server.handle_authenticate("SCRAM-SHA-256");
+        let reply = server.handle_authenticate(&BASE64.encode("y,,n=alice,r=abc"));
+        assert_eq!(commands(&reply), vec!["904"]);
+
+        // -PLUS requires a matching binding type
+        let mut server = plus_server();
+        server.handle_authenticate("SCRAM-SHA-256-PLUS");
+        let reply = server.handle_authenticate(&BASE64.encode("p=tls-exporter,,n=alice,r=abc"));
+        assert_eq!(commands(&reply), vec!["904"]);
+
+        let mut server = plus_server();
+        server.handle_authenticate("SCRAM-SHA-256-PLUS");
+        let reply = server.handle_authenticate(&BASE64.encode("p=tls-server-end-point,,n=alice,r=abc"));
+        assert_eq!(commands(&reply), vec!["AUTHENTICATE"]);
+    }
+
+    #[test]
+    fn test_scram_unknown_account_fails_at_proof() {
+        let mut server = server();
+        server.handle_authenticate("SCRAM-SHA-256");
+        let reply = server.handle_authenticate(&BASE64.encode("n,,n=mallory,r=abc"));
+        assert_eq!(reply[0].command, "AUTHENTICATE");
+
+        let server_first = String::from_utf8(BASE64.decode(&reply[0].params[0]).unwrap()).unwrap();
+        let attrs = scram::parse_attributes(&server_first).unwrap();
+        let nonce = scram::attribute(&attrs, 'r').unwrap();
+        let final_message = format!("c=biws,r={},p={}", nonce, BASE64.encode([0u8; 32]));
+        assert_eq!(commands(&server.handle_authenticate(&BASE64.encode(final_message))), vec!["904"]);
+    }
+
+    #[test]
+    fn test_scram_decoy_is_stable() {
+        let mut store = MemoryCredentialStore::new();
+        store.set_password("alice", "wonderland", 4096).unwrap();
+        let store: Arc<dyn CredentialStore> = Arc::new(store);
+        let server_first = |user: &str| {
+            let mut server = SaslServer::new(store.clone(), "irc.example.com")
+                .with_decoy_key("network-secret")
+                .with_decoy_iterations(4096);
+            server.handle_authenticate("SCRAM-SHA-256");
+            let reply = server.handle_authenticate(&BASE64.encode(format!("n,,n={},r=abc", user)));
+            let first = String::from_utf8(BASE64.decode(&reply[0].params[0]).unwrap()).unwrap();
+            let attrs = scram::parse_attributes(&first).unwrap();
+            (scram::attribute(&attrs, 's').unwrap().to_string(), scram::attribute(&attrs, 'i').unwrap().to_string())
+        };
+
+        // Unknown accounts look like real ones: same salt every time, same iteration count
+        assert_eq!(server_first("mallory"), server_first("mallory"));
+        assert_ne!(server_first("mallory").0, server_first("trudy").0);
+        assert_eq!(server_first("mallory").1, server_first("alice").1);
+        assert_eq!(server_first("alice"), server_first("alice"));
+    }
+
+    #[test]
+    fn test_saslprep_passwords() {
+        // Soft hyphen and a compatibility character both change under SASLprep
+        let password = "w\u{00AD}onder\u{2168}";
+        let mut store = MemoryCredentialStore::new();
+        store.set_password("alice", password, 4096).unwrap();
+        let store: Arc<dyn CredentialStore> = Arc::new(store);
+
+        let mut plain = SaslServer::new(store.clone(), "irc.example.com");
+        plain.handle_authenticate("PLAIN");
+        let reply = plain.handle_authenticate(&BASE64.encode(format!("\0alice\0{}", password)));
+        assert_eq!(commands(&reply), vec!["900", "903"]);
+
+        let mut server = SaslServer::new(store, "irc.example.com");
+        let mut client = scram::ScramClient::new(ScramHash::Sha256, "alice", password).unwrap();
+        server.handle_authenticate("SCRAM-SHA-256");
+        let reply = server.handle_authenticate(&BASE64.encode(client.client_first().unwrap()));
+        let server_first = String::from_utf8(BASE64.decode(&reply[0].params[0]).unwrap()).unwrap();
+        let reply = server.handle_authenticate(&BASE64.encode(client.client_final(&server_first).unwrap()));
+        let server_final = String::from_utf8(BASE64.decode(&reply[0].params[0]).unwrap()).unwrap();
+        client.verify_server_final(&server_final).unwrap();
+        assert_eq!(commands(&server.handle_authenticate("+")), vec!["900", "903"]);
+        assert_eq!(server.account(), Some("alice"));
+    }
+
+    #[test]
+    fn test_plain_decoy_matches_real_account() {
+        let server = server().with_decoy_iterations(4096);
+        let (account, real) = server.plain_credentials("alice").unwrap();
+        let (unknown, decoy) = server.plain_credentials("mallory").unwrap();
+        assert_eq!(account.as_deref(), Some("alice"));
+        assert!(unknown.is_none());
+        assert_eq!((real.hash, real.iterations), (decoy.hash, decoy.iterations));
+
+        let server = server.with_plain_hash(ScramHash::Sha512).with_decoy_iterations(10000);
+        let (_, real) = server.plain_credentials("alice").unwrap();
+        let (_, decoy) = server.plain_credentials("mallory").unwrap();
+        assert_eq!((real.hash, real.iterations), (decoy.hash, decoy.iterations));
+        assert_eq!(real.hash, ScramHash::Sha512);
+    }
+}
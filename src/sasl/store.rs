@@ -0,0 +1,451 @@
+//! Account credential storage for server-side SASL
+//!
+//! Stores only SCRAM salted keys (RFC 5802 `StoredKey`/`ServerKey`),
+//! certificate fingerprints and ECDSA public keys; plaintext passwords are
+//! never kept.
+
+use super::ecdsa::EcdsaPublicKey;
+use super::scram::{saslprep, ScramHash};
+use crate::casemap::{CaseFoldedKey, CaseMapping};
+use crate::error::{IronError, Result};
+use crate::utils::constant_time_eq;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use rand::RngCore;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Salted SCRAM keys for one account
+#[derive(Clone, PartialEq, Eq)]
+pub struct ScramCredentials {
+    /// Hash function the keys were derived with
+    pub hash: ScramHash,
+    /// Salt used to derive the salted password
+    pub salt: Vec<u8>,
+    /// PBKDF2 iteration count
+    pub iterations: u32,
+    /// `H(ClientKey)`
+    pub stored_key: Vec<u8>,
+    /// `HMAC(SaltedPassword, "Server Key")`
+    pub server_key: Vec<u8>,
+}
+
+impl ScramCredentials {
+    /// Derive credentials from a password with a fresh random salt
+    pub fn from_password(hash: ScramHash, password: &str, iterations: u32) -> Result<Self> {
+        let mut salt = vec![0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        Self::derive(hash, password, &salt, iterations)
+    }
+
+    /// Derive credentials from a password and a known salt
+    ///
+    /// The password is prepared with SASLprep, as SCRAM clients do.
+    pub fn derive(hash: ScramHash, password: &str, salt: &[u8], iterations: u32) -> Result<Self> {
+        if iterations < hash.min_iterations() {
+            return Err(IronError::Config(format!(
+                "SCRAM-{} iteration count {} is below the minimum of {}",
+                hash.as_str(), iterations, hash.min_iterations()
+            )));
+        }
+
+        let salted = hash.salted_password(saslprep(password)?.as_bytes(), salt, iterations)?;
+        let client_key = hash.hmac(&salted, b"Client Key")?;
+        Ok(Self {
+            hash,
+            salt: salt.to_vec(),
+            iterations,
+            stored_key: hash.hash(&client_key),
+            server_key: hash.hmac(&salted, b"Server Key")?,
+        })
+    }
+
+    /// Check a plaintext password (e.g. from SASL PLAIN) against these keys
+    pub fn verify_password(&self, password: &str) -> bool {
+        let h = self.hash;
+        let candidate = saslprep(password)
+            .and_then(|password| h.salted_password(password.as_bytes(), &self.salt, self.iterations))
+            .and_then(|salted| h.hmac(&salted, b"Client Key"))
+            .map(|client_key| h.hash(&client_key));
+
+        match candidate {
+            Ok(stored_key) => constant_time_eq(&stored_key, &self.stored_key),
+            Err(_) => false,
+        }
+    }
+}
+
+impl fmt::Debug for ScramCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScramCredentials")
+            .field("hash", &self.hash)
+            .field("iterations", &self.iterations)
+            .field("stored_key", &format_args!("[REDACTED]"))
+            .field("server_key", &format_args!("[REDACTED]"))
+            .finish()
+    }
+}
+
+/// Everything the server knows about an account's credentials
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccountCredentials {
+    /// Canonical account name
+    pub account: String,
+    /// SCRAM keys, at most one set per hash (used for SCRAM and to verify PLAIN)
+    pub scram: Vec<ScramCredentials>,
+    /// Client certificate fingerprints accepted for EXTERNAL
+    pub certfps: Vec<String>,
+    /// Public key accepted for ECDSA-NIST256P-CHALLENGE
+    pub ecdsa_public_key: Option<EcdsaPublicKey>,
+}
+
+impl AccountCredentials {
+    /// Create an account with no credentials
+    pub fn new(account: impl Into<String>) -> Self {
+        Self {
+            account: account.into(),
+            ..Self::default()
+        }
+    }
+
+    /// SCRAM keys for a particular hash
+    pub fn scram(&self, hash: ScramHash) -> Option<&ScramCredentials> {
+        self.scram.iter().find(|creds| creds.hash == hash)
+    }
+
+    /// Add SCRAM keys, replacing any existing keys for the same hash
+    pub fn set_scram(&mut self, credentials: ScramCredentials) {
+        self.scram.retain(|creds| creds.hash != credentials.hash);
+        self.scram.push(credentials);
+        self.scram.sort_by_key(|creds| creds.hash.output_len());
+    }
+}
+
+/// Lookup interface used by [`SaslServer`](super::SaslServer)
+pub trait CredentialStore: Send + Sync {
+    /// Find an account by name
+    fn lookup(&self, account: &str) -> Result<Option<AccountCredentials>>;
+
+    /// Find the account that owns a client certificate fingerprint
+    fn lookup_by_certfp(&self, fingerprint: &str) -> Result<Option<AccountCredentials>>;
+}
+
+/// Normalize a certificate fingerprint to lowercase hex without separators
+pub fn normalize_certfp(fingerprint: &str) -> String {
+    fingerprint.chars()
+        .filter(|c| *c != ':')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// In-memory credential store
+///
+/// Account names are matched under the store's casemapping (rfc1459 unless
+/// changed with [`with_casemapping`](Self::with_casemapping)).
+#[derive(Debug, Clone, Default)]
+pub struct MemoryCredentialStore {
+    accounts: HashMap<CaseFoldedKey, AccountCredentials>,
+    casemapping: CaseMapping,
+}
+
+impl MemoryCredentialStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Match account names under a different casemapping
+    pub fn with_casemapping(mut self, casemapping: CaseMapping) -> Self {
+        self.casemapping = casemapping;
+        self.accounts = std::mem::take(&mut self.accounts).into_values()
+            .map(|creds| (casemapping.key(&creds.account), creds))
+            .collect();
+        self
+    }
+
+    /// Insert or replace an account
+    pub fn insert(&mut self, credentials: AccountCredentials) {
+        self.accounts.insert(self.casemapping.key(&credentials.account), credentials);
+    }
+
+    /// Remove an account
+    pub fn remove(&mut self, account: &str) -> Option<AccountCredentials> {
+        self.accounts.remove(self.casemapping.fold(account).as_str())
+    }
+
+    fn entry(&mut self, account: &str) -> &mut AccountCredentials {
+        self.accounts.entry(self.casemapping.key(account))
+            .or_insert_with(|| AccountCredentials::new(account))
+    }
+
+    /// Set an account's password, creating the account if needed
+    ///
+    /// Keys are derived for every SCRAM hash; `iterations` is raised to each
+    /// hash's minimum where necessary.
+    pub fn set_password(&mut self, account: &str, password: &str, iterations: u32) -> Result<()> {
+        self.set_password_with(account, password, iterations, &ScramHash::ALL)
+    }
+
+    /// Set an account's password, deriving keys only for the given hashes
+    pub fn set_password_with(
+        &mut self,
+        account: &str,
+        password: &str,
+        iterations: u32,
+        hashes: &[ScramHash],
+    ) -> Result<()> {
+        let derived = hashes.iter()
+            .map(|hash| ScramCredentials::from_password(*hash, password, iterations.max(hash.min_iterations())))
+            .collect::<Result<Vec<_>>>()?;
+        let entry = self.entry(account);
+        entry.scram.clear();
+        for scram in derived {
+            entry.set_scram(scram);
+        }
+        Ok(())
+    }
+
+    /// Allow a certificate fingerprint to log in to an account via EXTERNAL
+    pub fn add_certfp(&mut self, account: &str, fingerprint: &str) {
+        let fingerprint = normalize_certfp(fingerprint);
+        let entry = self.entry(account);
+        if !entry.certfps.contains(&fingerprint) {
+            entry.certfps.push(fingerprint);
+        }
+    }
+
+    /// Set the public key accepted for ECDSA-NIST256P-CHALLENGE
+    pub fn set_ecdsa_key(&mut self, account: &str, key: EcdsaPublicKey) {
+        self.entry(account).ecdsa_public_key = Some(key);
+    }
+
+    /// Iterate over all accounts
+    pub fn accounts(&self) -> impl Iterator<Item = &AccountCredentials> {
+        self.accounts.values()
+    }
+}
+
+impl CredentialStore for MemoryCredentialStore {
+    fn lookup(&self, account: &str) -> Result<Option<AccountCredentials>> {
+        Ok(self.accounts.get(self.casemapping.fold(account).as_str()).cloned())
+    }
+
+    fn lookup_by_certfp(&self, fingerprint: &str) -> Result<Option<AccountCredentials>> {
+        let fingerprint = normalize_certfp(fingerprint);
+        Ok(self.accounts.values()
+            .find(|creds| creds.certfps.contains(&fingerprint))
+            .cloned())
+    }
+}
+
+/// Credential store persisted to a text file
+///
+/// Each line holds one record:
+///
+/// ```text
+/// scram-sha-256 <account> <iterations> <salt> <stored-key> <server-key>
+/// scram-sha-512 <account> <iterations> <salt> <stored-key> <server-key>
+/// certfp <account> <fingerprint>
+/// ecdsa-nist256p <account> <public-key>
+/// ```
+///
+/// Binary fields are base64. Blank lines and lines starting with `#` are
+/// ignored.
+#[derive(Debug, Clone)]
+pub struct FileCredentialStore {
+    path: PathBuf,
+    inner: MemoryCredentialStore,
+}
+
+impl FileCredentialStore {
+    /// Load a store from disk; a missing file yields an empty store
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut inner = MemoryCredentialStore::new();
+
+        if path.exists() {
+            let contents = fs::read_to_string(&path)?;
+            for (lineno, line) in contents.lines().enumerate() {
+                Self::parse_line(&mut inner, line).map_err(|e| IronError::Config(
+                    format!("{}:{}: {}", path.display(), lineno + 1, e)
+                ))?;
+            }
+        }
+
+        Ok(Self { path, inner })
+    }
+
+    /// Set an account's password (only the derived SCRAM keys are stored)
+    pub fn set_password(&mut self, account: &str, password: &str, iterations: u32) -> Result<()> {
+        Self::check_account_name(account)?;
+        self.inner.set_password(account, password, iterations)
+    }
+
+    /// Allow a certificate fingerprint to log in to an account
+    pub fn add_certfp(&mut self, account: &str, fingerprint: &str) -> Result<()> {
+        Self::check_account_name(account)?;
+        self.inner.add_certfp(account, fingerprint);
+        Ok(())
+    }
+
+    /// Set the public key accepted for ECDSA-NIST256P-CHALLENGE
+    pub fn set_ecdsa_key(&mut self, account: &str, key: EcdsaPublicKey) -> Result<()> {
+        Self::check_account_name(account)?;
+        self.inner.set_ecdsa_key(account, key);
+        Ok(())
+    }
+
+    /// Remove an account
+    pub fn remove(&mut self, account: &str) -> Option<AccountCredentials> {
+        self.inner.remove(account)
+    }
+
+    /// Write the store back to disk
+    pub fn save(&self) -> Result<()> {
+        let mut accounts: Vec<&AccountCredentials> = self.inner.accounts().collect();
+        accounts.sort_by(|a, b| a.account.cmp(&b.account));
+
+        let mut out = String::new();
+        for creds in accounts {
+            for scram in &creds.scram {
+                out.push_str(&format!(
+                    "scram-{} {} {} {} {} {}\n",
+                    scram.hash.as_str().to_lowercase(),
+                    creds.account,
+                    scram.iterations,
+                    BASE64.encode(&scram.salt),
+                    BASE64.encode(&scram.stored_key),
+                    BASE64.encode(&scram.server_key),
+                ));
+            }
+            for fp in &creds.certfps {
+                out.push_str(&format!("certfp {} {}\n", creds.account, fp));
+            }
+            if let Some(key) = &creds.ecdsa_public_key {
+                out.push_str(&format!("ecdsa-nist256p {} {}\n", creds.account, key.to_base64()));
+            }
+        }
+
+        // Write to a sibling file first so a crash never leaves a torn store
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    fn check_account_name(account: &str) -> Result<()> {
+        if account.is_empty() || account.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(IronError::InvalidInput(format!("Invalid account name: {:?}", account)));
+        }
+        Ok(())
+    }
+
+    fn parse_line(store: &mut MemoryCredentialStore, line: &str) -> Result<()> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let decode = |field: &str| BASE64.decode(field)
+            .map_err(|_| IronError::Parse("Invalid base64 field".to_string()));
+
+        match fields.as_slice() {
+            [record, account, iterations, salt, stored_key, server_key] if record.starts_with("scram-") => {
+                let hash = ScramHash::from_str(&record["scram-".len()..])
+                    .ok_or_else(|| IronError::Parse(format!("Unknown SCRAM hash: {}", record)))?;
+                let scram = ScramCredentials {
+                    hash,
+                    iterations: iterations.parse()
+                        .map_err(|_| IronError::Parse("Invalid iteration count".to_string()))?,
+                    salt: decode(salt)?,
+                    stored_key: decode(stored_key)?,
+                    server_key: decode(server_key)?,
+                };
+                let mut creds = store.lookup(account)?
+                    .unwrap_or_else(|| AccountCredentials::new(*account));
+                creds.set_scram(scram);
+                store.insert(creds);
+                Ok(())
+            }
+            ["certfp", account, fingerprint] => {
+                store.add_certfp(account, fingerprint);
+                Ok(())
+            }
+            ["ecdsa-nist256p", account, key] => {
+                store.set_ecdsa_key(account, EcdsaPublicKey::from_base64(key)?);
+                Ok(())
+            }
+            _ => Err(IronError::Parse(format!("Unrecognized record: {}", fields[0]))),
+        }
+    }
+}
+
+impl CredentialStore for FileCredentialStore {
+    fn lookup(&self, account: &str) -> Result<Option<AccountCredentials>> {
+        self.inner.lookup(account)
+    }
+
+    fn lookup_by_certfp(&self, fingerprint: &str) -> Result<Option<AccountCredentials>> {
+        self.inner.lookup_by_certfp(fingerprint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scram_credentials_verify_password() {
+        let creds = ScramCredentials::from_password(ScramHash::Sha256, "hunter2", 4096).unwrap();
+        assert!(creds.verify_password("hunter2"));
+        assert!(!creds.verify_password("hunter3"));
+        assert!(ScramCredentials::from_password(ScramHash::Sha256, "weak", 100).is_err());
+        assert!(ScramCredentials::from_password(ScramHash::Sha512, "weak", 4096).is_err());
+
+        let mut account = AccountCredentials::new("alice");
+        account.set_scram(creds.clone());
+        let debug = format!("{:?}", account);
+        assert!(debug.contains("[REDACTED]"));
+        assert!(!debug.contains(&format!("{:?}", creds.server_key)));
+    }
+
+    #[test]
+    fn test_memory_store_casemapping() {
+        let mut store = MemoryCredentialStore::new();
+        store.add_certfp("[Alice]", "aa");
+        assert_eq!(store.lookup("{alice}").unwrap().unwrap().account, "[Alice]");
+
+        let store = store.with_casemapping(CaseMapping::Ascii);
+        assert!(store.lookup("{alice}").unwrap().is_none());
+        assert!(store.lookup("[ALICE]").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_file_store_roundtrip() {
+        let path = std::env::temp_dir().join(format!("legion-creds-{}.txt", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut store = FileCredentialStore::open(&path).unwrap();
+        store.set_password("alice", "correct horse", 4096).unwrap();
+        store.add_certfp("bob", "AB:CD:EF").unwrap();
+        let key = crate::sasl::EcdsaPrivateKey::generate().public_key();
+        store.set_ecdsa_key("bot", key.clone()).unwrap();
+        store.save().unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("correct horse"));
+
+        let reloaded = FileCredentialStore::open(&path).unwrap();
+        let alice = reloaded.lookup("alice").unwrap().unwrap();
+        assert_eq!(alice.scram.len(), ScramHash::ALL.len());
+        assert!(alice.scram(ScramHash::Sha1).unwrap().verify_password("correct horse"));
+        assert!(alice.scram(ScramHash::Sha512).unwrap().verify_password("correct horse"));
+        let bob = reloaded.lookup_by_certfp("abcdef").unwrap().unwrap();
+        assert_eq!(bob.account, "bob");
+        assert_eq!(reloaded.lookup("bot").unwrap().unwrap().ecdsa_public_key, Some(key));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+//! Redacted, zeroizing storage for passwords and keys
+//!
+//! [`Secret`] keeps sensitive strings out of logs and serialized state: its
+//! `Debug` and `Display` output is redacted, its buffer is wiped on drop, and
+//! with the `serde` feature it serializes as a `[REDACTED]` placeholder
+//! rather than the plaintext. Deserializing reads plaintext (e.g. from
+//! configuration). Structs holding a secret skip it when serializing, so
+//! they round-trip with the secret empty or `None`. Call [`Secret::expose`]
+//! where the value is really needed.
+
+use crate::utils::constant_time_eq;
+use std::fmt;
+use zeroize::Zeroize;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Placeholder shown instead of a secret value
+const REDACTED: &str = "[REDACTED]";
+
+/// A sensitive string that is redacted when printed and wiped on drop
+#[derive(Clone, Default)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Wrap a sensitive value
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// Access the plaintext value
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Check if the secret is empty
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Hex SHA-256 digest of the value, prefixed with `sha256:`
+    pub fn digest(&self) -> String {
+        use sha2::{Digest, Sha256};
+        let hash = Sha256::digest(self.0.as_bytes());
+        let hex: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("sha256:{}", hex)
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl PartialEq for Secret {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(self.0.as_bytes(), other.0.as_bytes())
+    }
+}
+
+impl Eq for Secret {}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Secret").field(&format_args!("{}", REDACTED)).finish()
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+/// Serialized as a placeholder so neither the plaintext nor a guessable
+/// hash of it reaches logs or snapshots
+#[cfg(feature = "serde")]
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+/// Serialize a secret as an empty string, for fields that cannot be skipped
+///
+/// Use with `#[serde(serialize_with = "...")]`, e.g. on a newtype variant.
+#[cfg(feature = "serde")]
+pub fn serialize_empty<S: Serializer>(_secret: &Secret, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str("")
+}
+
+/// Deserialized from plaintext, e.g. when loading configuration
+///
+/// A bare redacted placeholder is rejected rather than taken as the
+/// password.
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        if value == REDACTED {
+            return Err(serde::de::Error::custom("secret was serialized redacted and cannot be restored"));
+        }
+        Ok(Secret(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redaction() {
+        let secret = Secret::new("hunter2");
+        assert_eq!(format!("{}", secret), "[REDACTED]");
+        assert_eq!(format!("{:?}", secret), "Secret([REDACTED])");
+        assert_eq!(secret.expose(), "hunter2");
+        assert_eq!(secret, Secret::from("hunter2"));
+        assert_ne!(secret, Secret::from("hunter3"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serializes_redacted() {
+        let json = serde_json::to_string(&Secret::new("abc")).unwrap();
+        assert_eq!(json, "\"[REDACTED]\"");
+        assert!(!json.contains("abc"));
+
+        let secret: Secret = serde_json::from_str("\"abc\"").unwrap();
+        assert_eq!(secret.expose(), "abc");
+        assert!(serde_json::from_str::<Secret>("\"[REDACTED]\"").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_holders_round_trip_without_secret() {
+        use crate::admin::ChannelSettings;
+        use crate::command::Command;
+        use crate::connection::WebIrc;
+
+        let json = serde_json::to_string(&Command::Pass(Secret::new("hunter2"))).unwrap();
+        assert!(!json.contains("hunter2") && !json.contains(REDACTED));
+        assert_eq!(serde_json::from_str::<Command>(&json).unwrap(), Command::Pass(Secret::default()));
+
+        let oper = Command::Oper { name: "root".to_string(), password: Secret::new("hunter2") };
+        let restored: Command = serde_json::from_str(&serde_json::to_string(&oper).unwrap()).unwrap();
+        assert!(matches!(restored, Command::Oper { ref name, ref password } if name == "root" && password.is_empty()));
+
+        let settings = ChannelSettings { password: Some(Secret::new("key")), ..Default::default() };
+        let json = serde_json::to_string(&settings).unwrap();
+        assert!(!json.contains("key\""));
+        assert!(serde_json::from_str::<ChannelSettings>(&json).unwrap().password.is_none());
+
+        let webirc = WebIrc::new("s3cret", "kiwi", "host", "192.0.2.1".parse().unwrap());
+        let restored: WebIrc = serde_json::from_str(&serde_json::to_string(&webirc).unwrap()).unwrap();
+        assert!(restored.password.is_empty());
+        assert_eq!(restored.hostname, "host");
+    }
+}
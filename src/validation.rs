@@ -2,6 +2,7 @@
 
 use crate::error::{IronError, Result};
 use crate::constants::*;
+use crate::formatting::is_formatting_char;
 use crate::utils::{is_valid_nick, is_valid_channel};
 
 /// Validate an IRC nickname
@@ -33,8 +34,9 @@ pub fn validate_message_content(content: &str) -> Result<()> {
         ));
     }
 
-    // Check for control characters that could cause issues
-    if content.chars().any(|c| c.is_control() && c != '\t') {
+    // Check for control characters that could cause issues; IRC formatting
+    // codes are allowed
+    if content.chars().any(|c| c.is_control() && c != '\t' && !is_formatting_char(c)) {
         return Err(IronError::SecurityViolation(
             "Message contains dangerous control characters".to_string()
         ));
@@ -142,27 +144,6 @@ pub fn validate_ctcp_message(message: &str) -> Result<()> {
     Ok(())
 }
 
-/// Check for flood/spam patterns
-pub fn check_flood_protection(messages: &[&str], _time_window: std::time::Duration) -> Result<()> {
-    if messages.len() > 10 {
-        return Err(IronError::RateLimit(
-            "Too many messages in time window".to_string()
-        ));
-    }
-
-    // Check for repeated messages (simple spam detection)
-    if messages.len() >= 3 {
-        let last_three: Vec<&str> = messages.iter().rev().take(3).cloned().collect();
-        if last_three.iter().all(|&msg| msg == last_three[0]) {
-            return Err(IronError::RateLimit(
-                "Repeated message spam detected".to_string()
-            ));
-        }
-    }
-
-    Ok(())
-}
-
 /// Validate IRC mode string
 pub fn validate_mode_string(mode_string: &str) -> Result<()> {
     if mode_string.is_empty() {
@@ -199,33 +180,147 @@ pub fn sanitize_user_input(input: &str) -> String {
         .replace('\n', " ")       // Replace newlines with spaces
         .replace('\t', " ")       // Replace tabs with spaces
         .chars()
-        .filter(|c| !c.is_control() || *c == ' ') // Remove other control characters
+        .filter(|c| !c.is_control() || is_formatting_char(*c)) // Remove other control characters, keeping formatting
         .take(MAX_MESSAGE_LENGTH) // Truncate to max length
         .collect()
 }
 
-/// Check if a string contains potentially dangerous content
-pub fn contains_dangerous_content(content: &str) -> bool {
-    // Check for common IRC injection patterns
-    let dangerous_patterns = [
-        "\r\n",           // IRC line breaks
-        "\x01",           // CTCP delimiter
-        "PRIVMSG",        // Command injection attempts
-        "NOTICE",
-        "JOIN",
-        "PART",
-        "QUIT",
-        "KICK",
-        "MODE",
-    ];
-
-    for pattern in &dangerous_patterns {
-        if content.to_uppercase().contains(&pattern.to_uppercase()) {
-            return true;
+/// Kind of protocol injection found in user-supplied text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InjectionKind {
+    /// CR/LF followed by something that parses as an IRC command
+    CommandInjection,
+    /// CR/LF followed by a message-tag section and a command
+    TagSmuggling,
+    /// CR/LF that does not start a recognisable command
+    LineBreak,
+    /// A CTCP delimiter outside a single leading CTCP request
+    CtcpSmuggling,
+    /// A NUL byte, at which many servers and clients truncate the line
+    NulTruncation,
+}
+
+impl InjectionKind {
+    /// A short description for moderation logs
+    pub fn description(&self) -> &'static str {
+        match self {
+            InjectionKind::CommandInjection => "line break followed by an IRC command",
+            InjectionKind::TagSmuggling => "line break followed by a message-tag section",
+            InjectionKind::LineBreak => "embedded line break",
+            InjectionKind::CtcpSmuggling => "CTCP delimiter inside message text",
+            InjectionKind::NulTruncation => "NUL byte",
         }
     }
+}
+
+/// How serious an injection finding is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Harmless on its own, e.g. a trailing newline
+    Low,
+    /// Breaks the line but does not form a command
+    Medium,
+    /// Changes how clients interpret the message
+    High,
+    /// Injects a command or tags into the connection
+    Critical,
+}
 
-    false
+/// A single injection finding in analyzed text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InjectionFinding {
+    /// What was found
+    pub kind: InjectionKind,
+    /// How serious it is
+    pub severity: Severity,
+    /// Byte offset of the offending bytes in the analyzed text
+    pub offset: usize,
+    /// Length in bytes of the offending bytes
+    pub len: usize,
+}
+
+/// Find protocol injection attempts in user-supplied text
+///
+/// Ordinary words such as "join" or "mode" are never flagged on their own;
+/// only bytes that can change how the line is framed or interpreted are.
+/// Findings are returned in offset order.
+pub fn analyze_injection(content: &str) -> Vec<InjectionFinding> {
+    let mut findings = Vec::new();
+    let bytes = content.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\0' => {
+                findings.push(InjectionFinding {
+                    kind: InjectionKind::NulTruncation,
+                    severity: Severity::High,
+                    offset: i,
+                    len: 1,
+                });
+                i += 1;
+            }
+            b'\r' | b'\n' => {
+                let run = bytes[i..].iter().take_while(|b| matches!(b, b'\r' | b'\n')).count();
+                let rest = &content[i + run..];
+                let line = &rest[..rest.find(['\r', '\n', '\0']).unwrap_or(rest.len())];
+                let (kind, severity) = classify_injected_line(line);
+                findings.push(InjectionFinding { kind, severity, offset: i, len: run });
+                i += run;
+            }
+            b'\x01' => {
+                // A CTCP request is one leading delimiter with an optional
+                // closing delimiter at the very end
+                let leading = i == 0;
+                let closing = bytes[0] == b'\x01' && i == bytes.len() - 1 && i > 0
+                    && !bytes[1..i].contains(&b'\x01');
+                if !leading && !closing {
+                    findings.push(InjectionFinding {
+                        kind: InjectionKind::CtcpSmuggling,
+                        severity: Severity::High,
+                        offset: i,
+                        len: 1,
+                    });
+                }
+                i += 1;
+            }
+            _ => i += 1,
+        }
+    }
+
+    findings
+}
+
+/// Decide what the text after a line break would do on the wire
+fn classify_injected_line(line: &str) -> (InjectionKind, Severity) {
+    if line.trim().is_empty() {
+        return (InjectionKind::LineBreak, Severity::Low);
+    }
+
+    let Ok(message) = line.parse::<crate::message::IrcMessage>() else {
+        return (InjectionKind::LineBreak, Severity::Medium);
+    };
+    let is_numeric = message.command.len() == 3
+        && message.command.chars().all(|c| c.is_ascii_digit());
+    let known = is_numeric || !matches!(
+        crate::command::Command::parse(&message.command, message.params.clone()),
+        crate::command::Command::Unknown(..)
+    );
+
+    if !known {
+        (InjectionKind::LineBreak, Severity::Medium)
+    } else if line.starts_with('@') {
+        (InjectionKind::TagSmuggling, Severity::Critical)
+    } else {
+        (InjectionKind::CommandInjection, Severity::Critical)
+    }
+}
+
+/// Check if a string contains potentially dangerous content
+///
+/// Shorthand for a non-empty [`analyze_injection`] result.
+pub fn contains_dangerous_content(content: &str) -> bool {
+    !analyze_injection(content).is_empty()
 }
 
 #[cfg(test)]
@@ -256,7 +351,12 @@ mod tests {
     fn test_message_content_validation() {
         assert!(validate_message_content("Hello world").is_ok());
         
+        assert!(validate_message_content("\x02bold\x02 and \x034,12colored\x0F").is_ok());
+        assert!(validate_message_content("\x04ff0000hex\x1D\x1E\x1F\x11\x16").is_ok());
+        
         assert!(validate_message_content("Bad\0message").is_err()); // Null byte
+        assert!(validate_message_content("\x1b[31mansi").is_err()); // Terminal escape
+        assert!(validate_message_content("bell\x07").is_err());
         assert!(validate_message_content(&"x".repeat(600)).is_err()); // Too long
     }
 
@@ -302,6 +402,7 @@ mod tests {
     fn test_sanitize_user_input() {
         assert_eq!(sanitize_user_input("Hello\0world\r\n"), "Helloworld ");
         assert_eq!(sanitize_user_input("Normal text"), "Normal text");
+        assert_eq!(sanitize_user_input("\x02hi\x02\x1b\x07"), "\x02hi\x02");
         
         let long_input = "x".repeat(1000);
         let sanitized = sanitize_user_input(&long_input);
@@ -310,11 +411,41 @@ mod tests {
 
     #[test]
     fn test_dangerous_content_detection() {
-        assert!(contains_dangerous_content("PRIVMSG #test :hello"));
         assert!(contains_dangerous_content("Some\r\nmessage"));
-        assert!(contains_dangerous_content("\x01ACTION test\x01"));
+        assert!(contains_dangerous_content("hi \x01ACTION test\x01"));
         
         assert!(!contains_dangerous_content("Normal message"));
         assert!(!contains_dangerous_content("Hello world"));
+        assert!(!contains_dangerous_content("Please join us in party mode"));
+        assert!(!contains_dangerous_content("PRIVMSG #test :hello"));
+        assert!(!contains_dangerous_content("\x01ACTION test\x01"));
+    }
+
+    #[test]
+    fn test_injection_findings() {
+        let findings = analyze_injection("hi\r\nPRIVMSG #ops :owned");
+        assert_eq!(findings, vec![InjectionFinding {
+            kind: InjectionKind::CommandInjection,
+            severity: Severity::Critical,
+            offset: 2,
+            len: 2,
+        }]);
+
+        let findings = analyze_injection("x\n@+draft/reply=1 :nick!u@h JOIN #a");
+        assert_eq!(findings[0].kind, InjectionKind::TagSmuggling);
+
+        let findings = analyze_injection("a\nhello there\n");
+        assert_eq!(findings.len(), 2);
+        assert_eq!((findings[0].kind, findings[0].severity), (InjectionKind::LineBreak, Severity::Medium));
+        assert_eq!((findings[1].offset, findings[1].severity), (13, Severity::Low));
+
+        let findings = analyze_injection("ok\0\x01DCC SEND x\x01");
+        let kinds: Vec<_> = findings.iter().map(|f| (f.kind, f.offset)).collect();
+        assert_eq!(kinds, vec![
+            (InjectionKind::NulTruncation, 2),
+            (InjectionKind::CtcpSmuggling, 3),
+            (InjectionKind::CtcpSmuggling, 14),
+        ]);
+        assert!(analyze_injection("\x01VERSION").is_empty());
     }
 }
\ No newline at end of file